@@ -0,0 +1,50 @@
+use std::borrow::Cow;
+
+use serde::Deserialize;
+use utoipa::ToSchema;
+use validator::ValidationError;
+use validator_derive::Validate;
+
+use crate::shared::normalize::deserialize_email;
+use crate::users::dto::create_user_dto::{
+  PASSWORD_MAX_LENGTH, PASSWORD_MIN_LENGTH,
+};
+
+#[derive(ToSchema, Debug, Deserialize, Validate)]
+#[validate(schema(function = "validate_change_password_dto"))]
+pub struct ChangePasswordDto {
+  #[validate(length(
+    min = 1,
+    message = "email must have at least 1 characters"
+  ))]
+  #[schema(example = "jane.doe@example.com")]
+  #[serde(deserialize_with = "deserialize_email")]
+  pub email: String,
+  /// Current password, possibly a temporary one set by an admin.
+  #[validate(length(
+    min = 1,
+    message = "password must have at least 1 characters"
+  ))]
+  #[schema(example = "temporary-password")]
+  pub password: String,
+  #[serde(rename = "newPassword")]
+  #[validate(length(
+    max = PASSWORD_MAX_LENGTH,
+    min = PASSWORD_MIN_LENGTH,
+    message = "newPassword must have at least 1 characters"
+  ))]
+  #[schema(example = "correct-horse-battery-staple")]
+  pub new_password: String,
+}
+
+fn validate_change_password_dto(
+  dto: &ChangePasswordDto,
+) -> Result<(), ValidationError> {
+  if dto.new_password == dto.password {
+    return Err(
+      ValidationError::new("password_unchanged")
+        .with_message(Cow::from("newPassword must differ from password")),
+    );
+  }
+  Ok(())
+}
@@ -2,16 +2,21 @@ use serde::Deserialize;
 use utoipa::ToSchema;
 use validator_derive::Validate;
 
+use crate::shared::normalize::deserialize_email;
+
 #[derive(ToSchema, Debug, Deserialize, Validate)]
 pub struct LoginDto {
   #[validate(length(
     min = 1,
     message = "email must have at least 1 characters"
   ))]
+  #[schema(example = "jane.doe@example.com")]
+  #[serde(deserialize_with = "deserialize_email")]
   pub email: String,
   #[validate(length(
     min = 1,
     message = "password must have at least 1 characters"
   ))]
+  #[schema(example = "correct-horse-battery-staple")]
   pub password: String,
 }
@@ -0,0 +1,10 @@
+use serde::Deserialize;
+use utoipa::IntoParams;
+
+#[derive(IntoParams, Debug, Default, Deserialize)]
+#[into_params(parameter_in = Query)]
+pub struct LoginQuery {
+  /// Also set the refresh token as an HttpOnly cookie, for browser clients.
+  #[serde(default)]
+  pub cookie: bool,
+}
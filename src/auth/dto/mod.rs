@@ -1 +1,5 @@
+pub mod change_password_dto;
 pub mod login_dto;
+pub mod login_query;
+pub mod token_dto;
+pub mod verify_email_dto;
@@ -0,0 +1,21 @@
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+use crate::shared::normalize::deserialize_optional_email;
+
+/// Form body of an OAuth2 token request (RFC 6749 sections 4.3 and 6).
+/// Every field is optional so a missing one can be reported as an OAuth2
+/// `invalid_request` rather than a generic form error.
+#[derive(ToSchema, Debug, Default, Deserialize)]
+pub struct TokenDto {
+  #[schema(example = "password")]
+  pub grant_type: Option<String>,
+  /// Email of the user, for the `password` grant.
+  #[schema(example = "jane.doe@example.com")]
+  #[serde(default, deserialize_with = "deserialize_optional_email")]
+  pub username: Option<String>,
+  #[schema(example = "correct-horse-battery-staple")]
+  pub password: Option<String>,
+  /// For the `refresh_token` grant.
+  pub refresh_token: Option<String>,
+}
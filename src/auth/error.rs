@@ -0,0 +1,235 @@
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use thiserror::Error;
+
+use super::handlers::TokenGenerationError;
+use crate::{
+  shared::{
+    hash_worker::HashWorkerError,
+    http_error::{
+      database_timeout, hashing_failed, token_generation_failed, ErrorCode,
+      HttpError,
+    },
+  },
+  users::{
+    model::user_id::UserId, repository::user_repository::UserRepositoryError,
+  },
+};
+
+/// Why an auth handler failed, mapped to its status and JSON body in one
+/// place. Validation failures and OAuth2 token errors have their own
+/// formats and aren't covered.
+#[derive(Debug, Error)]
+pub enum AuthError {
+  /// Missing or wrong credentials, without saying which.
+  #[error("Unauthorized")]
+  Unauthorized,
+  /// No active user has the email. Only with `Config::verbose_auth_errors`.
+  #[error("No such user")]
+  NoSuchUser,
+  /// The password doesn't match. Only with `Config::verbose_auth_errors`.
+  #[error("Wrong password")]
+  WrongPassword,
+  /// Missing, malformed, expired or revoked token.
+  #[error("Invalid token")]
+  InvalidToken,
+  #[error("{}", .0.message())]
+  Forbidden(ForbiddenReason),
+  #[error(transparent)]
+  HasherFailed(#[from] HashWorkerError),
+  #[error("Failed to generate tokens for user {uuid}: {source}")]
+  TokenGenerationFailed {
+    uuid: UserId,
+    source: TokenGenerationError,
+  },
+  /// Boxed, as some backends' errors are large.
+  #[error(transparent)]
+  Internal(Box<UserRepositoryError>),
+}
+
+impl From<UserRepositoryError> for AuthError {
+  fn from(error: UserRepositoryError) -> Self {
+    AuthError::Internal(Box::new(error))
+  }
+}
+
+/// Why an authenticated caller is still turned away.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ForbiddenReason {
+  AccountDisabled,
+  EmailNotVerified,
+  /// An admin reset the password, which must be replaced through
+  /// `change_password` before tokens are issued.
+  PasswordChangeRequired,
+  InvalidCsrfToken,
+}
+
+impl ForbiddenReason {
+  fn message(&self) -> &'static str {
+    match self {
+      ForbiddenReason::AccountDisabled => "Account disabled",
+      ForbiddenReason::EmailNotVerified => "Email not verified",
+      ForbiddenReason::PasswordChangeRequired => "Password change required",
+      ForbiddenReason::InvalidCsrfToken => "Invalid CSRF token",
+    }
+  }
+
+  fn code(&self) -> ErrorCode {
+    match self {
+      ForbiddenReason::AccountDisabled => ErrorCode::AccountDisabled,
+      ForbiddenReason::EmailNotVerified => ErrorCode::EmailNotVerified,
+      ForbiddenReason::PasswordChangeRequired => {
+        ErrorCode::PasswordChangeRequired
+      }
+      ForbiddenReason::InvalidCsrfToken => ErrorCode::InvalidCsrfToken,
+    }
+  }
+}
+
+impl ResponseError for AuthError {
+  fn status_code(&self) -> StatusCode {
+    match self {
+      AuthError::Unauthorized
+      | AuthError::NoSuchUser
+      | AuthError::WrongPassword
+      | AuthError::InvalidToken => StatusCode::UNAUTHORIZED,
+      AuthError::Forbidden(_) => StatusCode::FORBIDDEN,
+      AuthError::Internal(error)
+        if matches!(**error, UserRepositoryError::Timeout) =>
+      {
+        StatusCode::SERVICE_UNAVAILABLE
+      }
+      AuthError::HasherFailed(_)
+      | AuthError::TokenGenerationFailed { .. }
+      | AuthError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+  }
+
+  /// Server errors are logged here, as their cause never reaches the
+  /// client.
+  fn error_response(&self) -> HttpResponse {
+    let error = match self {
+      AuthError::Unauthorized | AuthError::InvalidToken => {
+        HttpError::from("Unauthorized").with_code(ErrorCode::Unauthorized)
+      }
+      AuthError::NoSuchUser => {
+        HttpError::from("No such user").with_code(ErrorCode::UserNotFound)
+      }
+      AuthError::WrongPassword => {
+        HttpError::from("Wrong password").with_code(ErrorCode::InvalidPassword)
+      }
+      AuthError::Forbidden(reason) => {
+        HttpError::from(reason.message()).with_code(reason.code())
+      }
+      AuthError::HasherFailed(_) => {
+        eprintln!("{}", self);
+        return hashing_failed();
+      }
+      AuthError::TokenGenerationFailed { .. } => {
+        eprintln!("{}", self);
+        return token_generation_failed();
+      }
+      AuthError::Internal(error)
+        if matches!(**error, UserRepositoryError::Timeout) =>
+      {
+        eprintln!("{}", self);
+        return database_timeout();
+      }
+      AuthError::Internal(_) => {
+        eprintln!("{}", self);
+        HttpError::from("Internal server error")
+      }
+    };
+    HttpResponse::build(self.status_code())
+      .content_type("application/json")
+      .json(error)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use actix_web::body::to_bytes;
+
+  use super::*;
+
+  async fn body(error: AuthError) -> (StatusCode, HttpError) {
+    let response = error.error_response();
+    let status = response.status();
+    let body = to_bytes(response.into_body()).await.unwrap();
+    (status, serde_json::from_slice(&body).unwrap())
+  }
+
+  #[actix_web::test]
+  async fn test_auth_error_responses() {
+    for (error, status, message, code) in [
+      (
+        AuthError::Unauthorized,
+        StatusCode::UNAUTHORIZED,
+        "Unauthorized",
+        Some(ErrorCode::Unauthorized),
+      ),
+      (
+        AuthError::InvalidToken,
+        StatusCode::UNAUTHORIZED,
+        "Unauthorized",
+        Some(ErrorCode::Unauthorized),
+      ),
+      (
+        AuthError::NoSuchUser,
+        StatusCode::UNAUTHORIZED,
+        "No such user",
+        Some(ErrorCode::UserNotFound),
+      ),
+      (
+        AuthError::WrongPassword,
+        StatusCode::UNAUTHORIZED,
+        "Wrong password",
+        Some(ErrorCode::InvalidPassword),
+      ),
+      (
+        AuthError::Forbidden(ForbiddenReason::AccountDisabled),
+        StatusCode::FORBIDDEN,
+        "Account disabled",
+        Some(ErrorCode::AccountDisabled),
+      ),
+      (
+        AuthError::Forbidden(ForbiddenReason::InvalidCsrfToken),
+        StatusCode::FORBIDDEN,
+        "Invalid CSRF token",
+        Some(ErrorCode::InvalidCsrfToken),
+      ),
+      (
+        AuthError::HasherFailed(HashWorkerError::Receive),
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Password hashing failed",
+        Some(ErrorCode::HashingFailed),
+      ),
+      (
+        AuthError::TokenGenerationFailed {
+          uuid: UserId::try_from("some-uuid").unwrap(),
+          source: TokenGenerationError::ClaimsTooLarge(4096),
+        },
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Tokens could not be generated",
+        Some(ErrorCode::TokenGenerationFailed),
+      ),
+      (
+        AuthError::from(UserRepositoryError::NotFound),
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Internal server error",
+        None,
+      ),
+      (
+        AuthError::from(UserRepositoryError::Timeout),
+        StatusCode::SERVICE_UNAVAILABLE,
+        "Database unavailable, retry later",
+        Some(ErrorCode::DatabaseTimeout),
+      ),
+    ] {
+      assert_eq!(error.status_code(), status);
+      let (response_status, body) = body(error).await;
+      assert_eq!(response_status, status);
+      assert_eq!(body.message, message);
+      assert_eq!(body.code, code);
+    }
+  }
+}
@@ -1,158 +1,946 @@
+use actix_web::cookie::time;
+use actix_web::cookie::Cookie;
+use actix_web::cookie::SameSite;
+use actix_web::http::header;
 use actix_web::HttpRequest;
 use actix_web::{web, HttpResponse, Responder};
+use actix_web_httpauth::extractors::bearer::BearerAuth;
+use chrono::DateTime;
 use chrono::Utc;
 use jsonwebtoken::decode;
+use jsonwebtoken::decode_header;
 use jsonwebtoken::encode;
-use jsonwebtoken::Algorithm;
 use jsonwebtoken::DecodingKey;
 use jsonwebtoken::EncodingKey;
 use jsonwebtoken::Header;
 use jsonwebtoken::Validation;
+use serde::de::{DeserializeOwned, IgnoredAny};
 use serde::Deserialize;
 use serde::Serialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use subtle::ConstantTimeEq;
+use thiserror::Error;
 use validator::Validate;
 
+use super::dto::change_password_dto::ChangePasswordDto;
 use super::dto::login_dto::LoginDto;
+use super::dto::login_query::LoginQuery;
+use super::dto::token_dto::TokenDto;
+use super::dto::verify_email_dto::VerifyEmailDto;
+use super::error::{AuthError, ForbiddenReason};
 use super::rto::login_rto::LoginRto;
+use super::rto::password_policy_rto::PasswordPolicyRto;
+use super::rto::token_rto::{TokenError, TokenErrorRto, TokenRto};
+use super::rto::validate_token_rto::ValidateTokenRto;
 
+use crate::shared::audit_log::{AuditEntry, AuditEvent, AuditLog};
+use crate::shared::breach_check::{is_breached, BreachClient};
+use crate::shared::clock::Clock;
 use crate::shared::config::Config;
-use crate::shared::hash_worker::Hasher;
-use crate::shared::http_error::HttpError;
+use crate::shared::hash_worker::{HashWorkerError, Hasher};
+use crate::shared::http_error::{breached_password, HttpError};
 use crate::shared::role::Role;
+use crate::users::dto::create_user_dto::{
+  PASSWORD_MAX_LENGTH, PASSWORD_MIN_LENGTH,
+};
+use crate::users::model::email::Email;
 use crate::users::model::user::User;
+use crate::users::model::user_id::UserId;
 use crate::users::repository::user_repository::FindOneProperty;
 use crate::users::repository::user_repository::UserRepository;
+use crate::users::repository::user_repository::UserRepositoryError;
 
-const ACCESS_TOKEN_EXPIRY: u64 = 15 * 60; // 15 minutes in seconds
 const REFRESH_TOKEN_EXPIRY: u64 = 7 * 24 * 60 * 60; // 7 days in seconds
+const VERIFY_TOKEN_EXPIRY: u64 = 24 * 60 * 60; // 1 day in seconds
+
+/// Extra claims past this many bytes of JSON fail token generation rather
+/// than bloating every request that carries the token.
+const MAX_CUSTOM_CLAIMS_SIZE: usize = 1024;
+
+#[derive(Error, Debug)]
+pub enum TokenGenerationError {
+  #[error(
+    "custom claims take {0} bytes, over the {MAX_CUSTOM_CLAIMS_SIZE} byte cap"
+  )]
+  ClaimsTooLarge(usize),
+  #[error("encoding failed: {0}")]
+  Encoding(#[from] jsonwebtoken::errors::Error),
+}
+
+const REFRESH_COOKIE: &str = "refresh_token";
+/// Scoped to the auth routes so the cookie is only sent where it's consumed.
+const REFRESH_COOKIE_PATH: &str = "/v1/auth";
+/// Double-submit token: readable by our own scripts, which echo it back in
+/// `CSRF_HEADER` to prove a cookie-based refresh isn't cross-site.
+const CSRF_COOKIE: &str = "csrf_token";
+const CSRF_HEADER: &str = "X-CSRF-Token";
+
+/// Distinguishes the tokens we sign with the same secret, so one kind can
+/// never be replayed where another is expected.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenType {
+  Access,
+  Refresh,
+  Verify,
+}
 
 #[derive(Serialize, Deserialize)]
-struct AccessTokenClaims {
-  uuid: String,
-  role: Role,
-  sub: String,
+pub struct AccessTokenClaims {
+  pub uuid: UserId,
+  pub role: Role,
+  /// The user's uuid, or their name with `Config::jwt_legacy_subject`.
+  pub sub: String,
+  /// Display name, which unlike `sub` may change. Absent from tokens issued
+  /// before it was added.
+  #[serde(default)]
+  pub name: String,
+  pub token_type: TokenType,
+  /// `User::token_epoch` at issuance. Absent from tokens issued before it
+  /// was added, which count as the first epoch.
+  #[serde(default)]
+  pub epoch: u32,
+  pub iss: String,
+  pub aud: String,
+  pub iat: u64,
+  /// `iat` less the leeway, so verifiers whose clock runs behind accept the
+  /// token right away. Absent from tokens issued before it was added.
+  #[serde(default)]
+  pub nbf: u64,
+  pub exp: u64,
+  /// User attributes listed in `Config::access_token_claims`.
+  #[serde(flatten, default, skip_serializing_if = "HashMap::is_empty")]
+  pub claims: HashMap<String, Value>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RefreshTokenClaims {
+  uuid: UserId,
+  /// Absent from tokens issued before token types existed, which are taken
+  /// as refresh tokens for a transition period.
+  #[serde(default = "legacy_token_type")]
+  token_type: TokenType,
+  /// Only read, to tell those legacy refresh tokens apart from legacy
+  /// access tokens, which lack a type too but carry a role.
+  #[serde(rename = "role", default, skip_serializing)]
+  legacy_role: Option<IgnoredAny>,
+  /// `User::token_epoch` at issuance. Absent from tokens issued before
+  /// revocation existed, which count as the first epoch.
+  #[serde(default)]
+  epoch: u32,
   iat: u64,
+  #[serde(default)]
+  nbf: u64,
   exp: u64,
 }
 
 #[derive(Serialize, Deserialize)]
-struct RefreshTokenClaims {
-  uuid: String,
+struct VerifyTokenClaims {
+  uuid: UserId,
+  email: Email,
+  token_type: TokenType,
   iat: u64,
+  #[serde(default)]
+  nbf: u64,
   exp: u64,
 }
 
+trait TokenClaims: DeserializeOwned {
+  fn iat(&self) -> u64;
+  fn nbf(&self) -> u64;
+  fn exp(&self) -> u64;
+  /// Checks this kind of token needs on top of the signature.
+  fn restrict(_config: &Config, _validation: &mut Validation) {}
+}
+
+impl TokenClaims for AccessTokenClaims {
+  fn iat(&self) -> u64 {
+    self.iat
+  }
+
+  fn nbf(&self) -> u64 {
+    self.nbf
+  }
+
+  fn exp(&self) -> u64 {
+    self.exp
+  }
+
+  fn restrict(config: &Config, validation: &mut Validation) {
+    validation.set_required_spec_claims(&["exp", "iss", "aud"]);
+    validation.set_issuer(&[&config.jwt_issuer]);
+    validation.set_audience(&[&config.jwt_audience]);
+  }
+}
+
+impl TokenClaims for RefreshTokenClaims {
+  fn iat(&self) -> u64 {
+    self.iat
+  }
+
+  fn nbf(&self) -> u64 {
+    self.nbf
+  }
+
+  fn exp(&self) -> u64 {
+    self.exp
+  }
+}
+
+impl TokenClaims for VerifyTokenClaims {
+  fn iat(&self) -> u64 {
+    self.iat
+  }
+
+  fn nbf(&self) -> u64 {
+    self.nbf
+  }
+
+  fn exp(&self) -> u64 {
+    self.exp
+  }
+}
+
 #[utoipa::path(
   post,
   path = "/auth/login",
+  params(LoginQuery),
+  request_body = LoginDto,
   responses(
-    (status = 200, description = "Authenticate based on email/password", body = LoginRto)
+    (status = 200, description = "Authenticate based on email/password", body = LoginRto),
+    (status = 400, description = "Body isn't valid JSON for the schema"),
+    (status = 403, description = "Account disabled, email not verified, or password must be changed first"),
+    (status = 422, description = "Body breaks a validation rule")
   )
 )]
-pub async fn auth_login<UR: UserRepository, H: Hasher>(
+#[allow(clippy::too_many_arguments)]
+pub async fn auth_login<
+  UR: UserRepository,
+  H: Hasher,
+  A: AuditLog,
+  C: Clock,
+>(
   config: web::Data<Config>,
+  clock: web::Data<C>,
   user_repository: web::Data<UR>,
   hasher: web::Data<H>,
+  audit_log: web::Data<A>,
+  request: HttpRequest,
+  query: web::Query<LoginQuery>,
   dto: web::Json<LoginDto>,
-) -> impl Responder {
+) -> Result<HttpResponse, AuthError> {
   // Perform validation
   if let Err(validation_errors) = dto.validate() {
-    // If validation fails, return a 400 error with details
-    return HttpResponse::BadRequest().json(validation_errors);
+    // If validation fails, return a 422 error with details
+    return Ok(
+      HttpResponse::UnprocessableEntity()
+        .json(HttpError::from(validation_errors)),
+    );
   }
 
+  let user = authenticate(
+    &config,
+    user_repository.as_ref(),
+    hasher.as_ref(),
+    audit_log.as_ref(),
+    &request,
+    &dto.email,
+    &dto.password,
+  )
+  .await
+  .map_err(|failure| failure.into_error(&config))?;
+  generate_token_response(&config, clock.now(), user, query.cookie)
+}
+
+/// Why a login or refresh was refused, kept apart from `AuthError` so the
+/// OAuth2 token endpoint can report it in its own error format.
+enum AuthFailure {
+  /// The refresh token is invalid, or no longer matches its user.
+  InvalidToken,
+  /// No active user has the email. Only reported as such with
+  /// `Config::verbose_auth_errors`, otherwise as `Unauthorized`.
+  NoSuchUser,
+  /// Same as `NoSuchUser`, for a password that doesn't match.
+  WrongPassword,
+  AccountDisabled,
+  EmailNotVerified,
+  /// The hasher errored, so whether the password matched is unknown.
+  HashingFailed(HashWorkerError),
+  /// The user lookup failed for a reason other than the user being missing.
+  Internal(Box<UserRepositoryError>),
+  /// Credentials are right, but an admin reset the password and the user
+  /// must replace it through `change_password` before getting tokens.
+  PasswordChangeRequired(Box<User>),
+}
+
+impl AuthFailure {
+  fn into_error(self, config: &Config) -> AuthError {
+    match self {
+      AuthFailure::NoSuchUser if config.verbose_auth_errors => {
+        AuthError::NoSuchUser
+      }
+      AuthFailure::WrongPassword if config.verbose_auth_errors => {
+        AuthError::WrongPassword
+      }
+      AuthFailure::NoSuchUser | AuthFailure::WrongPassword => {
+        AuthError::Unauthorized
+      }
+      AuthFailure::InvalidToken => AuthError::InvalidToken,
+      AuthFailure::AccountDisabled => {
+        AuthError::Forbidden(ForbiddenReason::AccountDisabled)
+      }
+      AuthFailure::EmailNotVerified => {
+        AuthError::Forbidden(ForbiddenReason::EmailNotVerified)
+      }
+      AuthFailure::HashingFailed(error) => AuthError::HasherFailed(error),
+      AuthFailure::Internal(error) => AuthError::Internal(error),
+      AuthFailure::PasswordChangeRequired(_) => {
+        AuthError::Forbidden(ForbiddenReason::PasswordChangeRequired)
+      }
+    }
+  }
+}
+
+/// Checks `email`/`password`, recording the attempt in the audit log.
+async fn authenticate<UR: UserRepository, H: Hasher, A: AuditLog>(
+  config: &Config,
+  user_repository: &UR,
+  hasher: &H,
+  audit_log: &A,
+  request: &HttpRequest,
+  email: &str,
+  password: &str,
+) -> Result<User, AuthFailure> {
+  // Whoever failed to log in is unknown, only the client IP is, so there's
+  // no actor. The target is the account tried, or the email for none.
+  let login_failed = |target: &str| {
+    audit_log
+      .record(AuditEntry::new(AuditEvent::LoginFailed, request).target(target));
+  };
+
   // TODO: This solution below is vulnerable to time based attacks, transform the login
   // process into a time constant solution to prevent those issues.
   // Call `find_one` with `await` on the repository instance
-  let user = user_repository
-    .find_one(FindOneProperty::Email(&dto.email))
-    .await;
-  if user.is_err() {
-    return unauthorized();
-  }
-  let user = user.unwrap();
+  let user = match Email::try_from(email) {
+    Ok(email) => {
+      user_repository
+        .find_one(FindOneProperty::Email(&email))
+        .await
+    }
+    // Can't belong to anyone, so it fails like an unknown address.
+    Err(_) => Err(UserRepositoryError::NotFound),
+  };
+  let user = match user {
+    Ok(user) => user,
+    Err(UserRepositoryError::NotFound) => {
+      login_failed(email);
+      return Err(AuthFailure::NoSuchUser);
+    }
+    Err(error) => return Err(AuthFailure::Internal(Box::new(error))),
+  };
 
-  let password_match_result = hasher
-    .as_ref()
-    .verify_password(&dto.password, &user.password_hash)
-    .await;
+  let password_match_result =
+    hasher.verify_password(password, &user.password_hash).await;
 
-  if !password_match_result.unwrap_or(false) {
-    return unauthorized();
+  let password_matches =
+    password_match_result.map_err(AuthFailure::HashingFailed)?;
+  if !password_matches {
+    login_failed(&user.uuid);
+    return Err(AuthFailure::WrongPassword);
+  }
+  // Only revealed once the password matched, so it can't be used to probe
+  // which accounts are suspended.
+  if !user.enabled {
+    login_failed(&user.uuid);
+    return Err(AuthFailure::AccountDisabled);
+  }
+  if config.require_email_verification && !user.email_verified {
+    login_failed(&user.uuid);
+    return Err(AuthFailure::EmailNotVerified);
   }
-  generate_token_response(&config, user)
+  if user.must_change_password {
+    return Err(AuthFailure::PasswordChangeRequired(Box::new(user)));
+  }
+  audit_log.record(
+    AuditEntry::new(AuditEvent::LoginSucceeded, request)
+      .actor(&user.uuid)
+      .target(email),
+  );
+  Ok(user)
 }
 
 #[utoipa::path(
   post,
-  path = "/auth/access-token",
+  path = "/auth/change-password",
+  request_body = ChangePasswordDto,
   responses(
-    (status = 200, description = "Generate a JWT pair", body = LoginRto)
+    (status = 200, description = "Replace the password, signing out other sessions, and log in", body = LoginRto),
+    (status = 401, description = "Wrong email or current password"),
+    (status = 403, description = "Account disabled or email not verified"),
+    (status = 422, description = "Body breaks a validation rule, or the new password appears in a known breach")
   )
 )]
-pub async fn access_token<UR: UserRepository + 'static, H: Hasher>(
+#[allow(clippy::too_many_arguments)]
+pub async fn change_password<
+  UR: UserRepository,
+  H: Hasher,
+  A: AuditLog,
+  C: Clock,
+  B: BreachClient,
+>(
   config: web::Data<Config>,
+  clock: web::Data<C>,
   user_repository: web::Data<UR>,
+  hasher: web::Data<H>,
+  audit_log: web::Data<A>,
+  breach_client: web::Data<B>,
   request: HttpRequest,
-) -> impl Responder {
-  let refresh_token_claims = decode_refresh_token(&config, request).await;
-  if refresh_token_claims.is_none() {
-    return unauthorized();
+  dto: web::Json<ChangePasswordDto>,
+) -> Result<HttpResponse, AuthError> {
+  if let Err(validation_errors) = dto.validate() {
+    return Ok(
+      HttpResponse::UnprocessableEntity()
+        .json(HttpError::from(validation_errors)),
+    );
   }
-  let refresh_token_claims = refresh_token_claims.unwrap();
 
-  let user = user_repository
-    .find_one(FindOneProperty::Uuid(&refresh_token_claims.uuid))
-    .await;
-  if user.is_err() {
-    return unauthorized();
+  let mut user = match authenticate(
+    &config,
+    user_repository.as_ref(),
+    hasher.as_ref(),
+    audit_log.as_ref(),
+    &request,
+    &dto.email,
+    &dto.password,
+  )
+  .await
+  {
+    Ok(user) => user,
+    // The one failure this endpoint is here to resolve.
+    Err(AuthFailure::PasswordChangeRequired(user)) => *user,
+    Err(failure) => return Err(failure.into_error(&config)),
+  };
+  // Only checked once authenticated, so it can't be used to probe the API
+  // through us.
+  if config.check_pwned_passwords
+    && is_breached(breach_client.as_ref(), &dto.new_password).await
+  {
+    return Ok(breached_password("newPassword"));
   }
-  let user = user.unwrap();
 
-  generate_token_response(&config, user)
+  user.password_hash = hasher.hash_password(&dto.new_password).await?;
+  let now = clock.now();
+  user.password_changed_at = Some(now);
+  user.must_change_password = false;
+  user.token_epoch = user.token_epoch.wrapping_add(1);
+  user.touch(now);
+  user_repository.update(user.clone()).await?;
+  audit_log.record(
+    AuditEntry::new(AuditEvent::PasswordChanged, &request)
+      .actor(&user.uuid)
+      .target(&user.uuid),
+  );
+  generate_token_response(&config, now, user, false)
+}
+
+#[utoipa::path(
+  post,
+  path = "/auth/refresh",
+  responses(
+    (status = 200, description = "Generate a JWT pair from the refresh token in the Authorization header, or else the refresh cookie", body = LoginRto),
+    (status = 401, description = "Missing, malformed or invalid refresh token"),
+    (status = 403, description = "Refresh cookie sent without a matching CSRF token")
+  )
+)]
+pub async fn access_token<UR: UserRepository + 'static, H: Hasher, C: Clock>(
+  config: web::Data<Config>,
+  clock: web::Data<C>,
+  user_repository: web::Data<UR>,
+  request: HttpRequest,
+  credentials: Option<BearerAuth>,
+) -> Result<HttpResponse, AuthError> {
+  // The cookie is only a fallback, so a malformed header is never masked by
+  // a valid cookie.
+  let refresh_cookie = if request.headers().contains_key(header::AUTHORIZATION)
+  {
+    None
+  } else {
+    request.cookie(REFRESH_COOKIE)
+  };
+  // Anything but a well-formed `Bearer <token>` header fails extraction.
+  let token = match (&credentials, &refresh_cookie) {
+    (Some(credentials), _) => credentials.token(),
+    (None, Some(_)) if !csrf_token_matches(&request) => {
+      return Err(AuthError::Forbidden(ForbiddenReason::InvalidCsrfToken));
+    }
+    (None, Some(cookie)) => cookie.value(),
+    (None, None) => return Err(AuthError::Unauthorized),
+  };
+  let user = refresh(&config, clock.now(), user_repository.as_ref(), token)
+    .await
+    .map_err(|failure| failure.into_error(&config))?;
+  // Rotate the cookie along with the tokens when that's what was presented.
+  generate_token_response(&config, clock.now(), user, refresh_cookie.is_some())
 }
 
-async fn decode_refresh_token(
+/// Resolves the user a refresh token was issued to.
+async fn refresh<UR: UserRepository>(
   config: &Config,
+  now: DateTime<Utc>,
+  user_repository: &UR,
+  token: &str,
+) -> Result<User, AuthFailure> {
+  let Some(refresh_token_claims) = decode_refresh_token(config, now, token)
+  else {
+    return Err(AuthFailure::InvalidToken);
+  };
+
+  let user = match user_repository
+    .find_one(FindOneProperty::Uuid(&refresh_token_claims.uuid))
+    .await
+  {
+    Ok(user) => user,
+    Err(UserRepositoryError::NotFound) => {
+      return Err(AuthFailure::InvalidToken);
+    }
+    Err(error) => return Err(AuthFailure::Internal(Box::new(error))),
+  };
+  if user.token_epoch != refresh_token_claims.epoch {
+    return Err(AuthFailure::InvalidToken);
+  }
+  if !user.enabled {
+    return Err(AuthFailure::AccountDisabled);
+  }
+  Ok(user)
+}
+
+#[utoipa::path(
+  post,
+  path = "/auth/logout-all",
+  responses(
+    (status = 204, description = "Sign the user of the access token in the Authorization header out of every session, rejecting all their refresh and access tokens, this one included"),
+    (status = 401, description = "Missing, malformed, invalid or already revoked access token")
+  )
+)]
+pub async fn logout_all<UR: UserRepository, A: AuditLog, C: Clock>(
+  config: web::Data<Config>,
+  clock: web::Data<C>,
+  user_repository: web::Data<UR>,
+  audit_log: web::Data<A>,
   request: HttpRequest,
-) -> Option<RefreshTokenClaims> {
-  // Extract the Authorization header
-  let authorization_header = match request.headers().get("Authorization") {
-    Some(header_value) => match header_value.to_str() {
-      Ok(value) => value,
-      Err(_) => return None,
+  credentials: Option<BearerAuth>,
+) -> Result<HttpResponse, AuthError> {
+  let credentials = credentials.ok_or(AuthError::InvalidToken)?;
+  let (claims, mut user) = authorize_access_token(
+    &config,
+    clock.now(),
+    user_repository.as_ref(),
+    credentials.token(),
+  )
+  .await?
+  .ok_or(AuthError::InvalidToken)?;
+
+  user.token_epoch = user.token_epoch.wrapping_add(1);
+  user.touch(clock.now());
+  user_repository.update(user).await?;
+  audit_log.record(
+    AuditEntry::new(AuditEvent::TokensRevoked, &request)
+      .actor(&claims.uuid)
+      .target(&claims.uuid),
+  );
+  // The refresh cookie is dead now, so browsers may as well drop it.
+  Ok(
+    HttpResponse::NoContent()
+      .cookie(
+        Cookie::build(REFRESH_COOKIE, "")
+          .path(REFRESH_COOKIE_PATH)
+          .http_only(true)
+          .secure(true)
+          .same_site(SameSite::Strict)
+          .max_age(time::Duration::ZERO)
+          .finish(),
+      )
+      .finish(),
+  )
+}
+
+#[utoipa::path(
+  post,
+  path = "/auth/token",
+  request_body(content = TokenDto, content_type = "application/x-www-form-urlencoded"),
+  responses(
+    (status = 200, description = "Issue a JWT pair for an OAuth2 password or refresh_token grant", body = TokenRto),
+    (status = 400, description = "Missing parameter, rejected grant or unsupported grant type", body = TokenErrorRto)
+  )
+)]
+pub async fn oauth_token<
+  UR: UserRepository,
+  H: Hasher,
+  A: AuditLog,
+  C: Clock,
+>(
+  config: web::Data<Config>,
+  clock: web::Data<C>,
+  user_repository: web::Data<UR>,
+  hasher: web::Data<H>,
+  audit_log: web::Data<A>,
+  request: HttpRequest,
+  dto: web::Form<TokenDto>,
+) -> Result<HttpResponse, AuthError> {
+  let user = match (
+    dto.grant_type.as_deref(),
+    &dto.username,
+    &dto.password,
+    &dto.refresh_token,
+  ) {
+    (Some("password"), Some(username), Some(password), _) => {
+      authenticate(
+        &config,
+        user_repository.as_ref(),
+        hasher.as_ref(),
+        audit_log.as_ref(),
+        &request,
+        username,
+        password,
+      )
+      .await
+    }
+    (Some("refresh_token"), _, _, Some(refresh_token)) => {
+      refresh(
+        &config,
+        clock.now(),
+        user_repository.as_ref(),
+        refresh_token,
+      )
+      .await
+    }
+    (Some("password" | "refresh_token") | None, ..) => {
+      return Ok(token_error(TokenError::InvalidRequest));
+    }
+    (Some(_), ..) => {
+      return Ok(token_error(TokenError::UnsupportedGrantType));
+    }
+  };
+  // OAuth2 has no finer-grained code for disabled or unverified accounts.
+  let user = match user {
+    Ok(user) => user,
+    Err(AuthFailure::HashingFailed(error)) => return Err(error.into()),
+    Err(AuthFailure::Internal(error)) => {
+      return Err(AuthError::Internal(error));
+    }
+    Err(_) => return Ok(token_error(TokenError::InvalidGrant)),
+  };
+
+  let expires_in = config.access_token_ttl(&user.role);
+  let uuid = user.uuid.clone();
+  let tokens = generate_token_pair(&config, clock.now(), user)
+    .map_err(|source| AuthError::TokenGenerationFailed { uuid, source })?;
+  Ok(
+    HttpResponse::Ok()
+      .insert_header((header::CACHE_CONTROL, "no-store"))
+      .json(TokenRto {
+        access_token: tokens.access_token,
+        token_type: String::from("Bearer"),
+        expires_in,
+        refresh_token: tokens.refresh_token,
+      }),
+  )
+}
+
+#[utoipa::path(
+  get,
+  path = "/auth/validate",
+  responses(
+    (status = 200, description = "Identity of the access token in the Authorization header", body = ValidateTokenRto),
+    (status = 401, description = "Missing, malformed, expired, revoked or non-access token, or its user is disabled or deleted")
+  )
+)]
+pub async fn validate_token<UR: UserRepository, C: Clock>(
+  config: web::Data<Config>,
+  clock: web::Data<C>,
+  user_repository: web::Data<UR>,
+  credentials: Option<BearerAuth>,
+) -> Result<HttpResponse, AuthError> {
+  let credentials = credentials.ok_or(AuthError::InvalidToken)?;
+  let (claims, _) = authorize_access_token(
+    &config,
+    clock.now(),
+    user_repository.as_ref(),
+    credentials.token(),
+  )
+  .await?
+  .ok_or(AuthError::InvalidToken)?;
+  Ok(HttpResponse::Ok().json(ValidateTokenRto {
+    uuid: claims.uuid,
+    role: claims.role,
+    sub: claims.sub,
+    name: claims.name,
+  }))
+}
+
+#[utoipa::path(
+  get,
+  path = "/auth/password-policy",
+  responses(
+    (status = 200, description = "Rules new passwords must follow, for clients to check before submitting", body = PasswordPolicyRto)
+  )
+)]
+pub async fn password_policy(config: web::Data<Config>) -> impl Responder {
+  HttpResponse::Ok().json(PasswordPolicyRto {
+    min_length: PASSWORD_MIN_LENGTH,
+    max_length: PASSWORD_MAX_LENGTH,
+    required_character_classes: Vec::new(),
+    breach_check: config.check_pwned_passwords,
+  })
+}
+
+#[utoipa::path(
+  post,
+  path = "/auth/verify-email",
+  request_body = VerifyEmailDto,
+  responses(
+    (status = 204, description = "Mark the email of the token's user as verified"),
+    (status = 401, description = "Invalid or expired verification token"),
+    (status = 422, description = "Body breaks a validation rule")
+  )
+)]
+pub async fn verify_email<UR: UserRepository, C: Clock>(
+  config: web::Data<Config>,
+  clock: web::Data<C>,
+  user_repository: web::Data<UR>,
+  dto: web::Json<VerifyEmailDto>,
+) -> Result<HttpResponse, AuthError> {
+  if let Err(validation_errors) = dto.validate() {
+    return Ok(
+      HttpResponse::UnprocessableEntity()
+        .json(HttpError::from(validation_errors)),
+    );
+  }
+
+  let claims =
+    decode_token::<VerifyTokenClaims>(&config, clock.now(), &dto.token)
+      .filter(|claims| claims.token_type == TokenType::Verify)
+      .ok_or(AuthError::InvalidToken)?;
+
+  let mut user = match user_repository
+    .find_one(FindOneProperty::Uuid(&claims.uuid))
+    .await
+  {
+    Ok(user) => user,
+    Err(UserRepositoryError::NotFound) => return Err(AuthError::InvalidToken),
+    Err(error) => return Err(error.into()),
+  };
+  // A token minted for a previous address must not verify the current one.
+  if user.email != claims.email {
+    return Err(AuthError::InvalidToken);
+  }
+
+  if !user.email_verified {
+    user.email_verified = true;
+    user.touch(clock.now());
+    user_repository.update(user).await?;
+  }
+  Ok(HttpResponse::NoContent().finish())
+}
+
+/// Issues a short-lived token that proves ownership of `user`'s current
+/// email once consumed by `verify_email`.
+pub fn generate_verification_token(
+  config: &Config,
+  now: DateTime<Utc>,
+  user: &User,
+) -> Result<String, jsonwebtoken::errors::Error> {
+  let now = now.timestamp() as u64;
+  generate_jwt(
+    config,
+    VerifyTokenClaims {
+      uuid: user.uuid.clone(),
+      email: user.email.clone(),
+      token_type: TokenType::Verify,
+      iat: now,
+      nbf: not_before(config, now),
+      exp: now + VERIFY_TOKEN_EXPIRY,
     },
-    None => return None,
+  )
+}
+
+/// Verifies an access token's signature, expiry, type, issuer and audience.
+/// The one place access tokens are checked, whatever presents them.
+pub fn decode_access_token(
+  config: &Config,
+  now: DateTime<Utc>,
+  token: &str,
+) -> Option<AccessTokenClaims> {
+  decode_token::<AccessTokenClaims>(config, now, token)
+    .filter(|claims| claims.token_type == TokenType::Access)
+}
+
+/// Verifies an access token like `decode_access_token`, then that its user
+/// may still use it: the account must exist, be enabled and not deleted,
+/// and the token must be of the user's current epoch, so revoking a user's
+/// tokens rejects their access tokens too. Returns the claims along with
+/// the user.
+pub async fn authorize_access_token<UR: UserRepository>(
+  config: &Config,
+  now: DateTime<Utc>,
+  user_repository: &UR,
+  token: &str,
+) -> Result<Option<(AccessTokenClaims, User)>, UserRepositoryError> {
+  let Some(claims) = decode_access_token(config, now, token) else {
+    return Ok(None);
   };
-  let token = authorization_header.replace("Bearer ", "");
+  match user_repository
+    .find_one(FindOneProperty::Uuid(&claims.uuid))
+    .await
+  {
+    Ok(user)
+      if user.enabled
+        && user.deleted_at.is_none()
+        && user.token_epoch == claims.epoch =>
+    {
+      Ok(Some((claims, user)))
+    }
+    Ok(_) | Err(UserRepositoryError::NotFound) => Ok(None),
+    Err(error) => Err(error),
+  }
+}
 
-  let decode_result = decode::<RefreshTokenClaims>(
-    &token,
-    &DecodingKey::from_secret(config.jwt_secret.as_bytes()),
-    &Validation::default(),
-  );
+fn legacy_token_type() -> TokenType {
+  TokenType::Refresh
+}
 
-  if decode_result.is_err() {
-    return None;
+fn decode_refresh_token(
+  config: &Config,
+  now: DateTime<Utc>,
+  token: &str,
+) -> Option<RefreshTokenClaims> {
+  decode_token::<RefreshTokenClaims>(config, now, token).filter(|claims| {
+    claims.token_type == TokenType::Refresh && claims.legacy_role.is_none()
+  })
+}
+
+fn decode_token<T: TokenClaims>(
+  config: &Config,
+  now: DateTime<Utc>,
+  token: &str,
+) -> Option<T> {
+  // Expiry and issuance are checked below against the injected clock
+  // rather than the system time `jsonwebtoken` would use.
+  let mut validation = Validation::new(config.jwt_algorithm);
+  validation.validate_exp = false;
+  validation.leeway = config.jwt_leeway_seconds;
+  T::restrict(config, &mut validation);
+
+  // Tokens issued before key ids were added are tried against every secret.
+  let kid = decode_header(token).ok()?.kid;
+  config
+    .jwt_secrets()
+    .filter(|secret| kid.as_deref().is_none_or(|kid| kid == key_id(secret)))
+    .find_map(|secret| {
+      decode::<T>(
+        token,
+        &DecodingKey::from_secret(secret.as_bytes()),
+        &validation,
+      )
+      .ok()
+    })
+    .map(|token_data| token_data.claims)
+    .filter(|claims| is_current(claims, now, validation.leeway))
+}
+
+/// Whether `claims` are valid at `now`, give or take `leeway` seconds of
+/// clock skew between the issuer and this instance.
+fn is_current<T: TokenClaims>(
+  claims: &T,
+  now: DateTime<Utc>,
+  leeway: u64,
+) -> bool {
+  let now = now.timestamp() as u64;
+  claims.exp() + leeway >= now
+    && claims.iat() <= now + leeway
+    && claims.nbf() <= now + leeway
+}
+
+/// Start of a token's validity issued at `now`, early by `leeway` seconds.
+fn not_before(config: &Config, now: u64) -> u64 {
+  now.saturating_sub(config.jwt_leeway_seconds)
+}
+
+/// Identifies a secret in token headers without revealing anything about
+/// it beyond a truncated digest.
+fn key_id(secret: &str) -> String {
+  Sha256::digest(secret)[..8]
+    .iter()
+    .map(|byte| format!("{:02x}", byte))
+    .collect()
+}
+
+fn custom_claims(
+  config: &Config,
+  user: &User,
+) -> Result<HashMap<String, Value>, TokenGenerationError> {
+  if config.access_token_claims.is_empty() {
+    return Ok(HashMap::new());
   }
-  let decode_result = decode_result.unwrap();
+  let Ok(Value::Object(attributes)) = serde_json::to_value(user) else {
+    return Ok(HashMap::new());
+  };
+  // Standard claims already carry these, and must not be overridden.
+  let reserved = [
+    "uuid",
+    "role",
+    "sub",
+    "name",
+    "token_type",
+    "iss",
+    "aud",
+    "iat",
+    "nbf",
+    "exp",
+  ];
+  let claims: HashMap<String, Value> = attributes
+    .into_iter()
+    .filter(|(name, _)| {
+      config.access_token_claims.contains(name)
+        && !reserved.contains(&name.as_str())
+    })
+    .collect();
 
-  Some(decode_result.claims)
+  let size = serde_json::to_vec(&claims).map_or(usize::MAX, |json| json.len());
+  if size > MAX_CUSTOM_CLAIMS_SIZE {
+    return Err(TokenGenerationError::ClaimsTooLarge(size));
+  }
+  Ok(claims)
 }
 
 fn generate_jwt<T: Serialize>(
   config: &Config,
   claims: T,
 ) -> Result<String, jsonwebtoken::errors::Error> {
+  let mut header = Header::new(config.jwt_algorithm);
+  header.kid = Some(key_id(&config.jwt_secret));
   encode(
-    &Header::new(Algorithm::HS256),
+    &header,
     &claims,
     &EncodingKey::from_secret(config.jwt_secret.as_ref()),
   )
 }
 
-fn generate_token_response(config: &Config, user: User) -> HttpResponse {
-  let now = Utc::now().timestamp() as u64;
+pub fn generate_token_pair(
+  config: &Config,
+  now: DateTime<Utc>,
+  user: User,
+) -> Result<LoginRto, TokenGenerationError> {
+  let now = now.timestamp() as u64;
+  let access_token_ttl = config.access_token_ttl(&user.role);
+  let claims = custom_claims(config, &user)?;
+  let sub = if config.jwt_legacy_subject {
+    user.user_name.clone()
+  } else {
+    user.uuid.to_string()
+  };
 
   // Generate tokens
   let access_token = generate_jwt(
@@ -160,36 +948,1674 @@ fn generate_token_response(config: &Config, user: User) -> HttpResponse {
     AccessTokenClaims {
       uuid: user.uuid.clone(),
       role: user.role,
-      sub: user.user_name.clone(),
+      sub,
+      name: user.user_name.clone(),
+      token_type: TokenType::Access,
+      epoch: user.token_epoch,
+      iss: config.jwt_issuer.clone(),
+      aud: config.jwt_audience.clone(),
       iat: now,
-      exp: now + ACCESS_TOKEN_EXPIRY,
+      nbf: not_before(config, now),
+      exp: now + access_token_ttl,
+      claims,
     },
   );
   let refresh_token = generate_jwt(
     config,
     RefreshTokenClaims {
       uuid: user.uuid.clone(),
+      token_type: TokenType::Refresh,
+      legacy_role: None,
+      epoch: user.token_epoch,
       iat: now,
+      nbf: not_before(config, now),
       exp: now + REFRESH_TOKEN_EXPIRY,
     },
   );
 
-  if access_token.is_err() || refresh_token.is_err() {
-    return HttpResponse::InternalServerError().finish();
+  Ok(LoginRto {
+    access_token: access_token?,
+    refresh_token: refresh_token?,
+  })
+}
+
+/// Responds with a fresh token pair, also setting the refresh token as a
+/// cookie when `refresh_cookie` is set.
+fn generate_token_response(
+  config: &Config,
+  now: DateTime<Utc>,
+  user: User,
+  refresh_cookie: bool,
+) -> Result<HttpResponse, AuthError> {
+  let uuid = user.uuid.clone();
+  let tokens = generate_token_pair(config, now, user)
+    .map_err(|source| AuthError::TokenGenerationFailed { uuid, source })?;
+
+  let mut response = HttpResponse::Ok();
+  if refresh_cookie {
+    let max_age = time::Duration::seconds(REFRESH_TOKEN_EXPIRY as i64);
+    response
+      .cookie(
+        Cookie::build(REFRESH_COOKIE, tokens.refresh_token.clone())
+          .path(REFRESH_COOKIE_PATH)
+          .http_only(true)
+          .secure(true)
+          .same_site(SameSite::Strict)
+          .max_age(max_age)
+          .finish(),
+      )
+      .cookie(
+        Cookie::build(CSRF_COOKIE, nanoid::nanoid!(32))
+          .path("/")
+          .secure(true)
+          .same_site(SameSite::Strict)
+          .max_age(max_age)
+          .finish(),
+      );
   }
+  Ok(response.content_type("application/json").json(tokens))
+}
 
-  let tokens = LoginRto {
-    access_token: access_token.unwrap(),
-    refresh_token: refresh_token.unwrap(),
-  };
+fn csrf_token_matches(request: &HttpRequest) -> bool {
+  let header = request
+    .headers()
+    .get(CSRF_HEADER)
+    .and_then(|value| value.to_str().ok());
+  match (request.cookie(CSRF_COOKIE), header) {
+    (Some(cookie), Some(header)) => {
+      cookie.value().as_bytes().ct_eq(header.as_bytes()).into()
+    }
+    _ => false,
+  }
+}
 
-  HttpResponse::Ok()
-    .content_type("application/json")
-    .json(tokens)
+fn token_error(error: TokenError) -> HttpResponse {
+  HttpResponse::BadRequest()
+    .insert_header((header::CACHE_CONTROL, "no-store"))
+    .json(TokenErrorRto { error })
 }
 
-fn unauthorized() -> HttpResponse {
-  HttpResponse::Unauthorized()
-    .content_type("application/json")
-    .json(HttpError::from("Unauthorized"))
+#[cfg(test)]
+mod tests {
+  use std::sync::{Arc, RwLock};
+
+  use actix_web::{http::StatusCode, FromRequest, HttpRequest};
+  use arc_swap::ArcSwap;
+  use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+  use fake::{faker::internet::en::Password, Fake};
+  use jsonwebtoken::Algorithm;
+
+  use crate::{
+    custom_nanoid,
+    helpers::tests::{
+      fake_user, http_request, parse_http_response, FakeUserRepository,
+    },
+    shared::{
+      audit_log::InMemoryAuditLog,
+      breach_check::MockBreachClient,
+      clock::{FixedClock, SystemClock},
+      config::{ReloadableConfig, DEFAULT_ACCESS_TOKEN_TTL},
+      database::InMemoryDatabase,
+      hash_worker::{HashWorkerError, MockHasher},
+      http_error::ErrorCode,
+    },
+    users::repository::user_repository::UserRepositoryImpl,
+  };
+
+  use super::*;
+
+  fn repository_with(users: Vec<User>) -> UserRepositoryImpl<InMemoryDatabase> {
+    UserRepositoryImpl::new(Arc::new(InMemoryDatabase {
+      users: Arc::new(RwLock::new(users)),
+    }))
+  }
+
+  fn matching_hasher() -> MockHasher {
+    let mut hasher = MockHasher::new();
+    hasher.expect_verify_password().returning(|_, _| Ok(true));
+    hasher
+  }
+
+  #[actix_web::test]
+  async fn test_login_successful() {
+    let config = Config::default().await;
+    let user = fake_user();
+    let request: HttpRequest = http_request(&config.jwt_secret);
+
+    let responder = auth_login(
+      web::Data::new(config),
+      web::Data::new(SystemClock),
+      web::Data::new(repository_with(vec![user.clone()])),
+      web::Data::new(matching_hasher()),
+      web::Data::new(InMemoryAuditLog::new()),
+      request.clone(),
+      web::Query(LoginQuery::default()),
+      web::Json(LoginDto {
+        email: user.email.to_string(),
+        password: Password(12..13).fake(),
+      }),
+    )
+    .await;
+
+    let rto: LoginRto =
+      parse_http_response(responder, &request, StatusCode::OK).await;
+    assert!(!rto.access_token.is_empty());
+    assert!(!rto.refresh_token.is_empty());
+  }
+
+  #[actix_web::test]
+  async fn test_login_soft_deleted_user_unauthorized() {
+    let config = Config::default().await;
+    let mut user = fake_user();
+    user.deleted_at = Some(Utc::now());
+    let request: HttpRequest = http_request(&config.jwt_secret);
+
+    let responder = auth_login(
+      web::Data::new(config),
+      web::Data::new(SystemClock),
+      web::Data::new(repository_with(vec![user.clone()])),
+      web::Data::new(matching_hasher()),
+      web::Data::new(InMemoryAuditLog::new()),
+      request.clone(),
+      web::Query(LoginQuery::default()),
+      web::Json(LoginDto {
+        email: user.email.to_string(),
+        password: Password(12..13).fake(),
+      }),
+    )
+    .await;
+
+    let error: HttpError =
+      parse_http_response(responder, &request, StatusCode::UNAUTHORIZED).await;
+    assert_eq!(error.message, "Unauthorized");
+    assert_eq!(error.code, Some(ErrorCode::Unauthorized));
+  }
+
+  async fn failed_login(
+    verbose_auth_errors: bool,
+    users: Vec<User>,
+    email: &str,
+  ) -> HttpError {
+    let config = Config {
+      verbose_auth_errors,
+      ..Config::default().await
+    };
+    let mut hasher = MockHasher::new();
+    hasher.expect_verify_password().returning(|_, _| Ok(false));
+    let request: HttpRequest = http_request(&config.jwt_secret);
+
+    let responder = auth_login(
+      web::Data::new(config),
+      web::Data::new(SystemClock),
+      web::Data::new(repository_with(users)),
+      web::Data::new(hasher),
+      web::Data::new(InMemoryAuditLog::new()),
+      request.clone(),
+      web::Query(LoginQuery::default()),
+      web::Json(LoginDto {
+        email: email.to_string(),
+        password: Password(12..13).fake(),
+      }),
+    )
+    .await;
+    parse_http_response(responder, &request, StatusCode::UNAUTHORIZED).await
+  }
+
+  #[actix_web::test]
+  async fn test_login_failures_indistinguishable_by_default() {
+    let user = fake_user();
+
+    let unknown = failed_login(false, vec![], &user.email).await;
+    let wrong_password =
+      failed_login(false, vec![user.clone()], &user.email).await;
+
+    for error in [unknown, wrong_password] {
+      assert_eq!(error.message, "Unauthorized");
+      assert_eq!(error.code, Some(ErrorCode::Unauthorized));
+    }
+  }
+
+  #[actix_web::test]
+  async fn test_verbose_auth_errors_tell_login_failures_apart() {
+    let user = fake_user();
+
+    let error = failed_login(true, vec![], &user.email).await;
+    assert_eq!(error.code, Some(ErrorCode::UserNotFound));
+
+    let error = failed_login(true, vec![user.clone()], &user.email).await;
+    assert_eq!(error.code, Some(ErrorCode::InvalidPassword));
+  }
+
+  #[actix_web::test]
+  async fn test_login_hasher_error_is_not_a_wrong_password() {
+    let config = Config::default().await;
+    let user = fake_user();
+    let mut hasher = MockHasher::new();
+    hasher
+      .expect_verify_password()
+      .returning(|_, _| Err(HashWorkerError::Receive));
+    let audit_log = web::Data::new(InMemoryAuditLog::new());
+    let request: HttpRequest = http_request(&config.jwt_secret);
+
+    let responder = auth_login(
+      web::Data::new(config),
+      web::Data::new(SystemClock),
+      web::Data::new(repository_with(vec![user.clone()])),
+      web::Data::new(hasher),
+      audit_log.clone(),
+      request.clone(),
+      web::Query(LoginQuery::default()),
+      web::Json(LoginDto {
+        email: user.email.to_string(),
+        password: Password(12..13).fake(),
+      }),
+    )
+    .await;
+
+    let error: HttpError = parse_http_response(
+      responder,
+      &request,
+      StatusCode::INTERNAL_SERVER_ERROR,
+    )
+    .await;
+    assert_eq!(error.code, Some(ErrorCode::HashingFailed));
+    // Nothing is known about the password, so no failed login is recorded.
+    assert!(audit_log.entries().is_empty());
+  }
+
+  #[actix_web::test]
+  async fn test_login_database_timeout_is_not_an_unknown_user() {
+    let config = Config::default().await;
+    let audit_log = web::Data::new(InMemoryAuditLog::new());
+    let request: HttpRequest = http_request(&config.jwt_secret);
+
+    let responder = auth_login(
+      web::Data::new(config),
+      web::Data::new(SystemClock),
+      web::Data::new(
+        FakeUserRepository::new(fake_user())
+          .broken(|| UserRepositoryError::Timeout),
+      ),
+      web::Data::new(MockHasher::new()),
+      audit_log.clone(),
+      request.clone(),
+      web::Query(LoginQuery::default()),
+      web::Json(LoginDto {
+        email: String::from("someone@example.com"),
+        password: Password(12..13).fake(),
+      }),
+    )
+    .await;
+
+    let error: HttpError =
+      parse_http_response(responder, &request, StatusCode::SERVICE_UNAVAILABLE)
+        .await;
+    assert_eq!(error.code, Some(ErrorCode::DatabaseTimeout));
+    assert!(audit_log.entries().is_empty());
+  }
+
+  #[actix_web::test]
+  async fn test_login_requires_password_change_after_reset() {
+    let config = web::Data::new(Config::default().await);
+    let clock = web::Data::new(FixedClock::new(Utc::now()));
+    let user = User {
+      password_hash: String::from("hashed:password"),
+      must_change_password: true,
+      ..fake_user()
+    };
+    let user_repository = web::Data::new(repository_with(vec![user.clone()]));
+    let mut hasher = MockHasher::new();
+    hasher
+      .expect_verify_password()
+      .returning(|password, hash| Ok(hash == format!("hashed:{}", password)));
+    hasher
+      .expect_hash_password()
+      .returning(|password| Ok(format!("hashed:{}", password)));
+    let hasher = web::Data::new(hasher);
+    let request: HttpRequest = http_request(&config.jwt_secret);
+    let login = |password: &str| {
+      auth_login(
+        config.clone(),
+        clock.clone(),
+        user_repository.clone(),
+        hasher.clone(),
+        web::Data::new(InMemoryAuditLog::new()),
+        request.clone(),
+        web::Query(LoginQuery::default()),
+        web::Json(LoginDto {
+          email: user.email.to_string(),
+          password: password.to_string(),
+        }),
+      )
+    };
+    let change_password = |password: &str| {
+      change_password(
+        config.clone(),
+        clock.clone(),
+        user_repository.clone(),
+        hasher.clone(),
+        web::Data::new(InMemoryAuditLog::new()),
+        web::Data::new(MockBreachClient::new()),
+        request.clone(),
+        web::Json(ChangePasswordDto {
+          email: user.email.to_string(),
+          password: password.to_string(),
+          new_password: String::from("new-password"),
+        }),
+      )
+    };
+
+    // Right credentials, but no tokens until the password is replaced.
+    let responder = login("password").await;
+    let error: HttpError =
+      parse_http_response(responder, &request, StatusCode::FORBIDDEN).await;
+    assert_eq!(error.code, Some(ErrorCode::PasswordChangeRequired));
+
+    let responder = change_password("wrong-password").await;
+    let _: HttpError =
+      parse_http_response(responder, &request, StatusCode::UNAUTHORIZED).await;
+
+    let responder = change_password("password").await;
+    let _: LoginRto =
+      parse_http_response(responder, &request, StatusCode::OK).await;
+    let stored = user_repository
+      .find_one(FindOneProperty::Uuid(&user.uuid))
+      .await
+      .unwrap();
+    assert!(!stored.must_change_password);
+    assert_eq!(stored.password_hash, "hashed:new-password");
+    assert_eq!(stored.password_changed_at, Some(clock.now()));
+
+    let responder = login("new-password").await;
+    let _: LoginRto =
+      parse_http_response(responder, &request, StatusCode::OK).await;
+  }
+
+  #[actix_web::test]
+  async fn test_change_password_rejects_breached_password() {
+    let config = Config {
+      check_pwned_passwords: true,
+      ..Config::default().await
+    };
+    let user = User {
+      password_hash: String::from("hashed:password"),
+      ..fake_user()
+    };
+    let user_repository = web::Data::new(repository_with(vec![user.clone()]));
+    let mut hasher = MockHasher::new();
+    hasher
+      .expect_verify_password()
+      .returning(|password, hash| Ok(hash == format!("hashed:{}", password)));
+    let request: HttpRequest = http_request(&config.jwt_secret);
+
+    let responder = change_password(
+      web::Data::new(config),
+      web::Data::new(SystemClock),
+      user_repository.clone(),
+      web::Data::new(hasher),
+      web::Data::new(InMemoryAuditLog::new()),
+      web::Data::new(MockBreachClient::breached("breached-password")),
+      request.clone(),
+      web::Json(ChangePasswordDto {
+        email: user.email.to_string(),
+        password: String::from("password"),
+        new_password: String::from("breached-password"),
+      }),
+    )
+    .await;
+
+    let error: HttpError = parse_http_response(
+      responder,
+      &request,
+      StatusCode::UNPROCESSABLE_ENTITY,
+    )
+    .await;
+    assert!(error.errors.unwrap().contains_key("newPassword"));
+    let stored = user_repository
+      .find_one(FindOneProperty::Uuid(&user.uuid))
+      .await
+      .unwrap();
+    assert_eq!(stored.password_hash, "hashed:password");
+  }
+
+  #[actix_web::test]
+  async fn test_login_disabled_user_forbidden() {
+    let config = Config::default().await;
+    let mut user = fake_user();
+    user.enabled = false;
+    let request: HttpRequest = http_request(&config.jwt_secret);
+
+    let responder = auth_login(
+      web::Data::new(config),
+      web::Data::new(SystemClock),
+      web::Data::new(repository_with(vec![user.clone()])),
+      web::Data::new(matching_hasher()),
+      web::Data::new(InMemoryAuditLog::new()),
+      request.clone(),
+      web::Query(LoginQuery::default()),
+      web::Json(LoginDto {
+        email: user.email.to_string(),
+        password: Password(12..13).fake(),
+      }),
+    )
+    .await;
+
+    let error: HttpError =
+      parse_http_response(responder, &request, StatusCode::FORBIDDEN).await;
+    assert_eq!(error.message, "Account disabled");
+  }
+
+  #[actix_web::test]
+  async fn test_refresh_disabled_user_forbidden() {
+    let config = Config::default().await;
+    let mut user = fake_user();
+    user.enabled = false;
+
+    let now = Utc::now().timestamp() as u64;
+    let refresh_token = generate_jwt(
+      &config,
+      RefreshTokenClaims {
+        uuid: user.uuid.clone(),
+        token_type: TokenType::Refresh,
+        legacy_role: None,
+        epoch: 0,
+        iat: now,
+        nbf: now,
+        exp: now + REFRESH_TOKEN_EXPIRY,
+      },
+    )
+    .unwrap();
+    let request = actix_web::test::TestRequest::default()
+      .append_header((
+        actix_web::http::header::AUTHORIZATION,
+        format!("Bearer {}", refresh_token),
+      ))
+      .to_http_request();
+
+    let responder = access_token::<_, MockHasher, _>(
+      web::Data::new(config),
+      web::Data::new(SystemClock),
+      web::Data::new(repository_with(vec![user])),
+      request.clone(),
+      bearer(&request).await,
+    )
+    .await;
+
+    let error: HttpError =
+      parse_http_response(responder, &request, StatusCode::FORBIDDEN).await;
+    assert_eq!(error.message, "Account disabled");
+  }
+
+  #[actix_web::test]
+  async fn test_login_unverified_email_gated() {
+    let config = Config {
+      require_email_verification: true,
+      ..Config::default().await
+    };
+    let user = fake_user();
+    let request: HttpRequest = http_request(&config.jwt_secret);
+
+    let responder = auth_login(
+      web::Data::new(config),
+      web::Data::new(SystemClock),
+      web::Data::new(repository_with(vec![user.clone()])),
+      web::Data::new(matching_hasher()),
+      web::Data::new(InMemoryAuditLog::new()),
+      request.clone(),
+      web::Query(LoginQuery::default()),
+      web::Json(LoginDto {
+        email: user.email.to_string(),
+        password: Password(12..13).fake(),
+      }),
+    )
+    .await;
+
+    let error: HttpError =
+      parse_http_response(responder, &request, StatusCode::FORBIDDEN).await;
+    assert_eq!(error.message, "Email not verified");
+  }
+
+  #[actix_web::test]
+  async fn test_verify_email_then_login() {
+    let config = Config {
+      require_email_verification: true,
+      ..Config::default().await
+    };
+    let user = fake_user();
+    let users = Arc::new(RwLock::new(vec![user.clone()]));
+    let user_repository =
+      web::Data::new(UserRepositoryImpl::new(Arc::new(InMemoryDatabase {
+        users: users.clone(),
+      })));
+    let request: HttpRequest = http_request(&config.jwt_secret);
+
+    let token =
+      generate_verification_token(&config, Utc::now(), &user).unwrap();
+    let responder = verify_email(
+      web::Data::new(config.clone()),
+      web::Data::new(SystemClock),
+      user_repository.clone(),
+      web::Json(VerifyEmailDto { token }),
+    )
+    .await;
+    let response = responder.respond_to(&request);
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert!(users.read().unwrap()[0].email_verified);
+
+    let responder = auth_login(
+      web::Data::new(config),
+      web::Data::new(SystemClock),
+      user_repository,
+      web::Data::new(matching_hasher()),
+      web::Data::new(InMemoryAuditLog::new()),
+      request.clone(),
+      web::Query(LoginQuery::default()),
+      web::Json(LoginDto {
+        email: user.email.to_string(),
+        password: Password(12..13).fake(),
+      }),
+    )
+    .await;
+    let _: LoginRto =
+      parse_http_response(responder, &request, StatusCode::OK).await;
+  }
+
+  #[actix_web::test]
+  async fn test_verify_email_rejects_other_token_types() {
+    let config = Config::default().await;
+    let user = fake_user();
+    let users = Arc::new(RwLock::new(vec![user.clone()]));
+    let user_repository =
+      web::Data::new(UserRepositoryImpl::new(Arc::new(InMemoryDatabase {
+        users: users.clone(),
+      })));
+    let request: HttpRequest = http_request(&config.jwt_secret);
+
+    let now = Utc::now().timestamp() as u64;
+    let refresh_token = generate_jwt(
+      &config,
+      RefreshTokenClaims {
+        uuid: user.uuid.clone(),
+        token_type: TokenType::Refresh,
+        legacy_role: None,
+        epoch: 0,
+        iat: now,
+        nbf: now,
+        exp: now + REFRESH_TOKEN_EXPIRY,
+      },
+    )
+    .unwrap();
+    let responder = verify_email(
+      web::Data::new(config.clone()),
+      web::Data::new(SystemClock),
+      user_repository,
+      web::Json(VerifyEmailDto {
+        token: refresh_token,
+      }),
+    )
+    .await;
+    let response = responder.respond_to(&request);
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert!(!users.read().unwrap()[0].email_verified);
+
+    // Nor can a verification token be used as a refresh token.
+    let verification_token =
+      generate_verification_token(&config, Utc::now(), &user).unwrap();
+    assert!(
+      decode_refresh_token(&config, Utc::now(), &verification_token).is_none()
+    );
+  }
+
+  #[actix_web::test]
+  async fn test_login_audited() {
+    let config = Config::default().await;
+    let user = fake_user();
+    let request: HttpRequest = actix_web::test::TestRequest::default()
+      .peer_addr("10.0.0.1:12345".parse().unwrap())
+      .to_http_request();
+    let audit_log = web::Data::new(InMemoryAuditLog::new());
+
+    let responder = auth_login(
+      web::Data::new(config),
+      web::Data::new(SystemClock),
+      web::Data::new(repository_with(vec![user.clone()])),
+      web::Data::new(matching_hasher()),
+      audit_log.clone(),
+      request.clone(),
+      web::Query(LoginQuery::default()),
+      web::Json(LoginDto {
+        email: user.email.to_string(),
+        password: Password(12..13).fake(),
+      }),
+    )
+    .await;
+    let _: LoginRto =
+      parse_http_response(responder, &request, StatusCode::OK).await;
+
+    let entries = audit_log.entries();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].event, AuditEvent::LoginSucceeded);
+    assert_eq!(entries[0].actor.as_deref(), Some(user.uuid.as_str()));
+    assert_eq!(entries[0].target.as_deref(), Some(user.email.as_str()));
+    assert_eq!(entries[0].source_ip.as_deref(), Some("10.0.0.1"));
+  }
+
+  #[actix_web::test]
+  async fn test_failed_login_audited() {
+    let config = Config::default().await;
+    let user = fake_user();
+    let request: HttpRequest = actix_web::test::TestRequest::default()
+      .peer_addr("10.0.0.1:12345".parse().unwrap())
+      .to_http_request();
+    let audit_log = web::Data::new(InMemoryAuditLog::new());
+    let mut hasher = MockHasher::new();
+    hasher.expect_verify_password().returning(|_, _| Ok(false));
+
+    let responder = auth_login(
+      web::Data::new(config),
+      web::Data::new(SystemClock),
+      web::Data::new(repository_with(vec![user.clone()])),
+      web::Data::new(hasher),
+      audit_log.clone(),
+      request.clone(),
+      web::Query(LoginQuery::default()),
+      web::Json(LoginDto {
+        email: user.email.to_string(),
+        password: Password(12..13).fake(),
+      }),
+    )
+    .await;
+    let _: HttpError =
+      parse_http_response(responder, &request, StatusCode::UNAUTHORIZED).await;
+
+    let entries = audit_log.entries();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].event, AuditEvent::LoginFailed);
+    assert_eq!(entries[0].actor, None);
+    assert_eq!(entries[0].target.as_deref(), Some(user.uuid.as_str()));
+    assert_eq!(entries[0].source_ip.as_deref(), Some("10.0.0.1"));
+  }
+
+  async fn bearer(request: &HttpRequest) -> Option<BearerAuth> {
+    BearerAuth::extract(request).await.ok()
+  }
+
+  fn refresh_request(refresh_token: &str) -> HttpRequest {
+    actix_web::test::TestRequest::default()
+      .append_header((
+        actix_web::http::header::AUTHORIZATION,
+        format!("Bearer {}", refresh_token),
+      ))
+      .to_http_request()
+  }
+
+  #[actix_web::test]
+  async fn test_validate_access_token() {
+    let config = web::Data::new(Config::default().await);
+    let clock = web::Data::new(FixedClock::new(Utc::now()));
+    let user = fake_user();
+    let user_repository = web::Data::new(repository_with(vec![user.clone()]));
+    let tokens =
+      generate_token_pair(&config, clock.now(), user.clone()).unwrap();
+
+    let request = refresh_request(&tokens.access_token);
+    let responder = validate_token(
+      config.clone(),
+      clock.clone(),
+      user_repository.clone(),
+      bearer(&request).await,
+    )
+    .await;
+    let rto: ValidateTokenRto =
+      parse_http_response(responder, &request, StatusCode::OK).await;
+    assert_eq!(
+      rto,
+      ValidateTokenRto {
+        uuid: user.uuid.clone(),
+        role: user.role.clone(),
+        sub: user.uuid.to_string(),
+        name: user.user_name.clone(),
+      }
+    );
+
+    // Refresh tokens are signed alike but must not pass as access tokens.
+    let request = refresh_request(&tokens.refresh_token);
+    let responder = validate_token(
+      config.clone(),
+      clock.clone(),
+      user_repository.clone(),
+      bearer(&request).await,
+    )
+    .await;
+    let _: HttpError =
+      parse_http_response(responder, &request, StatusCode::UNAUTHORIZED).await;
+
+    // Neither do access tokens of a previous epoch, once revoked.
+    let mut revoked = user.clone();
+    revoked.token_epoch += 1;
+    user_repository.update(revoked).await.unwrap();
+    let request = refresh_request(&tokens.access_token);
+    let responder = validate_token(
+      config.clone(),
+      clock.clone(),
+      user_repository.clone(),
+      bearer(&request).await,
+    )
+    .await;
+    let _: HttpError =
+      parse_http_response(responder, &request, StatusCode::UNAUTHORIZED).await;
+  }
+
+  #[actix_web::test]
+  async fn test_validate_access_token_of_inactive_user() {
+    let config = web::Data::new(Config::default().await);
+    let clock = web::Data::new(FixedClock::new(Utc::now()));
+    let mut disabled = fake_user();
+    disabled.enabled = false;
+    let mut deleted = fake_user();
+    deleted.deleted_at = Some(clock.now());
+    let unknown = fake_user();
+    let user_repository =
+      web::Data::new(repository_with(vec![disabled.clone(), deleted.clone()]));
+
+    for user in [disabled, deleted, unknown] {
+      let tokens = generate_token_pair(&config, clock.now(), user).unwrap();
+      let request = refresh_request(&tokens.access_token);
+      let responder = validate_token(
+        config.clone(),
+        clock.clone(),
+        user_repository.clone(),
+        bearer(&request).await,
+      )
+      .await;
+      let _: HttpError =
+        parse_http_response(responder, &request, StatusCode::UNAUTHORIZED)
+          .await;
+    }
+  }
+
+  #[actix_web::test]
+  async fn test_access_token_subject_is_uuid() {
+    let config = Config::default().await;
+    let now = Utc::now();
+    let user = fake_user();
+    let tokens = generate_token_pair(&config, now, user.clone()).unwrap();
+    let claims = decode_access_token(&config, now, &tokens.access_token)
+      .expect("Valid access token");
+    assert_eq!(claims.sub, user.uuid.to_string());
+    assert_eq!(claims.name, user.user_name);
+
+    let legacy = Config {
+      jwt_legacy_subject: true,
+      ..config
+    };
+    let tokens = generate_token_pair(&legacy, now, user.clone()).unwrap();
+    let claims = decode_access_token(&legacy, now, &tokens.access_token)
+      .expect("Valid access token");
+    assert_eq!(claims.sub, user.user_name);
+    assert_eq!(claims.name, user.user_name);
+  }
+
+  #[actix_web::test]
+  async fn test_decode_access_token_rejections() {
+    let config = Config::default().await;
+    let now = Utc::now();
+    let user = fake_user();
+    let tokens = generate_token_pair(&config, now, user.clone()).unwrap();
+    assert!(decode_access_token(&config, now, &tokens.access_token).is_some());
+
+    // Tampered: the payload no longer matches the signature.
+    let mut parts: Vec<String> =
+      tokens.access_token.split('.').map(String::from).collect();
+    let claims =
+      decode_token::<AccessTokenClaims>(&config, now, &tokens.access_token)
+        .unwrap();
+    let forged = serde_json::to_vec(&AccessTokenClaims {
+      role: Role::Admin,
+      ..claims
+    })
+    .unwrap();
+    parts[1] = URL_SAFE_NO_PAD.encode(forged);
+    assert!(decode_access_token(&config, now, &parts.join(".")).is_none());
+
+    // Expired past the leeway.
+    let later = now
+      + chrono::Duration::seconds(
+        (config.access_token_ttl(&user.role) + config.jwt_leeway_seconds + 1)
+          as i64,
+      );
+    assert!(decode_access_token(&config, later, &tokens.access_token).is_none());
+
+    // Wrong type.
+    assert!(decode_access_token(&config, now, &tokens.refresh_token).is_none());
+
+    // Issued for another issuer or audience.
+    for other in [
+      Config {
+        jwt_issuer: String::from("other-issuer"),
+        ..config.clone()
+      },
+      Config {
+        jwt_audience: String::from("other-audience"),
+        ..config.clone()
+      },
+    ] {
+      assert!(decode_access_token(&other, now, &tokens.access_token).is_none());
+    }
+  }
+
+  #[actix_web::test]
+  async fn test_untyped_legacy_refresh_token_still_refreshes() {
+    let config = Config::default().await;
+    let now = Utc::now().timestamp() as u64;
+    let uuid = custom_nanoid();
+    let legacy = |claims: Value| generate_jwt(&config, claims).unwrap();
+
+    let refresh = legacy(serde_json::json!({
+      "uuid": uuid,
+      "iat": now,
+      "exp": now + REFRESH_TOKEN_EXPIRY,
+    }));
+    let claims = decode_refresh_token(&config, Utc::now(), &refresh)
+      .expect("Legacy refresh token");
+    assert_eq!(claims.uuid, uuid);
+    assert_eq!(claims.token_type, TokenType::Refresh);
+
+    // Legacy access tokens had no type either, but must not refresh.
+    let access = legacy(serde_json::json!({
+      "uuid": uuid,
+      "role": "customer",
+      "sub": "Jane Doe",
+      "iat": now,
+      "exp": now + DEFAULT_ACCESS_TOKEN_TTL,
+    }));
+    assert!(decode_refresh_token(&config, Utc::now(), &access).is_none());
+  }
+
+  #[actix_web::test]
+  async fn test_access_token_tolerates_issuer_clock_ahead() {
+    let config = Config {
+      jwt_leeway_seconds: 5,
+      ..Config::default().await
+    };
+    let now = Utc::now();
+    // The issuer's clock runs ahead of this instance's.
+    let issuer_clock = FixedClock::new(now + chrono::Duration::seconds(5));
+    let tokens =
+      generate_token_pair(&config, issuer_clock.now(), fake_user()).unwrap();
+
+    let claims = decode_access_token(&config, now, &tokens.access_token)
+      .expect("within the leeway");
+    assert_eq!(claims.nbf, claims.iat - config.jwt_leeway_seconds);
+
+    // Past the leeway, the token counts as issued in the future.
+    issuer_clock.advance(chrono::Duration::seconds(1));
+    let tokens =
+      generate_token_pair(&config, issuer_clock.now(), fake_user()).unwrap();
+    assert!(decode_access_token(&config, now, &tokens.access_token).is_none());
+  }
+
+  #[actix_web::test]
+  async fn test_validate_expired_access_token() {
+    let config = web::Data::new(Config::default().await);
+    let clock = web::Data::new(FixedClock::new(Utc::now()));
+    let user = fake_user();
+    let user_repository = web::Data::new(repository_with(vec![user.clone()]));
+    let tokens =
+      generate_token_pair(&config, clock.now(), user.clone()).unwrap();
+
+    clock.advance(chrono::Duration::seconds(
+      (config.access_token_ttl(&user.role) + config.jwt_leeway_seconds + 1)
+        as i64,
+    ));
+    let request = refresh_request(&tokens.access_token);
+    let responder = validate_token(
+      config.clone(),
+      clock.clone(),
+      user_repository.clone(),
+      bearer(&request).await,
+    )
+    .await;
+    let _: HttpError =
+      parse_http_response(responder, &request, StatusCode::UNAUTHORIZED).await;
+  }
+
+  #[actix_web::test]
+  async fn test_refresh_rotation_over_time() {
+    let config = web::Data::new(Config::default().await);
+    let clock = web::Data::new(FixedClock::new(Utc::now()));
+    let user = fake_user();
+    let user_repository = web::Data::new(repository_with(vec![user.clone()]));
+    let request: HttpRequest = http_request(&config.jwt_secret);
+
+    let responder = auth_login(
+      config.clone(),
+      clock.clone(),
+      user_repository.clone(),
+      web::Data::new(matching_hasher()),
+      web::Data::new(InMemoryAuditLog::new()),
+      request.clone(),
+      web::Query(LoginQuery::default()),
+      web::Json(LoginDto {
+        email: user.email.to_string(),
+        password: Password(12..13).fake(),
+      }),
+    )
+    .await;
+    let login_rto: LoginRto =
+      parse_http_response(responder, &request, StatusCode::OK).await;
+
+    // Past the access token's lifetime the refresh token still works.
+    clock.advance(chrono::Duration::seconds(
+      config.access_token_ttl(&user.role) as i64 + 60,
+    ));
+    let request = refresh_request(&login_rto.refresh_token);
+    let responder = access_token::<_, MockHasher, _>(
+      config.clone(),
+      clock.clone(),
+      user_repository.clone(),
+      request.clone(),
+      bearer(&request).await,
+    )
+    .await;
+    let refreshed_rto: LoginRto =
+      parse_http_response(responder, &request, StatusCode::OK).await;
+    assert!(refreshed_rto != login_rto);
+
+    // Refreshing again with the newer token keeps working.
+    let request = refresh_request(&refreshed_rto.refresh_token);
+    let responder = access_token::<_, MockHasher, _>(
+      config.clone(),
+      clock.clone(),
+      user_repository.clone(),
+      request.clone(),
+      bearer(&request).await,
+    )
+    .await;
+    let _: LoginRto =
+      parse_http_response(responder, &request, StatusCode::OK).await;
+
+    // Once the original refresh token expires (beyond the leeway) it's
+    // rejected, while the one issued later is still valid.
+    clock.advance(chrono::Duration::seconds(
+      (REFRESH_TOKEN_EXPIRY - config.access_token_ttl(&user.role)
+        + config.jwt_leeway_seconds) as i64,
+    ));
+    let request = refresh_request(&login_rto.refresh_token);
+    let responder = access_token::<_, MockHasher, _>(
+      config.clone(),
+      clock.clone(),
+      user_repository.clone(),
+      request.clone(),
+      bearer(&request).await,
+    )
+    .await;
+    let _: HttpError =
+      parse_http_response(responder, &request, StatusCode::UNAUTHORIZED).await;
+
+    let request = refresh_request(&refreshed_rto.refresh_token);
+    let responder = access_token::<_, MockHasher, _>(
+      config.clone(),
+      clock.clone(),
+      user_repository.clone(),
+      request.clone(),
+      bearer(&request).await,
+    )
+    .await;
+    let _: LoginRto =
+      parse_http_response(responder, &request, StatusCode::OK).await;
+
+    // Once the user's tokens are revoked, that token is turned away too.
+    let mut revoked = user_repository
+      .find_one(FindOneProperty::Uuid(&user.uuid))
+      .await
+      .unwrap();
+    revoked.token_epoch += 1;
+    user_repository.update(revoked).await.unwrap();
+    let responder = access_token::<_, MockHasher, _>(
+      config,
+      clock,
+      user_repository,
+      request.clone(),
+      bearer(&request).await,
+    )
+    .await;
+    let _: HttpError =
+      parse_http_response(responder, &request, StatusCode::UNAUTHORIZED).await;
+  }
+
+  #[actix_web::test]
+  async fn test_logout_all_rejects_every_token() {
+    let config = web::Data::new(Config::default().await);
+    let clock = web::Data::new(FixedClock::new(Utc::now()));
+    let user = fake_user();
+    let user_repository = web::Data::new(repository_with(vec![user.clone()]));
+    let audit_log = web::Data::new(InMemoryAuditLog::new());
+    let sessions: Vec<LoginRto> = (0..2)
+      .map(|_| generate_token_pair(&config, clock.now(), user.clone()).unwrap())
+      .collect();
+
+    // A refresh token doesn't authenticate the request.
+    let request = refresh_request(&sessions[0].refresh_token);
+    let responder = logout_all(
+      config.clone(),
+      clock.clone(),
+      user_repository.clone(),
+      audit_log.clone(),
+      request.clone(),
+      bearer(&request).await,
+    )
+    .await;
+    let _: HttpError =
+      parse_http_response(responder, &request, StatusCode::UNAUTHORIZED).await;
+
+    let request = refresh_request(&sessions[0].access_token);
+    let response = logout_all(
+      config.clone(),
+      clock.clone(),
+      user_repository.clone(),
+      audit_log.clone(),
+      request.clone(),
+      bearer(&request).await,
+    )
+    .await
+    .respond_to(&request);
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let entries = audit_log.entries();
+    assert_eq!(entries[0].event, AuditEvent::TokensRevoked);
+    assert_eq!(entries[0].actor.as_deref(), Some(user.uuid.as_str()));
+
+    for session in &sessions {
+      let request = refresh_request(&session.refresh_token);
+      let responder = access_token::<_, MockHasher, _>(
+        config.clone(),
+        clock.clone(),
+        user_repository.clone(),
+        request.clone(),
+        bearer(&request).await,
+      )
+      .await;
+      let _: HttpError =
+        parse_http_response(responder, &request, StatusCode::UNAUTHORIZED)
+          .await;
+
+      let request = refresh_request(&session.access_token);
+      let responder = validate_token(
+        config.clone(),
+        clock.clone(),
+        user_repository.clone(),
+        bearer(&request).await,
+      )
+      .await;
+      let _: HttpError =
+        parse_http_response(responder, &request, StatusCode::UNAUTHORIZED)
+          .await;
+    }
+
+    // The access token that logged out can't do it again.
+    let request = refresh_request(&sessions[0].access_token);
+    let responder = logout_all(
+      config,
+      clock,
+      user_repository,
+      audit_log,
+      request.clone(),
+      bearer(&request).await,
+    )
+    .await;
+    let _: HttpError =
+      parse_http_response(responder, &request, StatusCode::UNAUTHORIZED).await;
+  }
+
+  #[actix_web::test]
+  async fn test_access_token_rejects_malformed_authorization() {
+    let config = Config::default().await;
+    let user = fake_user();
+    let now = Utc::now().timestamp() as u64;
+    let refresh_token = generate_jwt(
+      &config,
+      RefreshTokenClaims {
+        uuid: user.uuid.clone(),
+        token_type: TokenType::Refresh,
+        legacy_role: None,
+        epoch: 0,
+        iat: now,
+        nbf: now,
+        exp: now + REFRESH_TOKEN_EXPIRY,
+      },
+    )
+    .unwrap();
+    let user_repository = web::Data::new(repository_with(vec![user]));
+
+    for authorization in [
+      // Missing prefix
+      refresh_token.clone(),
+      // Wrong scheme
+      format!("Basic {}", refresh_token),
+      format!("bearer {}", refresh_token),
+      // Extra space, which used to be stripped along with the prefix
+      format!("Bearer  {}", refresh_token),
+      // Embedded prefix, which used to be stripped wherever it occurred
+      format!("Bearer Bearer {}", refresh_token),
+    ] {
+      let request = actix_web::test::TestRequest::default()
+        .append_header((actix_web::http::header::AUTHORIZATION, authorization))
+        .to_http_request();
+      let responder = access_token::<_, MockHasher, _>(
+        web::Data::new(config.clone()),
+        web::Data::new(SystemClock),
+        user_repository.clone(),
+        request.clone(),
+        bearer(&request).await,
+      )
+      .await;
+      let _: HttpError =
+        parse_http_response(responder, &request, StatusCode::UNAUTHORIZED)
+          .await;
+    }
+
+    let request = refresh_request(&refresh_token);
+    let responder = access_token::<_, MockHasher, _>(
+      web::Data::new(config),
+      web::Data::new(SystemClock),
+      user_repository,
+      request.clone(),
+      bearer(&request).await,
+    )
+    .await;
+    let _: LoginRto =
+      parse_http_response(responder, &request, StatusCode::OK).await;
+  }
+
+  async fn token_response(
+    config: &web::Data<Config>,
+    user_repository: &web::Data<UserRepositoryImpl<InMemoryDatabase>>,
+    dto: TokenDto,
+  ) -> HttpResponse {
+    oauth_token(
+      config.clone(),
+      web::Data::new(FixedClock::new(Utc::now())),
+      user_repository.clone(),
+      web::Data::new(matching_hasher()),
+      web::Data::new(InMemoryAuditLog::new()),
+      actix_web::test::TestRequest::default().to_http_request(),
+      web::Form(dto),
+    )
+    .await
+    .respond_to(&actix_web::test::TestRequest::default().to_http_request())
+    .map_into_boxed_body()
+  }
+
+  #[actix_web::test]
+  async fn test_oauth_password_then_refresh_token_grant() {
+    let config = web::Data::new(Config::default().await);
+    let user = fake_user();
+    let user_repository = web::Data::new(repository_with(vec![user.clone()]));
+    let request = actix_web::test::TestRequest::default().to_http_request();
+
+    let response = token_response(
+      &config,
+      &user_repository,
+      TokenDto {
+        grant_type: Some(String::from("password")),
+        username: Some(user.email.to_string()),
+        password: Some(Password(12..13).fake()),
+        ..TokenDto::default()
+      },
+    )
+    .await;
+    assert_eq!(
+      response.headers().get(header::CACHE_CONTROL).unwrap(),
+      "no-store"
+    );
+    let token_rto: TokenRto =
+      parse_http_response(response, &request, StatusCode::OK).await;
+    assert_eq!(token_rto.token_type, "Bearer");
+    assert_eq!(token_rto.expires_in, config.access_token_ttl(&user.role));
+
+    let response = token_response(
+      &config,
+      &user_repository,
+      TokenDto {
+        grant_type: Some(String::from("refresh_token")),
+        refresh_token: Some(token_rto.refresh_token),
+        ..TokenDto::default()
+      },
+    )
+    .await;
+    let refreshed_rto: TokenRto =
+      parse_http_response(response, &request, StatusCode::OK).await;
+    assert_eq!(refreshed_rto.token_type, "Bearer");
+  }
+
+  #[actix_web::test]
+  async fn test_oauth_token_errors() {
+    let config = web::Data::new(Config::default().await);
+    let user_repository = web::Data::new(repository_with(vec![]));
+    let request = actix_web::test::TestRequest::default().to_http_request();
+    let cases = [
+      (
+        TokenDto {
+          grant_type: Some(String::from("client_credentials")),
+          ..TokenDto::default()
+        },
+        TokenError::UnsupportedGrantType,
+      ),
+      (
+        TokenDto {
+          grant_type: Some(String::from("password")),
+          username: Some(String::from("user@example.com")),
+          ..TokenDto::default()
+        },
+        TokenError::InvalidRequest,
+      ),
+      (TokenDto::default(), TokenError::InvalidRequest),
+      (
+        TokenDto {
+          grant_type: Some(String::from("refresh_token")),
+          refresh_token: Some(String::from("not-a-token")),
+          ..TokenDto::default()
+        },
+        TokenError::InvalidGrant,
+      ),
+    ];
+
+    for (dto, error) in cases {
+      let response = token_response(&config, &user_repository, dto).await;
+      let error_rto: TokenErrorRto =
+        parse_http_response(response, &request, StatusCode::BAD_REQUEST).await;
+      assert_eq!(error_rto.error, error);
+    }
+  }
+
+  fn response_cookie<B>(
+    response: &HttpResponse<B>,
+    name: &str,
+  ) -> Option<Cookie<'static>> {
+    response
+      .cookies()
+      .find(|cookie| cookie.name() == name)
+      .map(Cookie::into_owned)
+  }
+
+  /// Logs `user` in, opting into the refresh cookie when `cookie` is set.
+  async fn login_response(
+    config: &web::Data<Config>,
+    clock: &web::Data<FixedClock>,
+    user_repository: &web::Data<UserRepositoryImpl<InMemoryDatabase>>,
+    user: &User,
+    cookie: bool,
+  ) -> HttpResponse {
+    let request = actix_web::test::TestRequest::default().to_http_request();
+    auth_login(
+      config.clone(),
+      clock.clone(),
+      user_repository.clone(),
+      web::Data::new(matching_hasher()),
+      web::Data::new(InMemoryAuditLog::new()),
+      request.clone(),
+      web::Query(LoginQuery { cookie }),
+      web::Json(LoginDto {
+        email: user.email.to_string(),
+        password: Password(12..13).fake(),
+      }),
+    )
+    .await
+    .respond_to(&request)
+    .map_into_boxed_body()
+  }
+
+  fn cookie_request(
+    response: &HttpResponse,
+    csrf_token: Option<&str>,
+  ) -> HttpRequest {
+    let mut request = actix_web::test::TestRequest::get()
+      .cookie(response_cookie(response, REFRESH_COOKIE).unwrap())
+      .cookie(response_cookie(response, CSRF_COOKIE).unwrap());
+    if let Some(csrf_token) = csrf_token {
+      request = request.append_header((CSRF_HEADER, csrf_token));
+    }
+    request.to_http_request()
+  }
+
+  #[actix_web::test]
+  async fn test_refresh_cookie_round_trip() {
+    let config = web::Data::new(Config::default().await);
+    let clock = web::Data::new(FixedClock::new(Utc::now()));
+    let user = fake_user();
+    let user_repository = web::Data::new(repository_with(vec![user.clone()]));
+
+    // The header-based flow stays the default.
+    let response =
+      login_response(&config, &clock, &user_repository, &user, false).await;
+    assert!(response_cookie(&response, REFRESH_COOKIE).is_none());
+
+    let response =
+      login_response(&config, &clock, &user_repository, &user, true).await;
+    let cookie = response_cookie(&response, REFRESH_COOKIE).unwrap();
+    assert_eq!(cookie.http_only(), Some(true));
+    assert_eq!(cookie.secure(), Some(true));
+    assert_eq!(cookie.same_site(), Some(SameSite::Strict));
+    assert_eq!(cookie.path(), Some(REFRESH_COOKIE_PATH));
+    let csrf_cookie = response_cookie(&response, CSRF_COOKIE).unwrap();
+    assert_eq!(csrf_cookie.http_only(), None);
+
+    clock.advance(chrono::Duration::seconds(1));
+    let request = cookie_request(&response, Some(csrf_cookie.value()));
+    let response = access_token::<_, MockHasher, _>(
+      config.clone(),
+      clock.clone(),
+      user_repository.clone(),
+      request.clone(),
+      bearer(&request).await,
+    )
+    .await
+    .respond_to(&request);
+    assert_eq!(response.status(), StatusCode::OK);
+    let rotated = response_cookie(&response, REFRESH_COOKIE).unwrap();
+    assert_ne!(rotated.value(), cookie.value());
+
+    // A malformed header is rejected rather than falling back to the cookie.
+    let request = actix_web::test::TestRequest::get()
+      .cookie(rotated)
+      .append_header((header::AUTHORIZATION, "Basic Zm9vOmJhcg=="))
+      .to_http_request();
+    let responder = access_token::<_, MockHasher, _>(
+      config,
+      clock,
+      user_repository,
+      request.clone(),
+      bearer(&request).await,
+    )
+    .await;
+    let _: HttpError =
+      parse_http_response(responder, &request, StatusCode::UNAUTHORIZED).await;
+  }
+
+  #[actix_web::test]
+  async fn test_refresh_cookie_requires_matching_csrf_token() {
+    let config = web::Data::new(Config::default().await);
+    let clock = web::Data::new(FixedClock::new(Utc::now()));
+    let user = fake_user();
+    let user_repository = web::Data::new(repository_with(vec![user.clone()]));
+    let response =
+      login_response(&config, &clock, &user_repository, &user, true).await;
+
+    for csrf_token in [None, Some("not-the-csrf-token")] {
+      let request = cookie_request(&response, csrf_token);
+      let responder = access_token::<_, MockHasher, _>(
+        config.clone(),
+        clock.clone(),
+        user_repository.clone(),
+        request.clone(),
+        bearer(&request).await,
+      )
+      .await;
+      let error: HttpError =
+        parse_http_response(responder, &request, StatusCode::FORBIDDEN).await;
+      assert_eq!(error.message, "Invalid CSRF token");
+    }
+  }
+
+  #[actix_web::test]
+  async fn test_refresh_token_expiry_leeway() {
+    let config = Config {
+      jwt_leeway_seconds: 5,
+      ..Config::default().await
+    };
+    let user = fake_user();
+    let issued_at = Utc::now();
+    let clock = FixedClock::new(issued_at);
+
+    let now = issued_at.timestamp() as u64;
+    let refresh_token = generate_jwt(
+      &config,
+      RefreshTokenClaims {
+        uuid: user.uuid.clone(),
+        token_type: TokenType::Refresh,
+        legacy_role: None,
+        epoch: 0,
+        iat: now,
+        nbf: now,
+        exp: now + REFRESH_TOKEN_EXPIRY,
+      },
+    )
+    .unwrap();
+
+    clock.advance(chrono::Duration::seconds(
+      (REFRESH_TOKEN_EXPIRY + config.jwt_leeway_seconds) as i64,
+    ));
+    assert!(
+      decode_refresh_token(&config, clock.now(), &refresh_token).is_some()
+    );
+
+    clock.advance(chrono::Duration::seconds(1));
+    assert!(
+      decode_refresh_token(&config, clock.now(), &refresh_token).is_none()
+    );
+  }
+
+  #[actix_web::test]
+  async fn test_token_signed_with_previous_secret_is_accepted() {
+    let previous = Config {
+      jwt_secret: String::from("old-secret"),
+      jwt_previous_secrets: Vec::new(),
+      ..Config::default().await
+    };
+    let rotated = Config {
+      jwt_secret: String::from("new-secret"),
+      jwt_previous_secrets: vec![String::from("old-secret")],
+      ..Config::default().await
+    };
+    let unrelated = Config {
+      jwt_secret: String::from("other-secret"),
+      jwt_previous_secrets: Vec::new(),
+      ..Config::default().await
+    };
+    let user = fake_user();
+    let now = Utc::now();
+    let refresh_token = generate_jwt(
+      &previous,
+      RefreshTokenClaims {
+        uuid: user.uuid.clone(),
+        token_type: TokenType::Refresh,
+        legacy_role: None,
+        epoch: 0,
+        iat: now.timestamp() as u64,
+        nbf: now.timestamp() as u64,
+        exp: now.timestamp() as u64 + REFRESH_TOKEN_EXPIRY,
+      },
+    )
+    .unwrap();
+
+    assert_eq!(
+      decode_header(&refresh_token).unwrap().kid,
+      Some(key_id("old-secret"))
+    );
+    assert!(decode_refresh_token(&rotated, now, &refresh_token).is_some());
+    assert!(decode_refresh_token(&unrelated, now, &refresh_token).is_none());
+  }
+
+  #[actix_web::test]
+  async fn test_token_round_trip_per_algorithm() {
+    let algorithms = [Algorithm::HS256, Algorithm::HS384, Algorithm::HS512];
+    let user = fake_user();
+    let now = Utc::now();
+    for algorithm in algorithms {
+      let config = Config {
+        jwt_algorithm: algorithm,
+        ..Config::default().await
+      };
+      let tokens = generate_token_pair(&config, now, user.clone()).unwrap();
+      assert_eq!(decode_header(&tokens.access_token).unwrap().alg, algorithm);
+      assert!(decode_access_token(&config, now, &tokens.access_token).is_some());
+
+      // Signed with another variant of the same secret.
+      for other in algorithms.into_iter().filter(|other| *other != algorithm) {
+        let verifier = Config {
+          jwt_algorithm: other,
+          ..config.clone()
+        };
+        assert!(
+          decode_access_token(&verifier, now, &tokens.access_token).is_none()
+        );
+      }
+    }
+  }
+
+  #[actix_web::test]
+  async fn test_token_times_follow_clock() {
+    let config = Config::default().await;
+    let issued_at = Utc::now() - chrono::Duration::days(30);
+    let user = fake_user();
+    let access_token_ttl = config.access_token_ttl(&user.role);
+    let request: HttpRequest = http_request(&config.jwt_secret);
+
+    let responder = generate_token_response(&config, issued_at, user, false);
+    let rto: LoginRto =
+      parse_http_response(responder, &request, StatusCode::OK).await;
+
+    let mut validation = Validation::default();
+    validation.validate_exp = false;
+    validation.validate_aud = false;
+    let key = DecodingKey::from_secret(config.jwt_secret.as_bytes());
+    let access_claims =
+      decode::<AccessTokenClaims>(&rto.access_token, &key, &validation)
+        .unwrap()
+        .claims;
+    let refresh_claims =
+      decode::<RefreshTokenClaims>(&rto.refresh_token, &key, &validation)
+        .unwrap()
+        .claims;
+
+    let issued_at = issued_at.timestamp() as u64;
+    assert_eq!(access_claims.iat, issued_at);
+    assert_eq!(access_claims.exp, issued_at + access_token_ttl);
+    assert_eq!(refresh_claims.iat, issued_at);
+    assert_eq!(refresh_claims.exp, issued_at + REFRESH_TOKEN_EXPIRY);
+  }
+
+  #[actix_web::test]
+  async fn test_access_token_ttl_follows_role() {
+    let config = Config {
+      reloadable: Arc::new(ArcSwap::from_pointee(ReloadableConfig {
+        access_token_ttls: HashMap::from([(Role::Admin, 5 * 60)]),
+        ..ReloadableConfig::default()
+      })),
+      ..Config::default().await
+    };
+    let request: HttpRequest = http_request(&config.jwt_secret);
+    let mut validation = Validation::default();
+    validation.validate_exp = false;
+    validation.validate_aud = false;
+    let key = DecodingKey::from_secret(config.jwt_secret.as_bytes());
+
+    let mut lifetimes = Vec::new();
+    for role in [Role::Admin, Role::Customer] {
+      let user = User {
+        role,
+        ..fake_user()
+      };
+      let responder = generate_token_response(&config, Utc::now(), user, false);
+      let rto: LoginRto =
+        parse_http_response(responder, &request, StatusCode::OK).await;
+      let claims =
+        decode::<AccessTokenClaims>(&rto.access_token, &key, &validation)
+          .unwrap()
+          .claims;
+      lifetimes.push(claims.exp - claims.iat);
+    }
+
+    assert_eq!(lifetimes, vec![5 * 60, DEFAULT_ACCESS_TOKEN_TTL]);
+  }
+
+  #[actix_web::test]
+  async fn test_custom_claims_round_trip() {
+    let config = Config {
+      access_token_claims: vec![
+        String::from("email"),
+        String::from("email_verified"),
+        String::from("uuid"),
+      ],
+      ..Config::default().await
+    };
+    let user = fake_user();
+    let request: HttpRequest = http_request(&config.jwt_secret);
+
+    let responder =
+      generate_token_response(&config, Utc::now(), user.clone(), false);
+    let rto: LoginRto =
+      parse_http_response(responder, &request, StatusCode::OK).await;
+    let mut validation = Validation::default();
+    validation.validate_exp = false;
+    validation.validate_aud = false;
+    let claims = decode::<AccessTokenClaims>(
+      &rto.access_token,
+      &DecodingKey::from_secret(config.jwt_secret.as_bytes()),
+      &validation,
+    )
+    .unwrap()
+    .claims;
+
+    assert_eq!(claims.uuid, user.uuid);
+    assert_eq!(
+      claims.claims,
+      HashMap::from([
+        (String::from("email"), Value::from(user.email.as_str())),
+        (String::from("email_verified"), Value::from(false)),
+      ])
+    );
+  }
+
+  #[actix_web::test]
+  async fn test_password_policy_follows_config() {
+    for check_pwned_passwords in [true, false] {
+      let config = web::Data::new(Config {
+        check_pwned_passwords,
+        ..Config::default().await
+      });
+      let request = actix_web::test::TestRequest::default().to_http_request();
+      let rto: PasswordPolicyRto = parse_http_response(
+        password_policy(config).await,
+        &request,
+        StatusCode::OK,
+      )
+      .await;
+      assert_eq!(
+        rto,
+        PasswordPolicyRto {
+          min_length: PASSWORD_MIN_LENGTH,
+          max_length: PASSWORD_MAX_LENGTH,
+          required_character_classes: Vec::new(),
+          breach_check: check_pwned_passwords,
+        }
+      );
+    }
+  }
+
+  #[actix_web::test]
+  async fn test_oversized_custom_claims_fail_token_generation() {
+    let config = Config {
+      access_token_claims: vec![String::from("user_name")],
+      ..Config::default().await
+    };
+    let user = User {
+      user_name: "a".repeat(MAX_CUSTOM_CLAIMS_SIZE),
+      ..fake_user()
+    };
+    assert!(matches!(
+      custom_claims(&config, &user),
+      Err(TokenGenerationError::ClaimsTooLarge(_))
+    ));
+
+    let request = actix_web::test::TestRequest::default().to_http_request();
+    let responder = generate_token_response(&config, Utc::now(), user, false);
+    let error: HttpError = parse_http_response(
+      responder,
+      &request,
+      StatusCode::INTERNAL_SERVER_ERROR,
+    )
+    .await;
+    assert_eq!(error.code, Some(ErrorCode::TokenGenerationFailed));
+  }
 }
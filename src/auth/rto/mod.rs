@@ -1 +1,5 @@
 pub mod login_rto;
+pub mod password_policy_rto;
+pub mod token_rto;
+pub mod validate_token_rto;
+pub mod verification_token_rto;
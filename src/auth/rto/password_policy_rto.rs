@@ -0,0 +1,17 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// Rules new passwords are held to, for clients to validate against.
+#[derive(ToSchema, Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct PasswordPolicyRto {
+  #[schema(example = 1)]
+  pub min_length: u64,
+  #[schema(example = 1024)]
+  pub max_length: u64,
+  /// Character classes, e.g. `digit`, a password must include at least one
+  /// of. Empty when any characters will do.
+  pub required_character_classes: Vec<String>,
+  /// Whether passwords found in a known breach are refused.
+  pub breach_check: bool,
+}
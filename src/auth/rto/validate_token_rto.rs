@@ -0,0 +1,17 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{shared::role::Role, users::model::user_id::UserId};
+
+/// Identity carried by a valid access token, for gateways to forward.
+#[derive(ToSchema, Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ValidateTokenRto {
+  #[schema(value_type = String, example = "V1StGXR8Z5jdHi6B")]
+  pub uuid: UserId,
+  pub role: Role,
+  /// The uuid, or the user name with `JWT_LEGACY_SUBJECT`.
+  #[schema(example = "V1StGXR8Z5jdHi6B")]
+  pub sub: String,
+  #[schema(example = "Jane Doe")]
+  pub name: String,
+}
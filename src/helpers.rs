@@ -1,14 +1,170 @@
 #[cfg(test)]
 pub mod tests {
-  use crate::{custom_nanoid, shared::role::Role};
+  use std::{
+    sync::{
+      atomic::{AtomicU32, Ordering},
+      Mutex,
+    },
+    time::Duration,
+  };
+
+  use crate::{
+    custom_nanoid,
+    shared::role::Role,
+    users::{
+      model::{email::Email, user::User, user_id::UserId},
+      repository::user_repository::{
+        FindOneProperty, UserFilter, UserPage, UserRepository,
+        UserRepositoryError, UserSort,
+      },
+    },
+  };
   use actix_web::{
     http::{header::HeaderValue, StatusCode},
+    rt::time::sleep,
     HttpRequest, Responder,
   };
-  use fake::{faker::internet::en::SafeEmail, Fake};
+  use async_trait::async_trait;
+  use chrono::Utc;
+  use fake::{
+    faker::{internet::en::SafeEmail, name::raw::Name},
+    locales::EN,
+    Fake,
+  };
   use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
   use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
+  /// An active customer with a unique uuid and email. Tests override the
+  /// fields they care about with struct update syntax.
+  pub fn fake_user() -> User {
+    let now = Utc::now();
+    User {
+      uuid: UserId::try_from(custom_nanoid()).unwrap(),
+      email: Email::try_from(SafeEmail().fake::<String>()).unwrap(),
+      user_name: Name(EN).fake(),
+      password_hash: String::from("hashed_password"),
+      role: Role::Customer,
+      created_at: now,
+      updated_at: now,
+      deleted_at: None,
+      enabled: true,
+      email_verified: false,
+      token_epoch: 0,
+      password_changed_at: None,
+      must_change_password: false,
+    }
+  }
+
+  /// Repository holding a single user, which every lookup finds and every
+  /// update replaces. Calls can be made to fail or to take a while, and are
+  /// counted, to test the decorators and the handlers' error paths.
+  pub struct FakeUserRepository {
+    user: Mutex<User>,
+    failures: u32,
+    error: fn() -> UserRepositoryError,
+    delay: Duration,
+    calls: AtomicU32,
+    lookups: AtomicU32,
+  }
+
+  impl FakeUserRepository {
+    pub fn new(user: User) -> Self {
+      Self {
+        user: Mutex::new(user),
+        failures: 0,
+        error: || UserRepositoryError::NotFound,
+        delay: Duration::ZERO,
+        calls: AtomicU32::new(0),
+        lookups: AtomicU32::new(0),
+      }
+    }
+
+    /// Fails the first `failures` calls with `error`.
+    pub fn failing(
+      self,
+      failures: u32,
+      error: fn() -> UserRepositoryError,
+    ) -> Self {
+      Self {
+        failures,
+        error,
+        ..self
+      }
+    }
+
+    /// Fails every call with `error`.
+    pub fn broken(self, error: fn() -> UserRepositoryError) -> Self {
+      self.failing(u32::MAX, error)
+    }
+
+    /// Waits `delay` before answering each call.
+    pub fn delayed(self, delay: Duration) -> Self {
+      Self { delay, ..self }
+    }
+
+    /// Calls made so far, failed ones included.
+    pub fn calls(&self) -> u32 {
+      self.calls.load(Ordering::SeqCst)
+    }
+
+    /// `find_one` calls made so far.
+    pub fn lookups(&self) -> u32 {
+      self.lookups.load(Ordering::SeqCst)
+    }
+
+    async fn attempt(&self) -> Result<(), UserRepositoryError> {
+      if !self.delay.is_zero() {
+        sleep(self.delay).await;
+      }
+      let calls = self.calls.fetch_add(1, Ordering::SeqCst) + 1;
+      if calls <= self.failures {
+        return Err((self.error)());
+      }
+      Ok(())
+    }
+  }
+
+  #[async_trait]
+  impl UserRepository for FakeUserRepository {
+    async fn find_one(
+      &self,
+      _property: FindOneProperty<'_>,
+    ) -> Result<User, UserRepositoryError> {
+      self.lookups.fetch_add(1, Ordering::SeqCst);
+      self.attempt().await?;
+      Ok(self.user.lock().unwrap().clone())
+    }
+
+    async fn find_all(
+      &self,
+      _filter: &UserFilter,
+      _sort: UserSort,
+      _cursor: Option<&str>,
+      _limit: usize,
+    ) -> Result<UserPage, UserRepositoryError> {
+      self.attempt().await?;
+      Ok(UserPage {
+        users: vec![self.user.lock().unwrap().clone()],
+        next_cursor: None,
+      })
+    }
+
+    async fn create(&self, user: User) -> Result<User, UserRepositoryError> {
+      self.attempt().await?;
+      Ok(user)
+    }
+
+    async fn update(&self, user: User) -> Result<(), UserRepositoryError> {
+      self.attempt().await?;
+      *self.user.lock().unwrap() = user;
+      Ok(())
+    }
+
+    async fn delete(&self, _uuid: &UserId) -> Result<(), UserRepositoryError> {
+      self.attempt().await
+    }
+  }
+
   #[derive(Serialize, Deserialize)]
   pub struct FakeAccessTokenClaims {
     uuid: String,
@@ -8,28 +8,71 @@ use std::{
   sync::{Arc, LazyLock},
 };
 
-use actix_governor::{
-  governor::{clock::QuantaInstant, middleware::NoOpMiddleware},
-  Governor, GovernorConfig, GovernorConfigBuilder, PeerIpKeyExtractor,
+use actix_web::{
+  middleware::{from_fn, Compress, Condition},
+  web, App, HttpServer,
 };
-use actix_web::{web, App, HttpServer};
 use actix_web_httpauth::middleware::HttpAuthentication;
-use nanoid::nanoid;
 use rayon::ThreadPoolBuilder;
+#[cfg(all(feature = "dynamodb", not(test)))]
+use shared::database::DynamoDatabase;
+#[cfg(any(feature = "in-memory", test))]
+use shared::database::InMemoryDatabase;
+#[cfg(feature = "mongodb")]
+use shared::database::MongoDatabase;
 use shared::{
+  audit_log::{AuditLog, InMemoryAuditLog},
+  breach_check::{BreachClient, PwnedPasswordsClient},
+  clock::{Clock, SystemClock},
   config::Config,
-  database::resolve_database,
-  handlers::check_health,
+  database::{resolve_database, Database, DatabaseBackend, DatabaseError},
+  handlers::{
+    check_health, check_health_detailed, check_readiness, list_roles,
+  },
   hash_worker::{HashWorker, Hasher},
   health_check::{HealthCheck, HealthCheckImpl},
-  middleware::master_key_middleware::bearer_validator,
+  http_error::{form_error_handler, json_error_handler},
+  idempotency::IdempotencyStore,
+  mailer::{mailer, Mailer},
+  middleware::{
+    concurrency_limit_middleware::{limit_concurrency, ConcurrencyLimit},
+    cors_middleware::cors,
+    maintenance_middleware::block_writes_in_maintenance,
+    master_key_middleware::bearer_validator,
+    permission_middleware::require_permission,
+    problem_json_middleware::problem_json,
+    rate_limit_middleware::RateLimit,
+    slow_request_middleware::{log_slow_requests, SlowRequestLog},
+  },
+  permission::Permission,
+  reload::reload_on_sighup,
+  retry::RetryPolicy,
+  rto::created_rto::CreatedRto,
+  sweeper::spawn_sweeper,
+  tls::{listener, server_config, Listener},
+  webhook::Webhook,
+};
+use utoipa::{
+  openapi::{self, Deprecated},
+  Modify, OpenApi,
 };
-use utoipa::OpenApi;
 
-use auth::handlers::{access_token, auth_login};
+use auth::handlers::{
+  access_token, auth_login, change_password, logout_all, oauth_token,
+  password_policy, validate_token, verify_email,
+};
 use users::{
-  handlers::{create_user, get_users},
-  repository::user_repository::{UserRepository, UserRepositoryImpl},
+  handlers::{
+    create_user, create_verification_token, delete_user, get_hash_migration,
+    get_user, get_users, register_user, reset_password, revoke_tokens,
+    update_user_status, user_exists,
+  },
+  repository::{
+    caching_user_repository::CachingUserRepository,
+    retrying_user_repository::RetryingUserRepository,
+    timeout_user_repository::TimeoutUserRepository,
+    user_repository::{UserRepository, UserRepositoryImpl},
+  },
 };
 use utoipa_scalar::{Scalar, Servable};
 
@@ -37,97 +80,345 @@ use utoipa_scalar::{Scalar, Servable};
 async fn main() -> std::io::Result<()> {
   println!("Starting taille-auth...");
   let config = Config::default().await;
+  if config.verbose_auth_errors {
+    eprintln!(
+      "VERBOSE_AUTH_ERRORS is on, failed logins reveal which emails exist. \
+       Never enable it in production."
+    );
+  }
+
+  match config.database_backend {
+    #[cfg(all(feature = "dynamodb", not(test)))]
+    DatabaseBackend::DynamoDb => {
+      let database = resolve_database::<DynamoDatabase>(&config)
+        .await
+        .unwrap_or_else(|error| database_unavailable(&config, error));
+      serve(config, database).await
+    }
+    #[cfg(feature = "mongodb")]
+    DatabaseBackend::MongoDb => {
+      let database = resolve_database::<MongoDatabase>(&config)
+        .await
+        .unwrap_or_else(|error| database_unavailable(&config, error));
+      serve(config, database).await
+    }
+    #[cfg(any(feature = "in-memory", test))]
+    DatabaseBackend::InMemory => {
+      let database = resolve_database::<InMemoryDatabase>(&config)
+        .await
+        .unwrap_or_else(|error| database_unavailable(&config, error));
+      serve(config, database).await
+    }
+    #[allow(unreachable_patterns)]
+    backend => unreachable!("{} isn't compiled in", backend.as_str()),
+  }
+}
 
-  let database = Arc::new(resolve_database(&config).await);
+/// Exits with a non-zero status, as the service can't run without its
+/// database.
+fn database_unavailable(config: &Config, error: DatabaseError) -> ! {
+  eprintln!(
+    "Can't start, the {} database is unavailable after {} attempt(s): {}. \
+     Check it's running and reachable with the configured settings, or \
+     raise DATABASE_CONNECT_ATTEMPTS if it takes longer to come up.",
+    config.database_backend.as_str(),
+    config.database_connect_attempts,
+    error
+  );
+  std::process::exit(1)
+}
+
+/// Runs the server on `database`, whichever backend it is.
+async fn serve<DB: Database + Send + Sync + 'static>(
+  config: Config,
+  database: DB,
+) -> std::io::Result<()>
+where
+  UserRepositoryImpl<DB>: UserRepository,
+{
+  let database = Arc::new(database);
   let health_check = Arc::new(HealthCheckImpl::new(database.clone()));
 
   let thread_pool = ThreadPoolBuilder::new()
-    .num_threads(max(num_threads() - 2, 1))
+    .num_threads(max(num_threads().saturating_sub(2), 1))
     .build()
     .unwrap();
   let hasher = Arc::new(HashWorker::new(thread_pool, 2));
 
-  // Rate limit
-  // Allow bursts with up to five requests per IP address
-  // and replenishes two elements per second
-  let governor_config = GovernorConfigBuilder::default()
-    .requests_per_second(2)
-    .burst_size(5)
-    .finish()
-    .unwrap();
-
   let address = config.address.clone();
+  // Loaded up front so a bad certificate stops startup with a clear error.
+  let tls_config = match listener(&config).map_err(std::io::Error::other)? {
+    Listener::Plain => None,
+    Listener::Tls {
+      cert_path,
+      key_path,
+    } => {
+      Some(server_config(cert_path, key_path).map_err(std::io::Error::other)?)
+    }
+  };
 
+  let webhook = Arc::new(Webhook::new(config.user_created_webhook_url.clone()));
+  let breach_client = Arc::new(PwnedPasswordsClient::default());
+  let audit_log = Arc::new(InMemoryAuditLog::new());
+  let mailer = Arc::new(mailer(&config));
+  let idempotency = Arc::new(IdempotencyStore::new(config.idempotency_key_ttl));
+  // Shared by every worker, so the limit holds for the whole process.
+  let concurrency_limit =
+    Arc::new(ConcurrencyLimit::new(config.max_concurrent_requests));
+  let clock = Arc::new(SystemClock);
+  spawn_sweeper(
+    vec![idempotency.clone()],
+    clock.clone(),
+    config.sweep_interval,
+  );
+  // Shared by every worker, so a write through any of them invalidates
+  // the cached user for all.
+  let user_repository = Arc::new(CachingUserRepository::new(
+    RetryingUserRepository::new(
+      TimeoutUserRepository::new(
+        UserRepositoryImpl::new(database.clone()),
+        config.database_query_timeout,
+      ),
+      RetryPolicy::new(config.database_retry_attempts),
+    ),
+    clock.clone(),
+    config.user_cache_ttl,
+  ));
   let config = Arc::new(config);
+  reload_on_sighup(config.clone())?;
+  let rate_limit = RateLimit::new(config.clone());
 
   let http_server = HttpServer::new(move || {
     App::new().configure(|cfg| {
       apply_service_config(
         cfg,
-        &governor_config,
+        rate_limit.clone(),
         config.clone(),
         health_check.clone(),
         hasher.clone(),
-        UserRepositoryImpl::new(database.clone())
+        webhook.clone(),
+        breach_client.clone(),
+        audit_log.clone(),
+        mailer.clone(),
+        idempotency.clone(),
+        concurrency_limit.clone(),
+        clock.clone(),
+        user_repository.clone(),
       )
     })
   })
-  .workers(2)
-  .bind(address.clone())?
-  .run();
+  .workers(2);
+
+  let (http_server, scheme) = match tls_config {
+    Some(tls_config) => {
+      (http_server.bind_rustls_0_23(&address, tls_config)?, "https")
+    }
+    None => (http_server.bind(&address)?, "http"),
+  };
 
-  println!("Listening on http://{}", address);
-  http_server.await
+  println!("Listening on {}://{}", scheme, address);
+  http_server.run().await
 }
 
 // Function to initialize the App
+#[allow(clippy::too_many_arguments)]
 fn apply_service_config<
   UR: UserRepository + 'static,
   HC: HealthCheck + 'static,
   H: Hasher + 'static,
+  A: AuditLog + 'static,
+  M: Mailer + 'static,
+  B: BreachClient + 'static,
+  C: Clock + 'static,
 >(
   service_config: &mut web::ServiceConfig,
-  governor_config: &GovernorConfig<
-    PeerIpKeyExtractor,
-    NoOpMiddleware<QuantaInstant>,
-  >,
+  rate_limit: RateLimit,
   config: Arc<Config>,
   health_check: Arc<HC>,
   hasher: Arc<H>,
-  user_repository: UR,
+  webhook: Arc<Webhook>,
+  breach_client: Arc<B>,
+  audit_log: Arc<A>,
+  mailer: Arc<M>,
+  idempotency: Arc<IdempotencyStore<CreatedRto>>,
+  concurrency_limit: Arc<ConcurrencyLimit>,
+  clock: Arc<C>,
+  user_repository: Arc<UR>,
 ) {
   service_config
     .app_data(web::Data::from(config.clone()))
     .app_data(web::Data::from(health_check.clone()))
-    .app_data(web::Data::new(user_repository))
+    .app_data(web::Data::from(user_repository))
     .app_data(web::Data::from(hasher))
+    .app_data(web::Data::from(webhook))
+    .app_data(web::Data::from(breach_client))
+    .app_data(web::Data::from(audit_log))
+    .app_data(web::Data::from(mailer))
+    .app_data(web::Data::from(idempotency))
+    .app_data(web::Data::from(concurrency_limit))
+    .app_data(web::Data::from(clock))
+    .app_data(web::Data::new(SlowRequestLog::new(&config)))
+    .app_data(
+      web::JsonConfig::default()
+        .limit(config.max_body_bytes)
+        .error_handler(json_error_handler),
+    )
+    .app_data(
+      web::FormConfig::default()
+        .limit(config.max_body_bytes)
+        .error_handler(form_error_handler),
+    )
     .service(Scalar::with_url("/docs", ApiDoc::openapi()))
     .service(
       web::scope("/v1")
+        // Inside `problem_json`, so refusals get a problem body if asked for.
+        .wrap(from_fn(limit_concurrency))
+        .wrap(from_fn(block_writes_in_maintenance))
+        .wrap(from_fn(problem_json))
+        .wrap(from_fn(log_slow_requests))
+        // Outside the guards above, so preflights skip authentication and
+        // errors stay readable cross-origin.
+        .wrap(from_fn(cors))
+        // Outermost, so error bodies written by the middleware above are
+        // compressed too.
+        .wrap(Condition::new(
+          config.compress_responses,
+          Compress::default(),
+        ))
+        .route("/roles", web::get().to(list_roles))
+        // Registered ahead of the auth scope to stay out of its rate limit,
+        // as a gateway validates every request it forwards.
+        .route("/auth/validate", web::get().to(validate_token::<UR, C>))
         .service(
           web::scope("/auth")
-            .wrap(Governor::new(governor_config))
-            .route("/login", web::post().to(auth_login::<UR, H>))
-            .route("/access-token", web::post().to(access_token::<UR, H>)),
+            .wrap(rate_limit)
+            .route("/login", web::post().to(auth_login::<UR, H, A, C>))
+            .route("/refresh", web::post().to(access_token::<UR, H, C>))
+            // Former name of `/refresh`, kept for existing clients.
+            .route("/access-token", web::get().to(access_token::<UR, H, C>))
+            .route("/access-token", web::post().to(access_token::<UR, H, C>))
+            .route("/token", web::post().to(oauth_token::<UR, H, A, C>))
+            .route("/logout-all", web::post().to(logout_all::<UR, A, C>))
+            .route(
+              "/change-password",
+              web::post().to(change_password::<UR, H, A, C, B>),
+            )
+            .route("/verify-email", web::post().to(verify_email::<UR, C>))
+            .route("/password-policy", web::get().to(password_policy))
+            .route("/register", web::post().to(register_user::<UR, H, A, B>)),
         )
         .service(
           web::scope("/users")
             .wrap(HttpAuthentication::with_fn({
+              let config = config.clone();
               move |req, credentials| {
-                bearer_validator(req, credentials, config.clone())
+                bearer_validator::<UR, C>(req, credentials, config.clone())
               }
             }))
-            .route("", web::get().to(get_users::<UR>))
-            .route("", web::post().to(create_user::<UR, H>)),
+            .route(
+              "",
+              web::head()
+                .to(user_exists::<UR>)
+                .wrap(require_permission(Permission::UsersRead)),
+            )
+            .route(
+              "",
+              web::get()
+                .to(get_users::<UR>)
+                .wrap(require_permission(Permission::UsersRead)),
+            )
+            .route(
+              "",
+              web::post()
+                .to(create_user::<UR, H, A, C, B>)
+                .wrap(require_permission(Permission::UsersCreate)),
+            )
+            // Ahead of `/{uuid}`, which would otherwise match it.
+            .route(
+              "/hash-migration",
+              web::get()
+                .to(get_hash_migration::<UR>)
+                .wrap(require_permission(Permission::UsersRead)),
+            )
+            .route(
+              "/{uuid}",
+              web::get()
+                .to(get_user::<UR>)
+                .wrap(require_permission(Permission::UsersRead)),
+            )
+            .route(
+              "/{uuid}",
+              web::delete()
+                .to(delete_user::<UR, A>)
+                .wrap(require_permission(Permission::UsersDelete)),
+            )
+            .route(
+              "/{uuid}/status",
+              web::put()
+                .to(update_user_status::<UR, A>)
+                .wrap(require_permission(Permission::UsersUpdate)),
+            )
+            .route(
+              "/{uuid}/password",
+              web::post()
+                .to(reset_password::<UR, H, A, C, M, B>)
+                .wrap(require_permission(Permission::UsersUpdate)),
+            )
+            .route(
+              "/{uuid}/revoke-tokens",
+              web::post()
+                .to(revoke_tokens::<UR, A>)
+                .wrap(require_permission(Permission::TokensRevoke)),
+            )
+            .route(
+              "/{uuid}/verification-token",
+              web::post()
+                .to(create_verification_token::<UR, C, M>)
+                .wrap(require_permission(Permission::UsersUpdate)),
+            ),
         )
         .service(
-          web::scope("/health").route("", web::get().to(check_health::<HC>)),
+          web::scope("/health")
+            .route("", web::get().to(check_health::<HC>))
+            .route("/ready", web::get().to(check_readiness::<HC, H, C>))
+            .service(
+              web::resource("/detailed")
+                .wrap(HttpAuthentication::with_fn({
+                  let config = config.clone();
+                  move |req, credentials| {
+                    bearer_validator::<UR, C>(req, credentials, config.clone())
+                  }
+                }))
+                .route(
+                  web::get()
+                    .to(check_health_detailed::<HC, H>)
+                    .wrap(require_permission(Permission::HealthRead)),
+                ),
+            ),
         ),
     );
 }
 
+/// Thread count used when the platform can't report its parallelism, as
+/// happens in some containers.
+const FALLBACK_NUM_THREADS: usize = 4;
+
 fn num_threads() -> usize {
-  std::thread::available_parallelism().unwrap().get()
+  parallelism_or_fallback(std::thread::available_parallelism())
+}
+
+fn parallelism_or_fallback(
+  parallelism: std::io::Result<std::num::NonZeroUsize>,
+) -> usize {
+  parallelism
+    .map(|threads| threads.get())
+    .unwrap_or_else(|error| {
+      eprintln!(
+        "Could not determine available parallelism ({}), using {} threads",
+        error, FALLBACK_NUM_THREADS
+      );
+      FALLBACK_NUM_THREADS
+    })
 }
 
 static CUSTOM_ALPHABET: LazyLock<Vec<char>> = LazyLock::new(|| {
@@ -138,26 +429,87 @@ static CUSTOM_ALPHABET: LazyLock<Vec<char>> = LazyLock::new(|| {
     .collect()
 });
 
+#[cfg(test)]
 fn custom_nanoid() -> String {
   // Generate a nanoid with the custom alphabet and desired size
-  nanoid!(21, &*CUSTOM_ALPHABET)
+  nanoid::format(
+    nanoid::rngs::default,
+    &CUSTOM_ALPHABET,
+    shared::config::DEFAULT_NANOID_LENGTH,
+  )
+}
+
+/// Generates an id with the configured length and alphabet.
+fn configured_nanoid(config: &Config) -> String {
+  nanoid::format(
+    nanoid::rngs::default,
+    &config.nanoid_alphabet,
+    config.nanoid_length,
+  )
+}
+
+/// Documents `/auth/access-token` as a deprecated copy of `/auth/refresh`,
+/// as a handler only carries one path.
+struct DeprecatedAccessTokenPath;
+
+impl Modify for DeprecatedAccessTokenPath {
+  fn modify(&self, openapi: &mut openapi::OpenApi) {
+    let Some(mut item) = openapi.paths.paths.get("/auth/refresh").cloned()
+    else {
+      return;
+    };
+    item.get.clone_from(&item.post);
+    for operation in [&mut item.get, &mut item.post].into_iter().flatten() {
+      operation.deprecated = Some(Deprecated::True);
+    }
+    openapi
+      .paths
+      .paths
+      .insert(String::from("/auth/access-token"), item);
+  }
 }
 
 #[derive(OpenApi)]
-#[openapi(paths(
-  crate::auth::handlers::auth_login,
-  crate::auth::handlers::access_token,
-  crate::users::handlers::get_users,
-  crate::users::handlers::create_user,
-  crate::shared::handlers::check_health
-))]
+#[openapi(
+  modifiers(&DeprecatedAccessTokenPath),
+  paths(
+    crate::auth::handlers::auth_login,
+    crate::auth::handlers::access_token,
+    crate::auth::handlers::logout_all,
+    crate::auth::handlers::oauth_token,
+    crate::auth::handlers::validate_token,
+    crate::auth::handlers::change_password,
+    crate::auth::handlers::verify_email,
+    crate::auth::handlers::password_policy,
+    crate::users::handlers::register_user,
+    crate::users::handlers::get_users,
+    crate::users::handlers::get_hash_migration,
+    crate::users::handlers::user_exists,
+    crate::users::handlers::create_user,
+    crate::users::handlers::get_user,
+    crate::users::handlers::delete_user,
+    crate::users::handlers::update_user_status,
+    crate::users::handlers::reset_password,
+    crate::users::handlers::revoke_tokens,
+    crate::users::handlers::create_verification_token,
+    crate::shared::handlers::check_health,
+    crate::shared::handlers::check_readiness,
+    crate::shared::handlers::check_health_detailed,
+    crate::shared::handlers::list_roles
+  )
+)]
 struct ApiDoc;
 
 #[cfg(test)]
 mod tests {
   use super::*;
-  use actix_rt::time::sleep;
-  use actix_web::{http::header::HeaderValue, test, App};
+  use actix_web::{
+    http::{
+      header::{self, HeaderValue},
+      StatusCode,
+    },
+    test, App,
+  };
   use auth::rto::login_rto::LoginRto;
   use fake::{
     faker::{
@@ -167,7 +519,15 @@ mod tests {
     locales::EN,
     Fake,
   };
-  use shared::database::{Database, InMemoryDatabase};
+  use helpers::tests::fake_user;
+  use shared::mailer::LogMailer;
+  use shared::{
+    audit_log::AuditEvent,
+    clock::FixedClock,
+    config::{DEFAULT_MAX_BODY_BYTES, DEFAULT_MAX_CONCURRENT_REQUESTS},
+    database::{Database, InMemoryDatabase},
+    http_error::{ErrorCode, HttpError},
+  };
   use std::{env, net::SocketAddr, str::FromStr, time::Duration};
   use users::repository::user_repository::UserRepositoryImpl;
 
@@ -180,22 +540,30 @@ mod tests {
     let config = Arc::new(Config::default().await);
     let database = Arc::new(InMemoryDatabase::new(&config).await.unwrap());
     let health_check = Arc::new(HealthCheckImpl::new(database.clone()));
+    let clock = Arc::new(FixedClock::new(chrono::Utc::now()));
 
     // Initialize the service in-memory
     let app = test::init_service(App::new().configure(|cfg| {
       apply_service_config(
         cfg,
-        &GovernorConfigBuilder::default().finish().unwrap(),
+        RateLimit::new(config.clone()),
         config,
         health_check,
         Arc::new(HashWorker::new(
           ThreadPoolBuilder::new()
-            .num_threads(max(num_threads() - 2, 1))
+            .num_threads(max(num_threads().saturating_sub(2), 1))
             .build()
             .unwrap(),
           2,
         )),
-        UserRepositoryImpl::new(database.clone()),
+        Arc::new(Webhook::new(None)),
+        Arc::new(PwnedPasswordsClient::default()),
+        Arc::new(InMemoryAuditLog::new()),
+        Arc::new(LogMailer),
+        Arc::new(IdempotencyStore::new(Duration::from_secs(60))),
+        Arc::new(ConcurrencyLimit::new(DEFAULT_MAX_CONCURRENT_REQUESTS)),
+        clock.clone(),
+        Arc::new(UserRepositoryImpl::new(database.clone())),
       )
     }))
     .await;
@@ -226,7 +594,29 @@ mod tests {
     let create_resp = test::call_service(&app, create_req).await;
     assert!(create_resp.status().is_success(), "Create user failed");
 
-    // 2) Login
+    // 2) Follow the Location header to the created user
+    let location = create_resp
+      .headers()
+      .get(actix_web::http::header::LOCATION)
+      .unwrap()
+      .to_str()
+      .unwrap()
+      .to_string();
+    let get_req = test::TestRequest::get()
+      .uri(&location)
+      .peer_addr(SocketAddr::from_str("127.0.0.1:12345").unwrap())
+      .append_header((
+        actix_web::http::header::AUTHORIZATION,
+        HeaderValue::from_str(&format!("Bearer {}", master_key)).unwrap(),
+      ))
+      .to_request();
+    let get_resp = test::call_service(&app, get_req).await;
+    assert!(get_resp.status().is_success(), "Location did not resolve");
+    let found: users::rto::find_user_rto::FindUserRto =
+      test::read_body_json(get_resp).await;
+    assert_eq!(found.email, email);
+
+    // 3) Login
     let login_req = test::TestRequest::post()
       .uri("/v1/auth/login")
       .peer_addr(SocketAddr::from_str("127.0.0.1:12345").unwrap())
@@ -248,11 +638,11 @@ mod tests {
     let login_rto: LoginRto = serde_json::from_str(&login_body_str)
       .expect("Failed to parse response JSON");
 
-    // Required otherwise the test runs too fast and the JWT has the same second
-    // expiration. Making the same JWT.
-    sleep(Duration::from_secs(1)).await;
+    // Tokens only carry second precision, so move time forward to make the
+    // refreshed pair differ from the one issued at login.
+    clock.advance(chrono::Duration::seconds(1));
 
-    // 3) Refresh token
+    // 4) Refresh token
     let access_token_req = test::TestRequest::post()
       .uri("/v1/auth/access-token")
       .peer_addr(SocketAddr::from_str("127.0.0.1:12345").unwrap())
@@ -277,5 +667,406 @@ mod tests {
         .expect("Failed to parse response JSON");
 
     assert!(access_token_rto != login_rto);
+
+    // 5) Refresh again under the canonical name
+    clock.advance(chrono::Duration::seconds(1));
+    let refresh_req = test::TestRequest::post()
+      .uri("/v1/auth/refresh")
+      .peer_addr(SocketAddr::from_str("127.0.0.1:12345").unwrap())
+      .append_header((
+        actix_web::http::header::AUTHORIZATION,
+        HeaderValue::from_str(&format!(
+          "Bearer {}",
+          access_token_rto.refresh_token
+        ))
+        .unwrap(),
+      ))
+      .to_request();
+
+    let refresh_resp = test::call_service(&app, refresh_req).await;
+    assert!(refresh_resp.status().is_success(), "Refresh failed");
+    let refresh_rto: LoginRto = test::read_body_json(refresh_resp).await;
+    assert!(refresh_rto != access_token_rto);
+  }
+
+  #[actix_rt::test]
+  async fn test_num_threads_falls_back_when_unknown() {
+    let threads = parallelism_or_fallback(Err(std::io::Error::from(
+      std::io::ErrorKind::Unsupported,
+    )));
+    assert_eq!(threads, FALLBACK_NUM_THREADS);
+    assert!(max(threads.saturating_sub(2), 1) > 0);
+    assert!(num_threads() > 0);
+  }
+
+  #[actix_rt::test]
+  async fn test_configured_nanoid() {
+    let config = Config {
+      nanoid_length: 12,
+      nanoid_alphabet: "abcdefghijklmnopqrstuvwxyz".chars().collect(),
+      ..Config::default().await
+    };
+    for _ in 0..100 {
+      let id = configured_nanoid(&config);
+      assert_eq!(id.chars().count(), 12);
+      assert!(id.chars().all(|c| c.is_ascii_lowercase()));
+    }
+  }
+
+  #[actix_rt::test]
+  async fn test_openapi_includes_examples() {
+    let spec = serde_json::to_value(ApiDoc::openapi()).unwrap();
+    let schemas = &spec["components"]["schemas"];
+
+    assert_eq!(
+      schemas["LoginDto"]["properties"]["email"]["example"],
+      "jane.doe@example.com"
+    );
+    assert_eq!(
+      schemas["CreateUserDto"]["properties"]["userName"]["example"],
+      "Jane Doe"
+    );
+    assert_eq!(schemas["Role"]["example"], "customer");
+    assert!(
+      schemas["LoginRto"]["properties"]["accessToken"]["example"].is_string()
+    );
+  }
+
+  #[actix_rt::test]
+  async fn test_openapi_deprecates_access_token_path() {
+    let spec = serde_json::to_value(ApiDoc::openapi()).unwrap();
+    let paths = &spec["paths"];
+
+    assert!(paths["/auth/refresh"]["post"]["deprecated"].is_null());
+    assert_eq!(paths["/auth/access-token"]["get"]["deprecated"], true);
+    assert_eq!(paths["/auth/access-token"]["post"]["deprecated"], true);
+  }
+
+  #[actix_rt::test]
+  async fn test_throttled_login_carries_rate_limit_headers() {
+    let config = Arc::new(Config::default().await);
+    let database = Arc::new(InMemoryDatabase::new(&config).await.unwrap());
+    let app = test::init_service(App::new().configure(|cfg| {
+      apply_service_config(
+        cfg,
+        RateLimit::new(config.clone()),
+        config,
+        Arc::new(HealthCheckImpl::new(database.clone())),
+        Arc::new(HashWorker::new(
+          ThreadPoolBuilder::new().num_threads(1).build().unwrap(),
+          1,
+        )),
+        Arc::new(Webhook::new(None)),
+        Arc::new(PwnedPasswordsClient::default()),
+        Arc::new(InMemoryAuditLog::new()),
+        Arc::new(LogMailer),
+        Arc::new(IdempotencyStore::new(Duration::from_secs(60))),
+        Arc::new(ConcurrencyLimit::new(DEFAULT_MAX_CONCURRENT_REQUESTS)),
+        Arc::new(FixedClock::new(chrono::Utc::now())),
+        Arc::new(UserRepositoryImpl::new(database.clone())),
+      )
+    }))
+    .await;
+
+    let login = || {
+      test::TestRequest::post()
+        .uri("/v1/auth/login")
+        .peer_addr(SocketAddr::from_str("127.0.0.1:12345").unwrap())
+        .set_json(serde_json::json!({
+          "email": "nobody@example.com",
+          "password": "password"
+        }))
+        .to_request()
+    };
+
+    // The burst is spent well before any permit is replenished.
+    for remaining in (0..5).rev() {
+      let response = test::call_service(&app, login()).await;
+      assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+      let headers = response.headers();
+      assert_eq!(headers.get("x-ratelimit-limit").unwrap(), "5");
+      assert_eq!(
+        headers.get("x-ratelimit-remaining").unwrap(),
+        remaining.to_string().as_str()
+      );
+    }
+
+    let response = test::call_service(&app, login()).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert!(response.headers().contains_key(header::RETRY_AFTER));
+    assert_eq!(
+      response.headers().get("x-ratelimit-remaining").unwrap(),
+      "0"
+    );
+  }
+
+  #[actix_rt::test]
+  async fn test_malformed_and_invalid_bodies_are_distinct() {
+    let config = Arc::new(Config {
+      master_keys: vec![String::from("TEST_MASTER_KEY")],
+      ..Config::default().await
+    });
+    let database = Arc::new(InMemoryDatabase::new(&config).await.unwrap());
+    let app = test::init_service(App::new().configure(|cfg| {
+      apply_service_config(
+        cfg,
+        RateLimit::new(config.clone()),
+        config,
+        Arc::new(HealthCheckImpl::new(database.clone())),
+        Arc::new(HashWorker::new(
+          ThreadPoolBuilder::new().num_threads(1).build().unwrap(),
+          1,
+        )),
+        Arc::new(Webhook::new(None)),
+        Arc::new(PwnedPasswordsClient::default()),
+        Arc::new(InMemoryAuditLog::new()),
+        Arc::new(LogMailer),
+        Arc::new(IdempotencyStore::new(Duration::from_secs(60))),
+        Arc::new(ConcurrencyLimit::new(DEFAULT_MAX_CONCURRENT_REQUESTS)),
+        Arc::new(FixedClock::new(chrono::Utc::now())),
+        Arc::new(UserRepositoryImpl::new(database.clone())),
+      )
+    }))
+    .await;
+    let create_user = |body: &str| {
+      test::TestRequest::post()
+        .uri("/v1/users")
+        .insert_header((header::AUTHORIZATION, "Bearer TEST_MASTER_KEY"))
+        .insert_header((header::CONTENT_TYPE, "application/json"))
+        .set_payload(body.to_string())
+        .to_request()
+    };
+
+    let response = test::call_service(&app, create_user("not json")).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let error: HttpError = test::read_body_json(response).await;
+    assert_eq!(error.code, Some(ErrorCode::MalformedBody));
+
+    let invalid = serde_json::json!({
+      "email": "not an email",
+      "userName": "user",
+      "password": "password",
+      "role": "customer"
+    });
+    let response =
+      test::call_service(&app, create_user(&invalid.to_string())).await;
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let error: HttpError = test::read_body_json(response).await;
+    assert_eq!(error.code, Some(ErrorCode::ValidationFailed));
+
+    let unknown_role = serde_json::json!({
+      "email": "user@example.com",
+      "userName": "user",
+      "password": "password",
+      "role": "wizard"
+    });
+    let response =
+      test::call_service(&app, create_user(&unknown_role.to_string())).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let error: HttpError = test::read_body_json(response).await;
+    assert_eq!(error.code, Some(ErrorCode::MalformedBody));
+    assert!(error
+      .message
+      .contains("expected one of: admin, manager, driver, customer"));
+
+    let oversized = serde_json::json!({
+      "email": "user@example.com",
+      "userName": "a".repeat(DEFAULT_MAX_BODY_BYTES),
+      "password": "password",
+      "role": "customer"
+    });
+    let response =
+      test::call_service(&app, create_user(&oversized.to_string())).await;
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    let error: HttpError = test::read_body_json(response).await;
+    assert_eq!(error.code, Some(ErrorCode::PayloadTooLarge));
+
+    // Forms are refused alike.
+    let request = test::TestRequest::post()
+      .uri("/v1/auth/token")
+      .peer_addr(SocketAddr::from_str("127.0.0.1:12345").unwrap())
+      .insert_header((
+        header::CONTENT_TYPE,
+        "application/x-www-form-urlencoded",
+      ))
+      .set_payload(format!(
+        "grant_type=password&username={}",
+        "a".repeat(DEFAULT_MAX_BODY_BYTES)
+      ))
+      .to_request();
+    let response = test::call_service(&app, request).await;
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    let error: HttpError = test::read_body_json(response).await;
+    assert_eq!(error.code, Some(ErrorCode::PayloadTooLarge));
+  }
+
+  #[actix_rt::test]
+  async fn test_master_key_creation_is_attributed_to_system_actor() {
+    let config = Arc::new(Config {
+      master_keys: vec![String::from("TEST_MASTER_KEY")],
+      system_actor: String::from("ops-console"),
+      ..Config::default().await
+    });
+    let database = Arc::new(InMemoryDatabase::new(&config).await.unwrap());
+    let audit_log = Arc::new(InMemoryAuditLog::new());
+    let app = test::init_service(App::new().configure(|cfg| {
+      apply_service_config(
+        cfg,
+        RateLimit::new(config.clone()),
+        config,
+        Arc::new(HealthCheckImpl::new(database.clone())),
+        Arc::new(HashWorker::new(
+          ThreadPoolBuilder::new().num_threads(1).build().unwrap(),
+          1,
+        )),
+        Arc::new(Webhook::new(None)),
+        Arc::new(PwnedPasswordsClient::default()),
+        audit_log.clone(),
+        Arc::new(LogMailer),
+        Arc::new(IdempotencyStore::new(Duration::from_secs(60))),
+        Arc::new(ConcurrencyLimit::new(DEFAULT_MAX_CONCURRENT_REQUESTS)),
+        Arc::new(FixedClock::new(chrono::Utc::now())),
+        Arc::new(UserRepositoryImpl::new(database.clone())),
+      )
+    }))
+    .await;
+
+    let request = test::TestRequest::post()
+      .uri("/v1/users")
+      .insert_header((header::AUTHORIZATION, "Bearer TEST_MASTER_KEY"))
+      .set_json(serde_json::json!({
+        "email": "user@example.com",
+        "userName": "user",
+        "password": "password",
+        "role": "customer"
+      }))
+      .to_request();
+    let response = test::call_service(&app, request).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let entries = audit_log.entries();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].event, AuditEvent::UserCreated);
+    assert_eq!(entries[0].actor.as_deref(), Some("ops-console"));
+  }
+
+  #[actix_rt::test]
+  async fn test_large_user_list_is_compressed() {
+    let users = (0..100).map(|_| fake_user()).collect();
+    let database = Arc::new(InMemoryDatabase {
+      users: Arc::new(std::sync::RwLock::new(users)),
+    });
+
+    for (compress_responses, encoding) in [(true, Some("gzip")), (false, None)]
+    {
+      let config = Arc::new(Config {
+        master_keys: vec![String::from("TEST_MASTER_KEY")],
+        compress_responses,
+        ..Config::default().await
+      });
+      let app = test::init_service(App::new().configure(|cfg| {
+        apply_service_config(
+          cfg,
+          RateLimit::new(config.clone()),
+          config,
+          Arc::new(HealthCheckImpl::new(database.clone())),
+          Arc::new(HashWorker::new(
+            ThreadPoolBuilder::new().num_threads(1).build().unwrap(),
+            1,
+          )),
+          Arc::new(Webhook::new(None)),
+          Arc::new(PwnedPasswordsClient::default()),
+          Arc::new(InMemoryAuditLog::new()),
+          Arc::new(LogMailer),
+          Arc::new(IdempotencyStore::new(Duration::from_secs(60))),
+          Arc::new(ConcurrencyLimit::new(DEFAULT_MAX_CONCURRENT_REQUESTS)),
+          Arc::new(FixedClock::new(chrono::Utc::now())),
+          Arc::new(UserRepositoryImpl::new(database.clone())),
+        )
+      }))
+      .await;
+
+      for (uri, status) in [
+        ("/v1/users?limit=100", StatusCode::OK),
+        ("/v1/users?cursor=not-a-cursor", StatusCode::BAD_REQUEST),
+      ] {
+        let request = test::TestRequest::get()
+          .uri(uri)
+          .insert_header((header::AUTHORIZATION, "Bearer TEST_MASTER_KEY"))
+          .insert_header((header::ACCEPT_ENCODING, "gzip"))
+          .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), status);
+        assert_eq!(
+          response
+            .headers()
+            .get(header::CONTENT_ENCODING)
+            .map(|encoding| encoding.to_str().unwrap()),
+          encoding,
+          "{} with compression {}",
+          uri,
+          compress_responses,
+        );
+      }
+    }
+  }
+
+  #[actix_rt::test]
+  async fn test_registration_and_login_agree_on_email() {
+    let config = Arc::new(Config {
+      allow_self_signup: true,
+      ..Config::default().await
+    });
+    let database = Arc::new(InMemoryDatabase::new(&config).await.unwrap());
+    let app = test::init_service(App::new().configure(|cfg| {
+      apply_service_config(
+        cfg,
+        RateLimit::new(config.clone()),
+        config,
+        Arc::new(HealthCheckImpl::new(database.clone())),
+        Arc::new(HashWorker::new(
+          ThreadPoolBuilder::new().num_threads(1).build().unwrap(),
+          1,
+        )),
+        Arc::new(Webhook::new(None)),
+        Arc::new(PwnedPasswordsClient::default()),
+        Arc::new(InMemoryAuditLog::new()),
+        Arc::new(LogMailer),
+        Arc::new(IdempotencyStore::new(Duration::from_secs(60))),
+        Arc::new(ConcurrencyLimit::new(DEFAULT_MAX_CONCURRENT_REQUESTS)),
+        Arc::new(FixedClock::new(chrono::Utc::now())),
+        Arc::new(UserRepositoryImpl::new(database.clone())),
+      )
+    }))
+    .await;
+
+    let request = test::TestRequest::post()
+      .uri("/v1/auth/register")
+      .peer_addr(SocketAddr::from_str("127.0.0.1:12345").unwrap())
+      .set_json(serde_json::json!({
+        "email": "  Jane.Doe@Example.COM ",
+        "userName": "Jane Doe",
+        "password": "password"
+      }))
+      .to_request();
+    let response = test::call_service(&app, request).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(
+      database.users.read().unwrap()[0].email,
+      "jane.doe@example.com"
+    );
+
+    for email in ["jane.doe@example.com", "JANE.DOE@example.com\t"] {
+      let request = test::TestRequest::post()
+        .uri("/v1/auth/login")
+        .peer_addr(SocketAddr::from_str("127.0.0.1:12345").unwrap())
+        .set_json(serde_json::json!({
+          "email": email,
+          "password": "password"
+        }))
+        .to_request();
+      let response = test::call_service(&app, request).await;
+      assert_eq!(response.status(), StatusCode::OK, "login as {:?}", email);
+    }
   }
 }
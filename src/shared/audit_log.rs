@@ -0,0 +1,139 @@
+use std::{collections::VecDeque, sync::RwLock};
+
+use actix_web::{HttpMessage, HttpRequest};
+use chrono::{DateTime, Utc};
+use mockall::automock;
+use serde::{Deserialize, Serialize};
+
+/// Oldest entries are dropped past this point so the in-memory log can't grow
+/// without bound.
+const IN_MEMORY_CAPACITY: usize = 10_000;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditEvent {
+  LoginSucceeded,
+  LoginFailed,
+  UserCreated,
+  UserDeleted,
+  UserStatusChanged,
+  TokensRevoked,
+  PasswordReset,
+  PasswordChanged,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+  pub event: AuditEvent,
+  /// Uuid of whoever performed the action, when known.
+  pub actor: Option<String>,
+  /// What the action was applied to, e.g. a user uuid or a login email.
+  pub target: Option<String>,
+  pub timestamp: DateTime<Utc>,
+  pub source_ip: Option<String>,
+}
+
+/// Identity a request authenticated as, stored in its extensions by the
+/// authentication middleware so audit entries are attributed without each
+/// handler passing it along.
+#[derive(Debug, Clone)]
+pub struct AuditActor(pub String);
+
+impl AuditEntry {
+  pub fn new(event: AuditEvent, request: &HttpRequest) -> Self {
+    Self {
+      event,
+      actor: request
+        .extensions()
+        .get::<AuditActor>()
+        .map(|actor| actor.0.clone()),
+      target: None,
+      timestamp: Utc::now(),
+      source_ip: request.peer_addr().map(|address| address.ip().to_string()),
+    }
+  }
+
+  pub fn actor(mut self, actor: &str) -> Self {
+    self.actor = Some(actor.to_string());
+    self
+  }
+
+  pub fn target(mut self, target: &str) -> Self {
+    self.target = Some(target.to_string());
+    self
+  }
+}
+
+/// Sink for security-relevant events. Implementations must not block the
+/// request; anything slow (a database, a SIEM) should buffer internally.
+#[automock]
+pub trait AuditLog {
+  fn record(&self, entry: AuditEntry);
+}
+
+#[derive(Default)]
+pub struct InMemoryAuditLog {
+  entries: RwLock<VecDeque<AuditEntry>>,
+}
+
+impl InMemoryAuditLog {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  #[cfg(test)]
+  pub fn entries(&self) -> Vec<AuditEntry> {
+    self.entries.read().unwrap().iter().cloned().collect()
+  }
+}
+
+impl AuditLog for InMemoryAuditLog {
+  fn record(&self, entry: AuditEntry) {
+    let mut entries = self.entries.write().unwrap();
+    if entries.len() == IN_MEMORY_CAPACITY {
+      entries.pop_front();
+    }
+    entries.push_back(entry);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{net::SocketAddr, str::FromStr};
+
+  use super::*;
+
+  #[test]
+  fn test_in_memory_audit_log_records_entries() {
+    let request = actix_web::test::TestRequest::default()
+      .peer_addr(SocketAddr::from_str("10.0.0.1:12345").unwrap())
+      .to_http_request();
+    let audit_log = InMemoryAuditLog::new();
+
+    audit_log.record(
+      AuditEntry::new(AuditEvent::UserCreated, &request).target("some-uuid"),
+    );
+
+    let entries = audit_log.entries();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].event, AuditEvent::UserCreated);
+    assert_eq!(entries[0].actor, None);
+    assert_eq!(entries[0].target.as_deref(), Some("some-uuid"));
+    assert_eq!(entries[0].source_ip.as_deref(), Some("10.0.0.1"));
+  }
+
+  #[test]
+  fn test_in_memory_audit_log_is_bounded() {
+    let request = actix_web::test::TestRequest::default().to_http_request();
+    let audit_log = InMemoryAuditLog::new();
+
+    for _ in 0..IN_MEMORY_CAPACITY {
+      audit_log.record(AuditEntry::new(AuditEvent::LoginFailed, &request));
+    }
+    audit_log.record(AuditEntry::new(AuditEvent::LoginSucceeded, &request));
+
+    let entries = audit_log.entries();
+    assert_eq!(entries.len(), IN_MEMORY_CAPACITY);
+    assert_eq!(entries.last().unwrap().event, AuditEvent::LoginSucceeded);
+  }
+}
@@ -0,0 +1,129 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use mockall::automock;
+use sha1::{Digest, Sha1};
+
+const PWNED_PASSWORDS_RANGE_URL: &str = "https://api.pwnedpasswords.com/range/";
+/// Hex characters of the SHA-1 sent to the API, out of 40.
+const PREFIX_LENGTH: usize = 5;
+/// Lookups run inline with the request setting the password, so a slow API
+/// is given up on, and let through, after this long.
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Lists breached password hashes by the first characters of their SHA-1,
+/// so neither the password nor its full hash ever leaves the service.
+#[automock]
+#[async_trait]
+pub trait BreachClient: Send + Sync {
+  /// Body of a range query: a `SUFFIX:COUNT` line per breached hash
+  /// starting with `prefix`.
+  async fn range(&self, prefix: &str) -> Result<String, reqwest::Error>;
+}
+
+/// Client of the HaveIBeenPwned Pwned Passwords range API.
+pub struct PwnedPasswordsClient {
+  client: reqwest::Client,
+}
+
+impl Default for PwnedPasswordsClient {
+  fn default() -> Self {
+    Self {
+      client: reqwest::Client::builder()
+        .timeout(LOOKUP_TIMEOUT)
+        .build()
+        .expect("Failed to build the breached password client"),
+    }
+  }
+}
+
+#[async_trait]
+impl BreachClient for PwnedPasswordsClient {
+  async fn range(&self, prefix: &str) -> Result<String, reqwest::Error> {
+    self
+      .client
+      .get(format!("{}{}", PWNED_PASSWORDS_RANGE_URL, prefix))
+      // Pads responses so their size gives nothing away about the prefix.
+      .header("Add-Padding", "true")
+      .send()
+      .await?
+      .error_for_status()?
+      .text()
+      .await
+  }
+}
+
+/// Whether `password` shows up in a known breach. Lookups that fail or time
+/// out count as not breached, so an outage of the API never blocks signups
+/// or password changes.
+pub async fn is_breached<B: BreachClient + ?Sized>(
+  client: &B,
+  password: &str,
+) -> bool {
+  let hash = format!("{:X}", Sha1::digest(password));
+  let (prefix, suffix) = hash.split_at(PREFIX_LENGTH);
+  match client.range(prefix).await {
+    // Padding lines have a count of 0.
+    Ok(body) => body.lines().filter_map(|line| line.split_once(':')).any(
+      |(candidate, count)| {
+        candidate.eq_ignore_ascii_case(suffix) && count.trim() != "0"
+      },
+    ),
+    Err(error) => {
+      eprintln!(
+        "Breached password lookup failed, letting it through: {}",
+        error
+      );
+      false
+    }
+  }
+}
+
+#[cfg(test)]
+impl MockBreachClient {
+  /// Reports `password` as breached, for handler tests.
+  pub fn breached(password: &str) -> Self {
+    let hash = format!("{:X}", Sha1::digest(password));
+    let mut client = MockBreachClient::new();
+    client
+      .expect_range()
+      .withf({
+        let prefix = hash[..PREFIX_LENGTH].to_string();
+        move |range| range == prefix
+      })
+      .returning(move |_| Ok(format!("{}:42\r\n", &hash[PREFIX_LENGTH..])));
+    client
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// SHA-1 of "password".
+  const PASSWORD_SHA1: &str = "5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8";
+
+  fn client_returning(body: &'static str) -> MockBreachClient {
+    let mut client = MockBreachClient::new();
+    client
+      .expect_range()
+      .withf(|prefix| prefix == &PASSWORD_SHA1[..PREFIX_LENGTH])
+      .returning(move |_| Ok(String::from(body)));
+    client
+  }
+
+  #[actix_web::test]
+  async fn test_is_breached() {
+    let breached = client_returning(
+      "003D68EB55068C33ACE09247EE4C639306B:3\r\n\
+       1E4C9B93F3F0682250B6CF8331B7EE68FD8:9545824\r\n",
+    );
+    assert!(is_breached(&breached, "password").await);
+
+    let padded = client_returning(
+      "003D68EB55068C33ACE09247EE4C639306B:3\r\n\
+       1E4C9B93F3F0682250B6CF8331B7EE68FD8:0\r\n",
+    );
+    assert!(!is_breached(&padded, "password").await);
+  }
+}
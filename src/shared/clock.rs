@@ -0,0 +1,58 @@
+use chrono::{DateTime, Utc};
+
+/// Source of "now" for anything time-sensitive, so tests can pin and move
+/// time instead of sleeping.
+pub trait Clock {
+  fn now(&self) -> DateTime<Utc>;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+  fn now(&self) -> DateTime<Utc> {
+    Utc::now()
+  }
+}
+
+/// Clock frozen at a given instant that only moves when advanced.
+#[cfg(test)]
+pub struct FixedClock {
+  now: std::sync::RwLock<DateTime<Utc>>,
+}
+
+#[cfg(test)]
+impl FixedClock {
+  pub fn new(now: DateTime<Utc>) -> Self {
+    Self {
+      now: std::sync::RwLock::new(now),
+    }
+  }
+
+  pub fn advance(&self, duration: chrono::Duration) {
+    let mut now = self.now.write().unwrap();
+    *now += duration;
+  }
+}
+
+#[cfg(test)]
+impl Clock for FixedClock {
+  fn now(&self) -> DateTime<Utc> {
+    *self.now.read().unwrap()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_fixed_clock_only_moves_when_advanced() {
+    let start = Utc::now();
+    let clock = FixedClock::new(start);
+    assert_eq!(clock.now(), start);
+    assert_eq!(clock.now(), start);
+
+    clock.advance(chrono::Duration::minutes(15));
+    assert_eq!(clock.now(), start + chrono::Duration::minutes(15));
+  }
+}
@@ -1,24 +1,712 @@
-use std::env;
+use std::{collections::HashMap, env, sync::Arc, time::Duration};
+
+use arc_swap::ArcSwap;
+use jsonwebtoken::Algorithm;
+
+use super::{
+  database::DatabaseBackend, role::Role, sweeper::DEFAULT_SWEEP_INTERVAL,
+};
+use crate::users::model::user_id::UserId;
 
 #[derive(Clone, Debug)]
 pub struct Config {
   pub address: String,
-  pub master_key: String,
+  /// PEM certificate chain and private key. HTTPS is served when both are
+  /// set, plain HTTP when neither is, see `tls::listener`.
+  pub tls_cert_path: Option<String>,
+  pub tls_key_path: Option<String>,
+  /// Every key currently accepted for the admin API. `MASTER_KEY` takes a
+  /// comma-separated list so keys can be rotated without downtime.
+  pub master_keys: Vec<String>,
+  /// Actor audit entries are attributed to when a request authenticated
+  /// with a master key rather than as a user.
+  pub system_actor: String,
+  /// The secret new tokens are signed with.
   pub jwt_secret: String,
+  /// Secrets rotated out, still accepted when verifying so outstanding
+  /// tokens stay valid. `JWT_PREVIOUS_SECRETS` takes a comma-separated list.
+  pub jwt_previous_secrets: Vec<String>,
+  /// HMAC variant tokens are signed with, and the only one accepted when
+  /// verifying. `JWT_ALGORITHM` takes `HS256`, `HS384` or `HS512`.
+  pub jwt_algorithm: Algorithm,
+  /// Seconds a token is still accepted past its `exp`, to absorb clock skew
+  /// between us and our clients.
+  pub jwt_leeway_seconds: u64,
+  /// `iss` and `aud` of access tokens, which are rejected unless both
+  /// match. Deployments sharing a secret should set their own.
+  pub jwt_issuer: String,
+  pub jwt_audience: String,
+  /// Keeps the user name in the `sub` of access tokens rather than the
+  /// uuid, for consumers still migrating off it. Off by default.
+  pub jwt_legacy_subject: bool,
+  /// Settings that change without a restart, see `reload`. Shared by every
+  /// clone, so all workers see a reload.
+  pub reloadable: Arc<ArcSwap<ReloadableConfig>>,
+  /// File of `NAME=value` lines whose `ReloadableConfig` settings take
+  /// precedence over the environment, and are re-read on `reload`. Set with
+  /// `CONFIG_FILE`.
+  pub config_file: Option<String>,
+  /// User attributes copied into access tokens as extra claims, e.g.
+  /// `email,email_verified`, so consumers can skip a lookup.
+  pub access_token_claims: Vec<String>,
+  pub require_email_verification: bool,
+  /// DEBUG ONLY, off by default: tells unknown emails apart from wrong
+  /// passwords in failed login responses, which lets anyone enumerate
+  /// accounts. Only for internal deployments being debugged.
+  pub verbose_auth_errors: bool,
+  /// Expose the unauthenticated `POST /v1/auth/register`, which creates
+  /// customers. Admins can create users of any role either way.
+  pub allow_self_signup: bool,
+  /// Roles self-registration may pick. Anything else is refused.
+  pub self_signup_roles: Vec<Role>,
+  pub user_created_webhook_url: Option<String>,
+  /// Refuse signup passwords found in HaveIBeenPwned. Only the first five
+  /// characters of their SHA-1 are sent.
+  pub check_pwned_passwords: bool,
+  /// Refuse to create a user whose `user_name` is already taken, on top of
+  /// the email check. Adds an index on `user_name` at startup, unique on
+  /// MongoDB, so names already taken twice must be told apart first.
+  pub enforce_unique_username: bool,
+  /// Sends verification and password reset mail through this server when
+  /// set (needs the `smtp` feature). Mail is only logged otherwise.
+  pub smtp_url: Option<String>,
+  /// Sender address for outgoing mail.
+  #[cfg_attr(not(feature = "smtp"), allow(dead_code))]
+  pub mail_from: String,
+  /// Rate limit by the client IP a reverse proxy forwards rather than the
+  /// peer address. Only enable behind a proxy that sets `X-Forwarded-For`.
+  pub trust_proxy: bool,
+  /// Storage backend, see `DatabaseBackend::fallback` for the default.
+  pub database_backend: DatabaseBackend,
+  /// Attempts per database call, including the first. Defaults to 1, which
+  /// disables retries.
+  pub database_retry_attempts: u32,
+  /// How long a single database call may take before the request is
+  /// answered with a 503. Defaults to 5 seconds.
+  pub database_query_timeout: Duration,
+  /// Attempts to connect at startup before giving up. Defaults to 3.
+  pub database_connect_attempts: u32,
+  /// How long lookups by uuid are cached. Also how long other instances of
+  /// the service may accept tokens a revocation rejects, so keep it short
+  /// or unset when running several. Unset disables the cache.
+  pub user_cache_ttl: Option<Duration>,
+  /// How long the password hash queue may stay full before the service
+  /// reports itself as not ready.
+  pub hash_queue_saturation_threshold: Duration,
+  /// Externally reachable origin, e.g. `https://auth.example.com`, used to
+  /// build absolute links. Links stay relative when unset.
+  pub public_base_url: Option<String>,
+  /// How long a creation is replayed for retries with the same
+  /// `Idempotency-Key`.
+  pub idempotency_key_ttl: Duration,
+  /// How often expired entries are freed from in-memory stores.
+  pub sweep_interval: Duration,
+  /// Origins browsers may call the API from, or `*` for any. CORS headers
+  /// are left out when empty.
+  pub cors_allowed_origins: Vec<String>,
+  /// Response headers scripts on those origins may read. Always includes
+  /// `Location`, which points at created users.
+  pub cors_expose_headers: Vec<String>,
+  /// How long browsers may cache a preflight. Unset leaves it to them.
+  pub cors_max_age: Option<Duration>,
+  /// Requests taking longer are logged, see `SlowRequestLog`.
+  pub slow_request_threshold: Duration,
+  /// Thresholds for routes expected to be slower, such as those hashing
+  /// passwords, keyed by pattern, e.g. `/v1/auth/login`.
+  pub slow_request_route_thresholds: HashMap<String, Duration>,
+  /// Requests handled at once across all workers before the rest are
+  /// refused with a 503.
+  pub max_concurrent_requests: usize,
+  /// Compress responses with whichever encoding the client's
+  /// `Accept-Encoding` prefers. Set `DISABLE_COMPRESSION` behind a proxy
+  /// that already compresses.
+  pub compress_responses: bool,
+  /// Largest request body accepted, in bytes. Bigger ones are refused
+  /// before being buffered.
+  pub max_body_bytes: usize,
+  /// Length of generated ids, at least `MIN_NANOID_LENGTH`.
+  pub nanoid_length: usize,
+  /// Characters generated ids are drawn from. Defaults to the URL-safe set
+  /// without `_` and `-`.
+  pub nanoid_alphabet: Vec<char>,
 }
 
+pub const DEFAULT_ACCESS_TOKEN_TTL: u64 = 15 * 60; // 15 minutes in seconds
+/// Same tolerance `jsonwebtoken` applies by default.
+pub const DEFAULT_JWT_LEEWAY_SECONDS: u64 = 60;
+/// Login and token rate limit per client IP, see `RateLimit`.
+pub const DEFAULT_RATE_LIMIT_PER_SECOND: u64 = 2;
+pub const DEFAULT_RATE_LIMIT_BURST_SIZE: u32 = 5;
+pub const DEFAULT_SLOW_REQUEST_THRESHOLD: Duration = Duration::from_millis(500);
+pub const DEFAULT_DATABASE_QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+pub const DEFAULT_MAX_BODY_BYTES: usize = 16 * 1024;
+pub const DEFAULT_MAX_CONCURRENT_REQUESTS: usize = 1024;
+pub const DEFAULT_NANOID_LENGTH: usize = 21;
+/// Shorter ids make collisions too likely at any realistic user count.
+pub const MIN_NANOID_LENGTH: usize = 12;
+
 impl Config {
   pub async fn default() -> Self {
     let host = env::var("HOST").unwrap_or_else(|_| "0.0.0.0".to_string());
     let port = env::var("PORT").unwrap_or_else(|_| "3000".to_string());
-    let master_key =
-      env::var("MASTER_KEY").unwrap_or_else(|_| "DEV_MASTER_KEY".to_string());
+    let master_keys = comma_separated(
+      &env::var("MASTER_KEY").unwrap_or_else(|_| "DEV_MASTER_KEY".to_string()),
+    );
     let jwt_secret =
       env::var("JWT_SECRET").unwrap_or_else(|_| "DEV_JWT_SECRET".to_string());
+    let config_file = env::var("CONFIG_FILE").ok();
+    let reloadable = ReloadableConfig::read(config_file.as_deref())
+      .unwrap_or_else(|error| panic!("CONFIG_FILE can't be read: {}", error));
     Self {
       address: format!("{}:{}", host, port),
-      master_key,
+      tls_cert_path: env::var("TLS_CERT_PATH").ok(),
+      tls_key_path: env::var("TLS_KEY_PATH").ok(),
+      master_keys,
+      system_actor: env::var("AUDIT_SYSTEM_ACTOR")
+        .unwrap_or_else(|_| "master-key".to_string()),
       jwt_secret,
+      jwt_previous_secrets: comma_separated(
+        &env::var("JWT_PREVIOUS_SECRETS").unwrap_or_default(),
+      ),
+      jwt_algorithm: jwt_algorithm(env::var("JWT_ALGORITHM").ok().as_deref()),
+      jwt_leeway_seconds: env::var("JWT_LEEWAY_SECONDS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_JWT_LEEWAY_SECONDS),
+      jwt_issuer: env::var("JWT_ISSUER")
+        .unwrap_or_else(|_| "taille-auth".to_string()),
+      jwt_audience: env::var("JWT_AUDIENCE")
+        .unwrap_or_else(|_| "taille-auth".to_string()),
+      jwt_legacy_subject: env_flag("JWT_LEGACY_SUBJECT"),
+      reloadable: Arc::new(ArcSwap::from_pointee(reloadable)),
+      config_file,
+      access_token_claims: access_token_claims(
+        &env::var("ACCESS_TOKEN_CLAIMS").unwrap_or_default(),
+      ),
+      require_email_verification: env_flag("REQUIRE_EMAIL_VERIFICATION"),
+      allow_self_signup: env_flag("ALLOW_SELF_SIGNUP"),
+      self_signup_roles: self_signup_roles(
+        &env::var("SELF_SIGNUP_ALLOWED_ROLES")
+          .unwrap_or_else(|_| "customer".to_string()),
+      ),
+      verbose_auth_errors: env_flag("VERBOSE_AUTH_ERRORS"),
+      user_created_webhook_url: env::var("USER_CREATED_WEBHOOK_URL").ok(),
+      check_pwned_passwords: env_flag("CHECK_PWNED_PASSWORDS"),
+      enforce_unique_username: env_flag("ENFORCE_UNIQUE_USERNAME"),
+      smtp_url: env::var("SMTP_URL").ok(),
+      mail_from: env::var("MAIL_FROM")
+        .unwrap_or_else(|_| "no-reply@localhost".to_string()),
+      trust_proxy: env_flag("TRUST_PROXY"),
+      database_backend: database_backend(
+        env::var("DATABASE_BACKEND").ok().as_deref(),
+      ),
+      database_retry_attempts: env::var("DATABASE_RETRY_ATTEMPTS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(1),
+      database_query_timeout: env::var("DB_QUERY_TIMEOUT_MS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .map_or(DEFAULT_DATABASE_QUERY_TIMEOUT, Duration::from_millis),
+      database_connect_attempts: env::var("DATABASE_CONNECT_ATTEMPTS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(3),
+      user_cache_ttl: env::var("USER_CACHE_TTL_SECONDS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .map(Duration::from_secs),
+      hash_queue_saturation_threshold: Duration::from_secs(
+        env::var("HASH_QUEUE_SATURATION_SECONDS")
+          .ok()
+          .and_then(|value| value.parse().ok())
+          .unwrap_or(5),
+      ),
+      public_base_url: env::var("PUBLIC_BASE_URL")
+        .ok()
+        .map(|url| url.trim_end_matches('/').to_string()),
+      idempotency_key_ttl: Duration::from_secs(
+        env::var("IDEMPOTENCY_KEY_TTL_SECONDS")
+          .ok()
+          .and_then(|value| value.parse().ok())
+          .unwrap_or(24 * 60 * 60),
+      ),
+      sweep_interval: env::var("SWEEP_INTERVAL_SECONDS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .map_or(DEFAULT_SWEEP_INTERVAL, Duration::from_secs),
+      cors_allowed_origins: comma_separated(
+        &env::var("CORS_ALLOWED_ORIGINS").unwrap_or_default(),
+      ),
+      cors_expose_headers: cors_expose_headers(
+        &env::var("CORS_EXPOSE_HEADERS").unwrap_or_default(),
+      ),
+      cors_max_age: env::var("CORS_MAX_AGE_SECONDS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .map(Duration::from_secs),
+      slow_request_threshold: env::var("SLOW_REQUEST_THRESHOLD_MS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .map_or(DEFAULT_SLOW_REQUEST_THRESHOLD, Duration::from_millis),
+      slow_request_route_thresholds: slow_request_route_thresholds(
+        &env::var("SLOW_REQUEST_ROUTE_THRESHOLDS_MS").unwrap_or_default(),
+      ),
+      max_concurrent_requests: env::var("MAX_CONCURRENT_REQUESTS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_MAX_CONCURRENT_REQUESTS),
+      compress_responses: !env_flag("DISABLE_COMPRESSION"),
+      max_body_bytes: env::var("MAX_BODY_BYTES")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_MAX_BODY_BYTES),
+      nanoid_length: nanoid_length(env::var("NANOID_LENGTH").ok().as_deref()),
+      nanoid_alphabet: nanoid_alphabet(
+        env::var("NANOID_ALPHABET").ok().as_deref(),
+      ),
+    }
+  }
+
+  /// Every secret tokens are verified against, the current one first.
+  pub fn jwt_secrets(&self) -> impl Iterator<Item = &str> {
+    std::iter::once(self.jwt_secret.as_str())
+      .chain(self.jwt_previous_secrets.iter().map(String::as_str))
+  }
+
+  /// Seconds an access token issued to `role` stays valid.
+  pub fn access_token_ttl(&self, role: &Role) -> u64 {
+    self
+      .reloadable
+      .load()
+      .access_token_ttls
+      .get(role)
+      .copied()
+      .unwrap_or(DEFAULT_ACCESS_TOKEN_TTL)
+  }
+
+  pub fn maintenance_mode(&self) -> bool {
+    self.reloadable.load().maintenance_mode
+  }
+
+  /// Re-reads the `ReloadableConfig` settings from `config_file`, or for
+  /// those it leaves out, from the environment the process started with.
+  /// Everything else keeps its startup value, as does everything when the
+  /// file can't be read.
+  pub fn reload(&self) -> std::io::Result<()> {
+    let reloadable = ReloadableConfig::read(self.config_file.as_deref())?;
+    self.reloadable.store(Arc::new(reloadable));
+    Ok(())
+  }
+}
+
+/// Settings an operator may need to change on a running instance.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReloadableConfig {
+  /// Access token lifetimes in seconds for roles with an `ACCESS_TTL_<ROLE>`
+  /// override, see `Config::access_token_ttl`.
+  pub access_token_ttls: HashMap<Role, u64>,
+  /// Read-only mode: writes are refused with a 503 while reads and logins
+  /// keep working. Set with `MAINTENANCE_MODE`.
+  pub maintenance_mode: bool,
+  /// Requests replenished per second and client IP on the auth routes. Set
+  /// with `RATE_LIMIT_PER_SECOND`.
+  pub rate_limit_per_second: u64,
+  /// Requests a client IP may send at once on the auth routes. Set with
+  /// `RATE_LIMIT_BURST_SIZE`.
+  pub rate_limit_burst_size: u32,
+}
+
+impl Default for ReloadableConfig {
+  fn default() -> Self {
+    Self {
+      access_token_ttls: HashMap::new(),
+      maintenance_mode: false,
+      rate_limit_per_second: DEFAULT_RATE_LIMIT_PER_SECOND,
+      rate_limit_burst_size: DEFAULT_RATE_LIMIT_BURST_SIZE,
+    }
+  }
+}
+
+impl ReloadableConfig {
+  /// Reads the settings from `config_file` if set, falling back to the
+  /// environment for those it doesn't list.
+  fn read(config_file: Option<&str>) -> std::io::Result<Self> {
+    let file = match config_file {
+      Some(path) => config_file_vars(&std::fs::read_to_string(path)?),
+      None => HashMap::new(),
+    };
+    Ok(Self::load(|name| {
+      file.get(name).cloned().or_else(|| env::var(name).ok())
+    }))
+  }
+
+  /// Reads the settings through `var`, which looks up a variable by name.
+  fn load(var: impl Fn(&str) -> Option<String>) -> Self {
+    Self {
+      access_token_ttls: access_token_ttls(&var),
+      maintenance_mode: flag(var("MAINTENANCE_MODE")),
+      rate_limit_per_second: var("RATE_LIMIT_PER_SECOND")
+        .and_then(|value| value.parse().ok())
+        .filter(|&value| value > 0)
+        .unwrap_or(DEFAULT_RATE_LIMIT_PER_SECOND),
+      rate_limit_burst_size: var("RATE_LIMIT_BURST_SIZE")
+        .and_then(|value| value.parse().ok())
+        .filter(|&value| value > 0)
+        .unwrap_or(DEFAULT_RATE_LIMIT_BURST_SIZE),
+    }
+  }
+}
+
+/// Variables of a config file, one `NAME=value` per line. Blank lines and
+/// lines starting with `#` are skipped, as are lines without a `=`.
+fn config_file_vars(contents: &str) -> HashMap<String, String> {
+  contents
+    .lines()
+    .map(str::trim)
+    .filter(|line| !line.starts_with('#'))
+    .filter_map(|line| line.split_once('='))
+    .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+    .collect()
+}
+
+/// Reads a boolean switch, treating `true`/`1` as on and anything else,
+/// including an unset variable, as off.
+fn env_flag(name: &str) -> bool {
+  flag(env::var(name).ok())
+}
+
+fn flag(value: Option<String>) -> bool {
+  value.is_some_and(|value| value == "true" || value == "1")
+}
+
+fn comma_separated(value: &str) -> Vec<String> {
+  value
+    .split(',')
+    .map(str::trim)
+    .filter(|key| !key.is_empty())
+    .map(String::from)
+    .collect()
+}
+
+fn access_token_ttls(
+  var: impl Fn(&str) -> Option<String>,
+) -> HashMap<Role, u64> {
+  [
+    (Role::Admin, "ACCESS_TTL_ADMIN"),
+    (Role::Manager, "ACCESS_TTL_MANAGER"),
+    (Role::Driver, "ACCESS_TTL_DRIVER"),
+    (Role::Customer, "ACCESS_TTL_CUSTOMER"),
+  ]
+  .into_iter()
+  .filter_map(|(role, name)| {
+    let ttl = var(name)?.parse().ok()?;
+    Some((role, ttl))
+  })
+  .collect()
+}
+
+/// Panics if the password hash is requested, as tokens are only signed, not
+/// encrypted.
+fn access_token_claims(value: &str) -> Vec<String> {
+  let claims = comma_separated(value);
+  assert!(
+    !claims.iter().any(|claim| claim == "password_hash"),
+    "ACCESS_TOKEN_CLAIMS must not include password_hash"
+  );
+  claims
+}
+
+fn cors_expose_headers(value: &str) -> Vec<String> {
+  let mut headers = comma_separated(value);
+  if !headers
+    .iter()
+    .any(|header| header.eq_ignore_ascii_case("Location"))
+  {
+    headers.push(String::from("Location"));
+  }
+  headers
+}
+
+/// Reads `route=milliseconds` pairs, e.g. `/v1/auth/login=2000`. Panics on
+/// anything else so a typo doesn't go unnoticed.
+fn slow_request_route_thresholds(value: &str) -> HashMap<String, Duration> {
+  comma_separated(value)
+    .iter()
+    .map(|pair| {
+      let threshold = pair
+        .split_once('=')
+        .and_then(|(route, millis)| Some((route, millis.parse().ok()?)));
+      let Some((route, millis)) = threshold else {
+        panic!(
+          "SLOW_REQUEST_ROUTE_THRESHOLDS_MS: expected `route=milliseconds`, got `{}`",
+          pair
+        );
+      };
+      (route.to_string(), Duration::from_millis(millis))
+    })
+    .collect()
+}
+
+/// Panics on anything but an HMAC algorithm, as only shared secrets are
+/// configured.
+fn jwt_algorithm(value: Option<&str>) -> Algorithm {
+  let Some(value) = value else {
+    return Algorithm::HS256;
+  };
+  match value.parse() {
+    Ok(
+      algorithm @ (Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512),
+    ) => algorithm,
+    _ => panic!(
+      "JWT_ALGORITHM must be HS256, HS384 or HS512, got `{}`",
+      value
+    ),
+  }
+}
+
+/// Panics on an unknown backend or one this binary was built without.
+fn database_backend(value: Option<&str>) -> DatabaseBackend {
+  let Some(value) = value else {
+    return DatabaseBackend::fallback();
+  };
+  let backend: DatabaseBackend = value
+    .parse()
+    .unwrap_or_else(|error| panic!("DATABASE_BACKEND: {}", error));
+  assert!(
+    backend.is_compiled(),
+    "DATABASE_BACKEND `{}` isn't compiled in, enable its cargo feature",
+    value
+  );
+  backend
+}
+
+/// Panics on an unknown role, so a typo doesn't silently close signups.
+fn self_signup_roles(value: &str) -> Vec<Role> {
+  comma_separated(value)
+    .iter()
+    .map(|role| {
+      role
+        .parse()
+        .unwrap_or_else(|error| panic!("SELF_SIGNUP_ALLOWED_ROLES: {}", error))
+    })
+    .collect()
+}
+
+/// Panics on a length below `MIN_NANOID_LENGTH` so a bad setting stops the
+/// service at startup rather than weakening ids.
+fn nanoid_length(value: Option<&str>) -> usize {
+  let Some(value) = value else {
+    return DEFAULT_NANOID_LENGTH;
+  };
+  let length = value.parse().expect("NANOID_LENGTH must be a number");
+  assert!(
+    length >= MIN_NANOID_LENGTH,
+    "NANOID_LENGTH must be at least {}",
+    MIN_NANOID_LENGTH
+  );
+  length
+}
+
+/// Repeated characters are dropped, as they would skew the distribution.
+fn nanoid_alphabet(value: Option<&str>) -> Vec<char> {
+  let Some(value) = value else {
+    return crate::CUSTOM_ALPHABET.clone();
+  };
+  let mut alphabet: Vec<char> = Vec::new();
+  for c in value.chars() {
+    if !alphabet.contains(&c) {
+      alphabet.push(c);
+    }
+  }
+  assert!(
+    (2..=256).contains(&alphabet.len()),
+    "NANOID_ALPHABET must have between 2 and 256 distinct characters"
+  );
+  assert!(
+    alphabet.iter().copied().all(UserId::is_valid_char),
+    "NANOID_ALPHABET must not have whitespace, control characters or `/`"
+  );
+  alphabet
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_comma_separated() {
+    assert_eq!(comma_separated("key"), vec!["key"]);
+    assert_eq!(comma_separated("old, new,"), vec!["old", "new"]);
+    assert!(comma_separated("").is_empty());
+  }
+
+  #[test]
+  #[should_panic(expected = "ACCESS_TOKEN_CLAIMS must not include")]
+  fn test_access_token_claims_exclude_password_hash() {
+    access_token_claims("email,password_hash");
+  }
+
+  #[test]
+  fn test_cors_expose_headers_include_location() {
+    assert_eq!(cors_expose_headers(""), vec!["Location"]);
+    assert_eq!(
+      cors_expose_headers("X-Request-Id"),
+      vec!["X-Request-Id", "Location"]
+    );
+    assert_eq!(
+      cors_expose_headers("location, X-Request-Id"),
+      vec!["location", "X-Request-Id"]
+    );
+  }
+
+  #[test]
+  fn test_slow_request_route_thresholds() {
+    assert!(slow_request_route_thresholds("").is_empty());
+    assert_eq!(
+      slow_request_route_thresholds("/v1/auth/login=2000, /v1/users=1500"),
+      HashMap::from([
+        (String::from("/v1/auth/login"), Duration::from_secs(2)),
+        (String::from("/v1/users"), Duration::from_millis(1500)),
+      ])
+    );
+  }
+
+  #[test]
+  #[should_panic(expected = "expected `route=milliseconds`, got `/v1/users`")]
+  fn test_slow_request_route_thresholds_malformed() {
+    slow_request_route_thresholds("/v1/users");
+  }
+
+  #[test]
+  fn test_jwt_algorithm() {
+    assert_eq!(jwt_algorithm(None), Algorithm::HS256);
+    assert_eq!(jwt_algorithm(Some("HS384")), Algorithm::HS384);
+    assert_eq!(jwt_algorithm(Some("HS512")), Algorithm::HS512);
+  }
+
+  #[test]
+  #[should_panic(expected = "JWT_ALGORITHM must be HS256, HS384 or HS512")]
+  fn test_jwt_algorithm_asymmetric() {
+    jwt_algorithm(Some("RS256"));
+  }
+
+  #[test]
+  fn test_database_backend() {
+    for backend in DatabaseBackend::ALL
+      .into_iter()
+      .filter(DatabaseBackend::is_compiled)
+    {
+      assert_eq!(database_backend(Some(backend.as_str())), backend);
+    }
+    assert_eq!(database_backend(None), DatabaseBackend::fallback());
+  }
+
+  #[test]
+  #[should_panic(expected = "DATABASE_BACKEND `dynamodb` isn't compiled in")]
+  fn test_database_backend_not_compiled() {
+    database_backend(Some("dynamodb"));
+  }
+
+  #[test]
+  #[should_panic(expected = "unknown database backend `postgres`")]
+  fn test_database_backend_unknown() {
+    database_backend(Some("postgres"));
+  }
+
+  #[test]
+  fn test_self_signup_roles() {
+    assert_eq!(self_signup_roles("customer"), vec![Role::Customer]);
+    assert_eq!(
+      self_signup_roles("customer, driver"),
+      vec![Role::Customer, Role::Driver]
+    );
+  }
+
+  #[test]
+  #[should_panic(expected = "SELF_SIGNUP_ALLOWED_ROLES: invalid role `wizard`")]
+  fn test_self_signup_roles_unknown() {
+    self_signup_roles("customer,wizard");
+  }
+
+  #[test]
+  fn test_nanoid_length() {
+    assert_eq!(nanoid_length(None), DEFAULT_NANOID_LENGTH);
+    assert_eq!(nanoid_length(Some("12")), 12);
+  }
+
+  #[test]
+  #[should_panic(expected = "NANOID_LENGTH must be at least 12")]
+  fn test_nanoid_length_too_short() {
+    nanoid_length(Some("11"));
+  }
+
+  #[test]
+  #[should_panic(expected = "NANOID_ALPHABET must not have whitespace")]
+  fn test_nanoid_alphabet_unusable_in_ids() {
+    nanoid_alphabet(Some("abc/"));
+  }
+
+  #[test]
+  fn test_nanoid_alphabet() {
+    assert_eq!(nanoid_alphabet(None), *crate::CUSTOM_ALPHABET);
+    assert_eq!(nanoid_alphabet(Some("abcabd")), vec!['a', 'b', 'c', 'd']);
+  }
+
+  #[test]
+  #[should_panic(expected = "NANOID_ALPHABET must have between 2 and 256")]
+  fn test_nanoid_alphabet_too_small() {
+    nanoid_alphabet(Some("aaa"));
+  }
+
+  #[test]
+  fn test_config_file_vars() {
+    let vars = config_file_vars(
+      "# Comment\n\nMAINTENANCE_MODE=true\n  ACCESS_TTL_ADMIN = 120 \nJUNK\n",
+    );
+    assert_eq!(
+      vars,
+      HashMap::from([
+        (String::from("MAINTENANCE_MODE"), String::from("true")),
+        (String::from("ACCESS_TTL_ADMIN"), String::from("120")),
+      ])
+    );
+  }
+
+  #[actix_web::test]
+  async fn test_reload_updates_shared_config() {
+    let path = env::temp_dir().join(format!("{}.env", crate::custom_nanoid()));
+    let config = Config {
+      config_file: Some(path.to_string_lossy().into_owned()),
+      ..Config::default().await
+    };
+    let worker = config.clone();
+    assert!(!config.maintenance_mode());
+
+    std::fs::write(
+      &path,
+      "MAINTENANCE_MODE=true\nACCESS_TTL_ADMIN=120\nRATE_LIMIT_BURST_SIZE=9\n",
+    )
+    .unwrap();
+    config.reload().unwrap();
+
+    for config in [&config, &worker] {
+      assert!(config.maintenance_mode());
+      assert_eq!(config.access_token_ttl(&Role::Admin), 120);
+      assert_eq!(
+        config.access_token_ttl(&Role::Customer),
+        DEFAULT_ACCESS_TOKEN_TTL
+      );
+      assert_eq!(config.reloadable.load().rate_limit_burst_size, 9);
+      assert_eq!(
+        config.reloadable.load().rate_limit_per_second,
+        DEFAULT_RATE_LIMIT_PER_SECOND
+      );
     }
+
+    // A file that went missing leaves the settings as they were.
+    std::fs::remove_file(&path).unwrap();
+    assert!(config.reload().is_err());
+    assert!(worker.maintenance_mode());
   }
 }
@@ -1,8 +1,19 @@
-use super::config::Config;
+use std::str::FromStr;
+
+use thiserror::Error;
+
+use super::{
+  config::Config,
+  retry::{ErrorClass, RetryPolicy, Retryable},
+};
 
 pub trait Database: Sized {
-  async fn new(config: &Config) -> Option<Self>;
+  async fn new(config: &Config) -> Result<Self, DatabaseError>;
   async fn stats(&self) -> DatabaseStats;
+  /// Creates the indexes the repositories rely on, and brings users stored
+  /// by earlier versions up to date. Safe to run on every startup: existing
+  /// indexes and up to date users are left untouched.
+  async fn ensure_indexes(&self) -> Result<(), DatabaseError>;
 }
 
 pub struct DatabaseStats {
@@ -10,76 +21,388 @@ pub struct DatabaseStats {
   pub name: String,
 }
 
+#[derive(Debug, Error)]
+pub enum DatabaseError {
+  #[error("{0} isn't set")]
+  #[cfg_attr(
+    not(any(feature = "mongodb", all(feature = "dynamodb", not(test)))),
+    allow(dead_code)
+  )]
+  NotConfigured(&'static str),
+  #[error("Failed to connect: {0}")]
+  #[cfg_attr(not(any(feature = "mongodb", test)), allow(dead_code))]
+  Connect(String),
+  #[error("Failed to ensure indexes: {0}")]
+  #[cfg_attr(
+    not(any(feature = "mongodb", all(feature = "dynamodb", not(test)))),
+    allow(dead_code)
+  )]
+  Indexes(String),
+}
+
+impl Retryable for DatabaseError {
+  fn class(&self) -> ErrorClass {
+    match self {
+      DatabaseError::NotConfigured(_) => ErrorClass::Permanent,
+      DatabaseError::Connect(_) | DatabaseError::Indexes(_) => {
+        ErrorClass::Indeterminate
+      }
+    }
+  }
+}
+
 #[cfg(all(feature = "dynamodb", not(test)))]
-pub async fn resolve_database(
-  config: &Config,
-) -> crate::shared::database::DynamoDatabase {
-  crate::shared::database::DynamoDatabase::new(&config)
-    .await
-    .unwrap()
+pub const USERS_TABLE: &str = "users";
+
+/// Global secondary index on `email`, used for lookups by email since the
+/// table itself is keyed by `uuid`.
+#[cfg(all(feature = "dynamodb", not(test)))]
+pub const EMAIL_INDEX: &str = "email-index";
+
+/// Global secondary index on `user_name`, only created and looked up with
+/// `ENFORCE_UNIQUE_USERNAME`.
+#[cfg(all(feature = "dynamodb", not(test)))]
+pub const USER_NAME_INDEX: &str = "user-name-index";
+
+/// How often, and how many times, `ensure_indexes` checks on an index being
+/// built. Indexes on large tables can take a while to backfill.
+#[cfg(all(feature = "dynamodb", not(test)))]
+const INDEX_POLL_INTERVAL: std::time::Duration =
+  std::time::Duration::from_secs(5);
+#[cfg(all(feature = "dynamodb", not(test)))]
+const INDEX_POLLS: u32 = 360;
+
+/// Storage backends, picked at startup with `DATABASE_BACKEND`. Cargo
+/// features decide which are compiled in, so one binary can carry several.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DatabaseBackend {
+  InMemory,
+  MongoDb,
+  DynamoDb,
 }
 
-#[cfg(all(feature = "mongodb", not(test)))]
-pub async fn resolve_database(
-  config: &Config,
-) -> crate::shared::database::MongoDatabase {
-  crate::shared::database::MongoDatabase::new(config)
-    .await
-    .unwrap()
+impl DatabaseBackend {
+  pub const ALL: [DatabaseBackend; 3] = [
+    DatabaseBackend::InMemory,
+    DatabaseBackend::MongoDb,
+    DatabaseBackend::DynamoDb,
+  ];
+
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      DatabaseBackend::InMemory => "in-memory",
+      DatabaseBackend::MongoDb => "mongodb",
+      DatabaseBackend::DynamoDb => "dynamodb",
+    }
+  }
+
+  pub fn is_compiled(&self) -> bool {
+    match self {
+      DatabaseBackend::InMemory => cfg!(any(feature = "in-memory", test)),
+      DatabaseBackend::MongoDb => cfg!(feature = "mongodb"),
+      DatabaseBackend::DynamoDb => cfg!(all(feature = "dynamodb", not(test))),
+    }
+  }
+
+  /// Used without `DATABASE_BACKEND`: the first compiled in of DynamoDB,
+  /// MongoDB and in-memory, so single-backend builds need no setting.
+  pub fn fallback() -> Self {
+    [
+      DatabaseBackend::DynamoDb,
+      DatabaseBackend::MongoDb,
+      DatabaseBackend::InMemory,
+    ]
+    .into_iter()
+    .find(DatabaseBackend::is_compiled)
+    .expect("no database backend is compiled in")
+  }
 }
 
-#[cfg(any(feature = "in-memory", test))]
-pub async fn resolve_database(
+impl FromStr for DatabaseBackend {
+  type Err = String;
+
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    DatabaseBackend::ALL
+      .into_iter()
+      .find(|backend| backend.as_str() == value)
+      .ok_or_else(|| {
+        let allowed: Vec<&str> = DatabaseBackend::ALL
+          .iter()
+          .map(DatabaseBackend::as_str)
+          .collect();
+        format!(
+          "unknown database backend `{}`, expected one of: {}",
+          value,
+          allowed.join(", ")
+        )
+      })
+  }
+}
+
+/// Connects to `DB` and prepares its indexes, trying up to
+/// `Config::database_connect_attempts` times, as the database may still be
+/// starting up alongside the service.
+pub async fn resolve_database<DB: Database>(
   config: &Config,
-) -> crate::shared::database::InMemoryDatabase {
-  crate::shared::database::InMemoryDatabase::new(config)
+) -> Result<DB, DatabaseError> {
+  RetryPolicy::new(config.database_connect_attempts)
+    .retry(|| async {
+      let database = DB::new(config).await?;
+      database.ensure_indexes().await?;
+      Ok(database)
+    })
     .await
-    .unwrap()
 }
 
 #[cfg(all(feature = "dynamodb", not(test)))]
 pub struct DynamoDatabase {
   pub client: std::sync::Arc<aws_sdk_dynamodb::Client>,
+  enforce_unique_username: bool,
+}
+
+#[cfg(all(feature = "dynamodb", not(test)))]
+impl DynamoDatabase {
+  /// Rewrites emails stored as typed, from before they were normalized, so
+  /// lookups by normalized email find them. Nothing enforces uniqueness
+  /// here, so emails differing only in case end up alike, and lookups get
+  /// either until the accounts are merged by hand.
+  async fn normalize_stored_emails(&self) -> Result<(), DatabaseError> {
+    use aws_sdk_dynamodb::types::AttributeValue;
+
+    use super::normalize::normalize_email;
+
+    let error =
+      |error: &dyn std::fmt::Display| DatabaseError::Indexes(error.to_string());
+    let mut start_key = None;
+    loop {
+      let result = self
+        .client
+        .scan()
+        .table_name(USERS_TABLE)
+        .projection_expression("#uuid, #email")
+        .expression_attribute_names("#uuid", "uuid")
+        .expression_attribute_names("#email", "email")
+        .set_exclusive_start_key(start_key)
+        .send()
+        .await
+        .map_err(|e| error(&e))?;
+      for item in result.items.unwrap_or_default() {
+        let (Some(AttributeValue::S(uuid)), Some(AttributeValue::S(email))) =
+          (item.get("uuid"), item.get("email"))
+        else {
+          continue;
+        };
+        let normalized = normalize_email(email);
+        if normalized == *email {
+          continue;
+        }
+        self
+          .client
+          .update_item()
+          .table_name(USERS_TABLE)
+          .key("uuid", AttributeValue::S(uuid.clone()))
+          .update_expression("SET #email = :email")
+          .expression_attribute_names("#email", "email")
+          .expression_attribute_values(":email", AttributeValue::S(normalized))
+          .send()
+          .await
+          .map_err(|e| error(&e))?;
+      }
+      start_key = result.last_evaluated_key;
+      if start_key.is_none() {
+        return Ok(());
+      }
+    }
+  }
 }
 
 #[cfg(all(feature = "dynamodb", not(test)))]
 impl Database for DynamoDatabase {
-  async fn new(_config: &Config) -> Option<Self> {
+  async fn new(config: &Config) -> Result<Self, DatabaseError> {
     let aws_config = aws_config::load_from_env().await;
+    // Every call would fail without one, so refuse to start instead.
+    if aws_config.region().is_none() {
+      return Err(DatabaseError::NotConfigured("AWS_REGION"));
+    }
     let client = aws_sdk_dynamodb::Client::new(&aws_config);
-    Some(Self {
+    Ok(Self {
       client: std::sync::Arc::new(client),
+      enforce_unique_username: config.enforce_unique_username,
     })
   }
   async fn stats(&self) -> DatabaseStats {
     let result = self
       .client
-      .database("admin")
-      .run_command(mongodb::bson::doc! { "ping": 1 })
+      .describe_table()
+      .table_name(USERS_TABLE)
+      .send()
       .await;
     DatabaseStats {
       connected: result.is_ok(),
-      name: String::from("MongoDB"),
+      name: String::from("DynamoDB"),
+    }
+  }
+  async fn ensure_indexes(&self) -> Result<(), DatabaseError> {
+    let error =
+      |error: &dyn std::fmt::Display| DatabaseError::Indexes(error.to_string());
+
+    self.normalize_stored_emails().await?;
+
+    // The table's own key guarantees `uuid` uniqueness. DynamoDB can't
+    // enforce uniqueness on a secondary index, so `email` and `user_name`
+    // rely on the existence checks in `create_user`.
+    let mut indexes = vec![(EMAIL_INDEX, "email")];
+    if self.enforce_unique_username {
+      indexes.push((USER_NAME_INDEX, "user_name"));
+    }
+    for (index, attribute) in indexes {
+      let table = self
+        .client
+        .describe_table()
+        .table_name(USERS_TABLE)
+        .send()
+        .await
+        .map_err(|e| error(&e))?
+        .table
+        .ok_or_else(|| error(&"users table not found"))?;
+      if !table
+        .global_secondary_indexes()
+        .iter()
+        .any(|existing| existing.index_name() == Some(index))
+      {
+        self.create_index(&table, index, attribute).await?;
+      }
+      // Also waits on an index an earlier start left building. DynamoDB
+      // rejects table updates until it's done, and lookups on it fail.
+      self.wait_for_index(index).await?;
     }
+    Ok(())
+  }
+}
+
+#[cfg(all(feature = "dynamodb", not(test)))]
+impl DynamoDatabase {
+  /// Adds a global secondary index keyed by `attribute`, with the table's
+  /// own throughput when it's provisioned.
+  async fn create_index(
+    &self,
+    table: &aws_sdk_dynamodb::types::TableDescription,
+    index: &str,
+    attribute: &str,
+  ) -> Result<(), DatabaseError> {
+    use aws_sdk_dynamodb::types::{
+      AttributeDefinition, BillingMode, CreateGlobalSecondaryIndexAction,
+      GlobalSecondaryIndexUpdate, KeySchemaElement, KeyType, Projection,
+      ProjectionType, ProvisionedThroughput, ScalarAttributeType,
+    };
+
+    let error =
+      |error: &dyn std::fmt::Display| DatabaseError::Indexes(error.to_string());
+
+    let mut index = CreateGlobalSecondaryIndexAction::builder()
+      .index_name(index)
+      .key_schema(
+        KeySchemaElement::builder()
+          .attribute_name(attribute)
+          .key_type(KeyType::Hash)
+          .build()
+          .map_err(|e| error(&e))?,
+      )
+      .projection(
+        Projection::builder()
+          .projection_type(ProjectionType::All)
+          .build(),
+      );
+    let on_demand = table
+      .billing_mode_summary()
+      .and_then(|summary| summary.billing_mode())
+      == Some(&BillingMode::PayPerRequest);
+    if let (false, Some(throughput)) =
+      (on_demand, table.provisioned_throughput())
+    {
+      index = index.provisioned_throughput(
+        ProvisionedThroughput::builder()
+          .read_capacity_units(throughput.read_capacity_units().unwrap_or(1))
+          .write_capacity_units(throughput.write_capacity_units().unwrap_or(1))
+          .build()
+          .map_err(|e| error(&e))?,
+      );
+    }
+
+    self
+      .client
+      .update_table()
+      .table_name(USERS_TABLE)
+      .attribute_definitions(
+        AttributeDefinition::builder()
+          .attribute_name(attribute)
+          .attribute_type(ScalarAttributeType::S)
+          .build()
+          .map_err(|e| error(&e))?,
+      )
+      .global_secondary_index_updates(
+        GlobalSecondaryIndexUpdate::builder()
+          .create(index.build().map_err(|e| error(&e))?)
+          .build(),
+      )
+      .send()
+      .await
+      .map_err(|e| error(&e))?;
+    Ok(())
+  }
+
+  /// Polls until both `index` and the users table are active.
+  async fn wait_for_index(&self, index: &str) -> Result<(), DatabaseError> {
+    use aws_sdk_dynamodb::types::{IndexStatus, TableStatus};
+
+    let error =
+      |error: &dyn std::fmt::Display| DatabaseError::Indexes(error.to_string());
+
+    for _ in 0..INDEX_POLLS {
+      let table = self
+        .client
+        .describe_table()
+        .table_name(USERS_TABLE)
+        .send()
+        .await
+        .map_err(|e| error(&e))?
+        .table
+        .ok_or_else(|| error(&"users table not found"))?;
+      let index_status = table
+        .global_secondary_indexes()
+        .iter()
+        .find(|existing| existing.index_name() == Some(index))
+        .and_then(|existing| existing.index_status());
+      if index_status == Some(&IndexStatus::Active)
+        && table.table_status() == Some(&TableStatus::Active)
+      {
+        return Ok(());
+      }
+      actix_web::rt::time::sleep(INDEX_POLL_INTERVAL).await;
+    }
+    Err(error(&format!("{} is still not active", index)))
   }
 }
 
 #[cfg(feature = "mongodb")]
 pub struct MongoDatabase {
   pub client: mongodb::Client,
+  enforce_unique_username: bool,
 }
 
 #[cfg(feature = "mongodb")]
 impl Database for MongoDatabase {
-  async fn new(_config: &Config) -> Option<Self> {
-    if let Ok(mongo_url) = std::env::var("MONGO_URL") {
-      println!("Starting MongoDB client at {}", mongo_url);
-      return Some(Self {
-        // Create a new MongoDB client with the parsed options
-        client: mongodb::Client::with_uri_str(mongo_url).await.unwrap(),
-      });
-    }
-    None
+  async fn new(config: &Config) -> Result<Self, DatabaseError> {
+    let mongo_url = std::env::var("MONGO_URL")
+      .map_err(|_| DatabaseError::NotConfigured("MONGO_URL"))?;
+    println!("Starting MongoDB client at {}", mongo_url);
+    Ok(Self {
+      // Create a new MongoDB client with the parsed options
+      client: mongodb::Client::with_uri_str(mongo_url)
+        .await
+        .map_err(|error| DatabaseError::Connect(error.to_string()))?,
+      enforce_unique_username: config.enforce_unique_username,
+    })
   }
   async fn stats(&self) -> DatabaseStats {
     let result = self
@@ -92,6 +415,90 @@ impl Database for MongoDatabase {
       name: String::from("MongoDB"),
     }
   }
+  async fn ensure_indexes(&self) -> Result<(), DatabaseError> {
+    use mongodb::{
+      bson::{doc, Document},
+      options::IndexOptions,
+      IndexModel,
+    };
+
+    let users = self.client.database("test").collection::<Document>("users");
+    // Users stored before soft-delete existed have no `deleted_at` at all,
+    // which the partial index below wouldn't cover. Partial filters can't
+    // match a missing field, so the field is written out instead, as new
+    // users always have it.
+    users
+      .update_many(
+        doc! { "deleted_at": { "$exists": false } },
+        doc! { "$set": { "deleted_at": null } },
+      )
+      .await
+      .map_err(|error| DatabaseError::Indexes(error.to_string()))?;
+    // Emails used to be stored as typed, but are now looked up normalized.
+    // Accounts whose emails differ only in case collide on the unique index
+    // and fail this, until merged by hand.
+    users
+      .update_many(
+        doc! { "email": { "$regex": "[A-Z]|^\\s|\\s$" } },
+        vec![doc! {
+          "$set": {
+            "email": { "$toLower": { "$trim": { "input": "$email" } } }
+          }
+        }],
+      )
+      .await
+      .map_err(|error| {
+        DatabaseError::Indexes(format!(
+          "Failed to normalize stored emails: {}",
+          error
+        ))
+      })?;
+
+    let email = IndexModel::builder()
+      .keys(doc! { "email": 1 })
+      .options(
+        IndexOptions::builder()
+          .unique(true)
+          // Soft-deleted users keep their email, which may be reused.
+          .partial_filter_expression(doc! {
+            "deleted_at": { "$type": "null" }
+          })
+          .build(),
+      )
+      .build();
+    let uuid = IndexModel::builder()
+      .keys(doc! { "uuid": 1 })
+      .options(IndexOptions::builder().unique(true).build())
+      .build();
+    // Backs the default `find_all` order.
+    let created_at = IndexModel::builder()
+      .keys(doc! { "created_at": 1, "uuid": 1 })
+      .build();
+
+    let mut indexes = vec![email, uuid, created_at];
+    // Only with the setting on, as names taken twice before would fail it.
+    if self.enforce_unique_username {
+      indexes.push(
+        IndexModel::builder()
+          .keys(doc! { "user_name": 1 })
+          .options(
+            IndexOptions::builder()
+              .unique(true)
+              .partial_filter_expression(doc! {
+                "deleted_at": { "$type": "null" }
+              })
+              .build(),
+          )
+          .build(),
+      );
+    }
+
+    users
+      .create_indexes(indexes)
+      .await
+      .map(|_| ())
+      .map_err(|error| DatabaseError::Indexes(error.to_string()))
+  }
 }
 
 #[cfg(any(feature = "in-memory", test))]
@@ -100,10 +507,37 @@ pub struct InMemoryDatabase {
     std::sync::Arc<std::sync::RwLock<Vec<crate::users::model::user::User>>>,
 }
 
+#[cfg(any(feature = "in-memory", test))]
+impl InMemoryDatabase {
+  /// Locks the users for reading. A panic while the lock was held leaves it
+  /// poisoned, which is ignored rather than failing every later request:
+  /// writers only ever push, replace or remove whole users, so the list is
+  /// never left half updated.
+  pub fn read_users(
+    &self,
+  ) -> std::sync::RwLockReadGuard<'_, Vec<crate::users::model::user::User>> {
+    self
+      .users
+      .read()
+      .unwrap_or_else(std::sync::PoisonError::into_inner)
+  }
+
+  /// Locks the users for writing, recovering from poisoning like
+  /// `read_users`.
+  pub fn write_users(
+    &self,
+  ) -> std::sync::RwLockWriteGuard<'_, Vec<crate::users::model::user::User>> {
+    self
+      .users
+      .write()
+      .unwrap_or_else(std::sync::PoisonError::into_inner)
+  }
+}
+
 #[cfg(any(feature = "in-memory", test))]
 impl Database for InMemoryDatabase {
-  async fn new(_config: &Config) -> Option<Self> {
-    Some(Self {
+  async fn new(_config: &Config) -> Result<Self, DatabaseError> {
+    Ok(Self {
       users: std::sync::Arc::new(std::sync::RwLock::new(Vec::new())),
     })
   }
@@ -113,4 +547,92 @@ impl Database for InMemoryDatabase {
       name: String::from("In-Memory"),
     }
   }
+  async fn ensure_indexes(&self) -> Result<(), DatabaseError> {
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::atomic::{AtomicU32, Ordering};
+
+  #[cfg(feature = "mongodb")]
+  use mongodb::bson::Document;
+
+  #[cfg(feature = "mongodb")]
+  use crate::{
+    helpers::tests::fake_user,
+    users::repository::user_repository::{
+      UserRepository, UserRepositoryError, UserRepositoryImpl,
+    },
+  };
+
+  use super::*;
+
+  static CONNECT_ATTEMPTS: AtomicU32 = AtomicU32::new(0);
+
+  /// Backend whose server never answers.
+  struct UnreachableDatabase;
+
+  impl Database for UnreachableDatabase {
+    async fn new(_config: &Config) -> Result<Self, DatabaseError> {
+      CONNECT_ATTEMPTS.fetch_add(1, Ordering::SeqCst);
+      Err(DatabaseError::Connect(String::from("connection refused")))
+    }
+    async fn stats(&self) -> DatabaseStats {
+      unreachable!()
+    }
+    async fn ensure_indexes(&self) -> Result<(), DatabaseError> {
+      unreachable!()
+    }
+  }
+
+  #[actix_web::test]
+  async fn test_resolve_database_gives_up_after_attempts() {
+    let config = Config {
+      database_connect_attempts: 3,
+      ..Config::default().await
+    };
+
+    let result = resolve_database::<UnreachableDatabase>(&config).await;
+    assert!(matches!(result, Err(DatabaseError::Connect(_))));
+    assert_eq!(CONNECT_ATTEMPTS.load(Ordering::SeqCst), 3);
+  }
+
+  #[cfg(feature = "mongodb")]
+  #[actix_web::test]
+  #[ignore = "requires a MongoDB instance at MONGO_URL"]
+  async fn test_mongo_indexes_exist_after_startup() {
+    let config = Config::default().await;
+    let database = MongoDatabase::new(&config)
+      .await
+      .expect("MONGO_URL must be set");
+
+    database.ensure_indexes().await.unwrap();
+    // Running it again on an already prepared collection is a no-op.
+    database.ensure_indexes().await.unwrap();
+
+    let names = database
+      .client
+      .database("test")
+      .collection::<Document>("users")
+      .list_index_names()
+      .await
+      .unwrap();
+    assert!(names.contains(&String::from("email_1")));
+    assert!(names.contains(&String::from("uuid_1")));
+    assert!(names.contains(&String::from("created_at_1_uuid_1")));
+
+    // A sign-up racing past the availability check is refused by the index.
+    let user_repository =
+      UserRepositoryImpl::new(std::sync::Arc::new(database));
+    let user = fake_user();
+    let mut twin = fake_user();
+    twin.email = user.email.clone();
+    user_repository.create(user).await.unwrap();
+    assert!(matches!(
+      user_repository.create(twin).await,
+      Err(UserRepositoryError::EmailTaken)
+    ));
+  }
 }
@@ -1,6 +1,14 @@
 use actix_web::{web, HttpResponse, Responder};
 
+use super::clock::Clock;
+use super::config::Config;
+use super::hash_worker::Hasher;
 use super::health_check::{HealthCheck, HealthCheckStats};
+use super::role::Role;
+use super::rto::detailed_health_rto::{
+  DetailedHealthRto, HashWorkerHealthRto, RateLimiterHealthRto,
+};
+use super::rto::readiness_rto::ReadinessRto;
 
 #[utoipa::path(
   post,
@@ -14,3 +22,262 @@ pub async fn check_health<HC: HealthCheck>(
 ) -> impl Responder {
   HttpResponse::Ok().json(check_health.collect())
 }
+
+#[utoipa::path(
+  get,
+  path = "/health/detailed",
+  responses(
+    (status = 200, description = "Database, password hashing and rate limiting status, for operators", body = DetailedHealthRto)
+  )
+)]
+pub async fn check_health_detailed<HC: HealthCheck, H: Hasher>(
+  config: web::Data<Config>,
+  health_check: web::Data<HC>,
+  hasher: web::Data<H>,
+) -> impl Responder {
+  let reloadable = config.reloadable.load();
+  HttpResponse::Ok().json(DetailedHealthRto {
+    stats: health_check.collect(),
+    hash_worker: HashWorkerHealthRto {
+      queue_depth: hasher.queue_depth(),
+      threads: hasher.num_threads(),
+      saturated: hasher.saturated_for().is_some(),
+    },
+    rate_limiter: RateLimiterHealthRto {
+      // The limiter always guards the auth routes, there's no switch yet.
+      active: true,
+      trust_proxy: config.trust_proxy,
+      requests_per_second: reloadable.rate_limit_per_second,
+      burst_size: reloadable.rate_limit_burst_size,
+    },
+  })
+}
+
+#[utoipa::path(
+  get,
+  path = "/health/ready",
+  responses(
+    (status = 200, description = "Ready to accept auth traffic", body = ReadinessRto),
+    (status = 503, description = "Health checks are stale or password hashing is backed up, route traffic elsewhere", body = ReadinessRto)
+  )
+)]
+pub async fn check_readiness<HC: HealthCheck, H: Hasher, C: Clock>(
+  config: web::Data<Config>,
+  clock: web::Data<C>,
+  health_check: web::Data<HC>,
+  hasher: web::Data<H>,
+) -> impl Responder {
+  // Missing two collections in a row means the checker is wedged and its
+  // stats can't be trusted.
+  let stale_after = chrono::Duration::from_std(health_check.interval() * 2)
+    .unwrap_or(chrono::Duration::MAX);
+  let stats_fresh = health_check
+    .collect()
+    .is_some_and(|stats| clock.now() - stats.updated_at <= stale_after);
+  // A briefly full queue is normal under bursts, only a sustained one means
+  // requests are timing out.
+  let hash_queue_saturated =
+    hasher.saturated_for().is_some_and(|saturated_for| {
+      saturated_for >= config.hash_queue_saturation_threshold
+    });
+
+  let reason = if !stats_fresh {
+    Some("Health check stats are stale")
+  } else if hasher.live_threads() == 0 {
+    Some("No hash worker threads left")
+  } else if hash_queue_saturated {
+    Some("Hash queue saturated")
+  } else {
+    None
+  };
+  match reason {
+    Some(reason) => HttpResponse::ServiceUnavailable().json(ReadinessRto {
+      ready: false,
+      reason: Some(String::from(reason)),
+    }),
+    None => HttpResponse::Ok().json(ReadinessRto {
+      ready: true,
+      reason: None,
+    }),
+  }
+}
+
+#[utoipa::path(
+  get,
+  path = "/roles",
+  responses(
+    (status = 200, description = "Every role a user can have, e.g. to fill a role picker", body = Vec<Role>)
+  )
+)]
+pub async fn list_roles() -> impl Responder {
+  HttpResponse::Ok().json(Role::ALL)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use actix_web::{http::StatusCode, test::TestRequest};
+  use chrono::Utc;
+  use rayon::ThreadPoolBuilder;
+
+  use crate::{
+    helpers::tests::parse_http_response,
+    shared::{
+      clock::FixedClock,
+      hash_worker::{HashWorker, MockHasher},
+      health_check::MockHealthCheck,
+    },
+  };
+
+  use super::*;
+
+  const INTERVAL: Duration = Duration::from_secs(60);
+
+  /// Health check whose stats were last collected `age` ago.
+  fn health_check(age: Duration) -> MockHealthCheck {
+    let updated_at = Utc::now() - chrono::Duration::from_std(age).unwrap();
+    let mut health_check = MockHealthCheck::new();
+    health_check.expect_collect().returning(move || {
+      Some(HealthCheckStats {
+        database_status: String::from("connected"),
+        database_name: String::from("In-Memory"),
+        updated_at,
+      })
+    });
+    health_check.expect_interval().return_const(INTERVAL);
+    health_check
+  }
+
+  async fn readiness<H: Hasher>(
+    threshold: Duration,
+    health_check: MockHealthCheck,
+    hasher: H,
+    status_code: StatusCode,
+  ) -> ReadinessRto {
+    let config = Config {
+      hash_queue_saturation_threshold: threshold,
+      ..Config::default().await
+    };
+    let request = TestRequest::default().to_http_request();
+    let responder = check_readiness(
+      web::Data::new(config),
+      web::Data::new(FixedClock::new(Utc::now())),
+      web::Data::new(health_check),
+      web::Data::new(hasher),
+    )
+    .await;
+    parse_http_response(responder, &request, status_code).await
+  }
+
+  fn idle_hasher() -> HashWorker {
+    HashWorker::new(ThreadPoolBuilder::new().build().unwrap(), 1)
+  }
+
+  #[actix_web::test]
+  async fn test_saturated_queue_flips_readiness() {
+    let fresh = health_check(Duration::ZERO);
+    let rto =
+      readiness(Duration::ZERO, fresh, idle_hasher(), StatusCode::OK).await;
+    assert!(rto.ready);
+
+    let mut stalled = MockHasher::new();
+    stalled.expect_live_threads().return_const(1u32);
+    stalled
+      .expect_saturated_for()
+      .returning(|| Some(Duration::from_secs(1)));
+    let fresh = health_check(Duration::ZERO);
+    let rto = readiness(
+      Duration::ZERO,
+      fresh,
+      stalled,
+      StatusCode::SERVICE_UNAVAILABLE,
+    )
+    .await;
+    assert!(!rto.ready);
+    assert_eq!(rto.reason.as_deref(), Some("Hash queue saturated"));
+  }
+
+  #[actix_web::test]
+  async fn test_dead_hash_workers_flip_readiness() {
+    let dead = HashWorker::new(ThreadPoolBuilder::new().build().unwrap(), 0);
+    let fresh = health_check(Duration::ZERO);
+    let rto =
+      readiness(Duration::ZERO, fresh, dead, StatusCode::SERVICE_UNAVAILABLE)
+        .await;
+    assert_eq!(rto.reason.as_deref(), Some("No hash worker threads left"));
+  }
+
+  #[actix_web::test]
+  async fn test_briefly_saturated_queue_stays_ready() {
+    let mut hasher = MockHasher::new();
+    hasher.expect_live_threads().return_const(1u32);
+    hasher
+      .expect_saturated_for()
+      .returning(|| Some(Duration::from_secs(1)));
+    let fresh = health_check(Duration::ZERO);
+    let rto =
+      readiness(Duration::from_secs(5), fresh, hasher, StatusCode::OK).await;
+    assert!(rto.ready);
+  }
+
+  #[actix_web::test]
+  async fn test_stale_health_check_flips_readiness() {
+    let threshold = Duration::from_secs(5);
+    let late = health_check(INTERVAL * 2 - Duration::from_secs(1));
+    let rto = readiness(threshold, late, idle_hasher(), StatusCode::OK).await;
+    assert!(rto.ready);
+
+    let stale = health_check(INTERVAL * 2 + Duration::from_secs(1));
+    let rto = readiness(
+      threshold,
+      stale,
+      idle_hasher(),
+      StatusCode::SERVICE_UNAVAILABLE,
+    )
+    .await;
+    assert_eq!(rto.reason.as_deref(), Some("Health check stats are stale"));
+  }
+
+  #[actix_web::test]
+  async fn test_detailed_health_reports_hash_worker() {
+    let config = Config {
+      trust_proxy: true,
+      ..Config::default().await
+    };
+    let mut hasher = MockHasher::new();
+    hasher.expect_queue_depth().return_const(3usize);
+    hasher.expect_num_threads().return_const(2u32);
+    hasher.expect_saturated_for().returning(|| None);
+    let request = TestRequest::default().to_http_request();
+
+    let responder = check_health_detailed(
+      web::Data::new(config),
+      web::Data::new(health_check(Duration::ZERO)),
+      web::Data::new(hasher),
+    )
+    .await;
+
+    let rto: DetailedHealthRto =
+      parse_http_response(responder, &request, StatusCode::OK).await;
+    assert!(rto.stats.is_some());
+    assert_eq!(
+      rto.hash_worker,
+      HashWorkerHealthRto {
+        queue_depth: 3,
+        threads: 2,
+        saturated: false,
+      }
+    );
+    assert!(rto.rate_limiter.active);
+    assert!(rto.rate_limiter.trust_proxy);
+  }
+
+  #[actix_web::test]
+  async fn test_list_roles() {
+    let request = TestRequest::default().to_http_request();
+    let roles: Vec<String> =
+      parse_http_response(list_roles().await, &request, StatusCode::OK).await;
+    assert_eq!(roles, ["admin", "manager", "driver", "customer"]);
+  }
+}
@@ -1,5 +1,5 @@
 use async_trait::async_trait;
-use bcrypt::{hash, verify, BcryptError, DEFAULT_COST};
+use bcrypt::{hash, verify, BcryptError, HashParts, DEFAULT_COST};
 // use scrypt::{
 //   password_hash::{
 //       rand_core::OsRng,
@@ -9,7 +9,9 @@ use bcrypt::{hash, verify, BcryptError, DEFAULT_COST};
 // };
 use flume;
 use rayon::ThreadPool;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -22,16 +24,43 @@ pub enum HashWorkerError {
   Send,
   #[error("Channel receive error")]
   Receive,
+  #[error("No hash worker threads left")]
+  NoWorkers,
 }
 
 enum WorkOrder {
   Hash(String, flume::Sender<Result<String, HashWorkerError>>),
   Verify(String, String, flume::Sender<Result<bool, HashWorkerError>>),
+  /// Stops the worker that takes it, standing in for a crashed thread.
+  #[cfg(test)]
+  Exit,
+}
+
+/// Held by each run-loop, so a thread counts as gone however it ends,
+/// panics included.
+struct LiveThread(Arc<AtomicU32>);
+
+impl Drop for LiveThread {
+  fn drop(&mut self) {
+    self.0.fetch_sub(1, Ordering::SeqCst);
+  }
+}
+
+/// Whether `hash` has weaker parameters than new hashes get, or isn't a
+/// bcrypt hash at all. Only a login, which has the plaintext, can replace it.
+pub fn is_outdated(hash: &str) -> bool {
+  !matches!(
+    hash.parse::<HashParts>(),
+    Ok(parts) if parts.get_cost() >= DEFAULT_COST
+  )
 }
 
 // Define the Worker struct that implements the Hasher trait
 pub struct HashWorker {
   sender: flume::Sender<WorkOrder>,
+  num_threads: u32,
+  live_threads: Arc<AtomicU32>,
+  saturated_since: Mutex<Option<Instant>>,
 }
 
 impl HashWorker {
@@ -44,13 +73,18 @@ impl HashWorker {
     // Create a channel for communication between async tasks and threads
     let (tx, rx) = flume::bounded::<WorkOrder>(channels_capacity as usize);
     let rx = Arc::new(rx);
+    // Counted up front rather than as each loop starts, so a pool that is
+    // still spinning up isn't mistaken for a dead one.
+    let live_threads = Arc::new(AtomicU32::new(num_threads));
 
     // Spin up a thread pool for CPU-bound tasks based on the number of required works.
     for _ in 0..num_threads {
       // Dispatch the run-loop.
       thread_pool.spawn({
         let arc_rx = Arc::clone(&rx);
+        let live_thread = LiveThread(Arc::clone(&live_threads));
         move || {
+          let _live_thread = live_thread;
           while let Ok(work_order) = arc_rx.recv() {
             match work_order {
               WorkOrder::Hash(password, response) => {
@@ -78,13 +112,40 @@ impl HashWorker {
                 //   });
                 // let _ = response.send(result);
               }
+              #[cfg(test)]
+              WorkOrder::Exit => return,
             };
           }
         }
       });
     }
 
-    Self { sender: tx }
+    Self {
+      sender: tx,
+      num_threads,
+      live_threads,
+      saturated_since: Mutex::new(None),
+    }
+  }
+
+  /// Fails straight away once every worker is gone, as nothing would ever
+  /// take the order off the queue.
+  fn ensure_live(&self) -> Result<(), HashWorkerError> {
+    match self.live_threads.load(Ordering::SeqCst) {
+      0 => Err(HashWorkerError::NoWorkers),
+      _ => Ok(()),
+    }
+  }
+
+  /// Sampled on every enqueue and readiness probe, so a queue that stays
+  /// full between samples counts as full throughout.
+  fn sample_saturation(&self) -> Option<Duration> {
+    let mut saturated_since = self.saturated_since.lock().unwrap();
+    if !self.sender.is_full() {
+      *saturated_since = None;
+      return None;
+    }
+    Some(saturated_since.get_or_insert_with(Instant::now).elapsed())
   }
 }
 
@@ -102,6 +163,20 @@ pub trait Hasher {
     password: &str,
     hash: &str,
   ) -> Result<bool, HashWorkerError>;
+  /// Verifies each `(password, hash)` pair, returning results in order.
+  // Meant for bulk tooling such as migrations, which no handler needs yet.
+  #[allow(dead_code)]
+  async fn verify_many(
+    &self,
+    pairs: &[(String, String)],
+  ) -> Vec<Result<bool, HashWorkerError>>;
+  /// How long the work queue has been full, if it currently is.
+  fn saturated_for(&self) -> Option<Duration>;
+  /// Work orders waiting for a free worker.
+  fn queue_depth(&self) -> usize;
+  fn num_threads(&self) -> u32;
+  /// Worker threads still running, below `num_threads` if any died.
+  fn live_threads(&self) -> u32;
 }
 
 #[async_trait]
@@ -110,7 +185,9 @@ impl Hasher for HashWorker {
     &self,
     password: &str,
   ) -> Result<String, HashWorkerError> {
+    self.ensure_live()?;
     let (response_tx, response_rx) = flume::bounded(1);
+    self.sample_saturation();
     self
       .sender
       .send_async(WorkOrder::Hash(password.to_string(), response_tx))
@@ -128,7 +205,9 @@ impl Hasher for HashWorker {
     password: &str,
     hash: &str,
   ) -> Result<bool, HashWorkerError> {
+    self.ensure_live()?;
     let (response_tx, response_rx) = flume::bounded(1);
+    self.sample_saturation();
     self
       .sender
       .send_async(WorkOrder::Verify(
@@ -144,6 +223,61 @@ impl Hasher for HashWorker {
       .await
       .map_err(|_| HashWorkerError::Receive)?
   }
+
+  async fn verify_many(
+    &self,
+    pairs: &[(String, String)],
+  ) -> Vec<Result<bool, HashWorkerError>> {
+    // Every pair is queued before waiting on any result, so they're spread
+    // across all workers instead of verified one at a time.
+    let mut responses = Vec::with_capacity(pairs.len());
+    for (password, hash) in pairs {
+      if let Err(error) = self.ensure_live() {
+        responses.push(Err(error));
+        continue;
+      }
+      let (response_tx, response_rx) = flume::bounded(1);
+      self.sample_saturation();
+      let sent = self
+        .sender
+        .send_async(WorkOrder::Verify(
+          password.clone(),
+          hash.clone(),
+          response_tx,
+        ))
+        .await;
+      responses
+        .push(sent.map(|_| response_rx).map_err(|_| HashWorkerError::Send));
+    }
+
+    let mut results = Vec::with_capacity(pairs.len());
+    for response in responses {
+      results.push(match response {
+        Ok(response_rx) => response_rx
+          .recv_async()
+          .await
+          .unwrap_or(Err(HashWorkerError::Receive)),
+        Err(error) => Err(error),
+      });
+    }
+    results
+  }
+
+  fn saturated_for(&self) -> Option<Duration> {
+    self.sample_saturation()
+  }
+
+  fn queue_depth(&self) -> usize {
+    self.sender.len()
+  }
+
+  fn num_threads(&self) -> u32 {
+    self.num_threads
+  }
+
+  fn live_threads(&self) -> u32 {
+    self.live_threads.load(Ordering::SeqCst)
+  }
 }
 
 #[cfg(test)]
@@ -152,6 +286,16 @@ mod tests {
   use fake::{faker::internet::en::Password, Fake};
   use rayon::ThreadPoolBuilder;
 
+  #[test]
+  fn test_is_outdated() {
+    let weak = hash("password", 4).unwrap();
+    let current = weak.replacen("$04$", &format!("${}$", DEFAULT_COST), 1);
+
+    assert!(is_outdated(&weak));
+    assert!(!is_outdated(&current));
+    assert!(is_outdated("not a bcrypt hash"));
+  }
+
   #[actix_web::test]
   async fn test_hash_and_verify_password() {
     // Create a thread pool with 4 threads
@@ -191,4 +335,70 @@ mod tests {
     // Assert that the verification fails for an incorrect password
     assert!(!is_invalid, "The password verification should have failed");
   }
+
+  #[actix_web::test]
+  async fn test_verify_many() {
+    let hash_worker =
+      HashWorker::new(ThreadPoolBuilder::new().build().unwrap(), 4);
+    let first = Password(12..13).fake::<String>();
+    let second = Password(12..13).fake::<String>();
+    let first_hash = hash_worker.hash_password(&first).await.unwrap();
+    let second_hash = hash_worker.hash_password(&second).await.unwrap();
+
+    let results = hash_worker
+      .verify_many(&[
+        (first.clone(), first_hash.clone()),
+        (second.clone(), first_hash),
+        (second, second_hash),
+        (first, String::from("not a hash")),
+      ])
+      .await;
+
+    assert_eq!(results.len(), 4);
+    assert!(results[0].as_ref().unwrap());
+    assert!(!results[1].as_ref().unwrap());
+    assert!(results[2].as_ref().unwrap());
+    assert!(matches!(results[3], Err(HashWorkerError::Bcrypt(_))));
+  }
+
+  #[actix_web::test]
+  async fn test_saturated_for() {
+    let idle = HashWorker::new(ThreadPoolBuilder::new().build().unwrap(), 1);
+    assert_eq!(idle.saturated_for(), None);
+
+    // Without workers nothing is ever taken off the queue.
+    let stalled = HashWorker::new(ThreadPoolBuilder::new().build().unwrap(), 0);
+    let first = stalled.saturated_for().unwrap();
+    std::thread::sleep(Duration::from_millis(10));
+    assert!(
+      stalled.saturated_for().unwrap() >= first + Duration::from_millis(10)
+    );
+  }
+
+  #[actix_web::test]
+  async fn test_fails_fast_without_live_workers() {
+    let hash_worker =
+      HashWorker::new(ThreadPoolBuilder::new().build().unwrap(), 2);
+    for _ in 0..2 {
+      hash_worker.sender.send(WorkOrder::Exit).unwrap();
+    }
+    while hash_worker.live_threads() > 0 {
+      actix_web::rt::time::sleep(Duration::from_millis(1)).await;
+    }
+
+    let hashed = actix_web::rt::time::timeout(
+      Duration::from_secs(1),
+      hash_worker.hash_password("password"),
+    )
+    .await
+    .expect("Hashing should fail rather than hang");
+    assert!(matches!(hashed, Err(HashWorkerError::NoWorkers)));
+    let verified = actix_web::rt::time::timeout(
+      Duration::from_secs(1),
+      hash_worker.verify_password("password", "hash"),
+    )
+    .await
+    .expect("Verification should fail rather than hang");
+    assert!(matches!(verified, Err(HashWorkerError::NoWorkers)));
+  }
 }
@@ -4,41 +4,77 @@ use std::{
 };
 
 use actix_web::rt::spawn;
-use actix_web::rt::time::interval;
+use actix_web::rt::time::{interval, sleep};
+use chrono::{DateTime, Utc};
 use mockall::automock;
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 
 use super::database::Database;
 
+pub const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+/// First delay before collecting again after `stats()` panicked, doubled on
+/// each consecutive panic up to the collection interval.
+const PANIC_BACKOFF: Duration = Duration::from_secs(1);
+
 #[derive(ToSchema, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct HealthCheckStats {
   pub database_status: String,
   pub database_name: String,
+  /// When these stats were collected, so stale ones can be told apart.
+  #[schema(value_type = String, format = DateTime)]
+  pub updated_at: DateTime<Utc>,
 }
 
 #[automock]
 pub trait HealthCheck {
   fn collect(&self) -> Option<HealthCheckStats>;
+  /// How often stats are refreshed.
+  fn interval(&self) -> Duration;
 }
 
 pub struct HealthCheckImpl {
   last_health_check_stats: Arc<RwLock<Option<HealthCheckStats>>>,
+  interval: Duration,
 }
 
 impl HealthCheckImpl {
   pub fn new<DB: Database + Send + 'static>(database: Arc<DB>) -> Self {
-    let database = database.clone();
+    Self::with_interval(database, HEALTH_CHECK_INTERVAL)
+  }
+
+  pub fn with_interval<DB: Database + Send + 'static>(
+    database: Arc<DB>,
+    every: Duration,
+  ) -> Self {
     let stats_storage: Arc<RwLock<Option<HealthCheckStats>>> =
       Arc::new(RwLock::new(None));
 
     spawn({
       let stats_storage = stats_storage.clone();
       async move {
-        let mut interval = interval(Duration::from_secs(60));
+        let mut interval = interval(every);
+        let mut backoff = PANIC_BACKOFF.min(every);
         loop {
           interval.tick().await;
-          let database_stats = database.stats().await;
+          // Collected on its own task so a panic in `stats()` is caught
+          // here instead of ending this loop.
+          let database_stats = loop {
+            let database = database.clone();
+            match spawn(async move { database.stats().await }).await {
+              Ok(database_stats) => break database_stats,
+              Err(_) => {
+                eprintln!(
+                  "Health check panicked, collecting again in {:?}",
+                  backoff
+                );
+                sleep(backoff).await;
+                backoff = (backoff * 2).min(every);
+              }
+            }
+          };
+          backoff = PANIC_BACKOFF.min(every);
           let mut stats = stats_storage.write().unwrap();
           *stats = Some(HealthCheckStats {
             database_status: String::from(if database_stats.connected {
@@ -47,6 +83,7 @@ impl HealthCheckImpl {
               "connecting"
             }),
             database_name: database_stats.name,
+            updated_at: Utc::now(),
           });
         }
       }
@@ -54,6 +91,7 @@ impl HealthCheckImpl {
 
     Self {
       last_health_check_stats: stats_storage.clone(),
+      interval: every,
     }
   }
 }
@@ -66,4 +104,69 @@ impl HealthCheck for HealthCheckImpl {
       .ok()
       .and_then(|stats| stats.clone())
   }
+
+  fn interval(&self) -> Duration {
+    self.interval
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::atomic::{AtomicU32, Ordering};
+
+  use crate::shared::{
+    config::Config,
+    database::{DatabaseError, DatabaseStats},
+  };
+
+  use super::*;
+
+  /// Database whose `stats()` panics on its first `panics` calls.
+  struct PanickingDatabase {
+    panics: u32,
+    calls: AtomicU32,
+  }
+
+  impl Database for PanickingDatabase {
+    async fn new(_config: &Config) -> Result<Self, DatabaseError> {
+      Err(DatabaseError::Connect(String::from("unused")))
+    }
+
+    async fn stats(&self) -> DatabaseStats {
+      if self.calls.fetch_add(1, Ordering::SeqCst) < self.panics {
+        panic!("stats failed");
+      }
+      DatabaseStats {
+        connected: true,
+        name: String::from("Panicking"),
+      }
+    }
+
+    async fn ensure_indexes(&self) -> Result<(), DatabaseError> {
+      Ok(())
+    }
+  }
+
+  #[actix_web::test]
+  async fn test_panicking_stats_do_not_wedge_checker() {
+    let database = Arc::new(PanickingDatabase {
+      panics: 2,
+      calls: AtomicU32::new(0),
+    });
+    let health_check = HealthCheckImpl::with_interval(
+      database.clone(),
+      Duration::from_millis(5),
+    );
+
+    for _ in 0..100 {
+      if health_check.collect().is_some() {
+        break;
+      }
+      sleep(Duration::from_millis(5)).await;
+    }
+
+    let stats = health_check.collect().expect("checker never recovered");
+    assert_eq!(stats.database_name, "Panicking");
+    assert!(database.calls.load(Ordering::SeqCst) > 2);
+  }
 }
@@ -1,14 +1,254 @@
+use actix_web::{
+  error::{InternalError, JsonPayloadError, UrlencodedError},
+  HttpRequest, HttpResponse,
+};
+use std::{borrow::Cow, collections::BTreeMap};
+
 use serde::{Deserialize, Serialize};
+use validator::{ValidationError, ValidationErrors};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HttpError {
+  /// Human readable, may change wording at any time.
   pub message: String,
+  /// Stable identifier for clients to branch on.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub code: Option<ErrorCode>,
+  /// Messages of each rule a field broke, keyed by its JSON name, e.g.
+  /// `{ "email": ["invalid email"], "password": ["too short"] }`. Rules spanning
+  /// several fields are under `body`. Only set on validation failures.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub errors: Option<FieldErrors>,
+}
+
+pub type FieldErrors = BTreeMap<String, Vec<String>>;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+  Unauthorized,
+  MalformedBody,
+  ValidationFailed,
+  UserAlreadyExists,
+  UserNameTaken,
+  UserNotFound,
+  IdempotencyKeyInUse,
+  IdempotencyKeyMismatch,
+  InvalidCursor,
+  InvalidPassword,
+  InvalidField,
+  InvalidCsrfToken,
+  AccountDisabled,
+  EmailNotVerified,
+  PayloadTooLarge,
+  HashingFailed,
+  RoleNotAllowed,
+  PasswordChangeRequired,
+  TokenGenerationFailed,
+  ServerBusy,
+  PermissionDenied,
+  MaintenanceMode,
+  DatabaseTimeout,
+}
+
+impl HttpError {
+  pub fn with_code(mut self, code: ErrorCode) -> Self {
+    self.code = Some(code);
+    self
+  }
 }
 
 impl From<&str> for HttpError {
   fn from(message: &str) -> Self {
     Self {
       message: String::from(message),
+      code: None,
+      errors: None,
     }
   }
 }
+
+impl From<ValidationErrors> for HttpError {
+  fn from(errors: ValidationErrors) -> Self {
+    Self {
+      errors: Some(field_errors(&errors)),
+      ..HttpError::from(errors.to_string().as_str())
+        .with_code(ErrorCode::ValidationFailed)
+    }
+  }
+}
+
+/// Flattens `validator`'s output, which names fields as in Rust, to the
+/// names clients send. A rule without a message is reported by its code.
+fn field_errors(errors: &ValidationErrors) -> FieldErrors {
+  errors
+    .field_errors()
+    .into_iter()
+    .map(|(field, errors)| {
+      let field = match field {
+        "__all__" => String::from("body"),
+        field => camel_case(field),
+      };
+      let messages = errors
+        .iter()
+        .map(|error| error.message.as_ref().unwrap_or(&error.code).to_string())
+        .collect();
+      (field, messages)
+    })
+    .collect()
+}
+
+/// `user_name` to `userName`, matching the DTOs' serde renames.
+fn camel_case(field: &str) -> String {
+  let mut words = field.split('_');
+  let first = words.next().unwrap_or_default().to_string();
+  words.fold(first, |mut name, word| {
+    let mut chars = word.chars();
+    if let Some(c) = chars.next() {
+      name.extend(c.to_uppercase());
+      name.push_str(chars.as_str());
+    }
+    name
+  })
+}
+
+/// Response to a password hash or verification that errored. The cause is
+/// only logged, as it says nothing actionable to the client.
+pub fn hashing_failed() -> HttpResponse {
+  HttpResponse::InternalServerError()
+    .content_type("application/json")
+    .json(
+      HttpError::from("Password hashing failed")
+        .with_code(ErrorCode::HashingFailed),
+    )
+}
+
+/// Response to a new password found by `is_breached`, reported like a
+/// validation failure of `field`.
+pub fn breached_password(field: &'static str) -> HttpResponse {
+  let mut errors = ValidationErrors::new();
+  errors.add(
+    field,
+    ValidationError::new("breached").with_message(Cow::from(
+      "Appears in a known data breach, pick another password",
+    )),
+  );
+  HttpResponse::UnprocessableEntity().json(HttpError::from(errors))
+}
+
+/// Response to tokens that couldn't be signed for a user who otherwise
+/// authenticated. Callers log the cause.
+pub fn token_generation_failed() -> HttpResponse {
+  HttpResponse::InternalServerError()
+    .content_type("application/json")
+    .json(
+      HttpError::from("Tokens could not be generated")
+        .with_code(ErrorCode::TokenGenerationFailed),
+    )
+}
+
+/// Response to a database call that didn't finish within
+/// `Config::database_query_timeout`. Callers log the cause.
+pub fn database_timeout() -> HttpResponse {
+  HttpResponse::ServiceUnavailable()
+    .content_type("application/json")
+    .json(
+      HttpError::from("Database unavailable, retry later")
+        .with_code(ErrorCode::DatabaseTimeout),
+    )
+}
+
+/// Replaces actix's plain text response to bodies that can't be
+/// deserialized, so they're told apart from validation failures.
+pub fn json_error_handler(
+  error: JsonPayloadError,
+  _request: &HttpRequest,
+) -> actix_web::Error {
+  let response = match error {
+    JsonPayloadError::OverflowKnownLength { .. }
+    | JsonPayloadError::Overflow { .. } => HttpResponse::PayloadTooLarge()
+      .json(
+        HttpError::from(error.to_string().as_str())
+          .with_code(ErrorCode::PayloadTooLarge),
+      ),
+    _ => HttpResponse::BadRequest().json(
+      HttpError::from(error.to_string().as_str())
+        .with_code(ErrorCode::MalformedBody),
+    ),
+  };
+  InternalError::from_response(error, response).into()
+}
+
+/// `json_error_handler` for form bodies, such as OAuth2 token requests.
+pub fn form_error_handler(
+  error: UrlencodedError,
+  _request: &HttpRequest,
+) -> actix_web::Error {
+  let response = match error {
+    UrlencodedError::Overflow { .. } => HttpResponse::PayloadTooLarge().json(
+      HttpError::from(error.to_string().as_str())
+        .with_code(ErrorCode::PayloadTooLarge),
+    ),
+    _ => HttpResponse::BadRequest().json(
+      HttpError::from(error.to_string().as_str())
+        .with_code(ErrorCode::MalformedBody),
+    ),
+  };
+  InternalError::from_response(error, response).into()
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::shared::role::Role;
+  use crate::users::dto::create_user_dto::CreateUserDto;
+  use validator::Validate;
+
+  use super::*;
+
+  #[test]
+  fn test_validation_errors_keyed_by_json_field() {
+    let dto = CreateUserDto {
+      email: String::from("invalid_email"),
+      user_name: String::new(),
+      password: String::from("password"),
+      password_confirm: None,
+      role: Role::Customer,
+    };
+    let error = HttpError::from(dto.validate().unwrap_err());
+
+    assert_eq!(error.code, Some(ErrorCode::ValidationFailed));
+    assert_eq!(
+      error.errors,
+      Some(BTreeMap::from([
+        (String::from("email"), vec![String::from("invalid email")]),
+        (
+          String::from("userName"),
+          vec![String::from("User name must have at least 1 characters")]
+        ),
+      ]))
+    );
+
+    // Schema rules only run once every field passes.
+    let dto = CreateUserDto {
+      email: String::from("jane.doe@example.com"),
+      user_name: String::from("Jane Doe"),
+      password_confirm: Some(String::from("other")),
+      ..dto
+    };
+    let error = HttpError::from(dto.validate().unwrap_err());
+    assert_eq!(
+      error.errors,
+      Some(BTreeMap::from([(
+        String::from("body"),
+        vec![String::from("passwordConfirm must match password")]
+      )]))
+    );
+  }
+
+  #[test]
+  fn test_camel_case() {
+    assert_eq!(camel_case("email"), "email");
+    assert_eq!(camel_case("user_name"), "userName");
+    assert_eq!(camel_case("must_change_password"), "mustChangePassword");
+  }
+}
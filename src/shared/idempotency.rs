@@ -0,0 +1,225 @@
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+use chrono::{DateTime, Utc};
+
+use super::sweeper::Expiring;
+
+const MAX_ENTRIES: usize = 10_000;
+
+/// Results of requests that carried an `Idempotency-Key`, so a client
+/// retrying after a network failure gets the original result back instead
+/// of the request being processed twice. Keys are scoped per endpoint.
+/// Shared by all workers, unlike the user cache.
+pub struct IdempotencyStore<T: Clone> {
+  ttl: chrono::Duration,
+  /// Keyed by the endpoint the key was sent to and the key itself.
+  entries: Mutex<HashMap<(&'static str, String), Entry<T>>>,
+}
+
+struct Entry<T> {
+  /// Fingerprint of the body the key was first sent with.
+  fingerprint: u64,
+  /// Unset while the request is still in progress.
+  result: Option<T>,
+  expires_at: DateTime<Utc>,
+}
+
+pub enum Reservation<'a, T: Clone> {
+  /// First use of the key, the request should be processed.
+  Reserved(ReservedKey<'a, T>),
+  /// An earlier request with the key is still being processed.
+  InProgress,
+  /// The key was first sent with another body.
+  Mismatch,
+  /// An earlier request with the key completed with this result.
+  Completed(T),
+}
+
+/// A key claimed by the request being processed. Dropped without being
+/// completed, as when the request fails, is cancelled or panics, it frees
+/// the key so a retry is processed rather than rejected.
+pub struct ReservedKey<'a, T: Clone> {
+  store: &'a IdempotencyStore<T>,
+  scope: &'static str,
+  key: String,
+}
+
+impl<T: Clone> ReservedKey<'_, T> {
+  /// Records the result of the request, to be replayed to its retries.
+  pub fn complete(self, result: T, now: DateTime<Utc>) {
+    let mut entries = self.store.entries.lock().unwrap();
+    if let Some(entry) = entries.get_mut(&(self.scope, self.key.clone())) {
+      entry.result = Some(result);
+      entry.expires_at = now + self.store.ttl;
+    }
+    drop(entries);
+    std::mem::forget(self);
+  }
+}
+
+impl<T: Clone> Drop for ReservedKey<'_, T> {
+  fn drop(&mut self) {
+    let mut entries = self
+      .store
+      .entries
+      .lock()
+      .unwrap_or_else(std::sync::PoisonError::into_inner);
+    let key = (self.scope, std::mem::take(&mut self.key));
+    if entries
+      .get(&key)
+      .is_some_and(|entry| entry.result.is_none())
+    {
+      entries.remove(&key);
+    }
+  }
+}
+
+impl<T: Clone> IdempotencyStore<T> {
+  pub fn new(ttl: Duration) -> Self {
+    Self {
+      ttl: chrono::Duration::from_std(ttl).unwrap_or(chrono::Duration::MAX),
+      entries: Mutex::new(HashMap::new()),
+    }
+  }
+
+  /// Claims `key` for a request about to be processed, unless an earlier
+  /// request already did. `fingerprint` identifies the body, which a key
+  /// must always be sent with.
+  pub fn reserve(
+    &self,
+    scope: &'static str,
+    key: &str,
+    fingerprint: u64,
+    now: DateTime<Utc>,
+  ) -> Reservation<'_, T> {
+    let mut entries = self.entries.lock().unwrap();
+    if let Some(entry) = entries
+      .get(&(scope, key.to_string()))
+      .filter(|entry| entry.expires_at > now)
+    {
+      return match &entry.result {
+        _ if entry.fingerprint != fingerprint => Reservation::Mismatch,
+        Some(result) => Reservation::Completed(result.clone()),
+        None => Reservation::InProgress,
+      };
+    }
+    if entries.len() >= MAX_ENTRIES {
+      entries.retain(|_, entry| entry.expires_at > now);
+    }
+    // Keys still in progress are kept, their requests would otherwise
+    // complete unprotected. They're bounded by the requests in flight.
+    if entries.len() >= MAX_ENTRIES {
+      entries.retain(|_, entry| entry.result.is_none());
+    }
+    entries.insert(
+      (scope, key.to_string()),
+      Entry {
+        fingerprint,
+        result: None,
+        expires_at: now + self.ttl,
+      },
+    );
+    Reservation::Reserved(ReservedKey {
+      store: self,
+      scope,
+      key: key.to_string(),
+    })
+  }
+}
+
+impl<T: Clone> Expiring for IdempotencyStore<T> {
+  fn sweep(&self, now: DateTime<Utc>) {
+    self
+      .entries
+      .lock()
+      .unwrap()
+      .retain(|_, entry| entry.expires_at > now);
+  }
+}
+
+#[cfg(test)]
+impl<T: Clone> IdempotencyStore<T> {
+  pub fn len(&self) -> usize {
+    self.entries.lock().unwrap().len()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const SCOPE: &str = "test";
+
+  #[test]
+  fn test_reserve_complete_and_expire() {
+    let store = IdempotencyStore::new(Duration::from_secs(60));
+    let now = Utc::now();
+
+    let Reservation::Reserved(reserved) = store.reserve(SCOPE, "key", 1, now)
+    else {
+      panic!("First use of the key");
+    };
+    assert!(matches!(
+      store.reserve(SCOPE, "key", 1, now),
+      Reservation::InProgress
+    ));
+    // Scopes don't share keys.
+    assert!(matches!(
+      store.reserve("other", "key", 1, now),
+      Reservation::Reserved(_)
+    ));
+
+    reserved.complete(7, now);
+    assert!(matches!(
+      store.reserve(SCOPE, "key", 1, now),
+      Reservation::Completed(7)
+    ));
+    assert!(matches!(
+      store.reserve(SCOPE, "key", 2, now),
+      Reservation::Mismatch
+    ));
+
+    let later = now + chrono::Duration::seconds(60);
+    assert!(matches!(
+      store.reserve(SCOPE, "key", 2, later),
+      Reservation::Reserved(_)
+    ));
+  }
+
+  #[test]
+  fn test_dropped_reservation_frees_key() {
+    let store = IdempotencyStore::<u32>::new(Duration::from_secs(60));
+    let now = Utc::now();
+
+    drop(store.reserve(SCOPE, "key", 1, now));
+
+    assert!(matches!(
+      store.reserve(SCOPE, "key", 1, now),
+      Reservation::Reserved(_)
+    ));
+  }
+
+  #[test]
+  fn test_full_store_keeps_keys_in_progress() {
+    let store = IdempotencyStore::<u32>::new(Duration::from_secs(60));
+    let now = Utc::now();
+    let in_progress = store.reserve(SCOPE, "in-progress", 1, now);
+    for key in 1..MAX_ENTRIES {
+      let Reservation::Reserved(reserved) =
+        store.reserve(SCOPE, &key.to_string(), 1, now)
+      else {
+        panic!("First use of the key");
+      };
+      reserved.complete(0, now);
+    }
+    assert_eq!(store.len(), MAX_ENTRIES);
+
+    drop(store.reserve(SCOPE, "overflow", 1, now));
+
+    assert!(matches!(
+      store.reserve(SCOPE, "in-progress", 1, now),
+      Reservation::InProgress
+    ));
+    drop(in_progress);
+  }
+}
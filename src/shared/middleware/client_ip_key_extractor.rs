@@ -0,0 +1,128 @@
+use std::net::IpAddr;
+
+use actix_governor::{KeyExtractor, SimpleKeyExtractionError};
+use actix_web::dev::ServiceRequest;
+
+/// Rate-limit key for the client's IP. Behind a reverse proxy the peer is
+/// always the proxy, so with `trust_proxy` the address it forwards is used
+/// instead. Off by default, as anyone can set those headers when talking to
+/// us directly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientIpKeyExtractor {
+  trust_proxy: bool,
+}
+
+impl ClientIpKeyExtractor {
+  pub fn new(trust_proxy: bool) -> Self {
+    Self { trust_proxy }
+  }
+
+  /// The last `X-Forwarded-For` entry is the one our proxy appended, so
+  /// it's the only one a client can't forge.
+  fn forwarded_ip(request: &ServiceRequest) -> Option<IpAddr> {
+    let headers = request.headers();
+    headers
+      .get("X-Forwarded-For")
+      .and_then(|value| value.to_str().ok())
+      .and_then(|value| value.rsplit(',').next())
+      .or_else(|| {
+        headers
+          .get("X-Real-IP")
+          .and_then(|value| value.to_str().ok())
+      })
+      .and_then(|ip| ip.trim().parse().ok())
+  }
+}
+
+impl KeyExtractor for ClientIpKeyExtractor {
+  type Key = IpAddr;
+  type KeyExtractionError = SimpleKeyExtractionError<&'static str>;
+
+  fn extract(
+    &self,
+    request: &ServiceRequest,
+  ) -> Result<Self::Key, Self::KeyExtractionError> {
+    let ip = self
+      .trust_proxy
+      .then(|| Self::forwarded_ip(request))
+      .flatten()
+      .or_else(|| request.peer_addr().map(|socket| socket.ip()))
+      .ok_or_else(|| {
+        SimpleKeyExtractionError::new(
+          "Could not extract client IP address from request",
+        )
+      })?;
+    // Like `PeerIpKeyExtractor`, limit IPv6 clients per /56 prefix as
+    // they're often handed a whole one.
+    Ok(match ip {
+      IpAddr::V6(ipv6) => {
+        let mut octets = ipv6.octets();
+        octets[7..16].fill(0);
+        IpAddr::V6(octets.into())
+      }
+      ip => ip,
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{net::SocketAddr, str::FromStr};
+
+  use actix_governor::{Governor, GovernorConfigBuilder};
+  use actix_web::{
+    http::StatusCode,
+    test::{call_service, init_service, TestRequest},
+    web, App, HttpResponse,
+  };
+
+  use super::*;
+
+  /// Status of one request per forwarded IP, all from the same peer, to an
+  /// app allowing a single request per key.
+  async fn statuses(trust_proxy: bool, ips: &[&str]) -> Vec<StatusCode> {
+    let governor_config = GovernorConfigBuilder::default()
+      .key_extractor(ClientIpKeyExtractor::new(trust_proxy))
+      .seconds_per_request(60)
+      .burst_size(1)
+      .finish()
+      .unwrap();
+    let app = init_service(
+      App::new()
+        .wrap(Governor::new(&governor_config))
+        .route("/", web::get().to(HttpResponse::Ok)),
+    )
+    .await;
+
+    let mut statuses = Vec::new();
+    for ip in ips {
+      let request = TestRequest::get()
+        .uri("/")
+        .peer_addr(SocketAddr::from_str("10.0.0.1:12345").unwrap())
+        .append_header(("X-Forwarded-For", format!("192.0.2.99, {}", ip)))
+        .to_request();
+      statuses.push(call_service(&app, request).await.status());
+    }
+    statuses
+  }
+
+  #[actix_web::test]
+  async fn test_trusted_forwarded_ips_get_separate_buckets() {
+    assert_eq!(
+      statuses(true, &["203.0.113.1", "203.0.113.2", "203.0.113.1"]).await,
+      vec![
+        StatusCode::OK,
+        StatusCode::OK,
+        StatusCode::TOO_MANY_REQUESTS
+      ]
+    );
+  }
+
+  #[actix_web::test]
+  async fn test_forwarded_ips_ignored_without_trust() {
+    assert_eq!(
+      statuses(false, &["203.0.113.1", "203.0.113.2"]).await,
+      vec![StatusCode::OK, StatusCode::TOO_MANY_REQUESTS]
+    );
+  }
+}
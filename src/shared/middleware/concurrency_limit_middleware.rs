@@ -0,0 +1,147 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use actix_web::{
+  body::{BoxBody, MessageBody},
+  dev::{ServiceRequest, ServiceResponse},
+  http::header,
+  middleware::Next,
+  web, Error, HttpResponse,
+};
+
+use crate::shared::http_error::{ErrorCode, HttpError};
+
+/// Seconds refused clients are told to wait. Slots free up as soon as any
+/// request completes, so the shortest wait `Retry-After` can express.
+const RETRY_AFTER_SECONDS: u64 = 1;
+
+/// Requests being handled across all workers, up to `max`.
+pub struct ConcurrencyLimit {
+  max: usize,
+  in_flight: AtomicUsize,
+}
+
+impl ConcurrencyLimit {
+  pub fn new(max: usize) -> Self {
+    Self {
+      max,
+      in_flight: AtomicUsize::new(0),
+    }
+  }
+
+  fn try_acquire(&self) -> Option<Permit<'_>> {
+    self
+      .in_flight
+      .fetch_update(Ordering::AcqRel, Ordering::Acquire, |in_flight| {
+        (in_flight < self.max).then_some(in_flight + 1)
+      })
+      .ok()
+      .map(|_| Permit(self))
+  }
+}
+
+/// Frees its slot once the response is built, or the request is dropped.
+struct Permit<'a>(&'a ConcurrencyLimit);
+
+impl Drop for Permit<'_> {
+  fn drop(&mut self) {
+    self.0.in_flight.fetch_sub(1, Ordering::AcqRel);
+  }
+}
+
+/// Refuses requests with a 503 while the `ConcurrencyLimit` in app data is
+/// used up, so a flood can't queue unbounded work on the hash workers.
+/// Does nothing without one.
+pub async fn limit_concurrency(
+  req: ServiceRequest,
+  next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+  let Some(concurrency_limit) =
+    req.app_data::<web::Data<ConcurrencyLimit>>().cloned()
+  else {
+    return Ok(next.call(req).await?.map_into_boxed_body());
+  };
+
+  let Some(_permit) = concurrency_limit.try_acquire() else {
+    return Ok(
+      req.into_response(
+        HttpResponse::ServiceUnavailable()
+          .content_type("application/json")
+          .insert_header((header::RETRY_AFTER, RETRY_AFTER_SECONDS))
+          .json(
+            HttpError::from("Too many requests in flight, retry later")
+              .with_code(ErrorCode::ServerBusy),
+          ),
+      ),
+    );
+  };
+  Ok(next.call(req).await?.map_into_boxed_body())
+}
+
+#[cfg(test)]
+mod tests {
+  use std::rc::Rc;
+
+  use actix_web::{
+    http::StatusCode,
+    middleware::from_fn,
+    rt::spawn,
+    test::{call_service, init_service, TestRequest},
+    App,
+  };
+
+  use super::*;
+
+  #[actix_web::test]
+  async fn test_requests_over_limit_refused() {
+    // Requests report reaching the handler, then are held there until a
+    // message arrives, to keep them in flight.
+    let (entered, reached) = flume::unbounded::<()>();
+    let (release, held) = flume::unbounded::<()>();
+    let app = Rc::new(
+      init_service(
+        App::new()
+          .app_data(web::Data::new(ConcurrencyLimit::new(2)))
+          .app_data(web::Data::new((entered, held)))
+          .wrap(from_fn(limit_concurrency))
+          .route(
+            "/held",
+            web::get().to(
+              |channels: web::Data<(
+                flume::Sender<()>,
+                flume::Receiver<()>,
+              )>| async move {
+                let (entered, held) = channels.as_ref();
+                entered.send(()).unwrap();
+                held.recv_async().await.unwrap();
+                HttpResponse::Ok().finish()
+              },
+            ),
+          ),
+      )
+      .await,
+    );
+    let get = || {
+      let app = app.clone();
+      async move {
+        call_service(&*app, TestRequest::get().uri("/held").to_request()).await
+      }
+    };
+
+    let within_limit = [spawn(get()), spawn(get())];
+    for _ in &within_limit {
+      reached.recv_async().await.unwrap();
+    }
+    let refused = get().await;
+    assert_eq!(refused.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(refused.headers().get(header::RETRY_AFTER).unwrap(), "1");
+
+    for request in within_limit {
+      release.send(()).unwrap();
+      assert_eq!(request.await.unwrap().status(), StatusCode::OK);
+    }
+
+    // Slots are given back once requests complete.
+    release.send(()).unwrap();
+    assert_eq!(get().await.status(), StatusCode::OK);
+  }
+}
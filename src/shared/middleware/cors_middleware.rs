@@ -0,0 +1,167 @@
+use actix_web::{
+  body::{BoxBody, MessageBody},
+  dev::{ServiceRequest, ServiceResponse},
+  http::{
+    header::{self, HeaderMap, HeaderValue},
+    Method,
+  },
+  middleware::Next,
+  web, Error, HttpResponse,
+};
+
+use crate::shared::config::Config;
+
+const ALLOWED_METHODS: &str = "GET, HEAD, POST, PUT, PATCH, DELETE";
+
+/// Answers preflights and marks responses as readable by the origins in
+/// `Config::cors_allowed_origins`. Requests from any other origin, or
+/// without one, pass through untouched.
+pub async fn cors(
+  req: ServiceRequest,
+  next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+  let origin = req
+    .app_data::<web::Data<Config>>()
+    .zip(req.headers().get(header::ORIGIN))
+    .filter(|(config, origin)| origin_allowed(config, origin))
+    .map(|(config, origin)| (config.clone(), origin.clone()));
+  let Some((config, origin)) = origin else {
+    return Ok(next.call(req).await?.map_into_boxed_body());
+  };
+
+  let preflight = req.method() == Method::OPTIONS
+    && req
+      .headers()
+      .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD);
+  if !preflight {
+    let mut response = next.call(req).await?.map_into_boxed_body();
+    allow_origin(&config, origin, response.headers_mut());
+    return Ok(response);
+  }
+
+  let mut response = HttpResponse::NoContent();
+  response
+    .insert_header((header::ACCESS_CONTROL_ALLOW_METHODS, ALLOWED_METHODS));
+  if let Some(headers) =
+    req.headers().get(header::ACCESS_CONTROL_REQUEST_HEADERS)
+  {
+    response
+      .insert_header((header::ACCESS_CONTROL_ALLOW_HEADERS, headers.clone()));
+  }
+  if let Some(max_age) = config.cors_max_age {
+    response.insert_header((header::ACCESS_CONTROL_MAX_AGE, max_age.as_secs()));
+  }
+  let mut response = req.into_response(response.finish());
+  allow_origin(&config, origin, response.headers_mut());
+  Ok(response)
+}
+
+fn origin_allowed(config: &Config, origin: &HeaderValue) -> bool {
+  config
+    .cors_allowed_origins
+    .iter()
+    .any(|allowed| allowed == "*" || origin == allowed.as_str())
+}
+
+fn allow_origin(config: &Config, origin: HeaderValue, headers: &mut HeaderMap) {
+  headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+  headers.append(header::VARY, HeaderValue::from_static("Origin"));
+  if let Ok(expose_headers) =
+    HeaderValue::from_str(&config.cors_expose_headers.join(", "))
+  {
+    headers.insert(header::ACCESS_CONTROL_EXPOSE_HEADERS, expose_headers);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use actix_web::{
+    middleware::from_fn,
+    test::{call_service, init_service, TestRequest},
+    App,
+  };
+
+  use super::*;
+
+  async fn app_config() -> Config {
+    Config {
+      cors_allowed_origins: vec![String::from("https://app.example.com")],
+      cors_expose_headers: vec![
+        String::from("X-Request-Id"),
+        String::from("Location"),
+      ],
+      cors_max_age: Some(Duration::from_secs(600)),
+      ..Config::default().await
+    }
+  }
+
+  #[actix_web::test]
+  async fn test_preflight_carries_expose_headers_and_max_age() {
+    let app = init_service(
+      App::new()
+        .app_data(web::Data::new(app_config().await))
+        .wrap(from_fn(cors))
+        .route("/", web::post().to(HttpResponse::Created)),
+    )
+    .await;
+
+    let request = TestRequest::default()
+      .method(Method::OPTIONS)
+      .uri("/")
+      .insert_header((header::ORIGIN, "https://app.example.com"))
+      .insert_header((header::ACCESS_CONTROL_REQUEST_METHOD, "POST"))
+      .insert_header((header::ACCESS_CONTROL_REQUEST_HEADERS, "content-type"))
+      .to_request();
+    let response = call_service(&app, request).await;
+
+    let headers = response.headers();
+    assert_eq!(
+      headers.get(header::ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(),
+      "https://app.example.com"
+    );
+    assert_eq!(
+      headers.get(header::ACCESS_CONTROL_EXPOSE_HEADERS).unwrap(),
+      "X-Request-Id, Location"
+    );
+    assert_eq!(headers.get(header::ACCESS_CONTROL_MAX_AGE).unwrap(), "600");
+    assert_eq!(
+      headers.get(header::ACCESS_CONTROL_ALLOW_HEADERS).unwrap(),
+      "content-type"
+    );
+  }
+
+  #[actix_web::test]
+  async fn test_cors_headers_only_for_allowed_origins() {
+    let app = init_service(
+      App::new()
+        .app_data(web::Data::new(app_config().await))
+        .wrap(from_fn(cors))
+        .route("/", web::post().to(HttpResponse::Created)),
+    )
+    .await;
+
+    let request = TestRequest::post()
+      .uri("/")
+      .insert_header((header::ORIGIN, "https://app.example.com"))
+      .to_request();
+    let response = call_service(&app, request).await;
+    assert_eq!(
+      response
+        .headers()
+        .get(header::ACCESS_CONTROL_EXPOSE_HEADERS)
+        .unwrap(),
+      "X-Request-Id, Location"
+    );
+
+    let request = TestRequest::post()
+      .uri("/")
+      .insert_header((header::ORIGIN, "https://evil.example.com"))
+      .to_request();
+    let response = call_service(&app, request).await;
+    assert!(!response
+      .headers()
+      .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+  }
+}
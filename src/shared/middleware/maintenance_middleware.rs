@@ -0,0 +1,104 @@
+use actix_web::{
+  body::{BoxBody, MessageBody},
+  dev::{ServiceRequest, ServiceResponse},
+  middleware::Next,
+  web, Error, HttpResponse,
+};
+
+use crate::shared::{
+  config::Config,
+  http_error::{ErrorCode, HttpError},
+};
+
+/// Auth routes that only read users, so logins keep working while writes
+/// are blocked.
+const READ_ONLY_AUTH_ROUTES: [&str; 5] = [
+  "/v1/auth/login",
+  "/v1/auth/refresh",
+  "/v1/auth/access-token",
+  "/v1/auth/token",
+  "/v1/auth/validate",
+];
+
+/// Refuses requests that may write with a 503 while the `Config` in app
+/// data is in maintenance mode, which a SIGHUP reload can toggle. Reads and
+/// logins go through. Does nothing without a config.
+pub async fn block_writes_in_maintenance(
+  req: ServiceRequest,
+  next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+  let blocked = req
+    .app_data::<web::Data<Config>>()
+    .is_some_and(|config| config.maintenance_mode())
+    && !req.method().is_safe()
+    && !READ_ONLY_AUTH_ROUTES.contains(&req.path());
+  if blocked {
+    return Ok(
+      req.into_response(
+        HttpResponse::ServiceUnavailable()
+          .content_type("application/json")
+          .json(
+            HttpError::from(
+              "Down for maintenance, only reads and logins are available",
+            )
+            .with_code(ErrorCode::MaintenanceMode),
+          ),
+      ),
+    );
+  }
+  Ok(next.call(req).await?.map_into_boxed_body())
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::Arc;
+
+  use actix_web::{
+    http::StatusCode,
+    middleware::from_fn,
+    test::{call_service, init_service, TestRequest},
+    App,
+  };
+
+  use arc_swap::ArcSwap;
+
+  use crate::shared::config::ReloadableConfig;
+
+  use super::*;
+
+  #[actix_web::test]
+  async fn test_maintenance_blocks_writes_only() {
+    for (enabled, write_status) in [
+      (true, StatusCode::SERVICE_UNAVAILABLE),
+      (false, StatusCode::OK),
+    ] {
+      let config = Config {
+        reloadable: Arc::new(ArcSwap::from_pointee(ReloadableConfig {
+          maintenance_mode: enabled,
+          ..ReloadableConfig::default()
+        })),
+        ..Config::default().await
+      };
+      let app = init_service(
+        App::new()
+          .app_data(web::Data::new(config))
+          .wrap(from_fn(block_writes_in_maintenance))
+          .route("/v1/users", web::get().to(HttpResponse::Ok))
+          .route("/v1/users", web::post().to(HttpResponse::Ok))
+          .route("/v1/users/{uuid}", web::delete().to(HttpResponse::Ok))
+          .route("/v1/auth/login", web::post().to(HttpResponse::Ok)),
+      )
+      .await;
+
+      for (request, status) in [
+        (TestRequest::get().uri("/v1/users"), StatusCode::OK),
+        (TestRequest::post().uri("/v1/auth/login"), StatusCode::OK),
+        (TestRequest::post().uri("/v1/users"), write_status),
+        (TestRequest::delete().uri("/v1/users/abc"), write_status),
+      ] {
+        let response = call_service(&app, request.to_request()).await;
+        assert_eq!(response.status(), status);
+      }
+    }
+  }
+}
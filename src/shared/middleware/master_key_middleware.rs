@@ -1,15 +1,38 @@
 use std::sync::Arc;
 
-use actix_web::{dev::ServiceRequest, error, Error};
+use actix_web::{
+  dev::ServiceRequest,
+  error::{self, InternalError},
+  web, Error, HttpMessage,
+};
 use actix_web_httpauth::extractors::bearer::BearerAuth;
-use subtle::ConstantTimeEq;
+use sha2::{Digest, Sha256};
+use subtle::{Choice, ConstantTimeEq};
 
-use crate::shared::config::Config;
+use crate::{
+  auth::handlers::authorize_access_token,
+  shared::{
+    audit_log::AuditActor,
+    clock::Clock,
+    config::Config,
+    http_error::database_timeout,
+    permission::{GrantedPermissions, Permission},
+  },
+  users::repository::user_repository::UserRepository,
+};
 
 /// Validator that:
-/// - accepts Bearer auth;
+/// - accepts Bearer auth, with a master key or a user's access token;
 /// - returns a custom response for requests without a valid Bearer Authorization header;
-pub async fn bearer_validator(
+/// - refuses access tokens of users since disabled or deleted, or revoked;
+/// - grants master keys every permission, and users those of their role;
+/// - attributes audit entries of accepted requests to the system actor or the user.
+///
+/// Reads the `UR` repository and `C` clock from the app data.
+pub async fn bearer_validator<
+  UR: UserRepository + 'static,
+  C: Clock + 'static,
+>(
   req: ServiceRequest,
   credentials: Option<BearerAuth>,
   config: Arc<Config>,
@@ -17,12 +40,191 @@ pub async fn bearer_validator(
   let Some(credentials) = credentials else {
     return Err((error::ErrorBadRequest("no bearer header"), req));
   };
-  if !constant_time_compare(credentials.token(), &config.master_key) {
-    return Err((error::ErrorBadRequest("Missing bearer token"), req));
-  }
+  let (actor, permissions) =
+    if matches_any_key(credentials.token(), &config.master_keys) {
+      (config.system_actor.clone(), &Permission::ALL[..])
+    } else {
+      let (Some(user_repository), Some(clock)) = (
+        req.app_data::<web::Data<UR>>().cloned(),
+        req.app_data::<web::Data<C>>().cloned(),
+      ) else {
+        return Err((
+          error::ErrorInternalServerError("Users can't be authenticated"),
+          req,
+        ));
+      };
+      match authorize_access_token(
+        &config,
+        clock.now(),
+        user_repository.as_ref(),
+        credentials.token(),
+      )
+      .await
+      {
+        Ok(Some((claims, _))) => {
+          (claims.uuid.to_string(), claims.role.permissions())
+        }
+        Ok(None) => {
+          return Err((error::ErrorBadRequest("Missing bearer token"), req))
+        }
+        Err(error) => {
+          eprintln!("{}", error);
+          let response = database_timeout();
+          return Err((
+            InternalError::from_response(error, response).into(),
+            req,
+          ));
+        }
+      }
+    };
+  req.extensions_mut().insert(AuditActor(actor));
+  req.extensions_mut().insert(GrantedPermissions(permissions));
   Ok(req)
 }
 
-fn constant_time_compare(a: &str, b: &str) -> bool {
-  a.as_bytes().ct_eq(b.as_bytes()).unwrap_u8() == 1
+/// Compares against every key, without stopping at a match, so timing
+/// doesn't reveal which key was used. Digests are compared rather than the
+/// raw values, as `ct_eq` returns early on a length mismatch.
+fn matches_any_key(token: &str, keys: &[String]) -> bool {
+  let token = Sha256::digest(token);
+  keys
+    .iter()
+    .fold(Choice::from(0), |matched, key| {
+      matched | token.ct_eq(&Sha256::digest(key))
+    })
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+  use actix_web::{
+    http::{header, StatusCode},
+    test::{call_service, init_service, TestRequest},
+    web, App, HttpResponse,
+  };
+  use actix_web_httpauth::middleware::HttpAuthentication;
+  use chrono::Utc;
+
+  use crate::{
+    auth::handlers::generate_token_pair,
+    helpers::tests::fake_user,
+    shared::{
+      clock::{FixedClock, SystemClock},
+      database::InMemoryDatabase,
+    },
+    users::repository::user_repository::UserRepositoryImpl,
+  };
+
+  use super::*;
+
+  #[actix_web::test]
+  async fn test_bearer_validator_accepts_every_configured_key() {
+    let config = Arc::new(Config {
+      master_keys: vec![String::from("OLD_KEY"), String::from("NEW_KEY")],
+      ..Config::default().await
+    });
+    let app = init_service(
+      App::new()
+        .app_data(web::Data::new(UserRepositoryImpl::new(Arc::new(
+          InMemoryDatabase {
+            users: Default::default(),
+          },
+        ))))
+        .app_data(web::Data::new(SystemClock))
+        .wrap(HttpAuthentication::with_fn(move |req, credentials| {
+          bearer_validator::<UserRepositoryImpl<InMemoryDatabase>, SystemClock>(
+            req,
+            credentials,
+            config.clone(),
+          )
+        }))
+        .route("/", web::get().to(HttpResponse::Ok)),
+    )
+    .await;
+
+    for (token, status) in [
+      ("OLD_KEY", StatusCode::OK),
+      ("NEW_KEY", StatusCode::OK),
+      ("WRONG_KEY", StatusCode::BAD_REQUEST),
+      ("OLD_KEY,NEW_KEY", StatusCode::BAD_REQUEST),
+    ] {
+      let request = TestRequest::get()
+        .uri("/")
+        .append_header((header::AUTHORIZATION, format!("Bearer {}", token)))
+        .to_request();
+      let response = call_service(&app, request).await;
+      assert_eq!(response.status(), status, "token {}", token);
+    }
+  }
+
+  #[actix_web::test]
+  async fn test_bearer_validator_rejects_expired_access_token() {
+    let config = Arc::new(Config::default().await);
+    let clock = web::Data::new(FixedClock::new(Utc::now()));
+    let user = fake_user();
+    let access_token = generate_token_pair(&config, clock.now(), user.clone())
+      .unwrap()
+      .access_token;
+    let app = init_service(
+      App::new()
+        .app_data(web::Data::new(UserRepositoryImpl::new(Arc::new(
+          InMemoryDatabase {
+            users: Arc::new(std::sync::RwLock::new(vec![user.clone()])),
+          },
+        ))))
+        .app_data(clock.clone())
+        .wrap(HttpAuthentication::with_fn({
+          let config = config.clone();
+          move |req, credentials| {
+            bearer_validator::<UserRepositoryImpl<InMemoryDatabase>, FixedClock>(
+              req,
+              credentials,
+              config.clone(),
+            )
+          }
+        }))
+        .route("/", web::get().to(HttpResponse::Ok)),
+    )
+    .await;
+    let request = || {
+      TestRequest::get()
+        .uri("/")
+        .append_header((
+          header::AUTHORIZATION,
+          format!("Bearer {}", access_token),
+        ))
+        .to_request()
+    };
+
+    // Still accepted within the leeway past `exp`.
+    let lifetime =
+      config.access_token_ttl(&user.role) + config.jwt_leeway_seconds;
+    clock.advance(chrono::Duration::seconds(lifetime as i64));
+    let response = call_service(&app, request()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    clock.advance(chrono::Duration::seconds(1));
+    let response = call_service(&app, request()).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+  }
+
+  #[test]
+  fn test_matches_any_key() {
+    let keys = vec![String::from("a"), String::from("b")];
+    assert!(matches_any_key("a", &keys));
+    assert!(matches_any_key("b", &keys));
+    assert!(!matches_any_key("c", &keys));
+    assert!(!matches_any_key("a", &[]));
+  }
+
+  #[test]
+  fn test_matches_any_key_ignores_length() {
+    let keys = vec![String::from("MASTER_KEY")];
+    assert!(!matches_any_key("MASTER_KE", &keys));
+    assert!(!matches_any_key("MASTER_KEY_", &keys));
+    assert!(!matches_any_key("", &keys));
+    // The lengths compared never depend on the inputs, so there's no length
+    // check for `ct_eq` to exit early on.
+    assert_eq!(Sha256::digest("").len(), Sha256::digest(&keys[0]).len());
+  }
 }
@@ -1 +1,9 @@
+pub mod client_ip_key_extractor;
+pub mod concurrency_limit_middleware;
+pub mod cors_middleware;
+pub mod maintenance_middleware;
 pub mod master_key_middleware;
+pub mod permission_middleware;
+pub mod problem_json_middleware;
+pub mod rate_limit_middleware;
+pub mod slow_request_middleware;
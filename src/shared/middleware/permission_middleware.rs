@@ -0,0 +1,170 @@
+use actix_web::{
+  body::BoxBody,
+  dev::{Service, ServiceRequest, ServiceResponse, Transform},
+  middleware::{from_fn, Next},
+  Error, HttpMessage, HttpResponse,
+};
+
+use crate::shared::{
+  http_error::{ErrorCode, HttpError},
+  permission::{GrantedPermissions, Permission},
+};
+
+/// Route middleware letting through requests the authentication middleware
+/// granted `permission`, and refusing the rest with a 403.
+pub fn require_permission<S>(
+  permission: Permission,
+) -> impl Transform<
+  S,
+  ServiceRequest,
+  Response = ServiceResponse<BoxBody>,
+  Error = Error,
+  InitError = (),
+>
+where
+  S: Service<ServiceRequest, Response = ServiceResponse<BoxBody>, Error = Error>
+    + 'static,
+{
+  from_fn(move |req: ServiceRequest, next: Next<BoxBody>| async move {
+    let granted = req
+      .extensions()
+      .get::<GrantedPermissions>()
+      .is_some_and(|granted| granted.0.contains(&permission));
+    if !granted {
+      return Ok(req.into_response(permission_denied(permission)));
+    }
+    next.call(req).await
+  })
+}
+
+fn permission_denied(permission: Permission) -> HttpResponse {
+  HttpResponse::Forbidden()
+    .content_type("application/json")
+    .json(
+      HttpError::from(
+        format!("Missing permission `{}`", permission.as_str()).as_str(),
+      )
+      .with_code(ErrorCode::PermissionDenied),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::{Arc, RwLock};
+
+  use actix_web::{
+    http::{header, StatusCode},
+    test::{call_service, init_service, TestRequest},
+    web, App,
+  };
+  use actix_web_httpauth::middleware::HttpAuthentication;
+  use chrono::Utc;
+
+  use crate::{
+    auth::handlers::generate_token_pair,
+    helpers::tests::fake_user,
+    shared::{
+      clock::SystemClock, config::Config, database::InMemoryDatabase,
+      middleware::master_key_middleware::bearer_validator, role::Role,
+    },
+    users::{
+      model::user::User, repository::user_repository::UserRepositoryImpl,
+    },
+  };
+
+  use super::*;
+
+  fn user(role: Role) -> User {
+    User {
+      role,
+      ..fake_user()
+    }
+  }
+
+  fn access_token(config: &Config, user: &User) -> String {
+    generate_token_pair(config, Utc::now(), user.clone())
+      .unwrap()
+      .access_token
+  }
+
+  #[actix_web::test]
+  async fn test_require_permission() {
+    let config = Arc::new(Config {
+      master_keys: vec![String::from("MASTER_KEY")],
+      ..Config::default().await
+    });
+    let manager = user(Role::Manager);
+    let customer = user(Role::Customer);
+    let mut disabled = user(Role::Admin);
+    disabled.enabled = false;
+    let mut deleted = user(Role::Admin);
+    deleted.deleted_at = Some(Utc::now());
+    let revoked = user(Role::Admin);
+    let user_repository =
+      web::Data::new(UserRepositoryImpl::new(Arc::new(InMemoryDatabase {
+        users: Arc::new(RwLock::new(vec![
+          manager.clone(),
+          customer.clone(),
+          disabled.clone(),
+          deleted.clone(),
+          User {
+            token_epoch: 1,
+            ..revoked.clone()
+          },
+        ])),
+      })));
+    let app =
+      init_service(
+        App::new()
+          .app_data(user_repository)
+          .app_data(web::Data::new(SystemClock))
+          .wrap(HttpAuthentication::with_fn({
+            let config = config.clone();
+            move |req, credentials| {
+              bearer_validator::<
+                UserRepositoryImpl<InMemoryDatabase>,
+                SystemClock,
+              >(req, credentials, config.clone())
+            }
+          }))
+          .route(
+            "/users",
+            web::get()
+              .to(HttpResponse::Ok)
+              .wrap(require_permission(Permission::UsersRead)),
+          )
+          .route(
+            "/users",
+            web::delete()
+              .to(HttpResponse::Ok)
+              .wrap(require_permission(Permission::UsersDelete)),
+          ),
+      )
+      .await;
+
+    let manager = access_token(&config, &manager);
+    let customer = access_token(&config, &customer);
+    let disabled = access_token(&config, &disabled);
+    let deleted = access_token(&config, &deleted);
+    let revoked = access_token(&config, &revoked);
+    let unknown = access_token(&config, &user(Role::Admin));
+    for (request, token, status) in [
+      (TestRequest::get(), manager.as_str(), StatusCode::OK),
+      (TestRequest::delete(), &manager, StatusCode::FORBIDDEN),
+      (TestRequest::get(), &customer, StatusCode::FORBIDDEN),
+      (TestRequest::get(), &disabled, StatusCode::BAD_REQUEST),
+      (TestRequest::get(), &deleted, StatusCode::BAD_REQUEST),
+      (TestRequest::get(), &revoked, StatusCode::BAD_REQUEST),
+      (TestRequest::get(), &unknown, StatusCode::BAD_REQUEST),
+      (TestRequest::get(), "MASTER_KEY", StatusCode::OK),
+      (TestRequest::delete(), "MASTER_KEY", StatusCode::OK),
+    ] {
+      let request = request
+        .uri("/users")
+        .append_header((header::AUTHORIZATION, format!("Bearer {}", token)))
+        .to_request();
+      let response = call_service(&app, request).await;
+      assert_eq!(response.status(), status, "token {}", token);
+    }
+  }
+}
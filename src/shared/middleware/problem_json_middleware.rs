@@ -0,0 +1,121 @@
+use actix_web::{
+  body::{to_bytes, BoxBody, MessageBody},
+  dev::{ServiceRequest, ServiceResponse},
+  error,
+  http::header::{self, HeaderValue},
+  middleware::Next,
+  Error,
+};
+
+use crate::shared::{http_error::HttpError, rto::problem_rto::ProblemRto};
+
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+/// Rewrites `HttpError` bodies as RFC 7807 problem details for requests
+/// that accept `application/problem+json`. Every other response, including
+/// errors that aren't an `HttpError`, passes through untouched.
+pub async fn problem_json(
+  req: ServiceRequest,
+  next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+  let accepts_problem = req
+    .headers()
+    .get(header::ACCEPT)
+    .and_then(|accept| accept.to_str().ok())
+    .is_some_and(|accept| accept.contains(PROBLEM_JSON));
+  let response = next.call(req).await?.map_into_boxed_body();
+  let status = response.status();
+  if !accepts_problem || !(status.is_client_error() || status.is_server_error())
+  {
+    return Ok(response);
+  }
+
+  let (request, response) = response.into_parts();
+  let (response, body) = response.into_parts();
+  let body = to_bytes(body)
+    .await
+    .map_err(error::ErrorInternalServerError)?;
+  let Ok(http_error) = serde_json::from_slice::<HttpError>(&body) else {
+    return Ok(ServiceResponse::new(
+      request,
+      response.set_body(body.boxed()),
+    ));
+  };
+
+  let problem = ProblemRto {
+    problem_type: String::from("about:blank"),
+    title: status.canonical_reason().unwrap_or_default().to_string(),
+    status: status.as_u16(),
+    detail: http_error.message,
+    code: http_error.code,
+    errors: http_error.errors,
+  };
+  let mut response =
+    response.set_body(BoxBody::new(serde_json::to_vec(&problem)?));
+  response
+    .headers_mut()
+    .insert(header::CONTENT_TYPE, HeaderValue::from_static(PROBLEM_JSON));
+  Ok(ServiceResponse::new(request, response))
+}
+
+#[cfg(test)]
+mod tests {
+  use actix_web::{
+    http::StatusCode,
+    middleware::from_fn,
+    test::{call_service, init_service, read_body, TestRequest},
+    web, App, HttpResponse,
+  };
+
+  use crate::shared::http_error::ErrorCode;
+
+  use super::*;
+
+  #[actix_web::test]
+  async fn test_accept_header_selects_problem_json() {
+    let app = init_service(App::new().wrap(from_fn(problem_json)).route(
+      "/",
+      web::get().to(|| async {
+        HttpResponse::NotFound().json(
+          HttpError::from("User not found").with_code(ErrorCode::UserNotFound),
+        )
+      }),
+    ))
+    .await;
+
+    let request = TestRequest::get()
+      .uri("/")
+      .insert_header((header::ACCEPT, PROBLEM_JSON))
+      .to_request();
+    let response = call_service(&app, request).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(
+      response.headers().get(header::CONTENT_TYPE).unwrap(),
+      PROBLEM_JSON
+    );
+    let problem: ProblemRto =
+      serde_json::from_slice(&read_body(response).await).unwrap();
+    assert_eq!(
+      problem,
+      ProblemRto {
+        problem_type: String::from("about:blank"),
+        title: String::from("Not Found"),
+        status: 404,
+        detail: String::from("User not found"),
+        code: Some(ErrorCode::UserNotFound),
+        errors: None,
+      }
+    );
+
+    // Without the Accept header the usual shape is kept.
+    let request = TestRequest::get().uri("/").to_request();
+    let response = call_service(&app, request).await;
+    assert_eq!(
+      response.headers().get(header::CONTENT_TYPE).unwrap(),
+      "application/json"
+    );
+    let error: HttpError =
+      serde_json::from_slice(&read_body(response).await).unwrap();
+    assert_eq!(error.code, Some(ErrorCode::UserNotFound));
+  }
+}
@@ -0,0 +1,222 @@
+use std::{
+  future::{ready, Future, Ready},
+  pin::Pin,
+  rc::Rc,
+  sync::{Arc, Mutex, PoisonError},
+  task::{Context, Poll},
+};
+
+use actix_governor::{
+  governor::middleware::StateInformationMiddleware, Governor, GovernorConfig,
+  GovernorConfigBuilder,
+};
+use actix_web::{
+  body::{EitherBody, MessageBody},
+  dev::{Service, ServiceRequest, ServiceResponse, Transform},
+  error, Error,
+};
+
+use crate::shared::config::Config;
+
+use super::client_ip_key_extractor::ClientIpKeyExtractor;
+
+type RateLimitConfig =
+  GovernorConfig<ClientIpKeyExtractor, StateInformationMiddleware>;
+
+/// Rate limit per client IP, allowing bursts of up to
+/// `rate_limit_burst_size` requests and replenishing `rate_limit_per_second`.
+/// Responses carry `x-ratelimit-*` headers, plus `retry-after` once
+/// throttled. Clones share their limiter, so the limit holds across
+/// workers. Both settings are reloadable: the first request to see new
+/// values rebuilds the limiter, whose counts start over.
+#[derive(Clone)]
+pub struct RateLimit {
+  config: Arc<Config>,
+  current: Arc<Mutex<Limiter>>,
+}
+
+/// Governor config built for the `settings` it limits to.
+struct Limiter {
+  settings: (u64, u32),
+  governor_config: Arc<RateLimitConfig>,
+}
+
+impl RateLimit {
+  pub fn new(config: Arc<Config>) -> Self {
+    let settings = settings(&config);
+    let governor_config = Arc::new(governor_config(&config, settings));
+    Self {
+      config,
+      current: Arc::new(Mutex::new(Limiter {
+        settings,
+        governor_config,
+      })),
+    }
+  }
+
+  fn governor_config(&self) -> Arc<RateLimitConfig> {
+    let settings = settings(&self.config);
+    let mut current =
+      self.current.lock().unwrap_or_else(PoisonError::into_inner);
+    if current.settings != settings {
+      *current = Limiter {
+        settings,
+        governor_config: Arc::new(governor_config(&self.config, settings)),
+      };
+    }
+    current.governor_config.clone()
+  }
+}
+
+fn settings(config: &Config) -> (u64, u32) {
+  let reloadable = config.reloadable.load();
+  (
+    reloadable.rate_limit_per_second,
+    reloadable.rate_limit_burst_size,
+  )
+}
+
+fn governor_config(
+  config: &Config,
+  (per_second, burst_size): (u64, u32),
+) -> RateLimitConfig {
+  GovernorConfigBuilder::default()
+    .key_extractor(ClientIpKeyExtractor::new(config.trust_proxy))
+    .requests_per_second(per_second)
+    .burst_size(burst_size)
+    .use_headers()
+    .finish()
+    .expect("Rate limit settings are positive")
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimit
+where
+  S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>
+    + 'static,
+  S::Future: Unpin,
+  B: MessageBody + 'static,
+{
+  type Response = ServiceResponse<EitherBody<B>>;
+  type Error = Error;
+  type Transform = RateLimitMiddleware<S>;
+  type InitError = ();
+  type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+  fn new_transform(&self, service: S) -> Self::Future {
+    ready(Ok(RateLimitMiddleware {
+      rate_limit: self.clone(),
+      service: Rc::new(service),
+    }))
+  }
+}
+
+pub struct RateLimitMiddleware<S> {
+  rate_limit: RateLimit,
+  service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimitMiddleware<S>
+where
+  S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>
+    + 'static,
+  S::Future: Unpin,
+  B: MessageBody + 'static,
+{
+  type Response = ServiceResponse<EitherBody<B>>;
+  type Error = Error;
+  type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Error>>>>;
+
+  fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+    self.service.poll_ready(cx)
+  }
+
+  fn call(&self, req: ServiceRequest) -> Self::Future {
+    // Building the governor only clones its limiter, so it's done per
+    // request rather than tracking which limiter each worker holds.
+    let governor = Governor::new(&self.rate_limit.governor_config());
+    let service = Shared(self.service.clone());
+    Box::pin(async move {
+      let governed = governor.new_transform(service).await.map_err(|()| {
+        error::ErrorInternalServerError("Rate limit unavailable")
+      })?;
+      governed.call(req).await
+    })
+  }
+}
+
+/// Lends the wrapped service to the governor built for a request.
+struct Shared<S>(Rc<S>);
+
+impl<S: Service<ServiceRequest>> Service<ServiceRequest> for Shared<S> {
+  type Response = S::Response;
+  type Error = S::Error;
+  type Future = S::Future;
+
+  fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), S::Error>> {
+    self.0.poll_ready(cx)
+  }
+
+  fn call(&self, req: ServiceRequest) -> Self::Future {
+    self.0.call(req)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{net::SocketAddr, str::FromStr};
+
+  use actix_web::{
+    http::StatusCode,
+    test::{call_service, init_service, TestRequest},
+    web, App, HttpResponse,
+  };
+
+  use crate::shared::config::DEFAULT_RATE_LIMIT_BURST_SIZE;
+
+  use super::*;
+
+  #[actix_web::test]
+  async fn test_reload_applies_new_limits() {
+    let path =
+      std::env::temp_dir().join(format!("{}.env", crate::custom_nanoid()));
+    let config = Arc::new(Config {
+      config_file: Some(path.to_string_lossy().into_owned()),
+      ..Config::default().await
+    });
+    let app = init_service(
+      App::new()
+        .wrap(RateLimit::new(config.clone()))
+        .route("/", web::get().to(HttpResponse::Ok)),
+    )
+    .await;
+    let request = || {
+      TestRequest::get()
+        .uri("/")
+        .peer_addr(SocketAddr::from_str("127.0.0.1:12345").unwrap())
+        .to_request()
+    };
+
+    let response = call_service(&app, request()).await;
+    assert_eq!(
+      response.headers().get("x-ratelimit-limit").unwrap(),
+      DEFAULT_RATE_LIMIT_BURST_SIZE.to_string().as_str()
+    );
+
+    std::fs::write(&path, "RATE_LIMIT_BURST_SIZE=2\n").unwrap();
+    config.reload().unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    // The counts start over under the new limit.
+    for remaining in (0..2).rev() {
+      let response = call_service(&app, request()).await;
+      assert_eq!(response.status(), StatusCode::OK);
+      assert_eq!(response.headers().get("x-ratelimit-limit").unwrap(), "2");
+      assert_eq!(
+        response.headers().get("x-ratelimit-remaining").unwrap(),
+        remaining.to_string().as_str()
+      );
+    }
+    let response = call_service(&app, request()).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+  }
+}
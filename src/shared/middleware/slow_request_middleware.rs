@@ -0,0 +1,142 @@
+use std::{
+  collections::HashMap,
+  time::{Duration, Instant},
+};
+
+use actix_web::{
+  body::MessageBody,
+  dev::{ServiceRequest, ServiceResponse},
+  middleware::Next,
+  web, Error,
+};
+
+use crate::shared::config::Config;
+
+/// Reports requests that took longer than their route's threshold.
+pub struct SlowRequestLog {
+  threshold: Duration,
+  route_thresholds: HashMap<String, Duration>,
+  sink: Box<dyn Fn(String) + Send + Sync>,
+}
+
+impl SlowRequestLog {
+  pub fn new(config: &Config) -> Self {
+    Self {
+      threshold: config.slow_request_threshold,
+      route_thresholds: config.slow_request_route_thresholds.clone(),
+      sink: Box::new(|line| eprintln!("{}", line)),
+    }
+  }
+
+  #[cfg(test)]
+  pub fn with_sink(
+    mut self,
+    sink: impl Fn(String) + Send + Sync + 'static,
+  ) -> Self {
+    self.sink = Box::new(sink);
+    self
+  }
+
+  /// `route` is the matched pattern, e.g. `/v1/users/{uuid}`, so one
+  /// override covers every user.
+  fn threshold(&self, route: &str) -> Duration {
+    self
+      .route_thresholds
+      .get(route)
+      .copied()
+      .unwrap_or(self.threshold)
+  }
+}
+
+/// Times each request and hands those over their threshold to the
+/// `SlowRequestLog` in app data. Does nothing without one.
+pub async fn log_slow_requests(
+  req: ServiceRequest,
+  next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+  let slow_request_log = req.app_data::<web::Data<SlowRequestLog>>().cloned();
+  let started = Instant::now();
+  let response = next.call(req).await?;
+  let Some(slow_request_log) = slow_request_log else {
+    return Ok(response);
+  };
+
+  let elapsed = started.elapsed();
+  let request = response.request();
+  let route = request
+    .match_pattern()
+    .unwrap_or_else(|| request.path().to_string());
+  if elapsed > slow_request_log.threshold(&route) {
+    (slow_request_log.sink)(format!(
+      "Slow request: {} {} returned {} after {}ms",
+      request.method(),
+      request.path(),
+      response.status().as_u16(),
+      elapsed.as_millis()
+    ));
+  }
+  Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::{Arc, Mutex};
+
+  use actix_web::{
+    middleware::from_fn,
+    test::{call_service, init_service, TestRequest},
+    App, HttpResponse,
+  };
+
+  use super::*;
+
+  async fn slow_request_lines(path: &str) -> Vec<String> {
+    let lines = Arc::new(Mutex::new(Vec::new()));
+    let slow_request_log = SlowRequestLog::new(&Config {
+      slow_request_threshold: Duration::from_millis(20),
+      slow_request_route_thresholds: HashMap::from([(
+        String::from("/login"),
+        Duration::from_secs(60),
+      )]),
+      ..Config::default().await
+    })
+    .with_sink({
+      let lines = lines.clone();
+      move |line| lines.lock().unwrap().push(line)
+    });
+    let slow = || async {
+      actix_web::rt::time::sleep(Duration::from_millis(50)).await;
+      HttpResponse::Ok().finish()
+    };
+    let app = init_service(
+      App::new()
+        .app_data(web::Data::new(slow_request_log))
+        .wrap(from_fn(log_slow_requests))
+        .route("/slow", web::get().to(slow))
+        .route("/login", web::get().to(slow))
+        .route("/fast", web::get().to(HttpResponse::Ok)),
+    )
+    .await;
+
+    call_service(&app, TestRequest::get().uri(path).to_request()).await;
+    let lines = lines.lock().unwrap().clone();
+    lines
+  }
+
+  #[actix_web::test]
+  async fn test_slow_request_logged() {
+    let lines = slow_request_lines("/slow").await;
+    assert_eq!(lines.len(), 1);
+    assert!(lines[0].starts_with("Slow request: GET /slow returned 200 after "));
+  }
+
+  #[actix_web::test]
+  async fn test_fast_request_not_logged() {
+    assert!(slow_request_lines("/fast").await.is_empty());
+  }
+
+  #[actix_web::test]
+  async fn test_route_threshold_overrides_default() {
+    assert!(slow_request_lines("/login").await.is_empty());
+  }
+}
@@ -1,9 +1,21 @@
+pub mod audit_log;
+pub mod breach_check;
+pub mod clock;
 pub mod config;
 pub mod database;
 pub mod handlers;
 pub mod hash_worker;
 pub mod health_check;
 pub mod http_error;
+pub mod idempotency;
+pub mod mailer;
 pub mod middleware;
+pub mod normalize;
+pub mod permission;
+pub mod reload;
+pub mod retry;
 pub mod role;
 pub mod rto;
+pub mod sweeper;
+pub mod tls;
+pub mod webhook;
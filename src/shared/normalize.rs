@@ -0,0 +1,44 @@
+use serde::{Deserialize, Deserializer};
+
+/// The one form emails are stored and looked up in, so an address typed
+/// with stray spaces or capitals still finds its user.
+pub fn normalize_email(email: &str) -> String {
+  email.trim().to_lowercase()
+}
+
+/// `deserialize_with` for email fields of request bodies and queries, so
+/// they're validated and looked up already normalized.
+pub fn deserialize_email<'de, D: Deserializer<'de>>(
+  deserializer: D,
+) -> Result<String, D::Error> {
+  String::deserialize(deserializer).map(|email| normalize_email(&email))
+}
+
+/// `deserialize_email` for optional fields.
+pub fn deserialize_optional_email<'de, D: Deserializer<'de>>(
+  deserializer: D,
+) -> Result<Option<String>, D::Error> {
+  Option::<String>::deserialize(deserializer)
+    .map(|email| email.as_deref().map(normalize_email))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_normalize_email() {
+    assert_eq!(
+      normalize_email("  Jane.Doe@Example.COM\n"),
+      "jane.doe@example.com"
+    );
+    assert_eq!(
+      normalize_email("jane.doe@example.com"),
+      "jane.doe@example.com"
+    );
+    assert_eq!(normalize_email(""), "");
+
+    let once = normalize_email(" JANE@EXAMPLE.COM ");
+    assert_eq!(normalize_email(&once), once);
+  }
+}
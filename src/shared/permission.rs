@@ -0,0 +1,62 @@
+use serde::Serialize;
+
+/// Something a caller may do. Roles grant permissions, see
+/// `Role::permissions`, and routes require permissions rather than roles,
+/// so roles can be regrouped without touching the routes.
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq, Hash)]
+pub enum Permission {
+  #[serde(rename = "users:read")]
+  UsersRead,
+  #[serde(rename = "users:create")]
+  UsersCreate,
+  #[serde(rename = "users:update")]
+  UsersUpdate,
+  #[serde(rename = "users:delete")]
+  UsersDelete,
+  #[serde(rename = "tokens:revoke")]
+  TokensRevoke,
+  #[serde(rename = "health:read")]
+  HealthRead,
+}
+
+impl Permission {
+  pub const ALL: [Permission; 6] = [
+    Permission::UsersRead,
+    Permission::UsersCreate,
+    Permission::UsersUpdate,
+    Permission::UsersDelete,
+    Permission::TokensRevoke,
+    Permission::HealthRead,
+  ];
+
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      Permission::UsersRead => "users:read",
+      Permission::UsersCreate => "users:create",
+      Permission::UsersUpdate => "users:update",
+      Permission::UsersDelete => "users:delete",
+      Permission::TokensRevoke => "tokens:revoke",
+      Permission::HealthRead => "health:read",
+    }
+  }
+}
+
+/// Permissions the request authenticated with, stored in its extensions by
+/// the authentication middleware for `require_permission` to check.
+#[derive(Debug, Clone, Copy)]
+pub struct GrantedPermissions(pub &'static [Permission]);
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_permission_serializes_as_str() {
+    for permission in Permission::ALL {
+      assert_eq!(
+        serde_json::to_string(&permission).unwrap(),
+        format!("\"{}\"", permission.as_str())
+      );
+    }
+  }
+}
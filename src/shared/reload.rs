@@ -0,0 +1,28 @@
+use std::sync::Arc;
+
+use actix_web::rt::{
+  signal::unix::{signal, SignalKind},
+  spawn,
+};
+
+use super::config::Config;
+
+/// Reloads the `ReloadableConfig` part of `config` from `CONFIG_FILE` on
+/// every SIGHUP, for the lifetime of the process. Workers share `config`,
+/// so they all pick up the change with their next request.
+pub fn reload_on_sighup(config: Arc<Config>) -> std::io::Result<()> {
+  let mut hangups = signal(SignalKind::hangup())?;
+  spawn(async move {
+    while hangups.recv().await.is_some() {
+      match config.reload() {
+        Ok(()) => eprintln!("Reloaded configuration on SIGHUP"),
+        Err(error) => eprintln!(
+          "Kept the current configuration on SIGHUP, CONFIG_FILE can't be \
+           read: {}",
+          error
+        ),
+      }
+    }
+  });
+  Ok(())
+}
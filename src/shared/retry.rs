@@ -0,0 +1,266 @@
+use std::{future::Future, time::Duration};
+
+use actix_web::rt::time::sleep;
+use rand::Rng;
+
+const BASE_DELAY: Duration = Duration::from_millis(20);
+const MAX_DELAY: Duration = Duration::from_secs(1);
+
+/// How a failed backend call may be retried.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorClass {
+  /// Retrying can't help, e.g. a validation or conditional-check failure.
+  Permanent,
+  /// The backend refused the request without applying it, e.g. throttling,
+  /// so any operation can be retried.
+  Rejected,
+  /// The outcome is unknown, e.g. a timeout, so only operations that are
+  /// safe to apply twice can be retried.
+  Indeterminate,
+}
+
+pub trait Retryable {
+  fn class(&self) -> ErrorClass;
+}
+
+/// Bounded retries with capped exponential backoff and full jitter. A policy
+/// with a single attempt never retries.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+  max_attempts: u32,
+}
+
+impl RetryPolicy {
+  pub fn new(max_attempts: u32) -> Self {
+    Self {
+      max_attempts: max_attempts.max(1),
+    }
+  }
+
+  /// Runs an idempotent operation, retrying any transient failure.
+  pub async fn retry<T, E, F, Fut>(&self, operation: F) -> Result<T, E>
+  where
+    E: Retryable,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+  {
+    self
+      .run(operation, |class| class != ErrorClass::Permanent)
+      .await
+  }
+
+  /// Runs an operation that must not be applied twice, retrying only when
+  /// the backend is known to have rejected it.
+  pub async fn retry_unapplied<T, E, F, Fut>(
+    &self,
+    operation: F,
+  ) -> Result<T, E>
+  where
+    E: Retryable,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+  {
+    self
+      .run(operation, |class| class == ErrorClass::Rejected)
+      .await
+  }
+
+  /// `retry` for an operation that takes `value` by value. Attempts that
+  /// may be followed by another get a clone, the last one the original.
+  pub async fn retry_with<V, T, E, F, Fut>(
+    &self,
+    value: V,
+    operation: F,
+  ) -> Result<T, E>
+  where
+    V: Clone,
+    E: Retryable,
+    F: FnMut(V) -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+  {
+    self.retry(self.handing_over(value, operation)).await
+  }
+
+  /// `retry_unapplied` for an operation that takes `value` by value, see
+  /// `retry_with`.
+  pub async fn retry_unapplied_with<V, T, E, F, Fut>(
+    &self,
+    value: V,
+    operation: F,
+  ) -> Result<T, E>
+  where
+    V: Clone,
+    E: Retryable,
+    F: FnMut(V) -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+  {
+    self
+      .retry_unapplied(self.handing_over(value, operation))
+      .await
+  }
+
+  /// Wraps `operation` to pass it a clone of `value` on every attempt but
+  /// the last, which takes `value` itself.
+  fn handing_over<V: Clone, Fut>(
+    &self,
+    value: V,
+    mut operation: impl FnMut(V) -> Fut,
+  ) -> impl FnMut() -> Fut {
+    let mut value = Some(value);
+    let mut attempt = 0;
+    let max_attempts = self.max_attempts;
+    move || {
+      attempt += 1;
+      let value = match attempt < max_attempts {
+        true => value.clone(),
+        false => value.take(),
+      };
+      operation(value.expect("no attempt is made after the last"))
+    }
+  }
+
+  async fn run<T, E, F, Fut>(
+    &self,
+    mut operation: F,
+    should_retry: impl Fn(ErrorClass) -> bool,
+  ) -> Result<T, E>
+  where
+    E: Retryable,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+  {
+    let mut attempt = 1;
+    loop {
+      match operation().await {
+        Err(error)
+          if attempt < self.max_attempts && should_retry(error.class()) =>
+        {
+          sleep(backoff(attempt)).await;
+          attempt += 1;
+        }
+        result => return result,
+      }
+    }
+  }
+}
+
+fn backoff(attempt: u32) -> Duration {
+  let cap = BASE_DELAY
+    .saturating_mul(2u32.saturating_pow(attempt - 1))
+    .min(MAX_DELAY);
+  rand::thread_rng().gen_range(Duration::ZERO..=cap)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::cell::Cell;
+
+  use super::*;
+
+  #[derive(Debug, PartialEq)]
+  struct FakeError(ErrorClass);
+
+  impl Retryable for FakeError {
+    fn class(&self) -> ErrorClass {
+      self.0
+    }
+  }
+
+  /// Backend that fails with `class` for the first `failures` calls.
+  struct FlakyBackend {
+    failures: u32,
+    class: ErrorClass,
+    calls: Cell<u32>,
+  }
+
+  impl FlakyBackend {
+    fn new(failures: u32, class: ErrorClass) -> Self {
+      Self {
+        failures,
+        class,
+        calls: Cell::new(0),
+      }
+    }
+
+    async fn call(&self) -> Result<u32, FakeError> {
+      let calls = self.calls.get() + 1;
+      self.calls.set(calls);
+      if calls <= self.failures {
+        return Err(FakeError(self.class));
+      }
+      Ok(calls)
+    }
+  }
+
+  #[actix_web::test]
+  async fn test_retry_succeeds_after_transient_failures() {
+    let backend = FlakyBackend::new(2, ErrorClass::Indeterminate);
+    let result = RetryPolicy::new(3).retry(|| backend.call()).await;
+    assert_eq!(result, Ok(3));
+  }
+
+  #[actix_web::test]
+  async fn test_retry_gives_up_after_max_attempts() {
+    let backend = FlakyBackend::new(5, ErrorClass::Rejected);
+    let result = RetryPolicy::new(3).retry(|| backend.call()).await;
+    assert_eq!(result, Err(FakeError(ErrorClass::Rejected)));
+    assert_eq!(backend.calls.get(), 3);
+  }
+
+  #[actix_web::test]
+  async fn test_retry_skips_permanent_failures() {
+    let backend = FlakyBackend::new(1, ErrorClass::Permanent);
+    let result = RetryPolicy::new(3).retry(|| backend.call()).await;
+    assert_eq!(result, Err(FakeError(ErrorClass::Permanent)));
+    assert_eq!(backend.calls.get(), 1);
+  }
+
+  #[actix_web::test]
+  async fn test_single_attempt_policy_never_retries() {
+    let backend = FlakyBackend::new(1, ErrorClass::Rejected);
+    let result = RetryPolicy::new(1).retry(|| backend.call()).await;
+    assert_eq!(result, Err(FakeError(ErrorClass::Rejected)));
+    assert_eq!(backend.calls.get(), 1);
+  }
+
+  #[actix_web::test]
+  async fn test_retry_unapplied_only_retries_rejections() {
+    let backend = FlakyBackend::new(1, ErrorClass::Rejected);
+    let result = RetryPolicy::new(3).retry_unapplied(|| backend.call()).await;
+    assert_eq!(result, Ok(2));
+
+    let backend = FlakyBackend::new(1, ErrorClass::Indeterminate);
+    let result = RetryPolicy::new(3).retry_unapplied(|| backend.call()).await;
+    assert_eq!(result, Err(FakeError(ErrorClass::Indeterminate)));
+    assert_eq!(backend.calls.get(), 1);
+  }
+
+  /// Counts how many times it was cloned.
+  struct Counted<'a>(&'a Cell<u32>);
+
+  impl Clone for Counted<'_> {
+    fn clone(&self) -> Self {
+      self.0.set(self.0.get() + 1);
+      Self(self.0)
+    }
+  }
+
+  #[actix_web::test]
+  async fn test_retry_with_hands_over_value_on_last_attempt() {
+    for (failures, max_attempts, clones) in [(5, 3, 2), (0, 3, 1), (0, 1, 0)] {
+      let backend = FlakyBackend::new(failures, ErrorClass::Rejected);
+      let cloned = Cell::new(0);
+      let _ = RetryPolicy::new(max_attempts)
+        .retry_unapplied_with(Counted(&cloned), |_| backend.call())
+        .await;
+      assert_eq!(cloned.get(), clones);
+    }
+  }
+
+  #[test]
+  fn test_backoff_is_capped() {
+    for attempt in 1..40 {
+      assert!(backoff(attempt) <= MAX_DELAY);
+    }
+  }
+}
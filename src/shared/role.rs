@@ -1,7 +1,12 @@
-use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+use serde::{de::Error, Deserialize, Deserializer, Serialize};
 use utoipa::ToSchema;
 
-#[derive(ToSchema, Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+use super::permission::Permission;
+
+#[derive(ToSchema, Debug, Clone, Serialize, PartialEq, Eq, Hash)]
+#[schema(example = "customer")]
 pub enum Role {
   #[serde(rename = "admin")]
   Admin,
@@ -12,3 +17,87 @@ pub enum Role {
   #[serde(rename = "customer")]
   Customer,
 }
+
+impl Role {
+  pub const ALL: [Role; 4] =
+    [Role::Admin, Role::Manager, Role::Driver, Role::Customer];
+
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      Role::Admin => "admin",
+      Role::Manager => "manager",
+      Role::Driver => "driver",
+      Role::Customer => "customer",
+    }
+  }
+
+  /// What the role may do on the admin routes. Managers only get to look,
+  /// as creating or updating users would let them hand out admin access.
+  pub fn permissions(&self) -> &'static [Permission] {
+    match self {
+      Role::Admin => &Permission::ALL,
+      Role::Manager => &[Permission::UsersRead],
+      Role::Driver | Role::Customer => &[],
+    }
+  }
+}
+
+impl FromStr for Role {
+  type Err = String;
+
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    Role::ALL
+      .into_iter()
+      .find(|role| role.as_str() == value)
+      .ok_or_else(|| {
+        let allowed: Vec<&str> = Role::ALL.iter().map(Role::as_str).collect();
+        format!(
+          "invalid role `{}`, expected one of: {}",
+          value,
+          allowed.join(", ")
+        )
+      })
+  }
+}
+
+/// Hand-written so an unknown role is reported along with the valid ones,
+/// instead of failing with serde's generic unknown variant error.
+impl<'de> Deserialize<'de> for Role {
+  fn deserialize<D: Deserializer<'de>>(
+    deserializer: D,
+  ) -> Result<Self, D::Error> {
+    String::deserialize(deserializer)?
+      .parse()
+      .map_err(D::Error::custom)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_role_round_trip() {
+    for role in Role::ALL {
+      let json = serde_json::to_string(&role).unwrap();
+      assert_eq!(json, format!("\"{}\"", role.as_str()));
+      assert_eq!(serde_json::from_str::<Role>(&json).unwrap(), role);
+    }
+  }
+
+  #[test]
+  fn test_role_permissions() {
+    assert_eq!(Role::Admin.permissions(), Permission::ALL);
+    assert_eq!(Role::Manager.permissions(), [Permission::UsersRead]);
+    assert!(Role::Driver.permissions().is_empty());
+    assert!(Role::Customer.permissions().is_empty());
+  }
+
+  #[test]
+  fn test_unknown_role_lists_allowed_values() {
+    let error = serde_json::from_str::<Role>("\"wizard\"").unwrap_err();
+    assert!(error.to_string().starts_with(
+      "invalid role `wizard`, expected one of: admin, manager, driver, customer"
+    ));
+  }
+}
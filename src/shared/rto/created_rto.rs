@@ -1,7 +1,10 @@
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 
+use crate::users::model::user_id::UserId;
+
 #[derive(ToSchema, Debug, Clone, Serialize, Deserialize)]
 pub struct CreatedRto {
-  pub uuid: String,
+  #[schema(value_type = String, example = "V1StGXR8Z5jdHi6BmyTzf")]
+  pub uuid: UserId,
 }
@@ -0,0 +1,37 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::shared::health_check::HealthCheckStats;
+
+#[derive(ToSchema, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DetailedHealthRto {
+  /// Latest database stats, absent until the first collection.
+  pub stats: Option<HealthCheckStats>,
+  pub hash_worker: HashWorkerHealthRto,
+  pub rate_limiter: RateLimiterHealthRto,
+}
+
+#[derive(ToSchema, Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct HashWorkerHealthRto {
+  /// Work orders waiting for a free worker.
+  #[schema(example = 0)]
+  pub queue_depth: usize,
+  #[schema(example = 2)]
+  pub threads: u32,
+  /// Whether the queue is full right now.
+  pub saturated: bool,
+}
+
+#[derive(ToSchema, Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct RateLimiterHealthRto {
+  pub active: bool,
+  /// Clients are told apart by the forwarded IP rather than the peer.
+  pub trust_proxy: bool,
+  #[schema(example = 2)]
+  pub requests_per_second: u64,
+  #[schema(example = 5)]
+  pub burst_size: u32,
+}
@@ -1 +1,8 @@
+//! Response bodies. Fields are camelCase, like request bodies, through
+//! `#[serde(rename_all = "camelCase")]` on each struct. The OAuth2
+//! `TokenRto` is the one exception, as its field names are standardized.
+
 pub mod created_rto;
+pub mod detailed_health_rto;
+pub mod problem_rto;
+pub mod readiness_rto;
@@ -0,0 +1,20 @@
+use serde::{Deserialize, Serialize};
+
+use crate::shared::http_error::{ErrorCode, FieldErrors};
+
+/// RFC 7807 rendering of an `HttpError`, for clients that ask for
+/// `application/problem+json`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ProblemRto {
+  #[serde(rename = "type")]
+  pub problem_type: String,
+  pub title: String,
+  pub status: u16,
+  pub detail: String,
+  /// Extension member carrying the same stable code as `HttpError`.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub code: Option<ErrorCode>,
+  /// Extension member carrying `HttpError::errors` on validation failures.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub errors: Option<FieldErrors>,
+}
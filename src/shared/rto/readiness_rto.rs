@@ -0,0 +1,10 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+#[derive(ToSchema, Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ReadinessRto {
+  pub ready: bool,
+  /// Why traffic shouldn't be routed here, when not ready.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub reason: Option<String>,
+}
@@ -0,0 +1,77 @@
+use std::{sync::Arc, time::Duration};
+
+use actix_web::rt::{spawn, time::interval};
+use chrono::{DateTime, Utc};
+
+use super::clock::Clock;
+
+pub const DEFAULT_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// In-memory store whose entries expire. Expired entries are skipped on
+/// lookup but only freed by `sweep`, so without one a long-running instance
+/// slowly leaks memory.
+pub trait Expiring {
+  /// Drops every entry expired at `now`.
+  fn sweep(&self, now: DateTime<Utc>);
+}
+
+/// Sweeps `stores` every `every` in the background, for the lifetime of
+/// the process.
+pub fn spawn_sweeper<C: Clock + Send + Sync + 'static>(
+  stores: Vec<Arc<dyn Expiring + Send + Sync>>,
+  clock: Arc<C>,
+  every: Duration,
+) {
+  spawn(async move {
+    let mut interval = interval(every);
+    loop {
+      interval.tick().await;
+      let now = clock.now();
+      for store in &stores {
+        store.sweep(now);
+      }
+    }
+  });
+}
+
+#[cfg(test)]
+mod tests {
+  use actix_web::rt::time::sleep;
+
+  use crate::shared::{
+    clock::FixedClock,
+    idempotency::{IdempotencyStore, Reservation},
+  };
+
+  use super::*;
+
+  #[actix_web::test]
+  async fn test_sweeper_evicts_expired_entries() {
+    let clock = Arc::new(FixedClock::new(Utc::now()));
+    let store = Arc::new(IdempotencyStore::<u32>::new(Duration::from_secs(60)));
+    let complete = |key| {
+      let Reservation::Reserved(reserved) =
+        store.reserve("test", key, 0, clock.now())
+      else {
+        panic!("First use of the key");
+      };
+      reserved.complete(1, clock.now());
+    };
+    complete("old");
+    clock.advance(chrono::Duration::seconds(30));
+    complete("new");
+
+    spawn_sweeper(
+      vec![store.clone()],
+      clock.clone(),
+      Duration::from_millis(10),
+    );
+    sleep(Duration::from_millis(50)).await;
+    assert_eq!(store.len(), 2);
+
+    // Only "old" is past its TTL.
+    clock.advance(chrono::Duration::seconds(30));
+    sleep(Duration::from_millis(50)).await;
+    assert_eq!(store.len(), 1);
+  }
+}
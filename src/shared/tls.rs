@@ -0,0 +1,120 @@
+use std::sync::Arc;
+
+use rustls::{
+  crypto::ring::default_provider,
+  pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+  ServerConfig,
+};
+use thiserror::Error;
+
+use super::config::Config;
+
+/// How the server is bound.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Listener<'a> {
+  Plain,
+  Tls {
+    cert_path: &'a str,
+    key_path: &'a str,
+  },
+}
+
+#[derive(Debug, Error)]
+pub enum TlsError {
+  #[error("TLS_CERT_PATH and TLS_KEY_PATH must be set together")]
+  Incomplete,
+  #[error("failed to read TLS certificate chain from {path}: {source}")]
+  Certificate {
+    path: String,
+    source: rustls::pki_types::pem::Error,
+  },
+  #[error("failed to read TLS private key from {path}: {source}")]
+  PrivateKey {
+    path: String,
+    source: rustls::pki_types::pem::Error,
+  },
+  #[error("TLS certificate and private key were rejected: {0}")]
+  Rustls(#[from] rustls::Error),
+}
+
+/// Serves HTTPS when both a certificate and a key are configured. Setting
+/// only one is refused rather than silently falling back to plain HTTP.
+pub fn listener(config: &Config) -> Result<Listener<'_>, TlsError> {
+  match (&config.tls_cert_path, &config.tls_key_path) {
+    (Some(cert_path), Some(key_path)) => Ok(Listener::Tls {
+      cert_path,
+      key_path,
+    }),
+    (None, None) => Ok(Listener::Plain),
+    _ => Err(TlsError::Incomplete),
+  }
+}
+
+/// Loads the PEM certificate chain and private key, so a bad path or a
+/// mismatched pair fails at startup rather than on the first handshake.
+pub fn server_config(
+  cert_path: &str,
+  key_path: &str,
+) -> Result<ServerConfig, TlsError> {
+  let certificates = CertificateDer::pem_file_iter(cert_path)
+    .and_then(|certificates| certificates.collect::<Result<Vec<_>, _>>())
+    .map_err(|source| TlsError::Certificate {
+      path: cert_path.to_string(),
+      source,
+    })?;
+  let key = PrivateKeyDer::from_pem_file(key_path).map_err(|source| {
+    TlsError::PrivateKey {
+      path: key_path.to_string(),
+      source,
+    }
+  })?;
+
+  Ok(
+    ServerConfig::builder_with_provider(Arc::new(default_provider()))
+      .with_safe_default_protocol_versions()?
+      .with_no_client_auth()
+      .with_single_cert(certificates, key)?,
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[actix_web::test]
+  async fn test_listener_follows_config() {
+    let config = Config {
+      tls_cert_path: None,
+      tls_key_path: None,
+      ..Config::default().await
+    };
+    assert_eq!(listener(&config).unwrap(), Listener::Plain);
+
+    let config = Config {
+      tls_cert_path: Some(String::from("cert.pem")),
+      tls_key_path: Some(String::from("key.pem")),
+      ..config
+    };
+    assert_eq!(
+      listener(&config).unwrap(),
+      Listener::Tls {
+        cert_path: "cert.pem",
+        key_path: "key.pem"
+      }
+    );
+
+    let config = Config {
+      tls_key_path: None,
+      ..config
+    };
+    assert!(matches!(listener(&config), Err(TlsError::Incomplete)));
+  }
+
+  #[test]
+  fn test_server_config_reports_missing_files() {
+    let error = server_config("/nonexistent/cert.pem", "/nonexistent/key.pem")
+      .unwrap_err();
+    assert!(matches!(error, TlsError::Certificate { .. }));
+    assert!(error.to_string().contains("/nonexistent/cert.pem"));
+  }
+}
@@ -0,0 +1,169 @@
+use std::time::Duration;
+
+use actix_web::rt::{spawn, time::sleep};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+  shared::role::Role,
+  users::model::{email::Email, user::User, user_id::UserId},
+};
+
+const MAX_ATTEMPTS: u32 = 3;
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct UserCreatedEvent {
+  pub event: String,
+  pub uuid: UserId,
+  pub email: Email,
+  pub role: Role,
+}
+
+impl From<&User> for UserCreatedEvent {
+  fn from(user: &User) -> Self {
+    Self {
+      event: String::from("user.created"),
+      uuid: user.uuid.clone(),
+      email: user.email.clone(),
+      role: user.role.clone(),
+    }
+  }
+}
+
+/// Outbound notifications for provisioning pipelines. Delivery happens in
+/// the background and never affects the response of the triggering request.
+pub struct Webhook {
+  client: reqwest::Client,
+  user_created_url: Option<String>,
+}
+
+impl Webhook {
+  pub fn new(user_created_url: Option<String>) -> Self {
+    Self {
+      client: reqwest::Client::new(),
+      user_created_url,
+    }
+  }
+
+  pub fn user_created(&self, user: &User) {
+    let Some(url) = self.user_created_url.clone() else {
+      return;
+    };
+    let client = self.client.clone();
+    let event = UserCreatedEvent::from(user);
+    spawn(async move {
+      let mut backoff = INITIAL_BACKOFF;
+      for attempt in 1..=MAX_ATTEMPTS {
+        let result = client
+          .post(&url)
+          .json(&event)
+          .send()
+          .await
+          .and_then(|response| response.error_for_status());
+        match result {
+          Ok(_) => return,
+          Err(error) => {
+            eprintln!(
+              "user.created webhook attempt {}/{} failed: {}",
+              attempt, MAX_ATTEMPTS, error
+            );
+          }
+        }
+        if attempt < MAX_ATTEMPTS {
+          sleep(backoff).await;
+          backoff *= 2;
+        }
+      }
+    });
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+  };
+
+  use actix_web::{web, App, HttpResponse, HttpServer};
+
+  use crate::helpers::tests::fake_user;
+
+  use super::*;
+
+  /// Starts a local server that records every event it receives and fails
+  /// the first `failures` requests with a 500.
+  fn start_receiver(
+    failures: usize,
+  ) -> (String, flume::Receiver<UserCreatedEvent>) {
+    let (tx, rx) = flume::unbounded();
+    let calls = Arc::new(AtomicUsize::new(0));
+    let server = HttpServer::new(move || {
+      let tx = tx.clone();
+      let calls = calls.clone();
+      App::new().route(
+        "/hook",
+        web::post().to(move |event: web::Json<UserCreatedEvent>| {
+          let tx = tx.clone();
+          let calls = calls.clone();
+          async move {
+            let _ = tx.send(event.into_inner());
+            if calls.fetch_add(1, Ordering::SeqCst) < failures {
+              return HttpResponse::InternalServerError().finish();
+            }
+            HttpResponse::Ok().finish()
+          }
+        }),
+      )
+    })
+    .workers(1)
+    .bind("127.0.0.1:0")
+    .unwrap();
+    let address = server.addrs()[0];
+    spawn(server.run());
+    (format!("http://{}/hook", address), rx)
+  }
+
+  #[actix_web::test]
+  async fn test_user_created_payload() {
+    let (url, rx) = start_receiver(0);
+    let user = User {
+      role: Role::Driver,
+      ..fake_user()
+    };
+
+    Webhook::new(Some(url)).user_created(&user);
+
+    let event =
+      actix_web::rt::time::timeout(Duration::from_secs(5), rx.recv_async())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+      event,
+      UserCreatedEvent {
+        event: String::from("user.created"),
+        uuid: user.uuid,
+        email: user.email,
+        role: Role::Driver,
+      }
+    );
+  }
+
+  #[actix_web::test]
+  async fn test_user_created_retries_on_failure() {
+    let (url, rx) = start_receiver(1);
+    let user = fake_user();
+
+    Webhook::new(Some(url)).user_created(&user);
+
+    for _ in 0..2 {
+      let event =
+        actix_web::rt::time::timeout(Duration::from_secs(5), rx.recv_async())
+          .await
+          .unwrap()
+          .unwrap();
+      assert_eq!(event.uuid, user.uuid);
+    }
+  }
+}
@@ -1,36 +1,72 @@
 use serde::Deserialize;
+use std::borrow::Cow;
 use utoipa::ToSchema;
+
+use validator::ValidationError;
 use validator_derive::Validate;
 
-use crate::shared::role::Role;
+use crate::shared::{normalize::deserialize_email, role::Role};
+
+/// Length bounds of every password a user can set, in characters.
+pub const PASSWORD_MIN_LENGTH: u64 = 1;
+pub const PASSWORD_MAX_LENGTH: u64 = 1024;
 
-#[derive(ToSchema, Debug, Clone, Deserialize, Validate)]
+#[derive(ToSchema, Debug, Clone, Hash, Deserialize, Validate)]
+#[validate(schema(function = "validate_create_user_dto"))]
 pub struct CreateUserDto {
-  #[validate(email)]
+  #[validate(email(message = "invalid email"))]
+  #[schema(example = "jane.doe@example.com")]
+  #[serde(deserialize_with = "deserialize_email")]
   pub email: String,
   #[serde(rename = "userName")]
   #[validate(length(
     max = 1024,
     min = 1,
-    message = "Password must have at least 1 characters"
+    message = "User name must have at least 1 characters"
   ))]
+  #[schema(example = "Jane Doe")]
   pub user_name: String,
   #[validate(length(
-    max = 1024,
-    min = 1,
+    max = PASSWORD_MAX_LENGTH,
+    min = PASSWORD_MIN_LENGTH,
     message = "Password must have at least 1 characters"
   ))]
+  #[schema(example = "correct-horse-battery-staple")]
   pub password: String,
+  /// Checked against `password` when sent, for forms that ask twice.
+  #[serde(rename = "passwordConfirm", default)]
+  #[schema(example = "correct-horse-battery-staple")]
+  pub password_confirm: Option<String>,
   pub role: Role,
 }
 
+fn validate_create_user_dto(
+  dto: &CreateUserDto,
+) -> Result<(), ValidationError> {
+  validate_password_confirm(&dto.password, dto.password_confirm.as_deref())
+}
+
+/// Rejects a confirmation that differs from the password. An absent one
+/// passes, as clients that only ask once never send it.
+pub fn validate_password_confirm(
+  password: &str,
+  password_confirm: Option<&str>,
+) -> Result<(), ValidationError> {
+  match password_confirm {
+    Some(password_confirm) if password_confirm != password => Err(
+      ValidationError::new("password_mismatch")
+        .with_message(Cow::from("passwordConfirm must match password")),
+    ),
+    _ => Ok(()),
+  }
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
   use fake::faker::internet::en::{Password, SafeEmail};
   use fake::faker::lorem::en::Word;
   use fake::Fake;
-  use serde_json::json;
   use validator::Validate;
 
   #[test]
@@ -43,6 +79,7 @@ mod tests {
       email: valid_email.clone(),
       user_name: valid_user_name.clone(),
       password: valid_password.clone(),
+      password_confirm: None,
       role: Role::Admin,
     };
 
@@ -56,6 +93,7 @@ mod tests {
       email: "invalid-email".to_string(),
       user_name: valid_user_name.clone(),
       password: valid_password.clone(),
+      password_confirm: None,
       role: Role::Admin,
     };
     assert!(
@@ -68,6 +106,7 @@ mod tests {
       email: valid_email.clone(),
       user_name: "".to_string(),
       password: valid_password.clone(),
+      password_confirm: None,
       role: Role::Admin,
     };
     assert!(
@@ -81,6 +120,7 @@ mod tests {
       email: valid_email.clone(),
       user_name: long_user_name,
       password: valid_password.clone(),
+      password_confirm: None,
       role: Role::Admin,
     };
     assert!(
@@ -93,6 +133,7 @@ mod tests {
       email: valid_email.clone(),
       user_name: valid_user_name.clone(),
       password: "".to_string(),
+      password_confirm: None,
       role: Role::Admin,
     };
     assert!(
@@ -106,6 +147,7 @@ mod tests {
       email: valid_email.clone(),
       user_name: valid_user_name.clone(),
       password: long_password,
+      password_confirm: None,
       role: Role::Admin,
     };
     assert!(
@@ -113,6 +155,29 @@ mod tests {
       "DTO with long password should fail validation"
     );
 
+    // Test case: Matching confirmation
+    let confirmed_dto = CreateUserDto {
+      password_confirm: Some(valid_password.clone()),
+      ..valid_dto.clone()
+    };
+    assert!(
+      confirmed_dto.validate().is_ok(),
+      "DTO with matching passwordConfirm should pass validation"
+    );
+
+    // Test case: Mismatched confirmation
+    let mismatched_dto = CreateUserDto {
+      password_confirm: Some(format!("{}x", valid_password)),
+      ..valid_dto.clone()
+    };
+    let errors = mismatched_dto.validate().unwrap_err();
+    assert!(
+      errors
+        .to_string()
+        .contains("passwordConfirm must match password"),
+      "DTO with mismatched passwordConfirm should fail validation"
+    );
+
     // Test case: Invalid role (not possible since Role is enum with predefined variants)
     // If validation logic for role is needed, it can be added in the `Role`.
   }
@@ -0,0 +1,10 @@
+use serde::Deserialize;
+use utoipa::IntoParams;
+
+#[derive(IntoParams, Debug, Clone, Default, Deserialize)]
+#[into_params(parameter_in = Query)]
+pub struct CreateUserQuery {
+  /// Only check the body and that the email is free, without creating the
+  /// user.
+  pub validate: Option<bool>,
+}
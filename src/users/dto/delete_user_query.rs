@@ -0,0 +1,9 @@
+use serde::Deserialize;
+use utoipa::IntoParams;
+
+#[derive(IntoParams, Debug, Clone, Deserialize)]
+#[into_params(parameter_in = Query)]
+pub struct DeleteUserQuery {
+  /// Permanently remove the user instead of soft-deleting it.
+  pub hard: Option<bool>,
+}
@@ -0,0 +1,76 @@
+use std::str::FromStr;
+
+use serde::Deserialize;
+use utoipa::IntoParams;
+
+use crate::{shared::role::Role, users::repository::user_repository::UserSort};
+
+#[derive(IntoParams, Debug, Clone, Default, Deserialize)]
+#[into_params(parameter_in = Query)]
+pub struct GetUsersQuery {
+  /// `nextCursor` of the previous page. Omit to start from the beginning.
+  pub cursor: Option<String>,
+  /// Maximum users per page, 50 by default and at most 100.
+  pub limit: Option<usize>,
+  /// Only list users with this role.
+  pub role: Option<Role>,
+  /// Only list enabled, or with `false` disabled, users.
+  pub enabled: Option<bool>,
+  /// `createdAt`, oldest first, by default, or `email`. Users sorting alike
+  /// are ordered by `uuid`. Keep it the same across pages.
+  pub sort: Option<UserSort>,
+  /// Comma-separated subset of `email,userName,role,uuid` to return for
+  /// each user. Every field is returned when omitted.
+  pub fields: Option<String>,
+}
+
+impl GetUsersQuery {
+  /// Parses `fields`, failing with the first unknown name.
+  pub fn fields(&self) -> Result<Option<Vec<UserField>>, String> {
+    self
+      .fields
+      .as_deref()
+      .map(|fields| {
+        fields
+          .split(',')
+          .map(|field| field.trim().parse())
+          .collect()
+      })
+      .transpose()
+  }
+}
+
+/// A field `get_users` can narrow each user down to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserField {
+  Email,
+  UserName,
+  Role,
+  Uuid,
+}
+
+impl UserField {
+  /// Key of the field in a serialized `FindUserRto`.
+  pub fn key(&self) -> &'static str {
+    match self {
+      UserField::Email => "email",
+      UserField::UserName => "userName",
+      UserField::Role => "role",
+      UserField::Uuid => "uuid",
+    }
+  }
+}
+
+impl FromStr for UserField {
+  type Err = String;
+
+  fn from_str(field: &str) -> Result<Self, Self::Err> {
+    match field {
+      "email" => Ok(UserField::Email),
+      "userName" => Ok(UserField::UserName),
+      "role" => Ok(UserField::Role),
+      "uuid" => Ok(UserField::Uuid),
+      field => Err(field.to_string()),
+    }
+  }
+}
@@ -1 +1,8 @@
 pub mod create_user_dto;
+pub mod create_user_query;
+pub mod delete_user_query;
+pub mod get_users_query;
+pub mod register_user_dto;
+pub mod reset_password_dto;
+pub mod update_user_status_dto;
+pub mod user_exists_query;
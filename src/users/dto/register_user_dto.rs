@@ -0,0 +1,65 @@
+use serde::Deserialize;
+use utoipa::ToSchema;
+use validator::ValidationError;
+use validator_derive::Validate;
+
+use crate::shared::{normalize::deserialize_email, role::Role};
+
+use super::create_user_dto::{
+  validate_password_confirm, CreateUserDto, PASSWORD_MAX_LENGTH,
+  PASSWORD_MIN_LENGTH,
+};
+
+/// Self-signup body. The role is limited to `Config::self_signup_roles`
+/// and defaults to customer.
+#[derive(ToSchema, Debug, Clone, Deserialize, Validate)]
+#[validate(schema(function = "validate_register_user_dto"))]
+pub struct RegisterUserDto {
+  #[validate(email(message = "invalid email"))]
+  #[schema(example = "jane.doe@example.com")]
+  #[serde(deserialize_with = "deserialize_email")]
+  pub email: String,
+  #[serde(rename = "userName")]
+  #[validate(length(
+    max = 1024,
+    min = 1,
+    message = "User name must have at least 1 characters"
+  ))]
+  #[schema(example = "Jane Doe")]
+  pub user_name: String,
+  #[validate(length(
+    max = PASSWORD_MAX_LENGTH,
+    min = PASSWORD_MIN_LENGTH,
+    message = "Password must have at least 1 characters"
+  ))]
+  #[schema(example = "correct-horse-battery-staple")]
+  pub password: String,
+  /// Checked against `password` when sent, for forms that ask twice.
+  #[serde(rename = "passwordConfirm", default)]
+  #[schema(example = "correct-horse-battery-staple")]
+  pub password_confirm: Option<String>,
+  #[serde(default = "default_role")]
+  pub role: Role,
+}
+
+fn default_role() -> Role {
+  Role::Customer
+}
+
+fn validate_register_user_dto(
+  dto: &RegisterUserDto,
+) -> Result<(), ValidationError> {
+  validate_password_confirm(&dto.password, dto.password_confirm.as_deref())
+}
+
+impl From<RegisterUserDto> for CreateUserDto {
+  fn from(dto: RegisterUserDto) -> Self {
+    Self {
+      email: dto.email,
+      user_name: dto.user_name,
+      password: dto.password,
+      password_confirm: dto.password_confirm,
+      role: dto.role,
+    }
+  }
+}
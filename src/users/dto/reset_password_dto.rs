@@ -0,0 +1,20 @@
+use serde::Deserialize;
+use utoipa::ToSchema;
+use validator_derive::Validate;
+
+use super::create_user_dto::{PASSWORD_MAX_LENGTH, PASSWORD_MIN_LENGTH};
+
+#[derive(ToSchema, Debug, Clone, Deserialize, Validate)]
+pub struct ResetPasswordDto {
+  #[validate(length(
+    max = PASSWORD_MAX_LENGTH,
+    min = PASSWORD_MIN_LENGTH,
+    message = "Password must have at least 1 characters"
+  ))]
+  #[schema(example = "temporary-password")]
+  pub password: String,
+  /// Makes the user pick a new password at their next login.
+  #[serde(rename = "mustChangePassword", default)]
+  #[schema(example = true)]
+  pub must_change_password: bool,
+}
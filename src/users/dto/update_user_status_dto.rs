@@ -0,0 +1,8 @@
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+#[derive(ToSchema, Debug, Clone, Deserialize)]
+pub struct UpdateUserStatusDto {
+  #[schema(example = false)]
+  pub enabled: bool,
+}
@@ -0,0 +1,12 @@
+use serde::Deserialize;
+use utoipa::IntoParams;
+
+use crate::shared::normalize::deserialize_email;
+
+#[derive(IntoParams, Debug, Clone, Deserialize)]
+#[into_params(parameter_in = Query)]
+pub struct UserExistsQuery {
+  /// Email to look up.
+  #[serde(deserialize_with = "deserialize_email")]
+  pub email: String,
+}
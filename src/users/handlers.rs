@@ -1,93 +1,732 @@
 use actix_web::http::header;
-use actix_web::{web, HttpResponse, Responder};
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
 use chrono::Utc;
+use serde_json::{Map, Value};
+use std::hash::{DefaultHasher, Hash, Hasher as _};
+
 use validator::Validate;
 
 use super::dto::create_user_dto::CreateUserDto;
+use super::dto::create_user_query::CreateUserQuery;
+use super::dto::delete_user_query::DeleteUserQuery;
+use super::dto::get_users_query::{GetUsersQuery, UserField};
+use super::dto::register_user_dto::RegisterUserDto;
+use super::dto::reset_password_dto::ResetPasswordDto;
+use super::dto::update_user_status_dto::UpdateUserStatusDto;
+use super::dto::user_exists_query::UserExistsQuery;
 use super::rto::find_user_rto::FindUserRto;
+use super::rto::find_users_rto::{FindUsersRto, ProjectedUsersRto};
+use super::rto::hash_migration_rto::HashMigrationRto;
 
-use crate::custom_nanoid;
-use crate::shared::hash_worker::Hasher;
-use crate::shared::http_error::HttpError;
+use crate::auth::handlers::generate_verification_token;
+use crate::auth::rto::verification_token_rto::VerificationTokenRto;
+use crate::configured_nanoid;
+use crate::shared::audit_log::{AuditEntry, AuditEvent, AuditLog};
+use crate::shared::breach_check::{is_breached, BreachClient};
+use crate::shared::clock::Clock;
+use crate::shared::config::Config;
+use crate::shared::hash_worker::{is_outdated, Hasher};
+use crate::shared::http_error::{
+  breached_password, database_timeout, hashing_failed, ErrorCode, HttpError,
+};
+use crate::shared::idempotency::{IdempotencyStore, Reservation};
+use crate::shared::mailer::{send_in_background, Mail, Mailer};
 use crate::shared::rto::created_rto::CreatedRto;
+use crate::shared::webhook::Webhook;
+use crate::users::model::email::Email;
 use crate::users::model::user::User;
+use crate::users::model::user_id::UserId;
 use crate::users::repository::user_repository::{
-  FindOneProperty, UserRepository,
+  FindOneProperty, UserFilter, UserPage, UserRepository, UserRepositoryError,
+  UserSort,
 };
 
+const DEFAULT_PAGE_SIZE: usize = 50;
+const MAX_PAGE_SIZE: usize = 100;
+/// Outdated users listed by uuid in the hash migration report, which keeps
+/// counting past them.
+const MAX_OUTDATED_UUIDS: usize = 100;
+
+const IDEMPOTENCY_KEY: &str = "Idempotency-Key";
+const CREATE_USER_SCOPE: &str = "POST /v1/users";
+
 #[utoipa::path(
   post,
   path = "/users",
+  request_body = CreateUserDto,
+  params(
+    ("Idempotency-Key" = Option<String>, Header, description = "Retries with the same key get the original result back instead of creating another user"),
+    CreateUserQuery
+  ),
   responses(
-    (status = 200, description = "Create a user", body = CreatedRto)
+    (status = 200, description = "Create a user, or with `validate=true` only confirm it could be", body = CreatedRto),
+    (status = 400, description = "Body isn't valid JSON for the schema"),
+    (status = 409, description = "Email, or user name with `ENFORCE_UNIQUE_USERNAME`, already in use, or a request with the same Idempotency-Key is still in progress"),
+    (status = 422, description = "Body breaks a validation rule, or the Idempotency-Key was first sent with another body")
   )
 )]
-pub async fn create_user<UR: UserRepository, H: Hasher>(
+#[allow(clippy::too_many_arguments)]
+pub async fn create_user<
+  UR: UserRepository,
+  H: Hasher,
+  A: AuditLog,
+  C: Clock,
+  B: BreachClient,
+>(
+  config: web::Data<Config>,
+  clock: web::Data<C>,
   user_repository: web::Data<UR>,
   hasher: web::Data<H>,
+  webhook: web::Data<Webhook>,
+  breach_client: web::Data<B>,
+  audit_log: web::Data<A>,
+  idempotency: web::Data<IdempotencyStore<CreatedRto>>,
+  request: HttpRequest,
+  query: web::Query<CreateUserQuery>,
   dto: web::Json<CreateUserDto>,
 ) -> impl Responder {
   // Perform validation
   if let Err(validation_errors) = dto.validate() {
-    // If validation fails, return a 400 error with details
-    return HttpResponse::BadRequest().json(validation_errors);
+    // If validation fails, return a 422 error with details
+    return HttpResponse::UnprocessableEntity()
+      .json(HttpError::from(validation_errors));
   }
 
-  let user = user_repository
-    .find_one(FindOneProperty::Email(&dto.email))
-    .await;
+  // A dry run never hashes or stores, so it's neither replayed nor reserved.
+  if query.validate.unwrap_or(false) {
+    let email = match Email::try_from(dto.email.as_str()) {
+      Ok(email) => email,
+      Err(error) => return invalid_email(error),
+    };
+    if config.check_pwned_passwords
+      && is_breached(breach_client.as_ref(), &dto.password).await
+    {
+      return breached_password("password");
+    }
+    return match ensure_available(
+      &config,
+      user_repository.as_ref(),
+      &email,
+      &dto.user_name,
+    )
+    .await
+    {
+      Ok(()) => HttpResponse::Ok().finish(),
+      Err(response) => response,
+    };
+  }
+
+  let idempotency_key = request
+    .headers()
+    .get(IDEMPOTENCY_KEY)
+    .and_then(|value| value.to_str().ok())
+    .map(String::from);
+  // Held until the user is created, failing or being cancelled before
+  // then frees the key for a retry.
+  let reserved = match &idempotency_key {
+    Some(key) => {
+      let mut fingerprint = DefaultHasher::new();
+      dto.hash(&mut fingerprint);
+      match idempotency.reserve(
+        CREATE_USER_SCOPE,
+        key,
+        fingerprint.finish(),
+        clock.now(),
+      ) {
+        Reservation::Reserved(reserved) => Some(reserved),
+        Reservation::InProgress => return idempotency_key_in_use(),
+        Reservation::Mismatch => return idempotency_key_mismatch(),
+        Reservation::Completed(rto) => return created(&config, rto),
+      }
+    }
+    None => None,
+  };
+
+  let result = insert_user(
+    &config,
+    user_repository.as_ref(),
+    hasher.as_ref(),
+    &webhook,
+    breach_client.as_ref(),
+    audit_log.as_ref(),
+    &request,
+    dto.into_inner(),
+  )
+  .await;
+  // Only creations are replayed, a failed attempt may be retried as is.
+  match result {
+    Ok(rto) => {
+      if let Some(reserved) = reserved {
+        reserved.complete(rto.clone(), clock.now());
+      }
+      created(&config, rto)
+    }
+    Err(response) => response,
+  }
+}
+
+#[utoipa::path(
+  post,
+  path = "/auth/register",
+  request_body = RegisterUserDto,
+  responses(
+    (status = 201, description = "Sign up, as a customer unless another allowed role is picked", body = CreatedRto),
+    (status = 400, description = "Body isn't valid JSON for the schema"),
+    (status = 403, description = "Role isn't open to self-signup"),
+    (status = 404, description = "Self-signup is disabled"),
+    (status = 409, description = "Email, or user name with `ENFORCE_UNIQUE_USERNAME`, already in use"),
+    (status = 422, description = "Body breaks a validation rule")
+  )
+)]
+#[allow(clippy::too_many_arguments)]
+pub async fn register_user<
+  UR: UserRepository,
+  H: Hasher,
+  A: AuditLog,
+  B: BreachClient,
+>(
+  config: web::Data<Config>,
+  user_repository: web::Data<UR>,
+  hasher: web::Data<H>,
+  webhook: web::Data<Webhook>,
+  breach_client: web::Data<B>,
+  audit_log: web::Data<A>,
+  request: HttpRequest,
+  dto: web::Json<RegisterUserDto>,
+) -> impl Responder {
+  // Answer as if the route didn't exist, so closed deployments don't
+  // advertise it.
+  if !config.allow_self_signup {
+    return HttpResponse::NotFound().finish();
+  }
+  if let Err(validation_errors) = dto.validate() {
+    return HttpResponse::UnprocessableEntity()
+      .json(HttpError::from(validation_errors));
+  }
+  if !config.self_signup_roles.contains(&dto.role) {
+    return HttpResponse::Forbidden()
+      .content_type("application/json")
+      .json(
+        HttpError::from(
+          format!("Role `{}` can't be self-assigned", dto.role.as_str())
+            .as_str(),
+        )
+        .with_code(ErrorCode::RoleNotAllowed),
+      );
+  }
+
+  insert_user(
+    &config,
+    user_repository.as_ref(),
+    hasher.as_ref(),
+    &webhook,
+    breach_client.as_ref(),
+    audit_log.as_ref(),
+    &request,
+    dto.into_inner().into(),
+  )
+  .await
+  .map_or_else(|response| response, |rto| created(&config, rto))
+}
 
-  if user.is_ok() {
-    return user_already_exists();
+/// Hashes the password and stores a new user, unless the email is taken
+/// or, when checked, the password breached.
+#[allow(clippy::too_many_arguments)]
+async fn insert_user<
+  UR: UserRepository,
+  H: Hasher,
+  A: AuditLog,
+  B: BreachClient,
+>(
+  config: &Config,
+  user_repository: &UR,
+  hasher: &H,
+  webhook: &Webhook,
+  breach_client: &B,
+  audit_log: &A,
+  request: &HttpRequest,
+  dto: CreateUserDto,
+) -> Result<CreatedRto, HttpResponse> {
+  let email = Email::try_from(dto.email.as_str()).map_err(invalid_email)?;
+  ensure_available(config, user_repository, &email, &dto.user_name).await?;
+  if config.check_pwned_passwords
+    && is_breached(breach_client, &dto.password).await
+  {
+    return Err(breached_password("password"));
   }
 
-  let password_hash_result = hasher.as_ref().hash_password(&dto.password).await;
+  let password_hash_result = hasher.hash_password(&dto.password).await;
 
   if let Err(error) = password_hash_result {
     eprintln!("{}", error);
-    return internal_server_error();
+    return Err(hashing_failed());
   }
   let password_hash = password_hash_result.unwrap();
   // Create a domain User from the DTO.
-  let user = User::from(dto.into_inner(), password_hash);
+  let uuid = UserId::try_from(configured_nanoid(config))
+    .expect("NANOID_ALPHABET is checked to only generate valid ids");
+  let user = User::new(uuid, email, dto, password_hash);
 
   user_repository
-    .create(user.clone())
+    .create(user)
     .await
-    .map(|_| {
-      HttpResponse::Created()
-        .content_type("application/json")
-        .append_header((header::LOCATION, format!("/v1/users/{}", &user.uuid)))
-        .json(CreatedRto::from(user))
+    .map(|user| {
+      audit_log.record(
+        AuditEntry::new(AuditEvent::UserCreated, request).target(&user.uuid),
+      );
+      webhook.user_created(&user);
+      CreatedRto::from(user)
     })
-    .unwrap_or_else(|error| {
-      eprintln!("{}", error);
-      internal_server_error()
+    .map_err(|error| match error {
+      UserRepositoryError::UserNameTaken => user_name_taken(),
+      UserRepositoryError::EmailTaken => user_already_exists(),
+      error => {
+        eprintln!("{}", error);
+        internal_server_error()
+      }
     })
 }
 
+/// Refuses an email already in use and, with `ENFORCE_UNIQUE_USERNAME`, a
+/// taken user name.
+async fn ensure_available<UR: UserRepository>(
+  config: &Config,
+  user_repository: &UR,
+  email: &Email,
+  user_name: &str,
+) -> Result<(), HttpResponse> {
+  let mut lookups =
+    vec![(FindOneProperty::Email(email), user_already_exists())];
+  if config.enforce_unique_username {
+    lookups.push((FindOneProperty::UserName(user_name), user_name_taken()));
+  }
+  for (property, taken) in lookups {
+    match user_repository.find_one(property).await {
+      Ok(_) => return Err(taken),
+      Err(UserRepositoryError::NotFound) => {}
+      Err(error) => return Err(repository_error(error)),
+    }
+  }
+  Ok(())
+}
+
+/// Response to an email `Email` refuses. DTOs validate emails by the same
+/// rules, so this is only a safeguard.
+fn invalid_email(error: String) -> HttpResponse {
+  HttpResponse::UnprocessableEntity().json(
+    HttpError::from(error.as_str()).with_code(ErrorCode::ValidationFailed),
+  )
+}
+
+/// Response to a new user, pointing at it with `Location`.
+fn created(config: &Config, rto: CreatedRto) -> HttpResponse {
+  HttpResponse::Created()
+    .content_type("application/json")
+    .append_header((header::LOCATION, user_location(config, &rto.uuid)))
+    .json(rto)
+}
+
 #[utoipa::path(
   get,
   path = "/users",
+  description = "Breaking change: the response used to be a bare array of every user. It's now a page, `{ users, nextCursor }`, to be followed with `cursor` until `nextCursor` is absent.",
+  params(GetUsersQuery),
   responses(
-    (status = 200, description = "List users", body = FindUserRto)
+    (status = 200, description = "List users a page at a time, narrowed to `fields` when given. An empty directory is an empty `users` list", body = FindUsersRto),
+    (status = 400, description = "Invalid cursor, filter or field"),
+    (status = 500, description = "The database failed, the page may not be empty")
   )
 )]
 pub async fn get_users<UR: UserRepository>(
   user_repository: web::Data<UR>,
+  query: web::Query<GetUsersQuery>,
+) -> impl Responder {
+  let limit = query
+    .limit
+    .unwrap_or(DEFAULT_PAGE_SIZE)
+    .clamp(1, MAX_PAGE_SIZE);
+  let fields = match query.fields() {
+    Ok(fields) => fields,
+    Err(field) => return invalid_field(&field),
+  };
+  let filter = UserFilter {
+    role: query.role.clone(),
+    enabled: query.enabled,
+  };
+  user_repository
+    .find_all(
+      &filter,
+      query.sort.unwrap_or_default(),
+      query.cursor.as_deref(),
+      limit,
+    )
+    .await
+    .map(|page| {
+      let rto = FindUsersRto::from(page);
+      let mut response = HttpResponse::Ok();
+      response.content_type("application/json");
+      match fields {
+        Some(fields) => response.json(project(rto, &fields)),
+        None => response.json(rto),
+      }
+    })
+    .unwrap_or_else(repository_error)
+}
+
+#[utoipa::path(
+  get,
+  path = "/users/hash-migration",
+  responses(
+    (status = 200, description = "Count users whose password hash predates the current parameters, listing the first 100 of them", body = HashMigrationRto)
+  )
+)]
+pub async fn get_hash_migration<UR: UserRepository>(
+  user_repository: web::Data<UR>,
+) -> impl Responder {
+  let mut total = 0;
+  let mut outdated = 0;
+  let mut outdated_uuids = Vec::new();
+  let mut cursor = None;
+  loop {
+    let page = match user_repository
+      .find_all(
+        &UserFilter::default(),
+        UserSort::default(),
+        cursor.as_deref(),
+        MAX_PAGE_SIZE,
+      )
+      .await
+    {
+      Ok(page) => page,
+      Err(error) => return repository_error(error),
+    };
+    total += page.users.len();
+    for user in page.users {
+      if !is_outdated(&user.password_hash) {
+        continue;
+      }
+      outdated += 1;
+      if outdated_uuids.len() < MAX_OUTDATED_UUIDS {
+        outdated_uuids.push(user.uuid);
+      }
+    }
+    cursor = page.next_cursor;
+    if cursor.is_none() {
+      break;
+    }
+  }
+
+  let migrated_percent = match total {
+    0 => 100.0,
+    total => (total - outdated) as f64 * 100.0 / total as f64,
+  };
+  HttpResponse::Ok()
+    .content_type("application/json")
+    .json(HashMigrationRto {
+      total,
+      outdated,
+      migrated_percent,
+      outdated_uuids,
+    })
+}
+
+#[utoipa::path(
+  head,
+  path = "/users",
+  params(UserExistsQuery),
+  responses(
+    (status = 200, description = "A user with this email exists"),
+    (status = 404, description = "No user with this email")
+  )
+)]
+pub async fn user_exists<UR: UserRepository>(
+  user_repository: web::Data<UR>,
+  query: web::Query<UserExistsQuery>,
+) -> impl Responder {
+  // Lets signup forms flag a taken email early. Only reachable with the
+  // `users:read` permission, as it would otherwise allow enumerating
+  // accounts.
+  // An invalid email can't belong to anyone.
+  let Ok(email) = Email::try_from(query.email.as_str()) else {
+    return HttpResponse::NotFound().finish();
+  };
+  match user_repository
+    .find_one(FindOneProperty::Email(&email))
+    .await
+  {
+    Ok(_) => HttpResponse::Ok().finish(),
+    Err(UserRepositoryError::NotFound) => HttpResponse::NotFound().finish(),
+    Err(error) => {
+      eprintln!("{}", error);
+      internal_server_error()
+    }
+  }
+}
+
+#[utoipa::path(
+  get,
+  path = "/users/{uuid}",
+  params(
+    ("uuid" = String, Path, description = "Uuid of the user to fetch")
+  ),
+  responses(
+    (status = 200, description = "Get a user", body = FindUserRto),
+    (status = 404, description = "User not found")
+  )
+)]
+pub async fn get_user<UR: UserRepository>(
+  user_repository: web::Data<UR>,
+  uuid: web::Path<UserId>,
 ) -> impl Responder {
   user_repository
-    .find_all()
+    .find_one(FindOneProperty::Uuid(&uuid))
     .await
-    .map(|users| {
-      HttpResponse::Created()
+    .map(|user| {
+      HttpResponse::Ok()
         .content_type("application/json")
-        .json(
-          users
-            .into_iter()
-            .map(FindUserRto::from)
-            .collect::<Vec<FindUserRto>>(),
-        )
+        .json(FindUserRto::from(user))
+    })
+    .unwrap_or_else(repository_error)
+}
+
+#[utoipa::path(
+  delete,
+  path = "/users/{uuid}",
+  params(
+    ("uuid" = String, Path, description = "Uuid of the user to delete"),
+    DeleteUserQuery
+  ),
+  responses(
+    (status = 204, description = "Soft-delete a user, or remove it with `hard=true`"),
+    (status = 404, description = "User not found")
+  )
+)]
+pub async fn delete_user<UR: UserRepository, A: AuditLog>(
+  user_repository: web::Data<UR>,
+  audit_log: web::Data<A>,
+  request: HttpRequest,
+  uuid: web::Path<UserId>,
+  query: web::Query<DeleteUserQuery>,
+) -> impl Responder {
+  let deleted = || {
+    audit_log
+      .record(AuditEntry::new(AuditEvent::UserDeleted, &request).target(&uuid));
+    HttpResponse::NoContent().finish()
+  };
+
+  if query.hard.unwrap_or(false) {
+    return user_repository
+      .delete(&uuid)
+      .await
+      .map(|_| deleted())
+      .unwrap_or_else(repository_error);
+  }
+
+  let mut user =
+    match user_repository.find_one(FindOneProperty::Uuid(&uuid)).await {
+      Ok(user) => user,
+      Err(error) => return repository_error(error),
+    };
+
+  let now = Utc::now();
+  user.deleted_at = Some(now);
+  user.touch(now);
+  user_repository
+    .update(user)
+    .await
+    .map(|_| deleted())
+    .unwrap_or_else(repository_error)
+}
+
+#[utoipa::path(
+  put,
+  path = "/users/{uuid}/status",
+  params(
+    ("uuid" = String, Path, description = "Uuid of the user to update")
+  ),
+  request_body = UpdateUserStatusDto,
+  responses(
+    (status = 204, description = "Enable or disable a user account"),
+    (status = 404, description = "User not found")
+  )
+)]
+pub async fn update_user_status<UR: UserRepository, A: AuditLog>(
+  user_repository: web::Data<UR>,
+  audit_log: web::Data<A>,
+  request: HttpRequest,
+  uuid: web::Path<UserId>,
+  dto: web::Json<UpdateUserStatusDto>,
+) -> impl Responder {
+  let mut user =
+    match user_repository.find_one(FindOneProperty::Uuid(&uuid)).await {
+      Ok(user) => user,
+      Err(error) => return repository_error(error),
+    };
+
+  user.enabled = dto.enabled;
+  user.touch(Utc::now());
+  user_repository
+    .update(user)
+    .await
+    .map(|_| {
+      audit_log.record(
+        AuditEntry::new(AuditEvent::UserStatusChanged, &request).target(&uuid),
+      );
+      HttpResponse::NoContent().finish()
+    })
+    .unwrap_or_else(repository_error)
+}
+
+#[utoipa::path(
+  post,
+  path = "/users/{uuid}/password",
+  params(
+    ("uuid" = String, Path, description = "Uuid of the user whose password is reset")
+  ),
+  request_body = ResetPasswordDto,
+  responses(
+    (status = 204, description = "Set a new password, signing the user out everywhere"),
+    (status = 404, description = "User not found"),
+    (status = 422, description = "Body breaks a validation rule, or the password appears in a known breach")
+  )
+)]
+#[allow(clippy::too_many_arguments)]
+pub async fn reset_password<
+  UR: UserRepository,
+  H: Hasher,
+  A: AuditLog,
+  C: Clock,
+  M: Mailer + 'static,
+  B: BreachClient,
+>(
+  config: web::Data<Config>,
+  clock: web::Data<C>,
+  user_repository: web::Data<UR>,
+  hasher: web::Data<H>,
+  audit_log: web::Data<A>,
+  mailer: web::Data<M>,
+  breach_client: web::Data<B>,
+  request: HttpRequest,
+  uuid: web::Path<UserId>,
+  dto: web::Json<ResetPasswordDto>,
+) -> impl Responder {
+  if let Err(validation_errors) = dto.validate() {
+    return HttpResponse::UnprocessableEntity()
+      .json(HttpError::from(validation_errors));
+  }
+  if config.check_pwned_passwords
+    && is_breached(breach_client.as_ref(), &dto.password).await
+  {
+    return breached_password("password");
+  }
+
+  let mut user =
+    match user_repository.find_one(FindOneProperty::Uuid(&uuid)).await {
+      Ok(user) => user,
+      Err(error) => return repository_error(error),
+    };
+
+  user.password_hash = match hasher.hash_password(&dto.password).await {
+    Ok(password_hash) => password_hash,
+    Err(error) => {
+      eprintln!("{}", error);
+      return hashing_failed();
+    }
+  };
+  let now = clock.now();
+  user.password_changed_at = Some(now);
+  user.must_change_password = dto.must_change_password;
+  // Whoever knew the old password may still hold a refresh token.
+  user.token_epoch = user.token_epoch.wrapping_add(1);
+  user.touch(now);
+  let mail = Mail::password_reset(&user.email);
+  user_repository
+    .update(user)
+    .await
+    .map(|_| {
+      audit_log.record(
+        AuditEntry::new(AuditEvent::PasswordReset, &request).target(&uuid),
+      );
+      send_in_background(mailer.into_inner(), mail);
+      HttpResponse::NoContent().finish()
+    })
+    .unwrap_or_else(repository_error)
+}
+
+#[utoipa::path(
+  post,
+  path = "/users/{uuid}/revoke-tokens",
+  params(
+    ("uuid" = String, Path, description = "Uuid of the user to sign out")
+  ),
+  responses(
+    (status = 204, description = "Reject every refresh and access token issued to the user so far, forcing a new login"),
+    (status = 404, description = "User not found")
+  )
+)]
+pub async fn revoke_tokens<UR: UserRepository, A: AuditLog>(
+  user_repository: web::Data<UR>,
+  audit_log: web::Data<A>,
+  request: HttpRequest,
+  uuid: web::Path<UserId>,
+) -> impl Responder {
+  let mut user =
+    match user_repository.find_one(FindOneProperty::Uuid(&uuid)).await {
+      Ok(user) => user,
+      Err(error) => return repository_error(error),
+    };
+
+  user.token_epoch = user.token_epoch.wrapping_add(1);
+  user.touch(Utc::now());
+  user_repository
+    .update(user)
+    .await
+    .map(|_| {
+      audit_log.record(
+        AuditEntry::new(AuditEvent::TokensRevoked, &request).target(&uuid),
+      );
+      HttpResponse::NoContent().finish()
+    })
+    .unwrap_or_else(repository_error)
+}
+
+#[utoipa::path(
+  post,
+  path = "/users/{uuid}/verification-token",
+  params(
+    ("uuid" = String, Path, description = "Uuid of the user to verify")
+  ),
+  responses(
+    (status = 200, description = "Issue an email verification token for delivery to the user", body = VerificationTokenRto),
+    (status = 404, description = "User not found")
+  )
+)]
+pub async fn create_verification_token<
+  UR: UserRepository,
+  C: Clock,
+  M: Mailer + 'static,
+>(
+  config: web::Data<Config>,
+  clock: web::Data<C>,
+  user_repository: web::Data<UR>,
+  mailer: web::Data<M>,
+  uuid: web::Path<UserId>,
+) -> impl Responder {
+  let user = match user_repository.find_one(FindOneProperty::Uuid(&uuid)).await
+  {
+    Ok(user) => user,
+    Err(error) => return repository_error(error),
+  };
+
+  generate_verification_token(&config, clock.now(), &user)
+    .map(|verification_token| {
+      send_in_background(
+        mailer.into_inner(),
+        Mail::verification(&user.email, &verification_token),
+      );
+      HttpResponse::Ok()
+        .content_type("application/json")
+        .json(VerificationTokenRto { verification_token })
     })
     .unwrap_or_else(|error| {
       eprintln!("{}", error);
@@ -98,35 +737,165 @@ pub async fn get_users<UR: UserRepository>(
 impl From<User> for FindUserRto {
   fn from(user: User) -> Self {
     Self {
+      uuid: user.uuid,
       email: user.email,
       user_name: user.user_name,
       role: user.role,
+      created_at: user.created_at,
+      updated_at: user.updated_at,
+    }
+  }
+}
+
+/// Link to the user's `get_user` endpoint, absolute when a public base URL
+/// is configured.
+fn user_location(config: &Config, uuid: &str) -> String {
+  format!(
+    "{}/v1/users/{}",
+    config.public_base_url.as_deref().unwrap_or_default(),
+    uuid
+  )
+}
+
+impl From<UserPage> for FindUsersRto {
+  fn from(page: UserPage) -> Self {
+    Self {
+      users: page.users.into_iter().map(FindUserRto::from).collect(),
+      next_cursor: page.next_cursor,
     }
   }
 }
 
+/// Narrows each user down to `fields`, in their serialized form.
+fn project(rto: FindUsersRto, fields: &[UserField]) -> ProjectedUsersRto {
+  let users = rto
+    .users
+    .iter()
+    .map(|user| {
+      let Ok(Value::Object(mut user)) = serde_json::to_value(user) else {
+        return Map::new();
+      };
+      user.retain(|key, _| fields.iter().any(|field| field.key() == key));
+      user
+    })
+    .collect();
+  ProjectedUsersRto {
+    users,
+    next_cursor: rto.next_cursor,
+  }
+}
+
+fn invalid_field(field: &str) -> HttpResponse {
+  let message = format!(
+    "Invalid field `{}`, expected a subset of email, userName, role, uuid",
+    field
+  );
+  HttpResponse::BadRequest()
+    .content_type("application/json")
+    .json(HttpError::from(message.as_str()).with_code(ErrorCode::InvalidField))
+}
+
+fn idempotency_key_in_use() -> HttpResponse {
+  HttpResponse::Conflict()
+    .content_type("application/json")
+    .json(
+      HttpError::from("A request with this Idempotency-Key is in progress")
+        .with_code(ErrorCode::IdempotencyKeyInUse),
+    )
+}
+
+fn idempotency_key_mismatch() -> HttpResponse {
+  HttpResponse::UnprocessableEntity()
+    .content_type("application/json")
+    .json(
+      HttpError::from("This Idempotency-Key was sent with another body")
+        .with_code(ErrorCode::IdempotencyKeyMismatch),
+    )
+}
+
 fn user_already_exists() -> HttpResponse {
   HttpResponse::Conflict()
     .content_type("application/json")
-    .json(HttpError::from("User already exists"))
+    .json(
+      HttpError::from("User already exists")
+        .with_code(ErrorCode::UserAlreadyExists),
+    )
+}
+
+fn user_name_taken() -> HttpResponse {
+  HttpResponse::Conflict()
+    .content_type("application/json")
+    .json(
+      HttpError::from("User name already taken")
+        .with_code(ErrorCode::UserNameTaken),
+    )
+}
+
+fn user_not_found() -> HttpResponse {
+  HttpResponse::NotFound()
+    .content_type("application/json")
+    .json(HttpError::from("User not found").with_code(ErrorCode::UserNotFound))
 }
 
 fn internal_server_error() -> HttpResponse {
   HttpResponse::InternalServerError().finish()
 }
 
+fn repository_error(error: UserRepositoryError) -> HttpResponse {
+  match error {
+    UserRepositoryError::NotFound => user_not_found(),
+    UserRepositoryError::InvalidCursor => HttpResponse::BadRequest()
+      .content_type("application/json")
+      .json(
+        HttpError::from("Invalid cursor").with_code(ErrorCode::InvalidCursor),
+      ),
+    UserRepositoryError::Timeout => {
+      eprintln!("{}", error);
+      database_timeout()
+    }
+    // Only the in-memory backend has no errors of its own.
+    #[allow(unreachable_patterns)]
+    error => {
+      eprintln!("{}", error);
+      internal_server_error()
+    }
+  }
+}
+
 impl User {
-  fn from(dto: CreateUserDto, password_hash: String) -> Self {
+  fn new(
+    uuid: UserId,
+    email: Email,
+    dto: CreateUserDto,
+    password_hash: String,
+  ) -> Self {
+    let now = Utc::now();
     Self {
-      uuid: custom_nanoid(),
-      email: dto.email,
+      uuid,
+      email,
       user_name: dto.user_name,
       password_hash,
       role: dto.role,
-      created_at: Utc::now(),
-      updated_at: Utc::now(),
+      created_at: now,
+      updated_at: now,
+      deleted_at: None,
+      enabled: true,
+      email_verified: false,
+      token_epoch: 0,
+      password_changed_at: None,
+      must_change_password: false,
     }
   }
+
+  #[cfg(test)]
+  fn from(dto: CreateUserDto, password_hash: String) -> Self {
+    Self::new(
+      UserId::try_from(crate::custom_nanoid()).unwrap(),
+      Email::try_from(dto.email.as_str()).unwrap(),
+      dto,
+      password_hash,
+    )
+  }
 }
 
 impl From<User> for CreatedRto {
@@ -137,9 +906,16 @@ impl From<User> for CreatedRto {
 
 #[cfg(test)]
 mod tests {
-  use std::sync::{Arc, RwLock};
+  use std::{
+    sync::{Arc, RwLock},
+    time::Duration,
+  };
 
-  use actix_web::{http::StatusCode, HttpRequest};
+  use actix_web::{
+    body::{BodySize, MessageBody},
+    http::StatusCode,
+    HttpRequest, ResponseError,
+  };
   use fake::{
     faker::{
       internet::en::{Password, SafeEmail},
@@ -152,8 +928,19 @@ mod tests {
 
   use crate::{
     custom_nanoid,
-    helpers::tests::{http_request, parse_http_response},
-    shared::{database::InMemoryDatabase, hash_worker::HashWorker, role::Role},
+    helpers::tests::{
+      fake_user, http_request, parse_http_response, FakeUserRepository,
+    },
+    shared::{
+      audit_log::InMemoryAuditLog,
+      breach_check::MockBreachClient,
+      clock::{FixedClock, SystemClock},
+      database::InMemoryDatabase,
+      hash_worker::{HashWorker, HashWorkerError, MockHasher},
+      mailer::{LogMailer, MockMailer},
+      role::Role,
+      webhook::Webhook,
+    },
     users::repository::user_repository::UserRepositoryImpl,
   };
 
@@ -167,6 +954,7 @@ mod tests {
       email: SafeEmail().fake(),
       user_name: Name(EN).fake(),
       password: Password(12..13).fake(),
+      password_confirm: None,
       role: Role::Customer,
     };
 
@@ -180,8 +968,16 @@ mod tests {
     let request: HttpRequest = http_request(&jwt_secret);
 
     let responder = create_user(
+      web::Data::new(Config::default().await),
+      web::Data::new(SystemClock),
       web::Data::new(user_repository),
       web::Data::new(hasher),
+      web::Data::new(Webhook::new(None)),
+      web::Data::new(MockBreachClient::new()),
+      web::Data::new(InMemoryAuditLog::new()),
+      web::Data::new(IdempotencyStore::new(Duration::from_secs(60))),
+      request.clone(),
+      web::Query(CreateUserQuery::default()),
       web::Json(dto),
     )
     .await;
@@ -197,42 +993,506 @@ mod tests {
   }
 
   #[actix_web::test]
-  async fn test_create_user_already_exists() {
-    let jwt_secret = custom_nanoid();
-
+  async fn test_create_user_rejects_breached_password() {
+    let config = Config {
+      check_pwned_passwords: true,
+      ..Config::default().await
+    };
     let dto = CreateUserDto {
       email: SafeEmail().fake(),
       user_name: Name(EN).fake(),
       password: Password(12..13).fake(),
+      password_confirm: None,
       role: Role::Customer,
     };
+    let breach_client = MockBreachClient::breached(&dto.password);
 
-    let users =
-      Arc::new(RwLock::new(vec![User::from(dto.clone(), String::new())]));
-    let database = Arc::new(InMemoryDatabase {
-      users: users.clone(),
-    });
-    let user_repository = UserRepositoryImpl::new(database);
-
-    let hasher = HashWorker::new(ThreadPoolBuilder::new().build().unwrap(), 2);
-
-    let request: HttpRequest = http_request(&jwt_secret);
-
+    let users = Arc::new(RwLock::new(Vec::new()));
+    let request: HttpRequest = http_request(&config.jwt_secret);
     let responder = create_user(
-      web::Data::new(user_repository),
-      web::Data::new(hasher),
+      web::Data::new(config),
+      web::Data::new(SystemClock),
+      web::Data::new(UserRepositoryImpl::new(Arc::new(InMemoryDatabase {
+        users: users.clone(),
+      }))),
+      web::Data::new(MockHasher::new()),
+      web::Data::new(Webhook::new(None)),
+      web::Data::new(breach_client),
+      web::Data::new(InMemoryAuditLog::new()),
+      web::Data::new(IdempotencyStore::new(Duration::from_secs(60))),
+      request.clone(),
+      web::Query(CreateUserQuery::default()),
       web::Json(dto),
     )
     .await;
 
-    let users = users.read().unwrap().clone();
-    assert_eq!(users.len(), 1);
+    let error: HttpError = parse_http_response(
+      responder,
+      &request,
+      StatusCode::UNPROCESSABLE_ENTITY,
+    )
+    .await;
+    assert_eq!(error.code, Some(ErrorCode::ValidationFailed));
+    assert!(error.errors.unwrap().contains_key("password"));
+    assert!(users.read().unwrap().is_empty());
+  }
+
+  async fn register(
+    allow_self_signup: bool,
+    users: Arc<RwLock<Vec<User>>>,
+    body: serde_json::Value,
+  ) -> HttpResponse {
+    let config = Config {
+      allow_self_signup,
+      self_signup_roles: vec![Role::Customer, Role::Driver],
+      ..Config::default().await
+    };
+    let request: HttpRequest = http_request(&config.jwt_secret);
+    register_user(
+      web::Data::new(config),
+      web::Data::new(UserRepositoryImpl::new(Arc::new(InMemoryDatabase {
+        users,
+      }))),
+      web::Data::new(HashWorker::new(
+        ThreadPoolBuilder::new().num_threads(1).build().unwrap(),
+        1,
+      )),
+      web::Data::new(Webhook::new(None)),
+      web::Data::new(MockBreachClient::new()),
+      web::Data::new(InMemoryAuditLog::new()),
+      request.clone(),
+      web::Json(serde_json::from_value(body).unwrap()),
+    )
+    .await
+    .respond_to(&request)
+    .map_into_boxed_body()
+  }
+
+  #[actix_web::test]
+  async fn test_register_user_defaults_to_customer_role() {
+    let users = Arc::new(RwLock::new(Vec::new()));
+    let body = serde_json::json!({
+      "email": SafeEmail().fake::<String>(),
+      "userName": Name(EN).fake::<String>(),
+      "password": Password(12..13).fake::<String>()
+    });
+
+    let response = register(true, users.clone(), body).await;
+
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let users = users.read().unwrap();
+    assert_eq!(users.len(), 1);
+    assert_eq!(users[0].role, Role::Customer);
+  }
+
+  #[actix_web::test]
+  async fn test_register_user_allowed_role() {
+    let users = Arc::new(RwLock::new(Vec::new()));
+    let body = serde_json::json!({
+      "email": SafeEmail().fake::<String>(),
+      "userName": Name(EN).fake::<String>(),
+      "password": Password(12..13).fake::<String>(),
+      "role": "driver"
+    });
+
+    let response = register(true, users.clone(), body).await;
+
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(users.read().unwrap()[0].role, Role::Driver);
+  }
+
+  #[actix_web::test]
+  async fn test_register_user_rejects_disallowed_role() {
+    let users = Arc::new(RwLock::new(Vec::new()));
+    let body = serde_json::json!({
+      "email": SafeEmail().fake::<String>(),
+      "userName": Name(EN).fake::<String>(),
+      "password": Password(12..13).fake::<String>(),
+      "role": "admin"
+    });
+
+    let response = register(true, users.clone(), body).await;
+
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let body = actix_web::body::to_bytes(response.into_body())
+      .await
+      .unwrap();
+    let error: HttpError = serde_json::from_slice(&body).unwrap();
+    assert_eq!(error.code, Some(ErrorCode::RoleNotAllowed));
+    assert!(users.read().unwrap().is_empty());
+  }
+
+  #[actix_web::test]
+  async fn test_register_user_rejects_mismatched_password_confirm() {
+    let users = Arc::new(RwLock::new(Vec::new()));
+    let body = serde_json::json!({
+      "email": SafeEmail().fake::<String>(),
+      "userName": Name(EN).fake::<String>(),
+      "password": "correct-horse-battery-staple",
+      "passwordConfirm": "correct-horse-battery-stapler"
+    });
+
+    let response = register(true, users.clone(), body).await;
+
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert!(users.read().unwrap().is_empty());
+  }
+
+  #[actix_web::test]
+  async fn test_register_user_disabled() {
+    let users = Arc::new(RwLock::new(Vec::new()));
+    let body = serde_json::json!({
+      "email": SafeEmail().fake::<String>(),
+      "userName": Name(EN).fake::<String>(),
+      "password": Password(12..13).fake::<String>()
+    });
+
+    let response = register(false, users.clone(), body).await;
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert!(users.read().unwrap().is_empty());
+  }
+
+  #[actix_web::test]
+  async fn test_create_user_replays_idempotency_key() {
+    let users = Arc::new(RwLock::new(Vec::new()));
+    let user_repository =
+      web::Data::new(UserRepositoryImpl::new(Arc::new(InMemoryDatabase {
+        users: users.clone(),
+      })));
+    let hasher = web::Data::new(HashWorker::new(
+      ThreadPoolBuilder::new().num_threads(1).build().unwrap(),
+      1,
+    ));
+    let idempotency =
+      web::Data::new(IdempotencyStore::new(Duration::from_secs(60)));
+    let config = web::Data::new(Config::default().await);
+    let dto = CreateUserDto {
+      email: SafeEmail().fake(),
+      user_name: Name(EN).fake(),
+      password: Password(12..13).fake(),
+      password_confirm: None,
+      role: Role::Customer,
+    };
+    let request = actix_web::test::TestRequest::default()
+      .insert_header((IDEMPOTENCY_KEY, "retry-me"))
+      .to_http_request();
+
+    let mut uuids = Vec::new();
+    for _ in 0..2 {
+      let responder = create_user(
+        config.clone(),
+        web::Data::new(SystemClock),
+        user_repository.clone(),
+        hasher.clone(),
+        web::Data::new(Webhook::new(None)),
+        web::Data::new(MockBreachClient::new()),
+        web::Data::new(InMemoryAuditLog::new()),
+        idempotency.clone(),
+        request.clone(),
+        web::Query(CreateUserQuery::default()),
+        web::Json(dto.clone()),
+      )
+      .await;
+      let rto: CreatedRto =
+        parse_http_response(responder, &request, StatusCode::CREATED).await;
+      uuids.push(rto.uuid);
+    }
+
+    // The retry got the original result rather than a conflict.
+    assert_eq!(uuids[0], uuids[1]);
+    assert_eq!(users.read().unwrap().len(), 1);
+
+    let responder = create_user(
+      config.clone(),
+      web::Data::new(SystemClock),
+      user_repository.clone(),
+      hasher.clone(),
+      web::Data::new(Webhook::new(None)),
+      web::Data::new(MockBreachClient::new()),
+      web::Data::new(InMemoryAuditLog::new()),
+      idempotency.clone(),
+      request.clone(),
+      web::Query(CreateUserQuery::default()),
+      web::Json(CreateUserDto {
+        email: SafeEmail().fake(),
+        ..dto
+      }),
+    )
+    .await;
+    let error: HttpError = parse_http_response(
+      responder,
+      &request,
+      StatusCode::UNPROCESSABLE_ENTITY,
+    )
+    .await;
+    assert_eq!(error.code, Some(ErrorCode::IdempotencyKeyMismatch));
+    assert_eq!(users.read().unwrap().len(), 1);
+  }
+
+  #[actix_web::test]
+  async fn test_create_user_hashing_failed() {
+    let users = Arc::new(RwLock::new(Vec::new()));
+    let user_repository =
+      web::Data::new(UserRepositoryImpl::new(Arc::new(InMemoryDatabase {
+        users: users.clone(),
+      })));
+    let mut hasher = MockHasher::new();
+    hasher
+      .expect_hash_password()
+      .returning(|_| Err(HashWorkerError::Receive));
+    let request = actix_web::test::TestRequest::default().to_http_request();
+
+    let responder = create_user(
+      web::Data::new(Config::default().await),
+      web::Data::new(SystemClock),
+      user_repository,
+      web::Data::new(hasher),
+      web::Data::new(Webhook::new(None)),
+      web::Data::new(MockBreachClient::new()),
+      web::Data::new(InMemoryAuditLog::new()),
+      web::Data::new(IdempotencyStore::new(Duration::from_secs(60))),
+      request.clone(),
+      web::Query(CreateUserQuery::default()),
+      web::Json(CreateUserDto {
+        email: SafeEmail().fake(),
+        user_name: Name(EN).fake(),
+        password: Password(12..13).fake(),
+        password_confirm: None,
+        role: Role::Customer,
+      }),
+    )
+    .await;
+
+    let error: HttpError = parse_http_response(
+      responder,
+      &request,
+      StatusCode::INTERNAL_SERVER_ERROR,
+    )
+    .await;
+    assert_eq!(error.code, Some(ErrorCode::HashingFailed));
+    // The underlying error stays in the logs.
+    assert_eq!(error.message, "Password hashing failed");
+    assert!(users.read().unwrap().is_empty());
+  }
+
+  #[actix_web::test]
+  async fn test_create_user_already_exists() {
+    let jwt_secret = custom_nanoid();
+
+    let dto = CreateUserDto {
+      email: SafeEmail().fake(),
+      user_name: Name(EN).fake(),
+      password: Password(12..13).fake(),
+      password_confirm: None,
+      role: Role::Customer,
+    };
+
+    let users =
+      Arc::new(RwLock::new(vec![User::from(dto.clone(), String::new())]));
+    let database = Arc::new(InMemoryDatabase {
+      users: users.clone(),
+    });
+    let user_repository = UserRepositoryImpl::new(database);
+
+    let hasher = HashWorker::new(ThreadPoolBuilder::new().build().unwrap(), 2);
+
+    let request: HttpRequest = http_request(&jwt_secret);
+
+    let responder = create_user(
+      web::Data::new(Config::default().await),
+      web::Data::new(SystemClock),
+      web::Data::new(user_repository),
+      web::Data::new(hasher),
+      web::Data::new(Webhook::new(None)),
+      web::Data::new(MockBreachClient::new()),
+      web::Data::new(InMemoryAuditLog::new()),
+      web::Data::new(IdempotencyStore::new(Duration::from_secs(60))),
+      request.clone(),
+      web::Query(CreateUserQuery::default()),
+      web::Json(dto),
+    )
+    .await;
+
+    let users = users.read().unwrap().clone();
+    assert_eq!(users.len(), 1);
 
     let error: HttpError =
       parse_http_response(responder, &request, StatusCode::CONFLICT).await;
 
     // Assertions
     assert_eq!(error.message, "User already exists");
+    assert_eq!(error.code, Some(ErrorCode::UserAlreadyExists));
+  }
+
+  #[actix_web::test]
+  async fn test_create_user_with_taken_user_name() {
+    let jwt_secret = custom_nanoid();
+
+    for enforce_unique_username in [true, false] {
+      let existing = User::from(
+        CreateUserDto {
+          email: SafeEmail().fake(),
+          user_name: Name(EN).fake(),
+          password: Password(12..13).fake(),
+          password_confirm: None,
+          role: Role::Customer,
+        },
+        String::new(),
+      );
+      let dto = CreateUserDto {
+        email: SafeEmail().fake(),
+        user_name: existing.user_name.clone(),
+        password: Password(12..13).fake(),
+        password_confirm: None,
+        role: Role::Customer,
+      };
+      let users = Arc::new(RwLock::new(vec![existing]));
+      let database = Arc::new(InMemoryDatabase {
+        users: users.clone(),
+      });
+      let mut hasher = MockHasher::new();
+      hasher
+        .expect_hash_password()
+        .returning(|_| Ok(String::from("hashed_password")));
+
+      let request: HttpRequest = http_request(&jwt_secret);
+      let config = web::Data::new(Config {
+        enforce_unique_username,
+        ..Config::default().await
+      });
+      let user_repository = web::Data::new(UserRepositoryImpl::new(database));
+      let hasher = web::Data::new(hasher);
+      let create = |validate| {
+        create_user(
+          config.clone(),
+          web::Data::new(SystemClock),
+          user_repository.clone(),
+          hasher.clone(),
+          web::Data::new(Webhook::new(None)),
+          web::Data::new(MockBreachClient::new()),
+          web::Data::new(InMemoryAuditLog::new()),
+          web::Data::new(IdempotencyStore::new(Duration::from_secs(60))),
+          request.clone(),
+          web::Query(CreateUserQuery { validate }),
+          web::Json(dto.clone()),
+        )
+      };
+
+      let responder = create(None).await;
+
+      if enforce_unique_username {
+        let error: HttpError =
+          parse_http_response(responder, &request, StatusCode::CONFLICT).await;
+        assert_eq!(error.code, Some(ErrorCode::UserNameTaken));
+        assert_eq!(users.read().unwrap().len(), 1);
+
+        // A dry run refuses it alike.
+        let error: HttpError = parse_http_response(
+          create(Some(true)).await,
+          &request,
+          StatusCode::CONFLICT,
+        )
+        .await;
+        assert_eq!(error.code, Some(ErrorCode::UserNameTaken));
+      } else {
+        let _: CreatedRto =
+          parse_http_response(responder, &request, StatusCode::CREATED).await;
+        assert_eq!(users.read().unwrap().len(), 2);
+      }
+    }
+  }
+
+  #[actix_web::test]
+  async fn test_create_user_dry_run() {
+    let jwt_secret = custom_nanoid();
+
+    let dto = CreateUserDto {
+      email: SafeEmail().fake(),
+      user_name: Name(EN).fake(),
+      password: Password(12..13).fake(),
+      password_confirm: None,
+      role: Role::Customer,
+    };
+
+    let users = Arc::new(RwLock::new(Vec::new()));
+    let database = Arc::new(InMemoryDatabase {
+      users: users.clone(),
+    });
+    let user_repository = UserRepositoryImpl::new(database);
+    let audit_log = web::Data::new(InMemoryAuditLog::new());
+
+    let request: HttpRequest = http_request(&jwt_secret);
+
+    // No expectations: hashing would panic.
+    let responder = create_user(
+      web::Data::new(Config::default().await),
+      web::Data::new(SystemClock),
+      web::Data::new(user_repository),
+      web::Data::new(MockHasher::new()),
+      web::Data::new(Webhook::new(None)),
+      web::Data::new(MockBreachClient::new()),
+      audit_log.clone(),
+      web::Data::new(IdempotencyStore::new(Duration::from_secs(60))),
+      request.clone(),
+      web::Query(CreateUserQuery {
+        validate: Some(true),
+      }),
+      web::Json(dto),
+    )
+    .await;
+
+    // Assertions
+    let response = responder.respond_to(&request);
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(users.read().unwrap().is_empty());
+    assert!(audit_log.entries().is_empty());
+  }
+
+  #[actix_web::test]
+  async fn test_create_user_dry_run_email_taken() {
+    let jwt_secret = custom_nanoid();
+
+    let dto = CreateUserDto {
+      email: SafeEmail().fake(),
+      user_name: Name(EN).fake(),
+      password: Password(12..13).fake(),
+      password_confirm: None,
+      role: Role::Customer,
+    };
+
+    let users =
+      Arc::new(RwLock::new(vec![User::from(dto.clone(), String::new())]));
+    let database = Arc::new(InMemoryDatabase {
+      users: users.clone(),
+    });
+    let user_repository = UserRepositoryImpl::new(database);
+
+    let request: HttpRequest = http_request(&jwt_secret);
+
+    let responder = create_user(
+      web::Data::new(Config::default().await),
+      web::Data::new(SystemClock),
+      web::Data::new(user_repository),
+      web::Data::new(MockHasher::new()),
+      web::Data::new(Webhook::new(None)),
+      web::Data::new(MockBreachClient::new()),
+      web::Data::new(InMemoryAuditLog::new()),
+      web::Data::new(IdempotencyStore::new(Duration::from_secs(60))),
+      request.clone(),
+      web::Query(CreateUserQuery {
+        validate: Some(true),
+      }),
+      web::Json(dto),
+    )
+    .await;
+
+    let error: HttpError =
+      parse_http_response(responder, &request, StatusCode::CONFLICT).await;
+
+    // Assertions
+    assert_eq!(error.code, Some(ErrorCode::UserAlreadyExists));
+    assert_eq!(users.read().unwrap().len(), 1);
   }
 
   #[actix_web::test]
@@ -243,6 +1503,7 @@ mod tests {
       email: "invalid_email".to_string(),
       user_name: "".to_string(),
       password: "short".to_string(),
+      password_confirm: None,
       role: Role::Customer,
     };
 
@@ -257,8 +1518,16 @@ mod tests {
     let request: HttpRequest = http_request(&jwt_secret);
 
     let responder = create_user(
+      web::Data::new(Config::default().await),
+      web::Data::new(SystemClock),
       web::Data::new(user_repository),
       web::Data::new(hasher),
+      web::Data::new(Webhook::new(None)),
+      web::Data::new(MockBreachClient::new()),
+      web::Data::new(InMemoryAuditLog::new()),
+      web::Data::new(IdempotencyStore::new(Duration::from_secs(60))),
+      request.clone(),
+      web::Query(CreateUserQuery::default()),
       web::Json(dto),
     )
     .await;
@@ -266,14 +1535,22 @@ mod tests {
     let users = users.read().unwrap().clone();
     assert!(users.is_empty());
 
-    let error: serde_json::Value =
-      parse_http_response(responder, &request, StatusCode::BAD_REQUEST).await;
+    let error: HttpError = parse_http_response(
+      responder,
+      &request,
+      StatusCode::UNPROCESSABLE_ENTITY,
+    )
+    .await;
 
     // Assertions
-    println!("{}", error);
-    // assert!(error.get("email").is_some());
-    // assert!(error.get("user_name").is_some());
-    // assert!(error.get("password").is_some());
+    assert_eq!(error.code, Some(ErrorCode::ValidationFailed));
+    let errors = error.errors.unwrap();
+    assert_eq!(errors["email"], vec!["invalid email"]);
+    assert_eq!(
+      errors["userName"],
+      vec!["User name must have at least 1 characters"]
+    );
+    assert!(!errors.contains_key("password"));
   }
 
   #[actix_web::test]
@@ -286,6 +1563,7 @@ mod tests {
           email: SafeEmail().fake(),
           user_name: Name(EN).fake(),
           password: Password(12..13).fake(),
+          password_confirm: None,
           role: Role::Admin,
         },
         "hashed_password".to_string(),
@@ -295,6 +1573,7 @@ mod tests {
           email: SafeEmail().fake(),
           user_name: Name(EN).fake(),
           password: Password(12..13).fake(),
+          password_confirm: None,
           role: Role::Customer,
         },
         "hashed_password".to_string(),
@@ -309,10 +1588,15 @@ mod tests {
 
     let request: HttpRequest = http_request(&jwt_secret);
 
-    let responder = get_users(web::Data::new(user_repository)).await;
+    let responder = get_users(
+      web::Data::new(user_repository),
+      web::Query(GetUsersQuery::default()),
+    )
+    .await;
 
-    let rtos: Vec<FindUserRto> =
-      parse_http_response(responder, &request, StatusCode::CREATED).await;
+    let rto: FindUsersRto =
+      parse_http_response(responder, &request, StatusCode::OK).await;
+    let rtos = rto.users;
 
     // Assertions
     assert_eq!(rtos.len(), users_data.len());
@@ -320,6 +1604,8 @@ mod tests {
       assert_eq!(rto.email, user.email);
       assert_eq!(rto.user_name, user.user_name);
       assert_eq!(rto.role, user.role);
+      assert_eq!(rto.created_at, user.created_at);
+      assert_eq!(rto.updated_at, user.updated_at);
     }
   }
 
@@ -335,12 +1621,968 @@ mod tests {
 
     let request: HttpRequest = http_request(&jwt_secret);
 
-    let responder = get_users(web::Data::new(user_repository)).await;
+    let responder = get_users(
+      web::Data::new(user_repository),
+      web::Query(GetUsersQuery::default()),
+    )
+    .await;
 
-    let rtos: Vec<FindUserRto> =
-      parse_http_response(responder, &request, StatusCode::CREATED).await;
+    let rto: FindUsersRto =
+      parse_http_response(responder, &request, StatusCode::OK).await;
+    let rtos = rto.users;
 
     // Assertions
     assert!(rtos.is_empty());
   }
+
+  fn backend_error() -> UserRepositoryError {
+    UserRepositoryError::MongoError(
+      std::io::Error::from(std::io::ErrorKind::ConnectionRefused).into(),
+    )
+  }
+
+  #[actix_web::test]
+  async fn test_get_users_backend_failure_is_not_empty() {
+    let request: HttpRequest = http_request(&custom_nanoid());
+
+    let responder = get_users(
+      web::Data::new(
+        FakeUserRepository::new(fake_user()).broken(backend_error),
+      ),
+      web::Query(GetUsersQuery::default()),
+    )
+    .await;
+
+    let response = responder.respond_to(&request);
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+  }
+
+  #[actix_web::test]
+  async fn test_get_user() {
+    let jwt_secret = custom_nanoid();
+
+    let user = User::from(
+      CreateUserDto {
+        email: SafeEmail().fake(),
+        user_name: Name(EN).fake(),
+        password: Password(12..13).fake(),
+        password_confirm: None,
+        role: Role::Driver,
+      },
+      "hashed_password".to_string(),
+    );
+    let database = Arc::new(InMemoryDatabase {
+      users: Arc::new(RwLock::new(vec![user.clone()])),
+    });
+    let user_repository = UserRepositoryImpl::new(database);
+
+    let request: HttpRequest = http_request(&jwt_secret);
+
+    let responder = get_user(
+      web::Data::new(user_repository),
+      web::Path::from(user.uuid.clone()),
+    )
+    .await;
+
+    let rto: FindUserRto =
+      parse_http_response(responder, &request, StatusCode::OK).await;
+
+    // Assertions
+    assert_eq!(rto, FindUserRto::from(user));
+  }
+
+  #[actix_web::test]
+  async fn test_get_user_not_found() {
+    let jwt_secret = custom_nanoid();
+
+    let database = Arc::new(InMemoryDatabase {
+      users: Arc::new(RwLock::new(Vec::new())),
+    });
+    let user_repository = UserRepositoryImpl::new(database);
+
+    let request: HttpRequest = http_request(&jwt_secret);
+
+    let responder = get_user(
+      web::Data::new(user_repository),
+      web::Path::from(UserId::try_from(custom_nanoid()).unwrap()),
+    )
+    .await;
+
+    let error: HttpError =
+      parse_http_response(responder, &request, StatusCode::NOT_FOUND).await;
+
+    // Assertions
+    assert_eq!(error.message, "User not found");
+  }
+
+  #[actix_web::test]
+  async fn test_user_exists() {
+    let jwt_secret = custom_nanoid();
+
+    let user = User::from(
+      CreateUserDto {
+        email: SafeEmail().fake(),
+        user_name: Name(EN).fake(),
+        password: Password(12..13).fake(),
+        password_confirm: None,
+        role: Role::Customer,
+      },
+      "hashed_password".to_string(),
+    );
+    let database = Arc::new(InMemoryDatabase {
+      users: Arc::new(RwLock::new(vec![user.clone()])),
+    });
+    let user_repository = web::Data::new(UserRepositoryImpl::new(database));
+
+    let request: HttpRequest = http_request(&jwt_secret);
+
+    let response = user_exists(
+      user_repository.clone(),
+      web::Query(UserExistsQuery {
+        email: user.email.to_string(),
+      }),
+    )
+    .await
+    .respond_to(&request);
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.body().size(), BodySize::Sized(0));
+
+    let response = user_exists(
+      user_repository,
+      web::Query(UserExistsQuery {
+        email: SafeEmail().fake(),
+      }),
+    )
+    .await
+    .respond_to(&request);
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(response.body().size(), BodySize::Sized(0));
+  }
+
+  #[actix_web::test]
+  async fn test_create_user_location_uses_public_base_url() {
+    let jwt_secret = custom_nanoid();
+
+    let config = Config {
+      public_base_url: Some(String::from("https://auth.example.com")),
+      ..Config::default().await
+    };
+    let users = Arc::new(RwLock::new(Vec::new()));
+    let database = Arc::new(InMemoryDatabase {
+      users: users.clone(),
+    });
+    let user_repository = UserRepositoryImpl::new(database);
+
+    let hasher = HashWorker::new(ThreadPoolBuilder::new().build().unwrap(), 2);
+    let request: HttpRequest = http_request(&jwt_secret);
+
+    let responder = create_user(
+      web::Data::new(config),
+      web::Data::new(SystemClock),
+      web::Data::new(user_repository),
+      web::Data::new(hasher),
+      web::Data::new(Webhook::new(None)),
+      web::Data::new(MockBreachClient::new()),
+      web::Data::new(InMemoryAuditLog::new()),
+      web::Data::new(IdempotencyStore::new(Duration::from_secs(60))),
+      request.clone(),
+      web::Query(CreateUserQuery::default()),
+      web::Json(CreateUserDto {
+        email: SafeEmail().fake(),
+        user_name: Name(EN).fake(),
+        password: Password(12..13).fake(),
+        password_confirm: None,
+        role: Role::Customer,
+      }),
+    )
+    .await;
+
+    let response = responder.respond_to(&request);
+
+    // Assertions
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(
+      response.headers().get(header::LOCATION).unwrap(),
+      &format!(
+        "https://auth.example.com/v1/users/{}",
+        users.read().unwrap()[0].uuid
+      )
+    );
+  }
+
+  #[actix_web::test]
+  async fn test_get_users_follows_cursor_through_all_pages() {
+    let jwt_secret = custom_nanoid();
+
+    let users_data: Vec<User> = (0..7)
+      .map(|_| {
+        User::from(
+          CreateUserDto {
+            email: SafeEmail().fake(),
+            user_name: Name(EN).fake(),
+            password: Password(12..13).fake(),
+            password_confirm: None,
+            role: Role::Customer,
+          },
+          "hashed_password".to_string(),
+        )
+      })
+      .collect();
+    let database = Arc::new(InMemoryDatabase {
+      users: Arc::new(RwLock::new(users_data.clone())),
+    });
+    let user_repository = web::Data::new(UserRepositoryImpl::new(database));
+
+    let request: HttpRequest = http_request(&jwt_secret);
+
+    let mut emails = Vec::new();
+    let mut cursor = None;
+    loop {
+      let responder = get_users(
+        user_repository.clone(),
+        web::Query(GetUsersQuery {
+          cursor: cursor.take(),
+          limit: Some(3),
+          ..GetUsersQuery::default()
+        }),
+      )
+      .await;
+      let rto: FindUsersRto =
+        parse_http_response(responder, &request, StatusCode::OK).await;
+      assert!(rto.users.len() <= 3);
+      emails.extend(rto.users.into_iter().map(|user| user.email));
+      match rto.next_cursor {
+        Some(next_cursor) => cursor = Some(next_cursor),
+        None => break,
+      }
+    }
+
+    // Assertions
+    let mut expected = users_data;
+    expected
+      .sort_by(|a, b| (a.created_at, &a.uuid).cmp(&(b.created_at, &b.uuid)));
+    let expected: Vec<Email> =
+      expected.into_iter().map(|user| user.email).collect();
+    assert_eq!(emails, expected);
+  }
+
+  #[actix_web::test]
+  async fn test_get_users_sorted() {
+    let jwt_secret = custom_nanoid();
+
+    let now = Utc::now();
+    let user = |uuid: &str, email: &str, created_at| {
+      let mut user = User::from(
+        CreateUserDto {
+          email: String::from(email),
+          user_name: Name(EN).fake(),
+          password: Password(12..13).fake(),
+          password_confirm: None,
+          role: Role::Customer,
+        },
+        "hashed_password".to_string(),
+      );
+      user.uuid = UserId::try_from(uuid).unwrap();
+      user.created_at = created_at;
+      user
+    };
+    // Stored out of order, with a tie on `created_at` broken by `uuid`.
+    let users_data = vec![
+      user("uuid-c", "a@example.com", now),
+      user("uuid-b", "c@example.com", now),
+      user(
+        "uuid-a",
+        "b@example.com",
+        now - chrono::Duration::seconds(1),
+      ),
+    ];
+    let database = Arc::new(InMemoryDatabase {
+      users: Arc::new(RwLock::new(users_data)),
+    });
+    let user_repository = web::Data::new(UserRepositoryImpl::new(database));
+
+    let request: HttpRequest = http_request(&jwt_secret);
+
+    for (sort, expected) in [
+      (None, ["uuid-a", "uuid-b", "uuid-c"]),
+      (Some(UserSort::CreatedAt), ["uuid-a", "uuid-b", "uuid-c"]),
+      (Some(UserSort::Email), ["uuid-c", "uuid-a", "uuid-b"]),
+    ] {
+      let responder = get_users(
+        user_repository.clone(),
+        web::Query(GetUsersQuery {
+          sort,
+          ..GetUsersQuery::default()
+        }),
+      )
+      .await;
+      let rto: FindUsersRto =
+        parse_http_response(responder, &request, StatusCode::OK).await;
+      let uuids: Vec<&str> =
+        rto.users.iter().map(|user| user.uuid.as_str()).collect();
+      assert_eq!(uuids, expected);
+    }
+  }
+
+  #[actix_web::test]
+  async fn test_get_hash_migration_flags_outdated_hashes() {
+    let jwt_secret = custom_nanoid();
+
+    let weak = bcrypt::hash("password", 4).unwrap();
+    let current =
+      weak.replacen("$04$", &format!("${}$", bcrypt::DEFAULT_COST), 1);
+    let users_data: Vec<User> =
+      [weak, current.clone(), current, "plain".into()]
+        .into_iter()
+        .map(|password_hash| {
+          User::from(
+            CreateUserDto {
+              email: SafeEmail().fake(),
+              user_name: Name(EN).fake(),
+              password: Password(12..13).fake(),
+              password_confirm: None,
+              role: Role::Customer,
+            },
+            password_hash,
+          )
+        })
+        .collect();
+    let database = Arc::new(InMemoryDatabase {
+      users: Arc::new(RwLock::new(users_data.clone())),
+    });
+    let user_repository = web::Data::new(UserRepositoryImpl::new(database));
+
+    let request: HttpRequest = http_request(&jwt_secret);
+    let responder = get_hash_migration(user_repository).await;
+    let rto: HashMigrationRto =
+      parse_http_response(responder, &request, StatusCode::OK).await;
+
+    // Assertions
+    assert_eq!(
+      rto,
+      HashMigrationRto {
+        total: 4,
+        outdated: 2,
+        migrated_percent: 50.0,
+        outdated_uuids: vec![
+          users_data[0].uuid.clone(),
+          users_data[3].uuid.clone()
+        ],
+      }
+    );
+  }
+
+  #[actix_web::test]
+  async fn test_get_hash_migration_caps_listed_uuids() {
+    let users = (0..MAX_OUTDATED_UUIDS + 1)
+      .map(|_| User {
+        password_hash: String::from("plain"),
+        ..fake_user()
+      })
+      .collect();
+    let database = Arc::new(InMemoryDatabase {
+      users: Arc::new(RwLock::new(users)),
+    });
+    let user_repository = web::Data::new(UserRepositoryImpl::new(database));
+
+    let request: HttpRequest = http_request(&custom_nanoid());
+    let responder = get_hash_migration(user_repository).await;
+    let rto: HashMigrationRto =
+      parse_http_response(responder, &request, StatusCode::OK).await;
+
+    assert_eq!(rto.total, MAX_OUTDATED_UUIDS + 1);
+    assert_eq!(rto.outdated, MAX_OUTDATED_UUIDS + 1);
+    assert_eq!(rto.outdated_uuids.len(), MAX_OUTDATED_UUIDS);
+  }
+
+  #[actix_web::test]
+  async fn test_get_hash_migration_without_users() {
+    let jwt_secret = custom_nanoid();
+
+    let database = Arc::new(InMemoryDatabase {
+      users: Arc::new(RwLock::new(Vec::new())),
+    });
+    let user_repository = web::Data::new(UserRepositoryImpl::new(database));
+
+    let request: HttpRequest = http_request(&jwt_secret);
+    let responder = get_hash_migration(user_repository).await;
+    let rto: HashMigrationRto =
+      parse_http_response(responder, &request, StatusCode::OK).await;
+
+    // Assertions
+    assert_eq!(rto.total, 0);
+    assert_eq!(rto.migrated_percent, 100.0);
+  }
+
+  #[actix_web::test]
+  async fn test_get_users_filtered() {
+    let jwt_secret = custom_nanoid();
+
+    let roles = [Role::Admin, Role::Manager, Role::Driver, Role::Customer];
+    let mut users_data: Vec<User> = roles
+      .iter()
+      .map(|role| {
+        User::from(
+          CreateUserDto {
+            email: SafeEmail().fake(),
+            user_name: Name(EN).fake(),
+            password: Password(12..13).fake(),
+            password_confirm: None,
+            role: role.clone(),
+          },
+          "hashed_password".to_string(),
+        )
+      })
+      .collect();
+    users_data[2].enabled = false;
+    let database = Arc::new(InMemoryDatabase {
+      users: Arc::new(RwLock::new(users_data.clone())),
+    });
+    let user_repository = web::Data::new(UserRepositoryImpl::new(database));
+
+    let request: HttpRequest = http_request(&jwt_secret);
+
+    for (role, user) in roles.iter().zip(users_data.iter()) {
+      let responder = get_users(
+        user_repository.clone(),
+        web::Query(GetUsersQuery {
+          role: Some(role.clone()),
+          ..GetUsersQuery::default()
+        }),
+      )
+      .await;
+      let rto: FindUsersRto =
+        parse_http_response(responder, &request, StatusCode::OK).await;
+      assert_eq!(rto.users, vec![FindUserRto::from(user.clone())]);
+    }
+
+    let responder = get_users(
+      user_repository.clone(),
+      web::Query(GetUsersQuery {
+        enabled: Some(false),
+        ..GetUsersQuery::default()
+      }),
+    )
+    .await;
+    let rto: FindUsersRto =
+      parse_http_response(responder, &request, StatusCode::OK).await;
+    assert_eq!(rto.users, vec![FindUserRto::from(users_data[2].clone())]);
+
+    let responder = get_users(
+      user_repository,
+      web::Query(GetUsersQuery {
+        role: Some(Role::Driver),
+        enabled: Some(true),
+        ..GetUsersQuery::default()
+      }),
+    )
+    .await;
+    let rto: FindUsersRto =
+      parse_http_response(responder, &request, StatusCode::OK).await;
+    assert!(rto.users.is_empty());
+  }
+
+  #[test]
+  fn test_get_users_invalid_role() {
+    let error =
+      web::Query::<GetUsersQuery>::from_query("role=pilot").unwrap_err();
+    assert_eq!(error.error_response().status(), StatusCode::BAD_REQUEST);
+  }
+
+  #[actix_web::test]
+  async fn test_get_users_projected_fields() {
+    let jwt_secret = custom_nanoid();
+    let user = User::from(
+      CreateUserDto {
+        email: SafeEmail().fake(),
+        user_name: Name(EN).fake(),
+        password: Password(12..13).fake(),
+        password_confirm: None,
+        role: Role::Manager,
+      },
+      "hashed_password".to_string(),
+    );
+    let database = Arc::new(InMemoryDatabase {
+      users: Arc::new(RwLock::new(vec![user.clone()])),
+    });
+    let request: HttpRequest = http_request(&jwt_secret);
+
+    let responder = get_users(
+      web::Data::new(UserRepositoryImpl::new(database)),
+      web::Query::<GetUsersQuery>::from_query("fields=uuid,userName").unwrap(),
+    )
+    .await;
+
+    let rto: ProjectedUsersRto =
+      parse_http_response(responder, &request, StatusCode::OK).await;
+    let mut expected = Map::new();
+    expected.insert(String::from("uuid"), Value::from(user.uuid.as_str()));
+    expected.insert(String::from("userName"), Value::from(user.user_name));
+    assert_eq!(rto.users, vec![expected]);
+  }
+
+  #[actix_web::test]
+  async fn test_get_users_invalid_field() {
+    let jwt_secret = custom_nanoid();
+    let database = Arc::new(InMemoryDatabase {
+      users: Arc::new(RwLock::new(Vec::new())),
+    });
+    let request: HttpRequest = http_request(&jwt_secret);
+
+    let responder = get_users(
+      web::Data::new(UserRepositoryImpl::new(database)),
+      web::Query::<GetUsersQuery>::from_query("fields=email,passwordHash")
+        .unwrap(),
+    )
+    .await;
+
+    let error: HttpError =
+      parse_http_response(responder, &request, StatusCode::BAD_REQUEST).await;
+    assert_eq!(error.code, Some(ErrorCode::InvalidField));
+    assert!(error.message.contains("`passwordHash`"));
+  }
+
+  #[actix_web::test]
+  async fn test_get_users_invalid_cursor() {
+    let jwt_secret = custom_nanoid();
+
+    let database = Arc::new(InMemoryDatabase {
+      users: Arc::new(RwLock::new(Vec::new())),
+    });
+    let user_repository = UserRepositoryImpl::new(database);
+
+    let request: HttpRequest = http_request(&jwt_secret);
+
+    let responder = get_users(
+      web::Data::new(user_repository),
+      web::Query(GetUsersQuery {
+        cursor: Some(String::from("not a cursor!")),
+        ..GetUsersQuery::default()
+      }),
+    )
+    .await;
+
+    let error: HttpError =
+      parse_http_response(responder, &request, StatusCode::BAD_REQUEST).await;
+
+    // Assertions
+    assert_eq!(error.message, "Invalid cursor");
+  }
+
+  #[actix_web::test]
+  async fn test_delete_user_soft_deletes() {
+    let jwt_secret = custom_nanoid();
+
+    let user = User::from(
+      CreateUserDto {
+        email: SafeEmail().fake(),
+        user_name: Name(EN).fake(),
+        password: Password(12..13).fake(),
+        password_confirm: None,
+        role: Role::Driver,
+      },
+      "hashed_password".to_string(),
+    );
+
+    let users = Arc::new(RwLock::new(vec![user.clone()]));
+    let database = Arc::new(InMemoryDatabase {
+      users: users.clone(),
+    });
+    let user_repository = web::Data::new(UserRepositoryImpl::new(database));
+
+    let request: HttpRequest = http_request(&jwt_secret);
+
+    let responder = delete_user(
+      user_repository.clone(),
+      web::Data::new(InMemoryAuditLog::new()),
+      request.clone(),
+      web::Path::from(user.uuid.clone()),
+      web::Query(DeleteUserQuery { hard: None }),
+    )
+    .await;
+    let response = responder.respond_to(&request);
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    // The record is kept, only flagged as deleted.
+    let stored = users.read().unwrap().clone();
+    assert_eq!(stored.len(), 1);
+    assert!(stored[0].deleted_at.is_some());
+
+    // Soft-deleted users are invisible to reads.
+    assert!(matches!(
+      user_repository
+        .find_one(FindOneProperty::Uuid(&user.uuid))
+        .await,
+      Err(UserRepositoryError::NotFound)
+    ));
+    assert!(matches!(
+      user_repository
+        .find_one(FindOneProperty::Email(&user.email))
+        .await,
+      Err(UserRepositoryError::NotFound)
+    ));
+    let rto: FindUsersRto = parse_http_response(
+      get_users(
+        user_repository.clone(),
+        web::Query(GetUsersQuery::default()),
+      )
+      .await,
+      &request,
+      StatusCode::OK,
+    )
+    .await;
+    assert!(rto.users.is_empty());
+
+    // Deleting again behaves as if the user doesn't exist.
+    let responder = delete_user(
+      user_repository,
+      web::Data::new(InMemoryAuditLog::new()),
+      request.clone(),
+      web::Path::from(user.uuid.clone()),
+      web::Query(DeleteUserQuery { hard: None }),
+    )
+    .await;
+    let response = responder.respond_to(&request);
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+  }
+
+  #[actix_web::test]
+  async fn test_delete_user_hard() {
+    let jwt_secret = custom_nanoid();
+
+    let mut user = User::from(
+      CreateUserDto {
+        email: SafeEmail().fake(),
+        user_name: Name(EN).fake(),
+        password: Password(12..13).fake(),
+        password_confirm: None,
+        role: Role::Driver,
+      },
+      "hashed_password".to_string(),
+    );
+    // Hard deletion also purges previously soft-deleted users.
+    user.deleted_at = Some(Utc::now());
+
+    let users = Arc::new(RwLock::new(vec![user.clone()]));
+    let database = Arc::new(InMemoryDatabase {
+      users: users.clone(),
+    });
+    let user_repository = web::Data::new(UserRepositoryImpl::new(database));
+
+    let request: HttpRequest = http_request(&jwt_secret);
+
+    let responder = delete_user(
+      user_repository,
+      web::Data::new(InMemoryAuditLog::new()),
+      request.clone(),
+      web::Path::from(user.uuid.clone()),
+      web::Query(DeleteUserQuery { hard: Some(true) }),
+    )
+    .await;
+    let response = responder.respond_to(&request);
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    assert!(users.read().unwrap().is_empty());
+  }
+
+  #[test]
+  fn test_user_from_timestamps_match() {
+    let user = User::from(
+      CreateUserDto {
+        email: SafeEmail().fake(),
+        user_name: Name(EN).fake(),
+        password: Password(12..13).fake(),
+        password_confirm: None,
+        role: Role::Customer,
+      },
+      "hashed_password".to_string(),
+    );
+
+    assert_eq!(user.created_at, user.updated_at);
+  }
+
+  #[actix_web::test]
+  async fn test_update_user_refuses_moving_created_at() {
+    let user = User::from(
+      CreateUserDto {
+        email: SafeEmail().fake(),
+        user_name: Name(EN).fake(),
+        password: Password(12..13).fake(),
+        password_confirm: None,
+        role: Role::Customer,
+      },
+      "hashed_password".to_string(),
+    );
+
+    let users = Arc::new(RwLock::new(vec![user.clone()]));
+    let database = Arc::new(InMemoryDatabase {
+      users: users.clone(),
+    });
+    let user_repository = UserRepositoryImpl::new(database);
+
+    let later = user.created_at + chrono::Duration::minutes(5);
+    let mut updated = user.clone();
+    updated.created_at = later;
+    updated.touch(later);
+    // Refused outright, as on every backend, rather than half applied.
+    assert!(matches!(
+      user_repository.update(updated.clone()).await,
+      Err(UserRepositoryError::CreatedAtChanged)
+    ));
+    let stored = users.read().unwrap()[0].clone();
+    assert_eq!(stored.created_at, user.created_at);
+    assert_eq!(stored.updated_at, user.updated_at);
+
+    updated.created_at = user.created_at;
+    user_repository.update(updated).await.unwrap();
+    let stored = users.read().unwrap()[0].clone();
+    assert_eq!(stored.created_at, user.created_at);
+    assert_eq!(stored.updated_at, later);
+  }
+
+  #[actix_web::test]
+  async fn test_update_user_status() {
+    let jwt_secret = custom_nanoid();
+
+    let user = User::from(
+      CreateUserDto {
+        email: SafeEmail().fake(),
+        user_name: Name(EN).fake(),
+        password: Password(12..13).fake(),
+        password_confirm: None,
+        role: Role::Driver,
+      },
+      "hashed_password".to_string(),
+    );
+    assert!(user.enabled);
+
+    let users = Arc::new(RwLock::new(vec![user.clone()]));
+    let database = Arc::new(InMemoryDatabase {
+      users: users.clone(),
+    });
+    let user_repository = web::Data::new(UserRepositoryImpl::new(database));
+
+    let request: HttpRequest = http_request(&jwt_secret);
+
+    let responder = update_user_status(
+      user_repository.clone(),
+      web::Data::new(InMemoryAuditLog::new()),
+      request.clone(),
+      web::Path::from(user.uuid.clone()),
+      web::Json(UpdateUserStatusDto { enabled: false }),
+    )
+    .await;
+    let response = responder.respond_to(&request);
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert!(!users.read().unwrap()[0].enabled);
+
+    let responder = update_user_status(
+      user_repository,
+      web::Data::new(InMemoryAuditLog::new()),
+      request.clone(),
+      web::Path::from(UserId::try_from(custom_nanoid()).unwrap()),
+      web::Json(UpdateUserStatusDto { enabled: true }),
+    )
+    .await;
+    let response = responder.respond_to(&request);
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+  }
+
+  #[actix_web::test]
+  async fn test_reset_password() {
+    let user = User::from(
+      CreateUserDto {
+        email: SafeEmail().fake(),
+        user_name: Name(EN).fake(),
+        password: Password(12..13).fake(),
+        password_confirm: None,
+        role: Role::Driver,
+      },
+      "hashed_password".to_string(),
+    );
+    let users = Arc::new(RwLock::new(vec![user.clone()]));
+    let user_repository =
+      web::Data::new(UserRepositoryImpl::new(Arc::new(InMemoryDatabase {
+        users: users.clone(),
+      })));
+    let mut hasher = MockHasher::new();
+    hasher
+      .expect_hash_password()
+      .returning(|password| Ok(format!("hashed:{}", password)));
+    let hasher = web::Data::new(hasher);
+    let clock = web::Data::new(FixedClock::new(Utc::now()));
+    let request = actix_web::test::TestRequest::default().to_http_request();
+
+    let config = web::Data::new(Config::default().await);
+    for must_change_password in [true, false] {
+      let responder = reset_password(
+        config.clone(),
+        clock.clone(),
+        user_repository.clone(),
+        hasher.clone(),
+        web::Data::new(InMemoryAuditLog::new()),
+        web::Data::new(LogMailer),
+        web::Data::new(MockBreachClient::new()),
+        request.clone(),
+        web::Path::from(user.uuid.clone()),
+        web::Json(ResetPasswordDto {
+          password: String::from("temporary-password"),
+          must_change_password,
+        }),
+      )
+      .await;
+      let response = responder.respond_to(&request);
+      assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+      let stored = users.read().unwrap()[0].clone();
+      assert_eq!(stored.password_hash, "hashed:temporary-password");
+      assert_eq!(stored.password_changed_at, Some(clock.now()));
+      assert_eq!(stored.must_change_password, must_change_password);
+    }
+    assert_eq!(users.read().unwrap()[0].token_epoch, 2);
+
+    let responder = reset_password(
+      config,
+      clock.clone(),
+      user_repository.clone(),
+      hasher.clone(),
+      web::Data::new(InMemoryAuditLog::new()),
+      web::Data::new(LogMailer),
+      web::Data::new(MockBreachClient::new()),
+      request.clone(),
+      web::Path::from(UserId::try_from(custom_nanoid()).unwrap()),
+      web::Json(ResetPasswordDto {
+        password: String::from("temporary-password"),
+        must_change_password: true,
+      }),
+    )
+    .await;
+    let response = responder.respond_to(&request);
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let responder = reset_password(
+      web::Data::new(Config {
+        check_pwned_passwords: true,
+        ..Config::default().await
+      }),
+      clock,
+      user_repository,
+      hasher,
+      web::Data::new(InMemoryAuditLog::new()),
+      web::Data::new(LogMailer),
+      web::Data::new(MockBreachClient::breached("breached-password")),
+      request.clone(),
+      web::Path::from(user.uuid.clone()),
+      web::Json(ResetPasswordDto {
+        password: String::from("breached-password"),
+        must_change_password: true,
+      }),
+    )
+    .await;
+    let error: HttpError = parse_http_response(
+      responder,
+      &request,
+      StatusCode::UNPROCESSABLE_ENTITY,
+    )
+    .await;
+    assert!(error.errors.unwrap().contains_key("password"));
+    assert_eq!(
+      users.read().unwrap()[0].password_hash,
+      "hashed:temporary-password"
+    );
+  }
+
+  #[actix_web::test]
+  async fn test_revoke_tokens() {
+    let user = User::from(
+      CreateUserDto {
+        email: SafeEmail().fake(),
+        user_name: Name(EN).fake(),
+        password: Password(12..13).fake(),
+        password_confirm: None,
+        role: Role::Driver,
+      },
+      "hashed_password".to_string(),
+    );
+    assert_eq!(user.token_epoch, 0);
+
+    let users = Arc::new(RwLock::new(vec![user.clone()]));
+    let database = Arc::new(InMemoryDatabase {
+      users: users.clone(),
+    });
+    let user_repository = web::Data::new(UserRepositoryImpl::new(database));
+    let audit_log = web::Data::new(InMemoryAuditLog::new());
+    let request = actix_web::test::TestRequest::default().to_http_request();
+
+    let responder = revoke_tokens(
+      user_repository.clone(),
+      audit_log.clone(),
+      request.clone(),
+      web::Path::from(user.uuid.clone()),
+    )
+    .await;
+    let response = responder.respond_to(&request);
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(users.read().unwrap()[0].token_epoch, 1);
+    assert_eq!(audit_log.entries()[0].event, AuditEvent::TokensRevoked);
+
+    let responder = revoke_tokens(
+      user_repository,
+      audit_log,
+      request.clone(),
+      web::Path::from(UserId::try_from(custom_nanoid()).unwrap()),
+    )
+    .await;
+    let response = responder.respond_to(&request);
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+  }
+
+  #[actix_web::test]
+  async fn test_create_verification_token() {
+    let config = Config::default().await;
+
+    let user = User::from(
+      CreateUserDto {
+        email: SafeEmail().fake(),
+        user_name: Name(EN).fake(),
+        password: Password(12..13).fake(),
+        password_confirm: None,
+        role: Role::Driver,
+      },
+      "hashed_password".to_string(),
+    );
+    assert!(!user.email_verified);
+
+    let users = Arc::new(RwLock::new(vec![user.clone()]));
+    let database = Arc::new(InMemoryDatabase {
+      users: users.clone(),
+    });
+    let user_repository = UserRepositoryImpl::new(database);
+
+    let (sender, receiver) = flume::unbounded();
+    let mut mailer = MockMailer::new();
+    mailer.expect_send().times(1).returning(move |mail| {
+      sender.send(mail).unwrap();
+      Ok(())
+    });
+
+    let request: HttpRequest = http_request(&config.jwt_secret);
+
+    let responder = create_verification_token(
+      web::Data::new(config),
+      web::Data::new(SystemClock),
+      web::Data::new(user_repository),
+      web::Data::new(mailer),
+      web::Path::from(user.uuid.clone()),
+    )
+    .await;
+
+    let rto: VerificationTokenRto =
+      parse_http_response(responder, &request, StatusCode::OK).await;
+    assert!(!rto.verification_token.is_empty());
+
+    let mail = receiver.recv_async().await.unwrap();
+    assert_eq!(mail.to, user.email);
+    assert!(mail.body.contains(&rto.verification_token));
+  }
 }
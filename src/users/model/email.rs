@@ -0,0 +1,133 @@
+use std::{fmt, ops::Deref};
+
+use serde::{Deserialize, Deserializer, Serialize};
+use validator::ValidateEmail;
+
+use crate::shared::normalize::normalize_email;
+
+/// An email address, checked by the same rules as `#[validate(email)]` on
+/// DTOs, so anything they accept converts. Kept normalized, see
+/// `normalize_email`, so every path stores and compares the same form.
+#[derive(Debug, Clone, Serialize, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(transparent)]
+pub struct Email(String);
+
+impl Email {
+  pub fn as_str(&self) -> &str {
+    &self.0
+  }
+}
+
+impl TryFrom<String> for Email {
+  type Error = String;
+
+  fn try_from(value: String) -> Result<Self, Self::Error> {
+    let value = normalize_email(&value);
+    if !value.validate_email() {
+      return Err(format!("invalid email `{}`", value));
+    }
+    Ok(Self(value))
+  }
+}
+
+impl TryFrom<&str> for Email {
+  type Error = String;
+
+  fn try_from(value: &str) -> Result<Self, Self::Error> {
+    Self::try_from(value.to_string())
+  }
+}
+
+impl From<Email> for String {
+  fn from(email: Email) -> Self {
+    email.0
+  }
+}
+
+impl Deref for Email {
+  type Target = str;
+
+  fn deref(&self) -> &str {
+    &self.0
+  }
+}
+
+impl fmt::Display for Email {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(&self.0)
+  }
+}
+
+impl PartialEq<str> for Email {
+  fn eq(&self, other: &str) -> bool {
+    self.0 == other
+  }
+}
+
+impl PartialEq<&str> for Email {
+  fn eq(&self, other: &&str) -> bool {
+    self.0 == *other
+  }
+}
+
+impl PartialEq<String> for Email {
+  fn eq(&self, other: &String) -> bool {
+    self.0 == *other
+  }
+}
+
+/// Normalizes without validating. Emails are only deserialized from stored
+/// users and tokens we signed, checked when written, and refusing one that
+/// stricter rules no longer accept would leave its user unreadable. Input
+/// goes through `try_from`.
+impl<'de> Deserialize<'de> for Email {
+  fn deserialize<D: Deserializer<'de>>(
+    deserializer: D,
+  ) -> Result<Self, D::Error> {
+    String::deserialize(deserializer).map(|email| Self(normalize_email(&email)))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_email_validation() {
+    assert_eq!(
+      Email::try_from("jane.doe@example.com").unwrap(),
+      "jane.doe@example.com"
+    );
+    assert!(Email::try_from("invalid_email").is_err());
+    assert!(Email::try_from("").is_err());
+  }
+
+  #[test]
+  fn test_email_is_normalized() {
+    assert_eq!(
+      Email::try_from(" Jane.Doe@Example.COM ").unwrap(),
+      "jane.doe@example.com"
+    );
+    assert_eq!(
+      serde_json::from_str::<Email>("\"Jane.Doe@Example.COM\"").unwrap(),
+      "jane.doe@example.com"
+    );
+  }
+
+  #[test]
+  fn test_email_serializes_as_string() {
+    let email = Email::try_from("jane.doe@example.com").unwrap();
+    let json = serde_json::to_string(&email).unwrap();
+    assert_eq!(json, "\"jane.doe@example.com\"");
+    assert_eq!(serde_json::from_str::<Email>(&json).unwrap(), email);
+  }
+
+  #[test]
+  fn test_stored_email_is_read_leniently() {
+    assert_eq!(
+      serde_json::from_str::<Email>("\"Legacy_Address\"").unwrap(),
+      "legacy_address"
+    );
+    assert!(Email::try_from("Legacy_Address").is_err());
+  }
+}
@@ -1 +1,3 @@
+pub mod email;
 pub mod user;
+pub mod user_id;
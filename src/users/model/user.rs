@@ -1,15 +1,43 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
+use super::{email::Email, user_id::UserId};
 use crate::shared::role::Role;
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct User {
-  pub uuid: String,
-  pub email: String,
+  pub uuid: UserId,
+  pub email: Email,
   pub user_name: String,
   pub password_hash: String,
   pub role: Role,
   pub created_at: DateTime<Utc>,
   pub updated_at: DateTime<Utc>,
+  pub deleted_at: Option<DateTime<Utc>>,
+  #[serde(default = "default_enabled")]
+  pub enabled: bool,
+  #[serde(default)]
+  pub email_verified: bool,
+  /// Bumped to revoke every refresh token issued so far, which carry the
+  /// epoch they were issued in.
+  #[serde(default)]
+  pub token_epoch: u32,
+  /// Last time the password was set other than at creation.
+  #[serde(default)]
+  pub password_changed_at: Option<DateTime<Utc>>,
+  /// Set by an admin reset, so the user replaces the temporary password.
+  #[serde(default)]
+  pub must_change_password: bool,
+}
+
+fn default_enabled() -> bool {
+  true
+}
+
+impl User {
+  /// Records a mutation at `now`. Only `updated_at` moves; `created_at` is
+  /// fixed at creation and repositories refuse updates that move it.
+  pub fn touch(&mut self, now: DateTime<Utc>) {
+    self.updated_at = now;
+  }
 }
@@ -0,0 +1,112 @@
+use std::{fmt, ops::Deref};
+
+use serde::{de::Error, Deserialize, Deserializer, Serialize};
+
+/// Identifies a user, as generated at creation. Kept apart from other
+/// strings so an email can't be passed where an id is expected.
+#[derive(Debug, Clone, Serialize, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(transparent)]
+pub struct UserId(String);
+
+impl UserId {
+  pub fn as_str(&self) -> &str {
+    &self.0
+  }
+
+  /// Ids are generated from a configurable alphabet, so only what could
+  /// never be in one is refused: whitespace, control characters and path
+  /// separators.
+  pub fn is_valid_char(c: char) -> bool {
+    !(c.is_whitespace() || c.is_control() || c == '/')
+  }
+}
+
+impl TryFrom<String> for UserId {
+  type Error = String;
+
+  fn try_from(value: String) -> Result<Self, Self::Error> {
+    if value.is_empty() || !value.chars().all(Self::is_valid_char) {
+      return Err(format!("invalid user id `{}`", value));
+    }
+    Ok(Self(value))
+  }
+}
+
+impl TryFrom<&str> for UserId {
+  type Error = String;
+
+  fn try_from(value: &str) -> Result<Self, Self::Error> {
+    Self::try_from(value.to_string())
+  }
+}
+
+impl From<UserId> for String {
+  fn from(id: UserId) -> Self {
+    id.0
+  }
+}
+
+impl Deref for UserId {
+  type Target = str;
+
+  fn deref(&self) -> &str {
+    &self.0
+  }
+}
+
+impl fmt::Display for UserId {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(&self.0)
+  }
+}
+
+impl PartialEq<str> for UserId {
+  fn eq(&self, other: &str) -> bool {
+    self.0 == other
+  }
+}
+
+impl PartialEq<&str> for UserId {
+  fn eq(&self, other: &&str) -> bool {
+    self.0 == *other
+  }
+}
+
+impl PartialEq<String> for UserId {
+  fn eq(&self, other: &String) -> bool {
+    self.0 == *other
+  }
+}
+
+impl<'de> Deserialize<'de> for UserId {
+  fn deserialize<D: Deserializer<'de>>(
+    deserializer: D,
+  ) -> Result<Self, D::Error> {
+    Self::try_from(String::deserialize(deserializer)?).map_err(D::Error::custom)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_user_id_validation() {
+    assert_eq!(
+      UserId::try_from("V1StGXR8Z5jdHi6B").unwrap(),
+      "V1StGXR8Z5jdHi6B"
+    );
+    assert!(UserId::try_from("").is_err());
+    assert!(UserId::try_from("a b").is_err());
+    assert!(UserId::try_from("a/b").is_err());
+  }
+
+  #[test]
+  fn test_user_id_serializes_as_string() {
+    let id = UserId::try_from("V1StGXR8Z5jdHi6B").unwrap();
+    let json = serde_json::to_string(&id).unwrap();
+    assert_eq!(json, "\"V1StGXR8Z5jdHi6B\"");
+    assert_eq!(serde_json::from_str::<UserId>(&json).unwrap(), id);
+    assert!(serde_json::from_str::<UserId>("\"\"").is_err());
+  }
+}
@@ -0,0 +1,258 @@
+use std::{
+  collections::HashMap,
+  sync::{Arc, Mutex},
+  time::Duration,
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+
+use crate::{
+  shared::clock::Clock,
+  users::model::{user::User, user_id::UserId},
+};
+
+use super::user_repository::{
+  FindOneProperty, UserFilter, UserPage, UserRepository, UserRepositoryError,
+  UserSort,
+};
+
+const MAX_ENTRIES: usize = 10_000;
+
+/// Short-lived cache of lookups by uuid, the hot path of token refreshes.
+/// Meant to be shared by every worker: writes through this repository
+/// invalidate the entry, but writes made by other instances of the service
+/// are only seen once the entry expires. Without a TTL every call goes
+/// straight to `inner`.
+pub struct CachingUserRepository<UR: UserRepository, C: Clock> {
+  inner: UR,
+  clock: Arc<C>,
+  ttl: Option<chrono::Duration>,
+  entries: Mutex<HashMap<UserId, (User, DateTime<Utc>)>>,
+}
+
+impl<UR: UserRepository, C: Clock> CachingUserRepository<UR, C> {
+  pub fn new(inner: UR, clock: Arc<C>, ttl: Option<Duration>) -> Self {
+    Self {
+      inner,
+      clock,
+      ttl: ttl.and_then(|ttl| chrono::Duration::from_std(ttl).ok()),
+      entries: Mutex::new(HashMap::new()),
+    }
+  }
+
+  fn cached(&self, uuid: &UserId) -> Option<User> {
+    let now = self.clock.now();
+    let entries = self.entries.lock().unwrap();
+    entries
+      .get(uuid)
+      .filter(|(_, expires_at)| *expires_at > now)
+      .map(|(user, _)| user.clone())
+  }
+
+  fn store(&self, user: &User, ttl: chrono::Duration) {
+    let now = self.clock.now();
+    let mut entries = self.entries.lock().unwrap();
+    if entries.len() >= MAX_ENTRIES {
+      entries.retain(|_, (_, expires_at)| *expires_at > now);
+      if entries.len() >= MAX_ENTRIES {
+        entries.clear();
+      }
+    }
+    entries.insert(user.uuid.clone(), (user.clone(), now + ttl));
+  }
+
+  fn invalidate(&self, uuid: &UserId) {
+    self.entries.lock().unwrap().remove(uuid);
+  }
+}
+
+#[async_trait]
+impl<UR: UserRepository, C: Clock + Send + Sync> UserRepository
+  for CachingUserRepository<UR, C>
+{
+  async fn find_one(
+    &self,
+    property: FindOneProperty<'_>,
+  ) -> Result<User, UserRepositoryError> {
+    let (Some(ttl), FindOneProperty::Uuid(uuid)) = (self.ttl, &property) else {
+      return self.inner.find_one(property).await;
+    };
+    if let Some(user) = self.cached(uuid) {
+      return Ok(user);
+    }
+    let user = self.inner.find_one(property).await?;
+    self.store(&user, ttl);
+    Ok(user)
+  }
+
+  async fn find_all(
+    &self,
+    filter: &UserFilter,
+    sort: UserSort,
+    cursor: Option<&str>,
+    limit: usize,
+  ) -> Result<UserPage, UserRepositoryError> {
+    self.inner.find_all(filter, sort, cursor, limit).await
+  }
+
+  async fn create(&self, user: User) -> Result<User, UserRepositoryError> {
+    self.inner.create(user).await
+  }
+
+  async fn update(&self, user: User) -> Result<(), UserRepositoryError> {
+    // Invalidate even if the write fails, as it may still have applied.
+    self.invalidate(&user.uuid);
+    self.inner.update(user).await
+  }
+
+  async fn delete(&self, uuid: &UserId) -> Result<(), UserRepositoryError> {
+    self.invalidate(uuid);
+    self.inner.delete(uuid).await
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::{
+    helpers::tests::{fake_user, FakeUserRepository},
+    shared::clock::FixedClock,
+    users::model::email::Email,
+  };
+
+  use super::*;
+
+  /// The user the repository holds, under the uuid the tests look up.
+  fn user() -> User {
+    User {
+      uuid: UserId::try_from("some-uuid").unwrap(),
+      ..fake_user()
+    }
+  }
+
+  fn caching_repository(
+    ttl: Option<Duration>,
+  ) -> (
+    CachingUserRepository<FakeUserRepository, FixedClock>,
+    Arc<FixedClock>,
+  ) {
+    let clock = Arc::new(FixedClock::new(Utc::now()));
+    let inner = FakeUserRepository::new(user());
+    (CachingUserRepository::new(inner, clock.clone(), ttl), clock)
+  }
+
+  #[actix_web::test]
+  async fn test_cached_hit_skips_repository() {
+    let (repository, _) = caching_repository(Some(Duration::from_secs(30)));
+
+    let first = repository
+      .find_one(FindOneProperty::Uuid(
+        &UserId::try_from("some-uuid").unwrap(),
+      ))
+      .await;
+    let second = repository
+      .find_one(FindOneProperty::Uuid(
+        &UserId::try_from("some-uuid").unwrap(),
+      ))
+      .await;
+
+    assert_eq!(first.unwrap().uuid, second.unwrap().uuid);
+    assert_eq!(repository.inner.lookups(), 1);
+  }
+
+  #[actix_web::test]
+  async fn test_update_invalidates_entry() {
+    let (repository, _) = caching_repository(Some(Duration::from_secs(30)));
+    repository
+      .find_one(FindOneProperty::Uuid(
+        &UserId::try_from("some-uuid").unwrap(),
+      ))
+      .await
+      .unwrap();
+
+    let updated = User {
+      password_hash: String::from("new_hash"),
+      ..user()
+    };
+    repository.update(updated).await.unwrap();
+    let user = repository
+      .find_one(FindOneProperty::Uuid(
+        &UserId::try_from("some-uuid").unwrap(),
+      ))
+      .await
+      .unwrap();
+
+    assert_eq!(user.password_hash, "new_hash");
+    assert_eq!(repository.inner.lookups(), 2);
+  }
+
+  #[actix_web::test]
+  async fn test_delete_invalidates_entry() {
+    let (repository, _) = caching_repository(Some(Duration::from_secs(30)));
+    repository
+      .find_one(FindOneProperty::Uuid(
+        &UserId::try_from("some-uuid").unwrap(),
+      ))
+      .await
+      .unwrap();
+
+    repository
+      .delete(&UserId::try_from("some-uuid").unwrap())
+      .await
+      .unwrap();
+    repository
+      .find_one(FindOneProperty::Uuid(
+        &UserId::try_from("some-uuid").unwrap(),
+      ))
+      .await
+      .unwrap();
+
+    assert_eq!(repository.inner.lookups(), 2);
+  }
+
+  #[actix_web::test]
+  async fn test_entry_expires_after_ttl() {
+    let (repository, clock) = caching_repository(Some(Duration::from_secs(30)));
+    repository
+      .find_one(FindOneProperty::Uuid(
+        &UserId::try_from("some-uuid").unwrap(),
+      ))
+      .await
+      .unwrap();
+
+    clock.advance(chrono::Duration::seconds(30));
+    repository
+      .find_one(FindOneProperty::Uuid(
+        &UserId::try_from("some-uuid").unwrap(),
+      ))
+      .await
+      .unwrap();
+
+    assert_eq!(repository.inner.lookups(), 2);
+  }
+
+  #[actix_web::test]
+  async fn test_disabled_cache_and_email_lookups_pass_through() {
+    let (repository, _) = caching_repository(None);
+    for _ in 0..2 {
+      repository
+        .find_one(FindOneProperty::Uuid(
+          &UserId::try_from("some-uuid").unwrap(),
+        ))
+        .await
+        .unwrap();
+    }
+    assert_eq!(repository.inner.lookups(), 2);
+
+    let (repository, _) = caching_repository(Some(Duration::from_secs(30)));
+    for _ in 0..2 {
+      repository
+        .find_one(FindOneProperty::Email(
+          &Email::try_from("user@example.com").unwrap(),
+        ))
+        .await
+        .unwrap();
+    }
+    assert_eq!(repository.inner.lookups(), 2);
+  }
+}
@@ -1 +1,4 @@
+pub mod caching_user_repository;
+pub mod retrying_user_repository;
+pub mod timeout_user_repository;
 pub mod user_repository;
@@ -0,0 +1,144 @@
+use async_trait::async_trait;
+
+use crate::{
+  shared::retry::RetryPolicy,
+  users::model::{user::User, user_id::UserId},
+};
+
+use super::user_repository::{
+  FindOneProperty, UserFilter, UserPage, UserRepository, UserRepositoryError,
+  UserSort,
+};
+
+/// Wraps a repository so transient backend failures are retried according
+/// to `policy`. Creates and deletes are only retried when the backend
+/// rejected them, as a timed out insert may already have been applied, and
+/// a timed out delete would report `NotFound` once retried.
+pub struct RetryingUserRepository<UR: UserRepository> {
+  inner: UR,
+  policy: RetryPolicy,
+}
+
+impl<UR: UserRepository> RetryingUserRepository<UR> {
+  pub fn new(inner: UR, policy: RetryPolicy) -> Self {
+    Self { inner, policy }
+  }
+}
+
+#[async_trait]
+impl<UR: UserRepository> UserRepository for RetryingUserRepository<UR> {
+  async fn find_one(
+    &self,
+    property: FindOneProperty<'_>,
+  ) -> Result<User, UserRepositoryError> {
+    self
+      .policy
+      .retry(|| self.inner.find_one(property.clone()))
+      .await
+  }
+
+  async fn find_all(
+    &self,
+    filter: &UserFilter,
+    sort: UserSort,
+    cursor: Option<&str>,
+    limit: usize,
+  ) -> Result<UserPage, UserRepositoryError> {
+    self
+      .policy
+      .retry(|| self.inner.find_all(filter, sort, cursor, limit))
+      .await
+  }
+
+  async fn create(&self, user: User) -> Result<User, UserRepositoryError> {
+    self
+      .policy
+      .retry_unapplied_with(user, |user| self.inner.create(user))
+      .await
+  }
+
+  async fn update(&self, user: User) -> Result<(), UserRepositoryError> {
+    self
+      .policy
+      .retry_with(user, |user| self.inner.update(user))
+      .await
+  }
+
+  async fn delete(&self, uuid: &UserId) -> Result<(), UserRepositoryError> {
+    self
+      .policy
+      .retry_unapplied(|| self.inner.delete(uuid))
+      .await
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::helpers::tests::{fake_user, FakeUserRepository};
+
+  use super::*;
+
+  fn timeout() -> UserRepositoryError {
+    UserRepositoryError::MongoError(
+      std::io::Error::from(std::io::ErrorKind::TimedOut).into(),
+    )
+  }
+
+  #[actix_web::test]
+  async fn test_reads_and_writes_retry_transient_errors() {
+    let repository = RetryingUserRepository::new(
+      FakeUserRepository::new(fake_user()).failing(2, timeout),
+      RetryPolicy::new(3),
+    );
+    assert!(repository
+      .find_one(FindOneProperty::Uuid(&UserId::try_from("a").unwrap()))
+      .await
+      .is_ok());
+    assert_eq!(repository.inner.calls(), 3);
+
+    let repository = RetryingUserRepository::new(
+      FakeUserRepository::new(fake_user()).failing(2, timeout),
+      RetryPolicy::new(3),
+    );
+    assert!(repository.update(fake_user()).await.is_ok());
+    assert_eq!(repository.inner.calls(), 3);
+  }
+
+  #[actix_web::test]
+  async fn test_not_found_is_not_retried() {
+    let repository = RetryingUserRepository::new(
+      FakeUserRepository::new(fake_user())
+        .failing(1, || UserRepositoryError::NotFound),
+      RetryPolicy::new(3),
+    );
+    assert!(matches!(
+      repository.update(fake_user()).await,
+      Err(UserRepositoryError::NotFound)
+    ));
+    assert_eq!(repository.inner.calls(), 1);
+  }
+
+  #[actix_web::test]
+  async fn test_create_is_not_retried_after_timeout() {
+    let repository = RetryingUserRepository::new(
+      FakeUserRepository::new(fake_user()).failing(1, timeout),
+      RetryPolicy::new(3),
+    );
+    assert!(repository.create(fake_user()).await.is_err());
+    assert_eq!(repository.inner.calls(), 1);
+  }
+
+  #[actix_web::test]
+  async fn test_delete_is_not_retried_after_timeout() {
+    let user = fake_user();
+    let repository = RetryingUserRepository::new(
+      FakeUserRepository::new(user.clone()).failing(1, timeout),
+      RetryPolicy::new(3),
+    );
+    assert!(matches!(
+      repository.delete(&user.uuid).await,
+      Err(UserRepositoryError::MongoError(_))
+    ));
+    assert_eq!(repository.inner.calls(), 1);
+  }
+}
@@ -0,0 +1,119 @@
+use std::time::Duration;
+
+use actix_web::rt::time::timeout;
+use async_trait::async_trait;
+
+use crate::users::model::{user::User, user_id::UserId};
+
+use super::user_repository::{
+  FindOneProperty, UserFilter, UserPage, UserRepository, UserRepositoryError,
+  UserSort,
+};
+
+/// Wraps a repository so a backend call that hangs fails with
+/// `UserRepositoryError::Timeout` after `timeout` instead of holding the
+/// request. Placed inside `RetryingUserRepository`, each attempt gets the
+/// full timeout.
+pub struct TimeoutUserRepository<UR: UserRepository> {
+  inner: UR,
+  timeout: Duration,
+}
+
+impl<UR: UserRepository> TimeoutUserRepository<UR> {
+  pub fn new(inner: UR, timeout: Duration) -> Self {
+    Self { inner, timeout }
+  }
+}
+
+#[async_trait]
+impl<UR: UserRepository> UserRepository for TimeoutUserRepository<UR> {
+  async fn find_one(
+    &self,
+    property: FindOneProperty<'_>,
+  ) -> Result<User, UserRepositoryError> {
+    timeout(self.timeout, self.inner.find_one(property))
+      .await
+      .map_err(|_| UserRepositoryError::Timeout)?
+  }
+
+  async fn find_all(
+    &self,
+    filter: &UserFilter,
+    sort: UserSort,
+    cursor: Option<&str>,
+    limit: usize,
+  ) -> Result<UserPage, UserRepositoryError> {
+    timeout(
+      self.timeout,
+      self.inner.find_all(filter, sort, cursor, limit),
+    )
+    .await
+    .map_err(|_| UserRepositoryError::Timeout)?
+  }
+
+  async fn create(&self, user: User) -> Result<User, UserRepositoryError> {
+    timeout(self.timeout, self.inner.create(user))
+      .await
+      .map_err(|_| UserRepositoryError::Timeout)?
+  }
+
+  async fn update(&self, user: User) -> Result<(), UserRepositoryError> {
+    timeout(self.timeout, self.inner.update(user))
+      .await
+      .map_err(|_| UserRepositoryError::Timeout)?
+  }
+
+  async fn delete(&self, uuid: &UserId) -> Result<(), UserRepositoryError> {
+    timeout(self.timeout, self.inner.delete(uuid))
+      .await
+      .map_err(|_| UserRepositoryError::Timeout)?
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::helpers::tests::{fake_user, FakeUserRepository};
+
+  use super::*;
+
+  #[actix_web::test]
+  async fn test_slow_backend_times_out() {
+    let repository = TimeoutUserRepository::new(
+      FakeUserRepository::new(fake_user()).delayed(Duration::from_secs(5)),
+      Duration::from_millis(10),
+    );
+    let uuid = UserId::try_from("some-uuid").unwrap();
+
+    assert!(matches!(
+      repository.find_one(FindOneProperty::Uuid(&uuid)).await,
+      Err(UserRepositoryError::Timeout)
+    ));
+    assert!(matches!(
+      repository
+        .find_all(&UserFilter::default(), UserSort::default(), None, 10)
+        .await,
+      Err(UserRepositoryError::Timeout)
+    ));
+    assert!(matches!(
+      repository.create(fake_user()).await,
+      Err(UserRepositoryError::Timeout)
+    ));
+    assert!(matches!(
+      repository.update(fake_user()).await,
+      Err(UserRepositoryError::Timeout)
+    ));
+    assert!(matches!(
+      repository.delete(&uuid).await,
+      Err(UserRepositoryError::Timeout)
+    ));
+  }
+
+  #[actix_web::test]
+  async fn test_fast_backend_is_unaffected() {
+    let repository = TimeoutUserRepository::new(
+      FakeUserRepository::new(fake_user()),
+      Duration::from_secs(1),
+    );
+    assert!(repository.update(fake_user()).await.is_ok());
+  }
+}
@@ -1,21 +1,39 @@
+#[cfg(all(feature = "dynamodb", not(test)))]
+use std::collections::HashMap;
 use std::sync::Arc;
 
+#[cfg(all(feature = "dynamodb", not(test)))]
+use aws_sdk_dynamodb::error::ProvideErrorMetadata;
 #[cfg(all(feature = "dynamodb", not(test)))]
 use aws_sdk_dynamodb::{
   error::SdkError,
-  operation::{get_item::GetItemError, put_item::PutItemError},
+  operation::{
+    delete_item::DeleteItemError, get_item::GetItemError,
+    put_item::PutItemError, query::QueryError, scan::ScanError,
+  },
   types::AttributeValue,
 };
 
 #[cfg(feature = "mongodb")]
 use mongodb::bson::{doc, to_document};
 
+use async_trait::async_trait;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::Deserialize;
 use thiserror::Error;
+use utoipa::ToSchema;
 
-use crate::{shared::database::Database, users::model::user::User};
+use crate::{
+  shared::role::Role,
+  shared::{
+    database::Database,
+    retry::{ErrorClass, Retryable},
+  },
+  users::model::{email::Email, user::User, user_id::UserId},
+};
 
 #[cfg(all(feature = "dynamodb", not(test)))]
-use crate::shared::database::DynamoDatabase;
+use crate::shared::database::{DynamoDatabase, EMAIL_INDEX, USER_NAME_INDEX};
 
 #[cfg(feature = "mongodb")]
 use crate::shared::database::MongoDatabase;
@@ -30,51 +48,435 @@ pub enum UserRepositoryError {
   #[error("Get item error: {0}")]
   GetItemError(#[from] SdkError<GetItemError>),
 
+  #[cfg(all(feature = "dynamodb", not(test)))]
+  #[error("Query error: {0}")]
+  QueryError(#[from] SdkError<QueryError>),
+
+  #[cfg(all(feature = "dynamodb", not(test)))]
+  #[error("Scan error: {0}")]
+  ScanError(#[from] SdkError<ScanError>),
+
   #[cfg(all(feature = "dynamodb", not(test)))]
   #[error("Put item error: {0}")]
   PutItemError(#[from] SdkError<PutItemError>),
 
-  #[error("Other error: {0}")]
-  Other(String),
+  #[cfg(all(feature = "dynamodb", not(test)))]
+  #[error("Delete item error: {0}")]
+  DeleteItemError(#[from] SdkError<DeleteItemError>),
+
+  #[cfg(feature = "mongodb")]
+  #[error("MongoDB error: {0}")]
+  MongoError(#[from] mongodb::error::Error),
+
+  #[error("Not found")]
+  NotFound,
+
+  /// Another user already goes by the name, caught by the unique index
+  /// `ENFORCE_UNIQUE_USERNAME` adds on MongoDB.
+  #[cfg_attr(not(feature = "mongodb"), allow(dead_code))]
+  #[error("User name already taken")]
+  UserNameTaken,
+
+  /// Another active user has the email, caught by the unique index on
+  /// MongoDB when two sign-ups race past the check in `create_user`.
+  #[cfg_attr(not(feature = "mongodb"), allow(dead_code))]
+  #[error("Email already in use")]
+  EmailTaken,
+
+  /// An update tried to move `created_at`, which is fixed at creation.
+  #[error("created_at can't be changed")]
+  CreatedAtChanged,
+
+  #[error("Invalid cursor")]
+  InvalidCursor,
+
+  /// The backend didn't answer within `Config::database_query_timeout`.
+  #[error("Database query timed out")]
+  Timeout,
+}
+
+impl Retryable for UserRepositoryError {
+  fn class(&self) -> ErrorClass {
+    match self {
+      #[cfg(all(feature = "dynamodb", not(test)))]
+      UserRepositoryError::SerializationError(_) => ErrorClass::Permanent,
+      #[cfg(all(feature = "dynamodb", not(test)))]
+      UserRepositoryError::GetItemError(error) => dynamo_error_class(error),
+      #[cfg(all(feature = "dynamodb", not(test)))]
+      UserRepositoryError::QueryError(error) => dynamo_error_class(error),
+      #[cfg(all(feature = "dynamodb", not(test)))]
+      UserRepositoryError::ScanError(error) => dynamo_error_class(error),
+      #[cfg(all(feature = "dynamodb", not(test)))]
+      UserRepositoryError::PutItemError(error) => dynamo_error_class(error),
+      #[cfg(all(feature = "dynamodb", not(test)))]
+      UserRepositoryError::DeleteItemError(error) => dynamo_error_class(error),
+      #[cfg(feature = "mongodb")]
+      UserRepositoryError::MongoError(error) => mongo_error_class(error),
+      // The call may still have reached the backend.
+      UserRepositoryError::Timeout => ErrorClass::Indeterminate,
+      UserRepositoryError::NotFound
+      | UserRepositoryError::InvalidCursor
+      | UserRepositoryError::UserNameTaken
+      | UserRepositoryError::EmailTaken
+      | UserRepositoryError::CreatedAtChanged => ErrorClass::Permanent,
+    }
+  }
+}
+
+#[cfg(all(feature = "dynamodb", not(test)))]
+fn dynamo_error_class<E: ProvideErrorMetadata, R>(
+  error: &SdkError<E, R>,
+) -> ErrorClass {
+  match error {
+    SdkError::TimeoutError(_)
+    | SdkError::DispatchFailure(_)
+    | SdkError::ResponseError(_) => ErrorClass::Indeterminate,
+    SdkError::ServiceError(_) => match error.code() {
+      Some(
+        "ThrottlingException"
+        | "ProvisionedThroughputExceededException"
+        | "RequestLimitExceeded",
+      ) => ErrorClass::Rejected,
+      Some("InternalServerError" | "ServiceUnavailable") => {
+        ErrorClass::Indeterminate
+      }
+      // Conditional-check failures, validation errors and the like.
+      _ => ErrorClass::Permanent,
+    },
+    _ => ErrorClass::Permanent,
+  }
+}
+
+/// Server error code of writes refused by a unique index.
+#[cfg(feature = "mongodb")]
+const DUPLICATE_KEY: i32 = 11000;
+
+/// The error for a write `DUPLICATE_KEY` refused, told apart by the unique
+/// index named in `message`.
+#[cfg(feature = "mongodb")]
+fn duplicate_key_error(message: &str) -> Option<UserRepositoryError> {
+  if message.contains("index: email_1 ") {
+    Some(UserRepositoryError::EmailTaken)
+  } else if message.contains("index: user_name_1 ") {
+    Some(UserRepositoryError::UserNameTaken)
+  } else {
+    None
+  }
 }
 
+#[cfg(feature = "mongodb")]
+fn mongo_error_class(error: &mongodb::error::Error) -> ErrorClass {
+  use mongodb::error::{ErrorKind, RETRYABLE_WRITE_ERROR};
+
+  match *error.kind {
+    // No server was picked, so nothing was sent.
+    ErrorKind::ServerSelection { .. } => ErrorClass::Rejected,
+    ErrorKind::Io(_) | ErrorKind::ConnectionPoolCleared { .. } => {
+      ErrorClass::Indeterminate
+    }
+    _ if error.contains_label(RETRYABLE_WRITE_ERROR) => {
+      ErrorClass::Indeterminate
+    }
+    _ => ErrorClass::Permanent,
+  }
+}
+
+#[derive(Clone)]
 pub enum FindOneProperty<'a> {
-  Uuid(&'a str),
-  Email(&'a str),
+  Uuid(&'a UserId),
+  Email(&'a Email),
+  UserName(&'a str),
+}
+
+/// How DynamoDB serves a lookup.
+#[cfg(all(feature = "dynamodb", not(test)))]
+enum DynamoLookup {
+  /// The table key.
+  Key,
+  Index(&'static str),
 }
 
 impl FindOneProperty<'_> {
+  /// The single mapping from a lookup to the stored field and value it
+  /// targets. Backends build their query from this, so a new variant only
+  /// needs wiring here and in `matches`.
+  fn field(&self) -> (&'static str, &str) {
+    match self {
+      FindOneProperty::Uuid(uuid) => ("uuid", uuid),
+      FindOneProperty::Email(email) => ("email", email),
+      FindOneProperty::UserName(user_name) => ("user_name", user_name),
+    }
+  }
+
+  /// Evaluates the lookup against a loaded user, for backends that filter in
+  /// process.
+  #[cfg(any(feature = "in-memory", test))]
+  fn matches(&self, user: &User) -> bool {
+    let (_, value) = self.field();
+    let stored = match self {
+      FindOneProperty::Uuid(_) => user.uuid.as_str(),
+      FindOneProperty::Email(_) => user.email.as_str(),
+      FindOneProperty::UserName(_) => user.user_name.as_str(),
+    };
+    stored == value
+  }
+
   #[cfg(all(feature = "dynamodb", not(test)))]
-  fn to_dynamo_key_value(&self) -> (&str, AttributeValue) {
+  fn dynamo_lookup(&self) -> DynamoLookup {
     match self {
-      FindOneProperty::Uuid(uuid) => {
-        ("uuid", AttributeValue::S(uuid.to_string()))
-      }
-      FindOneProperty::Email(email) => {
-        ("email", AttributeValue::S(email.to_string()))
-      }
+      FindOneProperty::Uuid(_) => DynamoLookup::Key,
+      FindOneProperty::Email(_) => DynamoLookup::Index(EMAIL_INDEX),
+      // Created along with `ENFORCE_UNIQUE_USERNAME`, the only reason
+      // user names are looked up.
+      FindOneProperty::UserName(_) => DynamoLookup::Index(USER_NAME_INDEX),
     }
   }
+
+  #[cfg(all(feature = "dynamodb", not(test)))]
+  fn to_dynamo_key_value(&self) -> (&str, AttributeValue) {
+    let (field, value) = self.field();
+    (field, AttributeValue::S(value.to_string()))
+  }
+
   #[cfg(feature = "mongodb")]
   fn to_mongo_key_value(&self) -> mongodb::bson::Document {
-    match self {
-      FindOneProperty::Uuid(uuid) => {
-        doc! { "uuid": uuid }
-      }
-      FindOneProperty::Email(email) => {
-        doc! { "email": email }
-      }
-    }
+    let (field, value) = self.field();
+    doc! { field: value }
   }
 }
 
-pub trait UserRepository {
+/// Send + Sync with boxed futures, so a backend picked at runtime can sit
+/// behind a `Box<dyn UserRepository>` shared across workers.
+#[async_trait]
+pub trait UserRepository: Send + Sync {
   async fn find_one(
     &self,
-    property: FindOneProperty,
+    property: FindOneProperty<'_>,
   ) -> Result<User, UserRepositoryError>;
-  async fn find_all(&self) -> Result<Vec<User>, UserRepositoryError>;
-  async fn create(&self, user: User) -> Result<(), UserRepositoryError>;
+  /// Lists users in `sort` order a page at a time, starting after `cursor`
+  /// or from the beginning without one. Cursors are only valid for the
+  /// `sort` they were issued with.
+  async fn find_all(
+    &self,
+    filter: &UserFilter,
+    sort: UserSort,
+    cursor: Option<&str>,
+    limit: usize,
+  ) -> Result<UserPage, UserRepositoryError>;
+  /// Stores a new user and hands it back, so callers can move it in without
+  /// keeping a copy.
+  async fn create(&self, user: User) -> Result<User, UserRepositoryError>;
+  async fn update(&self, user: User) -> Result<(), UserRepositoryError>;
+  /// Permanently removes the user, soft-deleted or not.
+  async fn delete(&self, uuid: &UserId) -> Result<(), UserRepositoryError>;
+}
+
+/// Lets code generic over `UserRepository`, such as the handlers, take a
+/// boxed backend.
+#[async_trait]
+impl<UR: UserRepository + ?Sized> UserRepository for Box<UR> {
+  async fn find_one(
+    &self,
+    property: FindOneProperty<'_>,
+  ) -> Result<User, UserRepositoryError> {
+    (**self).find_one(property).await
+  }
+
+  async fn find_all(
+    &self,
+    filter: &UserFilter,
+    sort: UserSort,
+    cursor: Option<&str>,
+    limit: usize,
+  ) -> Result<UserPage, UserRepositoryError> {
+    (**self).find_all(filter, sort, cursor, limit).await
+  }
+
+  async fn create(&self, user: User) -> Result<User, UserRepositoryError> {
+    (**self).create(user).await
+  }
+
+  async fn update(&self, user: User) -> Result<(), UserRepositoryError> {
+    (**self).update(user).await
+  }
+
+  async fn delete(&self, uuid: &UserId) -> Result<(), UserRepositoryError> {
+    (**self).delete(uuid).await
+  }
+}
+
+/// Narrows down `find_all`. Unset fields match every user.
+#[derive(Clone, Default)]
+pub struct UserFilter {
+  pub role: Option<Role>,
+  pub enabled: Option<bool>,
+}
+
+/// Order of `find_all`. Ties are broken by `uuid`, so backends list users
+/// alike and pages neither overlap nor skip users.
+#[derive(ToSchema, Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum UserSort {
+  /// Oldest first.
+  #[default]
+  #[serde(rename = "createdAt")]
+  CreatedAt,
+  #[serde(rename = "email")]
+  Email,
+}
+
+impl UserSort {
+  /// Backends that can't sort, sort in memory with this.
+  #[cfg(any(feature = "dynamodb", feature = "in-memory", test))]
+  fn compare(&self, a: &User, b: &User) -> std::cmp::Ordering {
+    match self {
+      UserSort::CreatedAt => a.created_at.cmp(&b.created_at),
+      UserSort::Email => a.email.cmp(&b.email),
+    }
+    .then_with(|| a.uuid.cmp(&b.uuid))
+  }
+
+  #[cfg(feature = "mongodb")]
+  fn to_mongo_sort(self) -> mongodb::bson::Document {
+    match self {
+      UserSort::CreatedAt => doc! { "created_at": 1, "uuid": 1 },
+      UserSort::Email => doc! { "email": 1, "uuid": 1 },
+    }
+  }
+}
+
+#[cfg(all(feature = "dynamodb", not(test)))]
+struct DynamoFilter {
+  expression: String,
+  names: HashMap<String, String>,
+  values: HashMap<String, AttributeValue>,
+}
+
+impl UserFilter {
+  #[cfg(any(feature = "in-memory", test))]
+  fn matches(&self, user: &User) -> bool {
+    self.role.as_ref().is_none_or(|role| *role == user.role)
+      && self.enabled.is_none_or(|enabled| enabled == user.enabled)
+  }
+
+  /// Scan filter that also leaves out soft-deleted users. Users stored
+  /// before `enabled` existed count as enabled.
+  #[cfg(all(feature = "dynamodb", not(test)))]
+  fn to_dynamo_filter(&self) -> Result<DynamoFilter, serde_dynamo::Error> {
+    let mut conditions = vec![
+      "(attribute_not_exists(#deleted_at) OR attribute_type(#deleted_at, :null))",
+    ];
+    let mut names = HashMap::from([(
+      String::from("#deleted_at"),
+      String::from("deleted_at"),
+    )]);
+    let mut values = HashMap::from([(
+      String::from(":null"),
+      AttributeValue::S(String::from("NULL")),
+    )]);
+    if let Some(role) = &self.role {
+      conditions.push("#role = :role");
+      names.insert(String::from("#role"), String::from("role"));
+      values.insert(
+        String::from(":role"),
+        serde_dynamo::to_attribute_value(role)?,
+      );
+    }
+    if let Some(enabled) = self.enabled {
+      conditions.push(if enabled {
+        "(attribute_not_exists(#enabled) OR #enabled = :enabled)"
+      } else {
+        "#enabled = :enabled"
+      });
+      names.insert(String::from("#enabled"), String::from("enabled"));
+      values.insert(String::from(":enabled"), AttributeValue::Bool(enabled));
+    }
+    Ok(DynamoFilter {
+      expression: conditions.join(" AND "),
+      names,
+      values,
+    })
+  }
+
+  /// Query that also leaves out soft-deleted users. Users stored before
+  /// `enabled` existed count as enabled.
+  #[cfg(feature = "mongodb")]
+  fn to_mongo_filter(
+    &self,
+  ) -> Result<mongodb::bson::Document, mongodb::error::Error> {
+    let mut filter = doc! { "deleted_at": mongodb::bson::Bson::Null };
+    if let Some(role) = &self.role {
+      filter.insert(
+        "role",
+        mongodb::bson::to_bson(role).map_err(mongodb::error::Error::from)?,
+      );
+    }
+    match self.enabled {
+      Some(true) => filter.insert("enabled", doc! { "$ne": false }),
+      Some(false) => filter.insert("enabled", false),
+      None => None,
+    };
+    Ok(filter)
+  }
+}
+
+/// One page of `find_all`. `next_cursor` is an opaque token for the
+/// following page and is absent on the last one.
+pub struct UserPage {
+  pub users: Vec<User>,
+  pub next_cursor: Option<String>,
+}
+
+#[cfg(any(
+  feature = "mongodb",
+  feature = "dynamodb",
+  feature = "in-memory",
+  test
+))]
+impl UserPage {
+  /// Builds a page from up to `limit + 1` users read at `offset`, for
+  /// backends that paginate by offset. The extra user only tells whether
+  /// another page follows.
+  fn from_offset(mut users: Vec<User>, offset: usize, limit: usize) -> Self {
+    let next_cursor = (users.len() > limit)
+      .then(|| encode_cursor(&(offset + limit).to_string()));
+    users.truncate(limit);
+    Self { users, next_cursor }
+  }
+
+  /// Builds the page at `offset` out of every user matching the filter,
+  /// for backends that can't sort.
+  #[cfg(any(feature = "dynamodb", feature = "in-memory", test))]
+  fn sorted(
+    mut users: Vec<User>,
+    sort: UserSort,
+    offset: usize,
+    limit: usize,
+  ) -> Self {
+    users.sort_by(|a, b| sort.compare(a, b));
+    let users = users.into_iter().skip(offset).take(limit + 1).collect();
+    Self::from_offset(users, offset, limit)
+  }
+}
+
+/// Cursors wrap an offset into the sorted users, encoded so clients can't
+/// depend on their contents.
+fn encode_cursor(position: &str) -> String {
+  URL_SAFE_NO_PAD.encode(position)
+}
+
+fn decode_cursor(cursor: &str) -> Option<String> {
+  let bytes = URL_SAFE_NO_PAD.decode(cursor).ok()?;
+  String::from_utf8(bytes).ok()
+}
+
+#[cfg(any(
+  feature = "mongodb",
+  feature = "dynamodb",
+  feature = "in-memory",
+  test
+))]
+fn decode_offset(cursor: &str) -> Option<usize> {
+  decode_cursor(cursor)?.parse().ok()
 }
 
 pub struct UserRepositoryImpl<DB: Database> {
@@ -88,112 +490,538 @@ impl<DB: Database> UserRepositoryImpl<DB> {
 }
 
 #[cfg(all(feature = "dynamodb", not(test)))]
+#[async_trait]
 impl UserRepository for UserRepositoryImpl<DynamoDatabase> {
-  async fn find_one<'a>(
+  async fn find_one(
     &self,
-    property: FindOneProperty<'a>,
+    property: FindOneProperty<'_>,
   ) -> Result<User, UserRepositoryError> {
     let (key, value) = property.to_dynamo_key_value();
-    let result = self
+    let items = match property.dynamo_lookup() {
+      DynamoLookup::Index(index) => self
+        .database
+        .client
+        .query()
+        .table_name("users")
+        .index_name(index)
+        .key_condition_expression("#key = :value")
+        .expression_attribute_names("#key", key)
+        .expression_attribute_values(":value", value)
+        .send()
+        .await?
+        .items
+        .unwrap_or_default(),
+      DynamoLookup::Key => self
+        .database
+        .client
+        .get_item()
+        .table_name("users")
+        .key(key, value)
+        .send()
+        .await?
+        .item
+        .into_iter()
+        .collect(),
+    };
+    for item in items {
+      let user: User = serde_dynamo::from_item(item)?;
+      if user.deleted_at.is_none() {
+        return Ok(user);
+      }
+    }
+    Err(UserRepositoryError::NotFound)
+  }
+
+  async fn find_all(
+    &self,
+    filter: &UserFilter,
+    sort: UserSort,
+    cursor: Option<&str>,
+    limit: usize,
+  ) -> Result<UserPage, UserRepositoryError> {
+    let offset = match cursor {
+      Some(cursor) => {
+        decode_offset(cursor).ok_or(UserRepositoryError::InvalidCursor)?
+      }
+      None => 0,
+    };
+    let filter = filter.to_dynamo_filter()?;
+    // A scan can't be sorted, so every matching user is read and sorted
+    // here, which costs a full scan per page.
+    let mut users: Vec<User> = Vec::new();
+    let mut start_key = None;
+    loop {
+      let result = self
+        .database
+        .client
+        .scan()
+        .table_name("users")
+        .set_exclusive_start_key(start_key)
+        .filter_expression(filter.expression.clone())
+        .set_expression_attribute_names(Some(filter.names.clone()))
+        .set_expression_attribute_values(Some(filter.values.clone()))
+        .send()
+        .await?;
+      users.extend(serde_dynamo::from_items::<_, User>(
+        result.items.unwrap_or_default(),
+      )?);
+      start_key = result.last_evaluated_key;
+      if start_key.is_none() {
+        break;
+      }
+    }
+    Ok(UserPage::sorted(users, sort, offset, limit))
+  }
+
+  async fn create(&self, user: User) -> Result<User, UserRepositoryError> {
+    let item = serde_dynamo::to_item(&user)?;
+    self
       .database
       .client
-      .get_item()
+      .put_item()
       .table_name("users")
-      .key(key, value)
+      .set_item(Some(item))
       .send()
       .await?;
-    if let Some(item) = result.item {
-      let user: User = serde_dynamo::from_item(item).unwrap();
-      return Ok(user);
-    }
-    Err(UserRepositoryError::Other(String::from("No item")))
-  }
-
-  async fn find_all(&self) -> Result<Vec<User>, UserRepositoryError> {
-    Ok(Vec::new())
+    Ok(user)
   }
 
-  async fn create(&self, user: User) -> Result<(), UserRepositoryError> {
-    let item = serde_dynamo::to_item(&user).unwrap();
+  async fn update(&self, user: User) -> Result<(), UserRepositoryError> {
+    let item = serde_dynamo::to_item(&user)?;
     self
       .database
       .client
       .put_item()
       .table_name("users")
       .set_item(Some(item))
+      // Refuse writes that would move `created_at`.
+      .condition_expression(
+        "attribute_exists(#uuid) AND #created_at = :created_at",
+      )
+      .expression_attribute_names("#uuid", "uuid")
+      .expression_attribute_names("#created_at", "created_at")
+      .expression_attribute_values(
+        ":created_at",
+        serde_dynamo::to_attribute_value(user.created_at)?,
+      )
+      .return_values_on_condition_check_failure(
+        aws_sdk_dynamodb::types::ReturnValuesOnConditionCheckFailure::AllOld,
+      )
+      .send()
+      .await
+      .map_err(|error| match error.as_service_error() {
+        // A user was found, so it's `created_at` that didn't match.
+        Some(PutItemError::ConditionalCheckFailedException(failed)) => {
+          match failed.item() {
+            Some(_) => UserRepositoryError::CreatedAtChanged,
+            None => UserRepositoryError::NotFound,
+          }
+        }
+        _ => UserRepositoryError::from(error),
+      })?;
+    Ok(())
+  }
+
+  async fn delete(&self, uuid: &UserId) -> Result<(), UserRepositoryError> {
+    let result = self
+      .database
+      .client
+      .delete_item()
+      .table_name("users")
+      .key("uuid", AttributeValue::S(uuid.to_string()))
+      .return_values(aws_sdk_dynamodb::types::ReturnValue::AllOld)
       .send()
       .await?;
+    if result.attributes.is_none() {
+      return Err(UserRepositoryError::NotFound);
+    }
     Ok(())
   }
 }
 
 // ### MongoDB implementation ###
 #[cfg(feature = "mongodb")]
+#[async_trait]
 impl UserRepository for UserRepositoryImpl<MongoDatabase> {
-  async fn find_one<'a>(
+  async fn find_one(
     &self,
-    property: FindOneProperty<'a>,
+    property: FindOneProperty<'_>,
   ) -> Result<User, UserRepositoryError> {
+    let mut filter = property.to_mongo_key_value();
+    // Matches both a missing field and an explicit null.
+    filter.insert("deleted_at", mongodb::bson::Bson::Null);
     let result: Option<User> = self
       .database
       .client
       .database("test")
       .collection("users")
-      .find_one(property.to_mongo_key_value())
-      .await
-      .unwrap(); // TODO: Remove unwrap
+      .find_one(filter)
+      .await?;
     if let Some(user) = result {
       return Ok(user);
     }
-    Err(UserRepositoryError::Other(String::from("No item")))
+    Err(UserRepositoryError::NotFound)
   }
 
-  async fn find_all(&self) -> Result<Vec<User>, UserRepositoryError> {
-    Ok(Vec::new())
+  async fn find_all(
+    &self,
+    filter: &UserFilter,
+    sort: UserSort,
+    cursor: Option<&str>,
+    limit: usize,
+  ) -> Result<UserPage, UserRepositoryError> {
+    let offset = match cursor {
+      Some(cursor) => {
+        decode_offset(cursor).ok_or(UserRepositoryError::InvalidCursor)?
+      }
+      None => 0,
+    };
+    let mut cursor = self
+      .database
+      .client
+      .database("test")
+      .collection::<User>("users")
+      .find(filter.to_mongo_filter()?)
+      .sort(sort.to_mongo_sort())
+      .skip(offset as u64)
+      .limit(limit as i64 + 1)
+      .await?;
+    let mut users = Vec::new();
+    while cursor.advance().await? {
+      users.push(cursor.deserialize_current()?);
+    }
+    Ok(UserPage::from_offset(users, offset, limit))
   }
 
-  async fn create(&self, user: User) -> Result<(), UserRepositoryError> {
-    _ = self
+  async fn create(&self, user: User) -> Result<User, UserRepositoryError> {
+    let document = to_document(&user).map_err(mongodb::error::Error::from)?;
+    self
       .database
       .client
       .database("test")
       .collection("users")
-      .insert_one(to_document(&user).unwrap())
-      .await;
+      .insert_one(document)
+      .await
+      .map_err(|error| match *error.kind {
+        mongodb::error::ErrorKind::Write(
+          mongodb::error::WriteFailure::WriteError(ref write_error),
+        ) if write_error.code == DUPLICATE_KEY => {
+          duplicate_key_error(&write_error.message)
+            .unwrap_or_else(|| UserRepositoryError::from(error))
+        }
+        _ => UserRepositoryError::from(error),
+      })?;
+    Ok(user)
+  }
+
+  async fn update(&self, user: User) -> Result<(), UserRepositoryError> {
+    let mut document =
+      to_document(&user).map_err(mongodb::error::Error::from)?;
+    // `created_at` is set once on insert and never rewritten.
+    let created_at = document
+      .remove("created_at")
+      .expect("users serialize with created_at");
+    let users = self
+      .database
+      .client
+      .database("test")
+      .collection::<User>("users");
+    let result = users
+      .update_one(
+        doc! { "uuid": user.uuid.as_str(), "created_at": created_at },
+        doc! { "$set": document },
+      )
+      .await?;
+    if result.matched_count == 0 {
+      let exists = users
+        .count_documents(doc! { "uuid": user.uuid.as_str() })
+        .await?
+        > 0;
+      return Err(match exists {
+        true => UserRepositoryError::CreatedAtChanged,
+        false => UserRepositoryError::NotFound,
+      });
+    }
+    Ok(())
+  }
+
+  async fn delete(&self, uuid: &UserId) -> Result<(), UserRepositoryError> {
+    let result = self
+      .database
+      .client
+      .database("test")
+      .collection::<User>("users")
+      .delete_one(doc! { "uuid": uuid.as_str() })
+      .await?;
+    if result.deleted_count == 0 {
+      return Err(UserRepositoryError::NotFound);
+    }
     Ok(())
   }
 }
 
 #[cfg(any(feature = "in-memory", test))]
+#[async_trait]
 impl UserRepository
   for UserRepositoryImpl<crate::shared::database::InMemoryDatabase>
 {
-  async fn find_one<'a>(
+  async fn find_one(
     &self,
-    property: FindOneProperty<'a>,
+    property: FindOneProperty<'_>,
   ) -> Result<User, UserRepositoryError> {
-    // Acquire read lock
     self
       .database
-      .users
-      .read()
-      .unwrap()
+      .read_users()
       .iter()
-      .find(|user| match property {
-        FindOneProperty::Uuid(uuid) => user.uuid == uuid,
-        FindOneProperty::Email(email) => user.email == email,
-      })
+      .filter(|user| user.deleted_at.is_none())
+      .find(|user| property.matches(user))
       .cloned()
-      .ok_or(UserRepositoryError::Other(String::new()))
+      .ok_or(UserRepositoryError::NotFound)
+  }
+
+  async fn create(&self, user: User) -> Result<User, UserRepositoryError> {
+    let mut users = self.database.write_users();
+    users.push(user);
+    // The store keeps its own copy, so the caller gets the stored one back.
+    Ok(users.last().expect("just pushed").clone())
   }
 
-  async fn create(&self, user: User) -> Result<(), UserRepositoryError> {
-    let mut users = self.database.users.write().unwrap(); // Acquire write lock
-    users.push(user.clone());
+  async fn find_all(
+    &self,
+    filter: &UserFilter,
+    sort: UserSort,
+    cursor: Option<&str>,
+    limit: usize,
+  ) -> Result<UserPage, UserRepositoryError> {
+    let offset = match cursor {
+      Some(cursor) => {
+        decode_offset(cursor).ok_or(UserRepositoryError::InvalidCursor)?
+      }
+      None => 0,
+    };
+    let users = self
+      .database
+      .read_users()
+      .iter()
+      .filter(|user| user.deleted_at.is_none() && filter.matches(user))
+      .cloned()
+      .collect();
+    Ok(UserPage::sorted(users, sort, offset, limit))
+  }
+
+  async fn update(&self, user: User) -> Result<(), UserRepositoryError> {
+    let mut users = self.database.write_users();
+    let existing = users
+      .iter_mut()
+      .find(|existing| existing.uuid == user.uuid)
+      .ok_or(UserRepositoryError::NotFound)?;
+    if existing.created_at != user.created_at {
+      return Err(UserRepositoryError::CreatedAtChanged);
+    }
+    *existing = user;
     Ok(())
   }
 
-  async fn find_all(&self) -> Result<Vec<User>, UserRepositoryError> {
-    Ok(self.database.users.read().unwrap().clone())
+  async fn delete(&self, uuid: &UserId) -> Result<(), UserRepositoryError> {
+    let mut users = self.database.write_users();
+    let length = users.len();
+    users.retain(|user| user.uuid != *uuid);
+    if users.len() == length {
+      return Err(UserRepositoryError::NotFound);
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::RwLock;
+
+  use crate::{
+    helpers::tests::fake_user,
+    shared::{database::InMemoryDatabase, role::Role},
+  };
+
+  use super::*;
+
+  async fn find_by_uuid<UR: UserRepository>(
+    user_repository: &UR,
+    uuid: &UserId,
+  ) -> Result<User, UserRepositoryError> {
+    user_repository.find_one(FindOneProperty::Uuid(uuid)).await
+  }
+
+  #[actix_web::test]
+  async fn test_boxed_repository() {
+    let user_repository: Box<dyn UserRepository> =
+      Box::new(UserRepositoryImpl::new(Arc::new(InMemoryDatabase {
+        users: Arc::new(RwLock::new(Vec::new())),
+      })));
+    let user = user_repository.create(fake_user()).await.unwrap();
+
+    // Usable both directly and wherever a generic repository is expected,
+    // from another thread too.
+    let found = find_by_uuid(&user_repository, &user.uuid).await.unwrap();
+    assert_eq!(found.email, user.email);
+    let user_repository = Arc::new(user_repository);
+    let handle = std::thread::spawn({
+      let user_repository = user_repository.clone();
+      let email = user.email.clone();
+      move || {
+        actix_web::rt::System::new().block_on(async move {
+          user_repository
+            .find_one(FindOneProperty::Email(&email))
+            .await
+        })
+      }
+    });
+    assert_eq!(handle.join().unwrap().unwrap().uuid, user.uuid);
+  }
+
+  #[test]
+  fn test_find_one_property_field() {
+    assert_eq!(
+      FindOneProperty::Uuid(&UserId::try_from("a").unwrap()).field(),
+      ("uuid", "a")
+    );
+    assert_eq!(
+      FindOneProperty::Email(&Email::try_from("b@example.com").unwrap())
+        .field(),
+      ("email", "b@example.com")
+    );
+  }
+
+  #[test]
+  fn test_page_from_offset() {
+    let users: Vec<User> = (0..3).map(|_| fake_user()).collect();
+
+    let page = UserPage::from_offset(users.clone(), 4, 2);
+    assert_eq!(page.users.len(), 2);
+    assert_eq!(decode_offset(&page.next_cursor.unwrap()), Some(6));
+
+    let page = UserPage::from_offset(users, 4, 3);
+    assert_eq!(page.users.len(), 3);
+    assert!(page.next_cursor.is_none());
+  }
+
+  #[test]
+  fn test_sorted_page_ignores_read_order() {
+    let users: Vec<User> = (0..5).map(|_| fake_user()).collect();
+    let mut expected = users.clone();
+    expected.sort_by(|a, b| UserSort::Email.compare(a, b));
+    let mut reversed = users.clone();
+    reversed.reverse();
+
+    for read in [users, reversed] {
+      let first = UserPage::sorted(read.clone(), UserSort::Email, 0, 3);
+      let offset = decode_offset(&first.next_cursor.unwrap()).unwrap();
+      let second = UserPage::sorted(read, UserSort::Email, offset, 3);
+      assert!(second.next_cursor.is_none());
+      let listed: Vec<UserId> = first
+        .users
+        .into_iter()
+        .chain(second.users)
+        .map(|user| user.uuid)
+        .collect();
+      let expected: Vec<UserId> =
+        expected.iter().map(|user| user.uuid.clone()).collect();
+      assert_eq!(listed, expected);
+    }
+  }
+
+  #[cfg(feature = "mongodb")]
+  #[test]
+  fn test_duplicate_key_error() {
+    let message = |index: &str, key: &str| {
+      format!(
+        "E11000 duplicate key error collection: test.users index: {} dup \
+         key: {{ {} }}",
+        index, key
+      )
+    };
+    assert!(matches!(
+      duplicate_key_error(&message("email_1", "email: \"a@example.com\"")),
+      Some(UserRepositoryError::EmailTaken)
+    ));
+    // Told apart by the index, not by the duplicated value.
+    assert!(matches!(
+      duplicate_key_error(&message("user_name_1", "user_name: \"email\"")),
+      Some(UserRepositoryError::UserNameTaken)
+    ));
+    assert!(duplicate_key_error(&message("uuid_1", "uuid: \"a\"")).is_none());
+  }
+
+  #[test]
+  fn test_decode_offset() {
+    assert_eq!(decode_offset(&encode_cursor("12")), Some(12));
+    assert_eq!(decode_offset(&encode_cursor("uuid")), None);
+    assert_eq!(decode_offset("***"), None);
+  }
+
+  #[test]
+  fn test_user_filter_matches() {
+    let user = fake_user();
+    assert!(UserFilter::default().matches(&user));
+    assert!(UserFilter {
+      role: Some(Role::Customer),
+      enabled: Some(true),
+    }
+    .matches(&user));
+    assert!(!UserFilter {
+      role: Some(Role::Driver),
+      ..UserFilter::default()
+    }
+    .matches(&user));
+    assert!(!UserFilter {
+      enabled: Some(false),
+      ..UserFilter::default()
+    }
+    .matches(&user));
+  }
+
+  #[test]
+  fn test_find_one_property_matches() {
+    let user = fake_user();
+    assert!(FindOneProperty::Uuid(&user.uuid).matches(&user));
+    assert!(FindOneProperty::Email(&user.email).matches(&user));
+    assert!(
+      !FindOneProperty::Uuid(&UserId::try_from("other-uuid").unwrap())
+        .matches(&user)
+    );
+    assert!(!FindOneProperty::Email(
+      &Email::try_from("other@example.com").unwrap()
+    )
+    .matches(&user));
+  }
+
+  #[actix_web::test]
+  async fn test_poisoned_lock_does_not_cascade() {
+    let user = fake_user();
+    let users = Arc::new(std::sync::RwLock::new(vec![user.clone()]));
+    let repository = UserRepositoryImpl::new(Arc::new(
+      crate::shared::database::InMemoryDatabase {
+        users: users.clone(),
+      },
+    ));
+
+    let poisoner = users.clone();
+    let _ = std::thread::spawn(move || {
+      let _guard = poisoner.write().unwrap();
+      panic!("poison the users lock");
+    })
+    .join();
+    assert!(users.is_poisoned());
+
+    assert!(repository
+      .find_one(FindOneProperty::Uuid(&user.uuid))
+      .await
+      .is_ok());
+    repository.delete(&user.uuid).await.unwrap();
+    assert!(repository
+      .find_all(&UserFilter::default(), UserSort::default(), None, 10)
+      .await
+      .unwrap()
+      .users
+      .is_empty());
   }
 }
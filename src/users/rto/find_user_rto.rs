@@ -1,11 +1,24 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 
-use crate::shared::role::Role;
+use crate::{
+  shared::role::Role,
+  users::model::{email::Email, user_id::UserId},
+};
 
 #[derive(ToSchema, Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
 pub struct FindUserRto {
-  pub email: String,
+  #[schema(value_type = String, example = "V1StGXR8Z5jdHi6B")]
+  pub uuid: UserId,
+  #[schema(value_type = String, example = "jane.doe@example.com")]
+  pub email: Email,
+  #[schema(example = "Jane Doe")]
   pub user_name: String,
   pub role: Role,
+  #[schema(value_type = String, format = DateTime, example = "2024-01-31T12:00:00Z")]
+  pub created_at: DateTime<Utc>,
+  #[schema(value_type = String, format = DateTime, example = "2024-01-31T12:00:00Z")]
+  pub updated_at: DateTime<Utc>,
 }
@@ -0,0 +1,23 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use utoipa::ToSchema;
+
+use super::find_user_rto::FindUserRto;
+
+#[derive(ToSchema, Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct FindUsersRto {
+  pub users: Vec<FindUserRto>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[schema(example = "NTA")]
+  pub next_cursor: Option<String>,
+}
+
+/// `FindUsersRto` with each user narrowed to the fields a client asked for.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ProjectedUsersRto {
+  pub users: Vec<Map<String, Value>>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub next_cursor: Option<String>,
+}
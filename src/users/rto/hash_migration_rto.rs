@@ -0,0 +1,20 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::users::model::user_id::UserId;
+
+/// Progress of moving stored password hashes to the current parameters.
+#[derive(ToSchema, Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct HashMigrationRto {
+  #[schema(example = 200)]
+  pub total: usize,
+  #[schema(example = 50)]
+  pub outdated: usize,
+  /// Share of users already on the current parameters, 100 without users.
+  #[schema(example = 75.0)]
+  pub migrated_percent: f64,
+  /// The first 100 outdated users, `outdated` counts them all.
+  #[schema(value_type = Vec<String>)]
+  pub outdated_uuids: Vec<UserId>,
+}
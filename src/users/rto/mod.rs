@@ -1 +1,3 @@
 pub mod find_user_rto;
+pub mod find_users_rto;
+pub mod hash_migration_rto;